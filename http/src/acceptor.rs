@@ -16,10 +16,13 @@ use crate::{virtual_host::VirtualHostPool,
             service::{ServiceFactory, HttpService},
             request::HttpRequest,
             connect::HttpConnect,
-            packet::UpStreamHeader};
+            server::{HttpLimits, set_header_timeout},
+            packet::{UpStreamHeader, reply_status, reply_page, reply_redirect},
+            h2_frame::is_http2,
+            h2_connect::{Http2Connect, upgrade_settings}};
 
 /*
-* Http连接时允许的最大Http头数量
+* Http连接时默认允许的最大Http头数量
 */
 pub const MAX_CONNECT_HTTP_HEADER_LIMIT: usize = 32;
 
@@ -59,11 +62,13 @@ impl<S: Socket, W: AsyncIOWait> HttpAcceptor<S, W> {
                            waits: W,
                            acceptor: HttpAcceptor<S, W>,
                            hosts: P,
-                           keep_alive: usize)
+                           keep_alive: usize,
+                           limits: HttpLimits)
         where P: VirtualHostPool<S, W> {
         //解析上行请求
         let mut http_request_result = None;
         let buf = Box::into_raw(Box::new(Vec::<u8>::new())) as usize;
+        set_header_timeout(&handle, &limits); //开始读取请求头前，设置读取请求头的超时定时器
         loop {
             match AsyncReadTask::async_read(handle.clone(), waits.clone(), 0).await {
                 Err(e) => {
@@ -72,20 +77,43 @@ impl<S: Socket, W: AsyncIOWait> HttpAcceptor<S, W> {
                 },
                 Ok(bin) => {
                     unsafe { (&mut *(buf as *mut Vec<u8>)).put(bin); }
+                    match is_http2(handle.get_alpn_protocol(), unsafe { (&*(buf as *mut Vec<u8>)).as_slice() }) {
+                        None => {
+                            //可能是不完整的Http2连接序言，则继续接收
+                            continue;
+                        },
+                        Some(true) => {
+                            //已协商或直接使用Http2，则由Http2连接处理后续请求
+                            let buf = unsafe { *Box::from_raw(buf as *mut Vec<u8>) };
+                            let connect = Http2Connect::new(handle.clone(), waits.clone(), hosts.clone(), keep_alive, limits);
+                            connect.run(buf).await;
+                            return;
+                        },
+                        Some(false) => (),
+                    }
+
                     let mut headers = HeaderMap::new();
-                    let mut header = [EMPTY_HEADER; MAX_CONNECT_HTTP_HEADER_LIMIT];
+                    let mut header = vec![EMPTY_HEADER; limits.max_headers];
                     let mut req = Request::new(&mut header);
                     if let Some(body_offset) = UpStreamHeader::read_header(handle.clone(),
                                                                            waits.clone(),
                                                                             unsafe { (&*(buf as *mut Vec<u8>)).as_slice() },
                                                                             &mut req,
-                                                                            &mut headers) {
+                                                                            &mut headers,
+                                                                            &limits) {
                         //解析成功，则根据请求的主机，获取相应的服务
                         let buf = unsafe { *Box::from_raw(buf as *mut Vec<u8>) };
                         if let Some(value) = headers.get(HOST) {
                             if let Ok(host_name) = value.to_str() {
+                                if let Some((status, location)) = hosts.redirect(host_name, handle.is_security(), req.path.unwrap_or("/")) {
+                                    //主机级重定向，则回应重定向，并关闭当前连接
+                                    reply_redirect(&handle, status, &location);
+                                    return;
+                                }
+
                                 if let Some(host) = hosts.get(host_name) {
                                     let mut connect = HttpConnect::new(handle.clone(), waits.clone(), host.new_service(), keep_alive);
+                                    connect.set_host(host_name);
                                     if let &Some(method) = &req.method {
                                         if let &Some(path) = &req.path {
                                             //构建本次Http连接请求
@@ -95,7 +123,32 @@ impl<S: Socket, W: AsyncIOWait> HttpAcceptor<S, W> {
                                                 "http://".to_string() + host_name + path
                                             };
 
-                                            if let Some(request) = HttpRequest::new(handle.clone(), waits.clone(), method, &url, Version::HTTP_11, headers, &buf[body_offset..]) {
+                                            //根据请求启始行设置Http版本，Http/1.0需要在回应后关闭连接
+                                            let version = if let Some(0) = req.version {
+                                                Version::HTTP_10
+                                            } else {
+                                                Version::HTTP_11
+                                            };
+
+                                            if let Some(mut request) = HttpRequest::new(handle.clone(), waits.clone(), method, &url, version, headers, &buf[body_offset..]) {
+                                                if let Err(e) = request.set_body_limit(limits.max_body) {
+                                                    //连接请求的请求体超过限制，则回应虚拟主机的错误页，并关闭当前连接
+                                                    let (mime, body) = hosts.error_page(request.headers().get(HOST).and_then(|value| value.to_str().ok()), StatusCode::PAYLOAD_TOO_LARGE, request.url().path());
+                                                    reply_page(&handle, StatusCode::PAYLOAD_TOO_LARGE, e, Some(&mime), body);
+                                                    return;
+                                                }
+
+                                                if let Some(settings) = upgrade_settings(&request) {
+                                                    //明文升级为Http2，则由Http2连接处理本次请求和后续请求
+                                                    let mut connect = Http2Connect::new(handle.clone(), waits.clone(), hosts.clone(), keep_alive, limits);
+                                                    if let Err(e) = connect.upgrade(request, &settings) {
+                                                        reply_status(&handle, StatusCode::BAD_REQUEST, e);
+                                                        return;
+                                                    }
+                                                    connect.run(Vec::new()).await;
+                                                    return;
+                                                }
+
                                                 http_request_result = Some((connect, request));
                                                 break;
                                             } else {
@@ -106,20 +159,26 @@ impl<S: Socket, W: AsyncIOWait> HttpAcceptor<S, W> {
                                         }
                                     }
                                 } else {
-                                    //连接请求中的主机不存在，则立即关闭当前连接
-                                    handle.close(Err(Error::new(ErrorKind::Other, format!("http connect failed, host: {:?}, reason: host not exist", host_name))));
+                                    //连接请求中的主机不存在，则回应主机不存在的错误页，并关闭当前连接
+                                    let status = hosts.unknown_host_status();
+                                    let (mime, body) = hosts.error_page(None, status, req.path.unwrap_or("/"));
+                                    reply_page(&handle, status, Error::new(ErrorKind::Other, format!("http connect failed, host: {:?}, reason: host not exist", host_name)), Some(&mime), body);
                                     return;
                                 }
                             } else {
-                                //连接请求的主机头无效，则立即关闭当前连接
-                                handle.close(Err(Error::new(ErrorKind::Other, "http connect failed, reason: invalid host header")));
+                                //连接请求的主机头无效，则回应错误，并关闭当前连接
+                                reply_status(&handle, StatusCode::BAD_REQUEST, Error::new(ErrorKind::Other, "http connect failed, reason: invalid host header"));
                                 return;
                             }
                         } else {
-                            //连接请求中没有主机头，则立即关闭当前连接
-                            handle.close(Err(Error::new(ErrorKind::Other, "http connect failed, reason: host header not exist")));
+                            //连接请求中没有主机头，则回应错误，并关闭当前连接
+                            reply_status(&handle, StatusCode::BAD_REQUEST, Error::new(ErrorKind::Other, "http connect failed, reason: host header not exist"));
                             return;
                         }
+                    } else if handle.is_closed() {
+                        //解析失败，且已回应错误并关闭当前连接，则释放缓冲
+                        unsafe { drop(Box::from_raw(buf as *mut Vec<u8>)); }
+                        return;
                     }
                 },
             }
@@ -0,0 +1,555 @@
+use std::thread;
+use std::sync::Arc;
+use std::path::PathBuf;
+use std::fs::{self, File, OpenOptions};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::io::{Error, Result, ErrorKind, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use regex::Regex;
+use https::header::{HeaderName, HOST, USER_AGENT, REFERER, CONTENT_LENGTH};
+use futures::future::{FutureExt, BoxFuture};
+use crossbeam_channel::{Sender, Receiver, bounded};
+use serde_json::json;
+use log::{info, warn};
+
+use handler::SGenType;
+use tcp::driver::{Socket, AsyncIOWait};
+
+use crate::{gateway::GatewayContext,
+            middleware::{MiddlewareResult, Middleware},
+            request::HttpRequest,
+            response::HttpResponse};
+
+/*
+* 请求id的请求头和响应头
+*/
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/*
+* 请求id在网关上下文中的属性名
+*/
+pub const REQUEST_ID_ATTR: &str = "request_id";
+
+/*
+* 请求id的最大长度，超过的请求头会被忽略，并重新生成请求id
+*/
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/*
+* 默认的访问日志目标，用于log门面
+*/
+const DEFAULT_ACCESS_LOG_TARGET: &str = "access";
+
+/*
+* 访问日志文件写入线程的缓冲行数，缓冲满时丢弃新的访问日志，以避免阻塞请求处理
+*/
+const ACCESS_LOG_BUFFER_LEN: usize = 8192;
+
+/*
+* 月份的英文缩写
+*/
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/*
+* 访问日志格式
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessLogFormat {
+    Common,     //通用日志格式
+    Combined,   //组合日志格式，在通用日志格式后增加Referer和User-Agent
+    Json,       //每行一个Json对象，包括所有访问记录的字段
+}
+
+impl AccessLogFormat {
+    //将访问记录格式化为一行访问日志，不包括换行
+    pub fn format(&self, record: &AccessRecord) -> String {
+        match self {
+            AccessLogFormat::Common => {
+                format!("{} - - [{}] \"{} {} {}\" {} {}",
+                        record.remote,
+                        format_clf_time(record.time),
+                        record.method,
+                        escape_clf(&record.url),
+                        record.version,
+                        record.status,
+                        clf_size(record.size))
+            },
+            AccessLogFormat::Combined => {
+                format!("{} - - [{}] \"{} {} {}\" {} {} \"{}\" \"{}\"",
+                        record.remote,
+                        format_clf_time(record.time),
+                        record.method,
+                        escape_clf(&record.url),
+                        record.version,
+                        record.status,
+                        clf_size(record.size),
+                        escape_clf(record.referer.as_ref().map_or("-", |r| r.as_str())),
+                        escape_clf(record.user_agent.as_ref().map_or("-", |r| r.as_str())))
+            },
+            AccessLogFormat::Json => {
+                json!({
+                    "time": format_iso_time(record.time),
+                    "remote": record.remote,
+                    "method": record.method,
+                    "url": record.url,
+                    "version": record.version,
+                    "host": record.host,
+                    "status": record.status,
+                    "size": record.size,
+                    "user_agent": record.user_agent,
+                    "referer": record.referer,
+                    "request_id": record.request_id,
+                    "duration_ms": record.duration.as_micros() as f64 / 1000.0,
+                }).to_string()
+            },
+        }
+    }
+}
+
+/*
+* 访问记录
+*/
+#[derive(Debug, Clone)]
+pub struct AccessRecord {
+    pub time:       SystemTime,     //请求开始处理的时间
+    pub remote:     String,         //客户端地址
+    pub method:     String,         //请求方法
+    pub url:        String,         //请求路径和查询
+    pub version:    String,         //Http版本
+    pub host:       Option<String>, //请求的主机
+    pub status:     u16,            //响应状态码
+    pub size:       u64,            //响应体大小，流式响应未设置响应体长度时为0
+    pub user_agent: Option<String>, //用户代理
+    pub referer:    Option<String>, //引用页
+    pub request_id: String,         //请求id
+    pub duration:   Duration,       //请求处理时长，流式响应只包括生成响应头的时长
+}
+
+/*
+* 访问日志输出
+*/
+#[derive(Debug, Clone)]
+pub enum AccessLogOutput {
+    Log(String),                    //输出到指定目标的log门面，日志级别为Info
+    File(PathBuf, u64, usize),      //输出到按大小滚动的文件，包括文件路径、单个文件的最大大小和保留的历史文件数量
+}
+
+/*
+* 访问日志写入器
+*/
+enum AccessLogWriter {
+    Log(String),            //log门面的目标
+    File(Sender<String>),   //文件写入线程的生产者
+}
+
+/*
+* 访问日志记录器，可以被多个访问日志中间件共享
+*/
+pub struct AccessLogger {
+    format:     AccessLogFormat,    //访问日志格式
+    writer:     AccessLogWriter,    //访问日志写入器
+    sample:     usize,              //采样间隔，每指定数量的成功请求记录一次，错误请求总是记录
+    counter:    AtomicUsize,        //采样计数器
+    includes:   Vec<Regex>,         //需要记录的请求路径，为空表示记录所有请求路径
+    excludes:   Vec<Regex>,         //不需要记录的请求路径
+    dropped:    AtomicUsize,        //因写入缓冲已满而丢弃的访问日志数量
+    id_counter: AtomicUsize,        //请求id计数器
+}
+
+unsafe impl Send for AccessLogger {}
+unsafe impl Sync for AccessLogger {}
+
+impl AccessLogger {
+    //构建指定格式和输出的访问日志记录器，输出到文件时会启动文件写入线程
+    pub fn new(format: AccessLogFormat, output: AccessLogOutput) -> Result<Self> {
+        let writer = match output {
+            AccessLogOutput::Log(target) => {
+                if target.is_empty() {
+                    AccessLogWriter::Log(DEFAULT_ACCESS_LOG_TARGET.to_string())
+                } else {
+                    AccessLogWriter::Log(target)
+                }
+            },
+            AccessLogOutput::File(path, max_size, max_files) => {
+                let file = RotatingFile::open(path, max_size, max_files)?;
+                let (sender, receiver) = bounded(ACCESS_LOG_BUFFER_LEN);
+                thread::Builder::new()
+                    .name("Http Access Log".to_string())
+                    .spawn(move || write_loop(file, receiver))?;
+                AccessLogWriter::File(sender)
+            },
+        };
+
+        Ok(AccessLogger {
+            format,
+            writer,
+            sample: 1,
+            counter: AtomicUsize::new(0),
+            includes: Vec::new(),
+            excludes: Vec::new(),
+            dropped: AtomicUsize::new(0),
+            id_counter: AtomicUsize::new(0),
+        })
+    }
+
+    //设置采样间隔，每指定数量的成功请求记录一次，状态码大于等于400的请求总是记录
+    pub fn set_sample(&mut self, sample: usize) {
+        self.sample = sample.max(1);
+    }
+
+    //增加需要记录的请求路径的正则表达式，设置后只记录匹配的请求路径
+    pub fn add_include(&mut self, pattern: &str) -> Result<()> {
+        self.includes.push(parse_pattern(pattern)?);
+        Ok(())
+    }
+
+    //增加不需要记录的请求路径的正则表达式
+    pub fn add_exclude(&mut self, pattern: &str) -> Result<()> {
+        self.excludes.push(parse_pattern(pattern)?);
+        Ok(())
+    }
+
+    //获取因写入缓冲已满而丢弃的访问日志数量
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    //判断是否需要记录指定请求路径和状态码的访问
+    pub fn is_match(&self, path: &str, status: u16) -> bool {
+        if !self.includes.is_empty() && !self.includes.iter().any(|regex| regex.is_match(path)) {
+            return false;
+        }
+
+        if self.excludes.iter().any(|regex| regex.is_match(path)) {
+            return false;
+        }
+
+        if status >= 400 || self.sample <= 1 {
+            return true;
+        }
+
+        self.counter.fetch_add(1, Ordering::Relaxed) % self.sample == 0
+    }
+
+    //记录访问
+    pub fn log(&self, record: &AccessRecord) {
+        let line = self.format.format(record);
+        match &self.writer {
+            AccessLogWriter::Log(target) => {
+                info!(target: target.as_str(), "{}", line);
+            },
+            AccessLogWriter::File(sender) => {
+                if sender.try_send(line).is_err() {
+                    //写入缓冲已满或写入线程已退出，则丢弃
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            },
+        }
+    }
+
+    //获取请求的请求id，请求头中没有有效的请求id时，生成新的请求id
+    fn request_id(&self, req_id: Option<&str>) -> String {
+        if let Some(id) = req_id {
+            if !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic()) {
+                return id.to_string();
+            }
+        }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let count = self.id_counter.fetch_add(1, Ordering::Relaxed);
+        format!("{:x}-{:08x}", now.as_micros(), count as u32)
+    }
+}
+
+/*
+* 访问日志中间件，包装指定的中间件，并在处理完成后记录访问
+* 包装的中间件在请求处理时退出，也会被记录
+*/
+pub struct AccessLog<S: Socket, W: AsyncIOWait> {
+    logger: Arc<AccessLogger>,                              //访问日志记录器
+    inner:  Arc<dyn Middleware<S, W, GatewayContext>>,      //被包装的中间件
+}
+
+unsafe impl<S: Socket, W: AsyncIOWait> Send for AccessLog<S, W> {}
+unsafe impl<S: Socket, W: AsyncIOWait> Sync for AccessLog<S, W> {}
+
+impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for AccessLog<S, W> {
+    fn request<'a>(&'a self, context: &'a mut GatewayContext, req: HttpRequest<S, W>)
+                   -> BoxFuture<'a, MiddlewareResult<S, W>> {
+        let future = async move {
+            //生成请求id，并写入网关上下文
+            let req_id = req.headers().get(REQUEST_ID_HEADER).and_then(|value| value.to_str().ok());
+            let request_id = self.logger.request_id(req_id);
+            context.set(REQUEST_ID_ATTR.to_string(), SGenType::Str(request_id.clone()));
+
+            //在调用被包装的中间件前记录请求，用于请求处理时退出的情况
+            let start = context.get_start_time().unwrap_or_else(Instant::now);
+            let record = new_record(&req, request_id);
+            match self.inner.request(context, req).await {
+                MiddlewareResult::Break(mut resp) => {
+                    self.finish(&record, start, &mut resp);
+                    MiddlewareResult::Break(resp)
+                },
+                MiddlewareResult::Throw(e) => {
+                    self.complete(&record, start, 500, 0);
+                    MiddlewareResult::Throw(e)
+                },
+                result => result,
+            }
+        };
+        future.boxed()
+    }
+
+    fn response<'a>(&'a self, context: &'a mut GatewayContext, req: HttpRequest<S, W>, resp: HttpResponse<S, W>)
+                    -> BoxFuture<'a, MiddlewareResult<S, W>> {
+        let future = async move {
+            let request_id = match context.get(&REQUEST_ID_ATTR.to_string()) {
+                Some(SGenType::Str(id)) => id.clone(),
+                _ => self.logger.request_id(None),
+            };
+            let start = context.get_start_time().unwrap_or_else(Instant::now);
+            let record = new_record(&req, request_id);
+
+            match self.inner.response(context, req, resp).await {
+                MiddlewareResult::ContinueResponse((req, mut resp)) => {
+                    self.finish(&record, start, &mut resp);
+                    MiddlewareResult::ContinueResponse((req, resp))
+                },
+                MiddlewareResult::Finish((req, mut resp)) => {
+                    self.finish(&record, start, &mut resp);
+                    MiddlewareResult::Finish((req, resp))
+                },
+                MiddlewareResult::Break(mut resp) => {
+                    self.finish(&record, start, &mut resp);
+                    MiddlewareResult::Break(resp)
+                },
+                MiddlewareResult::Throw(e) => {
+                    self.complete(&record, start, 500, 0);
+                    MiddlewareResult::Throw(e)
+                },
+                result => result,
+            }
+        };
+        future.boxed()
+    }
+}
+
+impl<S: Socket, W: AsyncIOWait> AccessLog<S, W> {
+    //构建包装指定中间件的访问日志中间件
+    pub fn new(logger: Arc<AccessLogger>, inner: Arc<dyn Middleware<S, W, GatewayContext>>) -> Self {
+        AccessLog {
+            logger,
+            inner,
+        }
+    }
+
+    //使用响应完成访问记录，并在响应中设置请求id
+    fn finish(&self, record: &AccessRecord, start: Instant, resp: &mut HttpResponse<S, W>) {
+        if !resp.contains_header(HeaderName::from_static(REQUEST_ID_HEADER)) {
+            resp.header(REQUEST_ID_HEADER, &record.request_id);
+        }
+
+        let size = match resp.get_headers().get(CONTENT_LENGTH).and_then(|value| value.to_str().ok()).and_then(|value| value.parse::<u64>().ok()) {
+            Some(len) => len,
+            None => resp.as_body().and_then(|body| body.len()).unwrap_or(0) as u64,
+        };
+        self.complete(record, start, resp.get_status(), size);
+    }
+
+    //在请求处理完成时设置访问记录的处理时长、状态码和响应体大小，并记录访问
+    fn complete(&self, record: &AccessRecord, start: Instant, status: u16, size: u64) {
+        let mut record = record.clone();
+        record.duration = start.elapsed();
+        record.time = SystemTime::now() - record.duration;
+        record.status = status;
+        record.size = size;
+        self.log(&record);
+    }
+
+    //记录访问
+    fn log(&self, record: &AccessRecord) {
+        let path = record.url.split('?').next().unwrap_or("");
+        if self.logger.is_match(path, record.status) {
+            self.logger.log(record);
+        }
+    }
+}
+
+/*
+* 按大小滚动的文件
+*/
+struct RotatingFile {
+    path:       PathBuf,    //文件路径
+    max_size:   u64,        //单个文件的最大大小，为0表示不滚动
+    max_files:  usize,      //保留的历史文件数量
+    file:       File,       //当前文件
+    size:       u64,        //当前文件大小
+}
+
+impl RotatingFile {
+    //打开指定路径的滚动文件
+    fn open(path: PathBuf, max_size: u64, max_files: usize) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            max_size,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    //写入一行访问日志，超过最大大小时滚动
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        if self.max_size > 0 && self.size > 0 && self.size + line.len() as u64 + 1 > self.max_size {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    //滚动文件，历史文件的序号越大越旧，超过保留数量的历史文件会被删除
+    fn rotate(&mut self) -> Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            //不保留历史文件，则清空当前文件
+            self.file = OpenOptions::new().create(true).write(true).truncate(true).open(&self.path)?;
+            self.size = 0;
+            return Ok(());
+        }
+
+        let _ = fs::remove_file(self.history(self.max_files));
+        for index in (1..self.max_files).rev() {
+            let from = self.history(index);
+            if from.exists() {
+                fs::rename(from, self.history(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.history(1))?;
+
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    //获取指定序号的历史文件路径
+    fn history(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+}
+
+//文件写入线程的循环，所有生产者被销毁后退出
+fn write_loop(mut file: RotatingFile, receiver: Receiver<String>) {
+    while let Ok(line) = receiver.recv() {
+        if let Err(e) = file.write_line(&line) {
+            warn!("!!!> Http Access Log Write Failed, path: {:?}, reason: {:?}", file.path, e);
+        }
+
+        if receiver.is_empty() {
+            //没有待写入的访问日志，则刷新
+            let _ = file.file.flush();
+        }
+    }
+}
+
+//使用请求生成访问记录，处理时长和响应相关的字段需要在请求处理完成后设置
+fn new_record<S: Socket, W: AsyncIOWait>(req: &HttpRequest<S, W>, request_id: String) -> AccessRecord {
+    let header = |key: HeaderName| req.headers().get(key).and_then(|value| value.to_str().ok()).map(|value| value.to_string());
+    let url = match req.url().query() {
+        Some(query) => req.url().path().to_string() + "?" + query,
+        None => req.url().path().to_string(),
+    };
+
+    AccessRecord {
+        time: SystemTime::now(),
+        remote: req.get_handle().get_remote().ip().to_string(),
+        method: req.method().as_str().to_string(),
+        url,
+        version: format!("{:?}", req.version()),
+        host: header(HOST),
+        status: 0,
+        size: 0,
+        user_agent: header(USER_AGENT),
+        referer: header(REFERER),
+        request_id,
+        duration: Duration::from_secs(0),
+    }
+}
+
+//解析请求路径的正则表达式
+fn parse_pattern(pattern: &str) -> Result<Regex> {
+    match Regex::new(pattern) {
+        Err(e) => Err(Error::new(ErrorKind::InvalidInput, format!("parse access log pattern failed, pattern: {:?}, reason: {:?}", pattern, e))),
+        Ok(regex) => Ok(regex),
+    }
+}
+
+//通用日志格式的响应体大小，为0时使用-
+fn clf_size(size: u64) -> String {
+    if size == 0 {
+        "-".to_string()
+    } else {
+        size.to_string()
+    }
+}
+
+//转义通用日志格式中的引号、反斜杠和控制字符
+fn escape_clf(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+//将时间格式化为通用日志格式的UTC时间，例如10/Oct/2000:13:55:36 +0000
+pub fn format_clf_time(time: SystemTime) -> String {
+    let (year, month, day, hour, min, sec, _) = utc_fields(time);
+    format!("{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000", day, MONTHS[(month - 1) as usize], year, hour, min, sec)
+}
+
+//将时间格式化为ISO 8601的UTC时间，例如2000-10-10T13:55:36.000Z
+pub fn format_iso_time(time: SystemTime) -> String {
+    let (year, month, day, hour, min, sec, millis) = utc_fields(time);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", year, month, day, hour, min, sec, millis)
+}
+
+//获取时间的UTC年、月、日、时、分、秒和毫秒
+fn utc_fields(time: SystemTime) -> (i64, u32, u32, u32, u32, u32, u32) {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs() as i64;
+    let days = secs / 86400;
+    let rem = (secs % 86400) as u32;
+
+    //按公历计算日期
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day, rem / 3600, rem % 3600 / 60, rem % 60, since.subsec_millis())
+}
@@ -0,0 +1,881 @@
+use std::fs;
+use std::result::Result as GenResult;
+use std::path::Path;
+use std::num::NonZeroU32;
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::io::{Error, Result, ErrorKind};
+
+use ring::{hmac, digest, signature, pbkdf2, rand::{SecureRandom, SystemRandom}};
+use https::{StatusCode, header::{AUTHORIZATION, WWW_AUTHENTICATE, CONTENT_LENGTH}};
+use futures::future::{FutureExt, BoxFuture};
+use parking_lot::{Mutex, RwLock};
+use serde_json::{json, Value};
+use log::warn;
+
+use hash::XHashMap;
+use tcp::driver::{Socket, AsyncIOWait};
+
+use crate::{gateway::GatewayContext,
+            middleware::{MiddlewareResult, Middleware},
+            request::HttpRequest,
+            response::HttpResponse};
+
+/*
+* 已认证主体在HttpPort请求参数中的参数名
+*/
+pub const PRINCIPAL_PARAM: &str = "principal";
+
+/*
+* Jwt的默认时钟偏差，单位秒
+*/
+const DEFAULT_JWT_LEEWAY: u64 = 60;
+
+/*
+* Jwt中默认的主体名声明
+*/
+const DEFAULT_JWT_PRINCIPAL_CLAIM: &str = "sub";
+
+/*
+* Hmac签名请求的密钥id请求头
+*/
+pub const HMAC_KEY_ID_HEADER: &str = "x-auth-key";
+
+/*
+* Hmac签名请求的时间戳请求头，单位秒
+*/
+pub const HMAC_TIMESTAMP_HEADER: &str = "x-auth-timestamp";
+
+/*
+* Hmac签名请求的随机数请求头
+*/
+pub const HMAC_NONCE_HEADER: &str = "x-auth-nonce";
+
+/*
+* Hmac签名请求的签名请求头，值为签名的base64编码
+*/
+pub const HMAC_SIGNATURE_HEADER: &str = "x-auth-signature";
+
+/*
+* Hmac签名请求的默认最大时间偏差，单位秒
+*/
+const DEFAULT_HMAC_MAX_SKEW: u64 = 300;
+
+/*
+* Hmac签名请求的默认最大缓存随机数数量
+*/
+const DEFAULT_HMAC_MAX_NONCES: usize = 1024 * 1024;
+
+/*
+* Hmac签名请求的随机数最大长度
+*/
+const MAX_HMAC_NONCE_LEN: usize = 128;
+
+/*
+* 内存凭据存储的默认密码摘要迭代次数
+*/
+const DEFAULT_PASSWORD_ITERATIONS: u32 = 100_000;
+
+/*
+* 内存凭据存储的密码盐值长度和摘要长度
+*/
+const PASSWORD_SALT_LEN: usize = 16;
+const PASSWORD_DIGEST_LEN: usize = 32;
+
+/*
+* 认证方式
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthScheme {
+    Basic,  //Http基础认证
+    Bearer, //Jwt令牌认证
+    Hmac,   //Hmac签名请求认证
+}
+
+/*
+* 已认证的主体
+*/
+#[derive(Debug, Clone)]
+pub struct Principal {
+    name:   String,         //主体名
+    scheme: AuthScheme,     //认证方式
+    claims: Option<Value>,  //Jwt令牌的声明
+}
+
+impl Principal {
+    //构建已认证的主体
+    pub fn new(name: String, scheme: AuthScheme, claims: Option<Value>) -> Self {
+        Principal {
+            name,
+            scheme,
+            claims,
+        }
+    }
+
+    //获取主体名
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    //获取认证方式
+    pub fn scheme(&self) -> AuthScheme {
+        self.scheme
+    }
+
+    //获取Jwt令牌的声明
+    pub fn claims(&self) -> Option<&Value> {
+        self.claims.as_ref()
+    }
+}
+
+/*
+* Http基础认证的凭据存储
+*/
+pub trait CredentialStore: Send + Sync + 'static {
+    //验证指定用户名和密码，成功返回主体名
+    fn verify(&self, user: &str, password: &str) -> Option<String>;
+}
+
+/*
+* 内存凭据存储，只保存用户名、每个用户随机的盐值和密码的Pbkdf2-Hmac-Sha256摘要
+*/
+pub struct MemoryCredentialStore {
+    users:      RwLock<XHashMap<String, (Vec<u8>, Vec<u8>)>>,   //用户名和密码的盐值、摘要表
+    iterations: NonZeroU32,                                     //密码摘要的迭代次数
+}
+
+unsafe impl Send for MemoryCredentialStore {}
+unsafe impl Sync for MemoryCredentialStore {}
+
+impl CredentialStore for MemoryCredentialStore {
+    fn verify(&self, user: &str, password: &str) -> Option<String> {
+        if let Some((salt, sign)) = self.users.read().get(user) {
+            if pbkdf2::verify(pbkdf2::PBKDF2_HMAC_SHA256, self.iterations, salt.as_slice(), password.as_bytes(), sign.as_slice()).is_ok() {
+                return Some(user.to_string());
+            }
+        }
+
+        None
+    }
+}
+
+impl MemoryCredentialStore {
+    //构建内存凭据存储
+    pub fn new() -> Self {
+        Self::with_iterations(DEFAULT_PASSWORD_ITERATIONS)
+    }
+
+    //构建指定密码摘要迭代次数的内存凭据存储，迭代次数至少为1
+    pub fn with_iterations(iterations: u32) -> Self {
+        MemoryCredentialStore {
+            users: RwLock::new(XHashMap::default()),
+            iterations: NonZeroU32::new(iterations.max(1)).unwrap(),
+        }
+    }
+
+    //增加或替换指定用户的密码，每次都会为用户生成新的随机盐值
+    pub fn add_user(&self, user: &str, password: &str) -> Result<()> {
+        let mut salt = vec![0u8; PASSWORD_SALT_LEN];
+        if let Err(e) = SystemRandom::new().fill(&mut salt) {
+            return Err(Error::new(ErrorKind::Other, format!("add user failed, user: {:?}, reason: {:?}", user, e)));
+        }
+
+        let mut sign = vec![0u8; PASSWORD_DIGEST_LEN];
+        pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, self.iterations, salt.as_slice(), password.as_bytes(), sign.as_mut_slice());
+        self.users.write().insert(user.to_string(), (salt, sign));
+        Ok(())
+    }
+
+    //移除指定用户，返回用户是否存在
+    pub fn remove_user(&self, user: &str) -> bool {
+        self.users.write().remove(user).is_some()
+    }
+}
+
+/*
+* Http基础认证
+*/
+pub struct BasicAuth {
+    realm: String,                      //认证域
+    store: Box<dyn CredentialStore>,    //凭据存储
+}
+
+unsafe impl Send for BasicAuth {}
+unsafe impl Sync for BasicAuth {}
+
+impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for BasicAuth {
+    fn request<'a>(&'a self, context: &'a mut GatewayContext, req: HttpRequest<S, W>)
+                   -> BoxFuture<'a, MiddlewareResult<S, W>> {
+        let future = async move {
+            let challenge = format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm);
+            let (user, password) = match req.headers().get(AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| parse_basic_credentials(value)) {
+                None => {
+                    //没有凭据或凭据无效
+                    return unauthorized(req, challenge.as_str());
+                },
+                Some(credentials) => credentials,
+            };
+
+            match self.store.verify(user.as_str(), password.as_str()) {
+                None => {
+                    //验证凭据失败
+                    unauthorized(req, challenge.as_str())
+                },
+                Some(name) => {
+                    //验证凭据成功，则写入已认证的主体，并继续请求处理
+                    context.set_principal(Some(Principal::new(name, AuthScheme::Basic, None)));
+                    MiddlewareResult::ContinueRequest(req)
+                },
+            }
+        };
+        future.boxed()
+    }
+
+    fn response<'a>(&'a self, context: &'a mut GatewayContext, req: HttpRequest<S, W>, resp: HttpResponse<S, W>)
+                    -> BoxFuture<'a, MiddlewareResult<S, W>> {
+        let future = async move {
+            MiddlewareResult::ContinueResponse((req, resp))
+        };
+        future.boxed()
+    }
+}
+
+impl BasicAuth {
+    //构建Http基础认证
+    pub fn new(realm: &str, store: Box<dyn CredentialStore>) -> Self {
+        BasicAuth {
+            realm: realm.to_string(),
+            store,
+        }
+    }
+}
+
+/*
+* Jwt令牌的签名算法
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JwtAlgorithm {
+    Hs256,  //Hmac-Sha256
+    Rs256,  //RsaSsa-Pkcs1-v1_5-Sha256
+}
+
+impl JwtAlgorithm {
+    //根据算法名获取签名算法
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "HS256" => Some(JwtAlgorithm::Hs256),
+            "RS256" => Some(JwtAlgorithm::Rs256),
+            _ => None,
+        }
+    }
+
+    //获取算法名
+    pub fn name(&self) -> &'static str {
+        match self {
+            JwtAlgorithm::Hs256 => "HS256",
+            JwtAlgorithm::Rs256 => "RS256",
+        }
+    }
+}
+
+/*
+* Jwt令牌的验证密钥
+*/
+enum JwtKey {
+    Hs256(hmac::Key),       //共享密钥
+    Rs256(Vec<u8>, Vec<u8>),//Rsa公钥的模数和指数，都为大端字节序
+}
+
+impl JwtKey {
+    //获取密钥的签名算法
+    fn algorithm(&self) -> JwtAlgorithm {
+        match self {
+            JwtKey::Hs256(_) => JwtAlgorithm::Hs256,
+            JwtKey::Rs256(_, _) => JwtAlgorithm::Rs256,
+        }
+    }
+
+    //验证签名
+    fn verify(&self, message: &[u8], sign: &[u8]) -> bool {
+        match self {
+            JwtKey::Hs256(key) => {
+                hmac::verify(key, message, sign).is_ok()
+            },
+            JwtKey::Rs256(n, e) => {
+                let key = signature::RsaPublicKeyComponents { n: n.as_slice(), e: e.as_slice() };
+                key.verify(&signature::RSA_PKCS1_2048_8192_SHA256, message, sign).is_ok()
+            },
+        }
+    }
+}
+
+/*
+* Jwt令牌认证
+*/
+pub struct JwtAuth {
+    realm:              String,                                 //认证域
+    keys:               RwLock<Vec<(Option<String>, JwtKey)>>,  //验证密钥列表，包括可选的密钥id
+    audience:           Option<String>,                         //要求的接收方
+    issuer:             Option<String>,                         //要求的签发方
+    leeway:             u64,                                    //验证有效期时允许的时钟偏差，单位秒
+    principal_claim:    String,                                 //主体名声明
+}
+
+unsafe impl Send for JwtAuth {}
+unsafe impl Sync for JwtAuth {}
+
+impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for JwtAuth {
+    fn request<'a>(&'a self, context: &'a mut GatewayContext, req: HttpRequest<S, W>)
+                   -> BoxFuture<'a, MiddlewareResult<S, W>> {
+        let future = async move {
+            let token = match req.headers().get(AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| parse_auth_param(value, "Bearer")) {
+                None => {
+                    //没有令牌
+                    let challenge = format!("Bearer realm=\"{}\"", self.realm);
+                    return unauthorized(req, challenge.as_str());
+                },
+                Some(token) => token.to_string(),
+            };
+
+            match self.verify(token.as_str()) {
+                Err(_) => {
+                    //验证令牌失败
+                    let challenge = format!("Bearer realm=\"{}\", error=\"invalid_token\"", self.realm);
+                    unauthorized(req, challenge.as_str())
+                },
+                Ok(principal) => {
+                    //验证令牌成功，则写入已认证的主体，并继续请求处理
+                    context.set_principal(Some(principal));
+                    MiddlewareResult::ContinueRequest(req)
+                },
+            }
+        };
+        future.boxed()
+    }
+
+    fn response<'a>(&'a self, context: &'a mut GatewayContext, req: HttpRequest<S, W>, resp: HttpResponse<S, W>)
+                    -> BoxFuture<'a, MiddlewareResult<S, W>> {
+        let future = async move {
+            MiddlewareResult::ContinueResponse((req, resp))
+        };
+        future.boxed()
+    }
+}
+
+impl JwtAuth {
+    //构建Jwt令牌认证
+    pub fn new(realm: &str) -> Self {
+        JwtAuth {
+            realm: realm.to_string(),
+            keys: RwLock::new(Vec::new()),
+            audience: None,
+            issuer: None,
+            leeway: DEFAULT_JWT_LEEWAY,
+            principal_claim: DEFAULT_JWT_PRINCIPAL_CLAIM.to_string(),
+        }
+    }
+
+    //设置要求的接收方
+    pub fn set_audience(&mut self, audience: Option<String>) {
+        self.audience = audience;
+    }
+
+    //设置要求的签发方
+    pub fn set_issuer(&mut self, issuer: Option<String>) {
+        self.issuer = issuer;
+    }
+
+    //设置验证有效期时允许的时钟偏差，单位秒
+    pub fn set_leeway(&mut self, leeway: u64) {
+        self.leeway = leeway;
+    }
+
+    //设置主体名声明
+    pub fn set_principal_claim(&mut self, claim: &str) {
+        self.principal_claim = claim.to_string();
+    }
+
+    //增加HS256的共享密钥
+    pub fn add_hs256_key(&self, kid: Option<&str>, secret: &[u8]) {
+        self.keys.write().push((kid.map(|kid| kid.to_string()), JwtKey::Hs256(hmac::Key::new(hmac::HMAC_SHA256, secret))));
+    }
+
+    //增加RS256的公钥，模数和指数都为大端字节序
+    pub fn add_rs256_key(&self, kid: Option<&str>, n: &[u8], e: &[u8]) {
+        self.keys.write().push((kid.map(|kid| kid.to_string()), JwtKey::Rs256(n.to_vec(), e.to_vec())));
+    }
+
+    //获取验证密钥数量
+    pub fn keys_len(&self) -> usize {
+        self.keys.read().len()
+    }
+
+    //从指定的JWKS文件中加载验证密钥，加载成功会替换所有已有的验证密钥，返回加载的密钥数量
+    pub fn load_jwks<P: AsRef<Path>>(&self, path: P) -> Result<usize> {
+        let path = path.as_ref();
+        let bin = match fs::read(path) {
+            Err(e) => {
+                return Err(Error::new(e.kind(), format!("load jwks failed, path: {:?}, reason: {:?}", path, e)));
+            },
+            Ok(bin) => bin,
+        };
+
+        let keys = match parse_jwks(bin.as_slice()) {
+            Err(e) => {
+                return Err(Error::new(ErrorKind::InvalidData, format!("load jwks failed, path: {:?}, reason: {}", path, e)));
+            },
+            Ok(keys) => keys,
+        };
+
+        let len = keys.len();
+        *self.keys.write() = keys;
+        Ok(len)
+    }
+
+    //验证指定的令牌，成功返回已认证的主体
+    pub fn verify(&self, token: &str) -> Result<Principal> {
+        let mut parts = token.split('.');
+        let (header, payload, sign) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(header), Some(payload), Some(sign), None) => (header, payload, sign),
+            _ => {
+                return Err(Error::new(ErrorKind::InvalidData, "verify jwt failed, reason: invalid token"));
+            },
+        };
+
+        let header = decode_json_part(header)?;
+        let claims = decode_json_part(payload)?;
+        let sign = match base64::decode_config(sign, base64::URL_SAFE_NO_PAD) {
+            Err(e) => {
+                return Err(Error::new(ErrorKind::InvalidData, format!("verify jwt failed, reason: {:?}", e)));
+            },
+            Ok(sign) => sign,
+        };
+
+        //验证签名，不允许未签名的令牌
+        let alg = match header.get("alg").and_then(|alg| alg.as_str()).and_then(|alg| JwtAlgorithm::from_name(alg)) {
+            None => {
+                return Err(Error::new(ErrorKind::InvalidData, format!("verify jwt failed, alg: {:?}, reason: unsupported algorithm", header.get("alg"))));
+            },
+            Some(alg) => alg,
+        };
+        let kid = header.get("kid").and_then(|kid| kid.as_str());
+        let message = &token.as_bytes()[..token.len() - sign_part_len(token)];
+        let is_verified = self.keys.read().iter().any(|(key_id, key)| {
+            if key.algorithm() != alg {
+                return false;
+            }
+
+            if let (Some(kid), Some(key_id)) = (kid, key_id) {
+                if kid != key_id.as_str() {
+                    return false;
+                }
+            }
+
+            key.verify(message, sign.as_slice())
+        });
+        if !is_verified {
+            return Err(Error::new(ErrorKind::PermissionDenied, "verify jwt failed, reason: invalid signature"));
+        }
+
+        //验证声明
+        let now = now_secs();
+        if let Some(exp) = claims.get("exp") {
+            match exp.as_f64() {
+                Some(exp) if exp >= 0.0 && now <= exp as u64 + self.leeway => (),
+                _ => {
+                    return Err(Error::new(ErrorKind::PermissionDenied, "verify jwt failed, reason: token expired"));
+                },
+            }
+        }
+        if let Some(nbf) = claims.get("nbf") {
+            match nbf.as_f64() {
+                Some(nbf) if nbf >= 0.0 && now + self.leeway >= nbf as u64 => (),
+                _ => {
+                    return Err(Error::new(ErrorKind::PermissionDenied, "verify jwt failed, reason: token not yet valid"));
+                },
+            }
+        }
+        if let Some(audience) = &self.audience {
+            let is_match = match claims.get("aud") {
+                Some(Value::String(aud)) => aud == audience,
+                Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(audience.as_str())),
+                _ => false,
+            };
+            if !is_match {
+                return Err(Error::new(ErrorKind::PermissionDenied, format!("verify jwt failed, audience: {:?}, reason: invalid audience", audience)));
+            }
+        }
+        if let Some(issuer) = &self.issuer {
+            if claims.get("iss").and_then(|iss| iss.as_str()) != Some(issuer.as_str()) {
+                return Err(Error::new(ErrorKind::PermissionDenied, format!("verify jwt failed, issuer: {:?}, reason: invalid issuer", issuer)));
+            }
+        }
+
+        let name = match claims.get(self.principal_claim.as_str()).and_then(|name| name.as_str()) {
+            None => {
+                return Err(Error::new(ErrorKind::InvalidData, format!("verify jwt failed, claim: {:?}, reason: principal not exist", self.principal_claim)));
+            },
+            Some(name) => name.to_string(),
+        };
+
+        Ok(Principal::new(name, AuthScheme::Bearer, Some(claims)))
+    }
+}
+
+/*
+* Hmac签名请求的随机数缓存
+*/
+struct NonceCache {
+    seen:   XHashMap<String, u64>,      //已使用的随机数和过期时间
+    queue:  VecDeque<(u64, String)>,    //按过期时间排序的随机数队列
+}
+
+impl NonceCache {
+    //检查随机数是否未使用，未使用则记录，只会淘汰已过期的随机数，未过期的随机数达到最大数量时拒绝新的随机数，以防止重放
+    fn check_and_insert(&mut self, nonce: String, now: u64, expire: u64, max_len: usize) -> GenResult<(), &'static str> {
+        while let Some((timeout, _)) = self.queue.front() {
+            if *timeout > now {
+                break;
+            }
+
+            if let Some((_, key)) = self.queue.pop_front() {
+                self.seen.remove(&key);
+            }
+        }
+
+        if self.seen.contains_key(&nonce) {
+            //随机数已使用
+            return Err("replayed nonce");
+        }
+
+        if self.queue.len() >= max_len {
+            //未过期的随机数已达最大数量
+            return Err("too many nonces");
+        }
+
+        self.seen.insert(nonce.clone(), expire);
+        self.queue.push_back((expire, nonce));
+        Ok(())
+    }
+}
+
+/*
+* Hmac签名请求认证，签名为Hmac-Sha256(密钥, 方法\n路径和查询\n时间戳\n随机数\n请求体的Sha256十六进制摘要)
+*/
+pub struct HmacAuth {
+    keys:       RwLock<XHashMap<String, hmac::Key>>,    //密钥id和密钥表
+    max_skew:   u64,                                    //时间戳允许的最大偏差，单位秒
+    max_nonces: usize,                                  //最大缓存随机数数量
+    nonces:     Mutex<NonceCache>,                      //随机数缓存
+}
+
+unsafe impl Send for HmacAuth {}
+unsafe impl Sync for HmacAuth {}
+
+impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for HmacAuth {
+    fn request<'a>(&'a self, context: &'a mut GatewayContext, req: HttpRequest<S, W>)
+                   -> BoxFuture<'a, MiddlewareResult<S, W>> {
+        let mut req = req;
+        let future = async move {
+            let (key_id, timestamp, nonce, sign) = match (header_str(&req, HMAC_KEY_ID_HEADER),
+                                                          header_str(&req, HMAC_TIMESTAMP_HEADER),
+                                                          header_str(&req, HMAC_NONCE_HEADER),
+                                                          header_str(&req, HMAC_SIGNATURE_HEADER)) {
+                (Some(key_id), Some(timestamp), Some(nonce), Some(sign)) => (key_id, timestamp, nonce, sign),
+                _ => {
+                    //缺少签名请求头
+                    return unauthorized(req, "HMAC-SHA256");
+                },
+            };
+
+            let method = req.method().as_str().to_string();
+            let path = match req.url().query() {
+                None => req.url().path().to_string(),
+                Some(query) => req.url().path().to_string() + "?" + query,
+            };
+            let body_sign = match req.body().await {
+                None => body_digest(&[]),
+                Some(body) => body_digest(body),
+            };
+            let message = string_to_sign(method.as_str(), path.as_str(), timestamp.as_str(), nonce.as_str(), body_sign.as_str());
+
+            if let Err(_) = self.verify(key_id.as_str(), timestamp.as_str(), nonce.as_str(), message.as_str(), sign.as_str()) {
+                //验证签名失败
+                return unauthorized(req, "HMAC-SHA256 error=\"invalid_signature\"");
+            }
+
+            //验证签名成功，则写入已认证的主体，并继续请求处理
+            context.set_principal(Some(Principal::new(key_id, AuthScheme::Hmac, None)));
+            MiddlewareResult::ContinueRequest(req)
+        };
+        future.boxed()
+    }
+
+    fn response<'a>(&'a self, context: &'a mut GatewayContext, req: HttpRequest<S, W>, resp: HttpResponse<S, W>)
+                    -> BoxFuture<'a, MiddlewareResult<S, W>> {
+        let future = async move {
+            MiddlewareResult::ContinueResponse((req, resp))
+        };
+        future.boxed()
+    }
+}
+
+impl HmacAuth {
+    //构建Hmac签名请求认证
+    pub fn new() -> Self {
+        HmacAuth {
+            keys: RwLock::new(XHashMap::default()),
+            max_skew: DEFAULT_HMAC_MAX_SKEW,
+            max_nonces: DEFAULT_HMAC_MAX_NONCES,
+            nonces: Mutex::new(NonceCache {
+                seen: XHashMap::default(),
+                queue: VecDeque::new(),
+            }),
+        }
+    }
+
+    //设置时间戳允许的最大偏差，单位秒
+    pub fn set_max_skew(&mut self, max_skew: u64) {
+        self.max_skew = max_skew;
+    }
+
+    //设置最大缓存随机数数量，未过期的随机数达到最大数量时会拒绝新的签名请求
+    pub fn set_max_nonces(&mut self, max_nonces: usize) {
+        self.max_nonces = max_nonces;
+    }
+
+    //增加或替换指定id的密钥
+    pub fn add_key(&self, key_id: &str, secret: &[u8]) {
+        self.keys.write().insert(key_id.to_string(), hmac::Key::new(hmac::HMAC_SHA256, secret));
+    }
+
+    //移除指定id的密钥，返回密钥是否存在
+    pub fn remove_key(&self, key_id: &str) -> bool {
+        self.keys.write().remove(key_id).is_some()
+    }
+
+    //验证签名，签名验证成功后才会检查并记录随机数，以防止重放
+    pub fn verify(&self, key_id: &str, timestamp: &str, nonce: &str, message: &str, sign: &str) -> Result<()> {
+        if nonce.is_empty() || nonce.len() > MAX_HMAC_NONCE_LEN {
+            return Err(Error::new(ErrorKind::InvalidData, format!("verify hmac failed, nonce: {:?}, reason: invalid nonce", nonce)));
+        }
+
+        let now = now_secs();
+        let timestamp = match timestamp.parse::<u64>() {
+            Err(e) => {
+                return Err(Error::new(ErrorKind::InvalidData, format!("verify hmac failed, timestamp: {:?}, reason: {:?}", timestamp, e)));
+            },
+            Ok(timestamp) => timestamp,
+        };
+        if timestamp + self.max_skew < now || timestamp > now + self.max_skew {
+            return Err(Error::new(ErrorKind::PermissionDenied, format!("verify hmac failed, timestamp: {:?}, now: {:?}, reason: timestamp out of range", timestamp, now)));
+        }
+
+        let sign = match base64::decode(sign) {
+            Err(e) => {
+                return Err(Error::new(ErrorKind::InvalidData, format!("verify hmac failed, reason: {:?}", e)));
+            },
+            Ok(sign) => sign,
+        };
+        let is_verified = match self.keys.read().get(key_id) {
+            None => false,
+            Some(key) => hmac::verify(key, message.as_bytes(), sign.as_slice()).is_ok(),
+        };
+        if !is_verified {
+            return Err(Error::new(ErrorKind::PermissionDenied, format!("verify hmac failed, key: {:?}, reason: invalid signature", key_id)));
+        }
+
+        //随机数在时间戳有效期内不允许重复使用
+        let expire = now + self.max_skew * 2;
+        if let Err(reason) = self.nonces.lock().check_and_insert(key_id.to_string() + ":" + nonce, now, expire, self.max_nonces) {
+            return Err(Error::new(ErrorKind::PermissionDenied, format!("verify hmac failed, key: {:?}, nonce: {:?}, reason: {}", key_id, nonce, reason)));
+        }
+
+        Ok(())
+    }
+}
+
+/*
+* 生成Hmac签名请求的待签名字符串
+*/
+pub fn string_to_sign(method: &str, path: &str, timestamp: &str, nonce: &str, body_sign: &str) -> String {
+    format!("{}\n{}\n{}\n{}\n{}", method.to_uppercase(), path, timestamp, nonce, body_sign)
+}
+
+/*
+* 生成请求体的Sha256十六进制摘要
+*/
+pub fn body_digest(body: &[u8]) -> String {
+    to_hex(digest::digest(&digest::SHA256, body).as_ref())
+}
+
+/*
+* 使用指定密钥对待签名字符串签名，返回签名的base64编码
+*/
+pub fn sign_hmac(secret: &[u8], message: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+    base64::encode(hmac::sign(&key, message.as_bytes()).as_ref())
+}
+
+/*
+* 使用指定的共享密钥签发HS256的Jwt令牌
+*/
+pub fn sign_hs256_token(kid: Option<&str>, claims: &Value, secret: &[u8]) -> String {
+    let header = match kid {
+        None => json!({"alg": "HS256", "typ": "JWT"}),
+        Some(kid) => json!({"alg": "HS256", "typ": "JWT", "kid": kid}),
+    };
+    let message = base64::encode_config(header.to_string().as_bytes(), base64::URL_SAFE_NO_PAD)
+        + "."
+        + base64::encode_config(claims.to_string().as_bytes(), base64::URL_SAFE_NO_PAD).as_str();
+
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+    let sign = base64::encode_config(hmac::sign(&key, message.as_bytes()).as_ref(), base64::URL_SAFE_NO_PAD);
+    message + "." + sign.as_str()
+}
+
+/*
+* 解析Http基础认证的凭据，返回用户名和密码
+*/
+pub fn parse_basic_credentials(value: &str) -> Option<(String, String)> {
+    let bin = base64::decode(parse_auth_param(value, "Basic")?).ok()?;
+    let credentials = String::from_utf8(bin).ok()?;
+    let index = credentials.find(':')?;
+    Some((credentials[..index].to_string(), credentials[index + 1..].to_string()))
+}
+
+//获取指定认证方式的认证参数，认证方式不区分大小写
+fn parse_auth_param<'a>(value: &'a str, scheme: &str) -> Option<&'a str> {
+    let value = value.trim();
+    if value.len() <= scheme.len() || !value.is_char_boundary(scheme.len()) {
+        return None;
+    }
+
+    let (name, param) = value.split_at(scheme.len());
+    if !name.eq_ignore_ascii_case(scheme) || !param.starts_with(' ') {
+        return None;
+    }
+
+    let param = param.trim();
+    if param.is_empty() {
+        None
+    } else {
+        Some(param)
+    }
+}
+
+//解析JWKS，只加载HS256和RS256的签名密钥
+fn parse_jwks(bin: &[u8]) -> GenResult<Vec<(Option<String>, JwtKey)>, String> {
+    let jwks: Value = serde_json::from_slice(bin).map_err(|e| format!("{:?}", e))?;
+    let list = match jwks.get("keys").and_then(|keys| keys.as_array()) {
+        None => return Err("keys not exist".to_string()),
+        Some(list) => list,
+    };
+
+    let mut keys = Vec::with_capacity(list.len());
+    for (index, jwk) in list.iter().enumerate() {
+        if let Some(key_use) = jwk.get("use").and_then(|key_use| key_use.as_str()) {
+            if key_use != "sig" {
+                //忽略非签名密钥
+                continue;
+            }
+        }
+
+        let kid = jwk.get("kid").and_then(|kid| kid.as_str()).map(|kid| kid.to_string());
+        let key = match jwk.get("kty").and_then(|kty| kty.as_str()) {
+            Some("oct") => {
+                let k = decode_jwk_param(jwk, "k").map_err(|e| format!("index: {}, {}", index, e))?;
+                JwtKey::Hs256(hmac::Key::new(hmac::HMAC_SHA256, k.as_slice()))
+            },
+            Some("RSA") => {
+                let n = decode_jwk_param(jwk, "n").map_err(|e| format!("index: {}, {}", index, e))?;
+                let e = decode_jwk_param(jwk, "e").map_err(|e| format!("index: {}, {}", index, e))?;
+                JwtKey::Rs256(n, e)
+            },
+            kty => {
+                //忽略不支持的密钥类型
+                warn!("!!!> Load Jwk Ignored, index: {}, kty: {:?}, reason: unsupported key type", index, kty);
+                continue;
+            },
+        };
+
+        if let Some(alg) = jwk.get("alg").and_then(|alg| alg.as_str()) {
+            if JwtAlgorithm::from_name(alg) != Some(key.algorithm()) {
+                //忽略算法与密钥类型不匹配的密钥
+                warn!("!!!> Load Jwk Ignored, index: {}, alg: {:?}, reason: unsupported algorithm", index, alg);
+                continue;
+            }
+        }
+
+        keys.push((kid, key));
+    }
+
+    if keys.is_empty() {
+        return Err("signature key not exist".to_string());
+    }
+
+    Ok(keys)
+}
+
+//解码JWK中base64url编码的参数
+fn decode_jwk_param(jwk: &Value, name: &str) -> GenResult<Vec<u8>, String> {
+    match jwk.get(name).and_then(|param| param.as_str()) {
+        None => Err(format!("param: {}, reason: param not exist", name)),
+        Some(param) => {
+            base64::decode_config(param.trim_end_matches('='), base64::URL_SAFE_NO_PAD)
+                .map_err(|e| format!("param: {}, reason: {:?}", name, e))
+        },
+    }
+}
+
+//解码Jwt中base64url编码的Json对象
+fn decode_json_part(part: &str) -> Result<Value> {
+    let bin = match base64::decode_config(part, base64::URL_SAFE_NO_PAD) {
+        Err(e) => {
+            return Err(Error::new(ErrorKind::InvalidData, format!("verify jwt failed, reason: {:?}", e)));
+        },
+        Ok(bin) => bin,
+    };
+
+    match serde_json::from_slice::<Value>(bin.as_slice()) {
+        Ok(value@Value::Object(_)) => Ok(value),
+        Ok(_) => Err(Error::new(ErrorKind::InvalidData, "verify jwt failed, reason: not a json object")),
+        Err(e) => Err(Error::new(ErrorKind::InvalidData, format!("verify jwt failed, reason: {:?}", e))),
+    }
+}
+
+//获取Jwt中签名部分的长度，包括分隔符
+fn sign_part_len(token: &str) -> usize {
+    match token.rfind('.') {
+        None => 0,
+        Some(index) => token.len() - index,
+    }
+}
+
+//获取指定请求头的字符串值
+fn header_str<S: Socket, W: AsyncIOWait>(req: &HttpRequest<S, W>, key: &str) -> Option<String> {
+    req.headers().get(key).and_then(|value| value.to_str().ok()).map(|value| value.trim().to_string())
+}
+
+//转换为小写十六进制字符串
+fn to_hex(bin: &[u8]) -> String {
+    bin.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+//获取当前系统时间，单位秒
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
+
+//返回未认证的响应，并退出请求处理
+fn unauthorized<S: Socket, W: AsyncIOWait>(req: HttpRequest<S, W>, challenge: &str) -> MiddlewareResult<S, W> {
+    let mut resp = HttpResponse::new(req.get_handle().clone(), req.get_waits().clone(), 1);
+    resp.status(StatusCode::UNAUTHORIZED.as_u16());
+    resp.header(WWW_AUTHENTICATE.as_str(), challenge);
+    resp.header(CONTENT_LENGTH.as_str(), "0");
+    MiddlewareResult::Break(resp)
+}
@@ -1,5 +1,6 @@
 use std::ffi::OsStr;
 use std::fs::DirEntry;
+use std::mem;
 use std::io::{Error, ErrorKind, Result};
 use std::path::{Component, Path, PathBuf};
 use std::result::Result as GenResult;
@@ -11,7 +12,7 @@ use std::sync::Arc;
 use bytes::BufMut;
 use futures::future::{BoxFuture, FutureExt, MapErr};
 use https::{
-    header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE},
+    header::{ACCEPT_ENCODING, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE, ETAG, VARY},
     StatusCode,
 };
 use log::warn;
@@ -25,15 +26,16 @@ use r#async::rt::multi_thread::MultiTaskRuntime;
 use tcp::driver::{AsyncIOWait, Socket};
 
 use crate::{
+    default_parser::{negotiate_encoding, BROTLI_ENCODING, GZIP_ENCODING},
     gateway::GatewayContext,
     middleware::{Middleware, MiddlewareResult},
     request::HttpRequest,
     response::HttpResponse,
     static_cache::{
-        is_modified, is_unmodified, request_get_cache, set_cache_resp_headers, CacheRes,
-        StaticCache,
+        check_cache_preconditions, format_etag, request_get_cache, set_cache_resp_headers,
+        CacheRes, Precondition, StaticCache,
     },
-    util::{trim_path, HttpRecvResult},
+    util::{async_files_call, is_precompressed_file, precompressed_files, trim_path, HttpRecvResult},
 };
 use std::time::SystemTime;
 
@@ -60,6 +62,8 @@ pub struct BatchLoad {
     //设置是否允许客户端更改前端资源内容
     is_only_if_cached: bool,
     //设置是否要求代理有缓存，则只由代理向客户端提供资源
+    is_precompressed: bool,
+    //设置是否将有预压缩文件的文件替换为客户端接受的预压缩文件
     max_age: u64, //缓存有效时长
 }
 
@@ -88,7 +92,18 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for BatchLoad {
             if let Some(SGenType::Str(f)) = context.as_params().borrow().get("f") {
                 fs = f.clone();
             }
-            let files_id = Atom::from(ds.to_string() + "&" + fs.as_str());
+            let mut files_id = Atom::from(ds.to_string() + "&" + fs.as_str());
+
+            //批量文件的每个文件独立选择预压缩文件，预压缩文件的文件名带有编码的扩展名，客户端根据扩展名解压
+            let encoding = if self.is_precompressed {
+                req.headers()
+                    .get(ACCEPT_ENCODING)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|accept| negotiate_encoding(accept, &[BROTLI_ENCODING, GZIP_ENCODING]))
+            } else {
+                None
+            };
+            let compressed_id = encoding.map(|enc| Atom::from(files_id.to_string() + "&" + enc));
             let root = if rp.as_path().to_str().unwrap().as_bytes().len() > 0 {
                 &rp
             } else {
@@ -97,44 +112,34 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for BatchLoad {
 
             //访问指定的批量文件的内存缓存
             if let Some(cache) = &self.cache {
-                //设置了文件缓存
-                match is_unmodified(cache.as_ref(), &req, None, files_id.clone()) {
-                    Err(e) => {
-                        return MiddlewareResult::Throw(e);
-                    }
-                    Ok(None) => (), //忽略这个判断
-                    Ok(Some(false)) => {
-                        //验证指定文件的缓存已修改，则立即返回指定错误
+                //设置了文件缓存，客户端接受且已缓存包含预压缩文件的批量文件，则优先使用
+                let cache_id = match &compressed_id {
+                    Some(id) if cache.contains(None, id.clone()) => id.clone(),
+                    _ => files_id.clone(),
+                };
+                match check_cache_preconditions(cache.as_ref(), &req, None, cache_id.clone(), false) {
+                    (Precondition::Pass, _) => (), //验证通过，则继续
+                    (Precondition::Failed, _) => {
+                        //前置条件失败，则立即返回指定错误
                         let mut resp =
                             HttpResponse::new(req.get_handle().clone(), req.get_waits().clone(), 1);
                         resp.status(StatusCode::PRECONDITION_FAILED.as_u16());
                         resp.header(CONTENT_LENGTH.as_str(), "0");
                         return MiddlewareResult::Break(resp);
                     }
-                    Ok(Some(true)) => {
-                        //验证指定文件的缓存未修改，则立即返回
-                        let mut resp =
-                            HttpResponse::new(req.get_handle().clone(), req.get_waits().clone(), 1);
-                        resp.status(StatusCode::NOT_MODIFIED.as_u16());
-                        return MiddlewareResult::Break(resp);
-                    }
-                }
-
-                match is_modified(cache.as_ref(), &req, None, files_id.clone()) {
-                    Err(e) => {
-                        return MiddlewareResult::Throw(e);
-                    }
-                    Ok(true) => (), //验证指定文件的缓存已修改，则继续
-                    Ok(false) => {
+                    (Precondition::NotModified, etag) => {
                         //验证指定文件的缓存未修改，则立即返回
                         let mut resp =
                             HttpResponse::new(req.get_handle().clone(), req.get_waits().clone(), 1);
                         resp.status(StatusCode::NOT_MODIFIED.as_u16());
+                        if let Some(etag) = etag {
+                            resp.header(ETAG.as_str(), etag.as_str());
+                        }
                         return MiddlewareResult::Break(resp);
                     }
                 }
 
-                match request_get_cache(cache.as_ref(), &req, None, files_id.clone()) {
+                match request_get_cache(cache.as_ref(), &req, None, cache_id) {
                     Err(e) => {
                         //获取指定文件的缓存错误，则立即抛出错误
                         return MiddlewareResult::Throw(e);
@@ -157,13 +162,17 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for BatchLoad {
                                     self.is_only_if_cached,
                                     max_age,
                                     None,
-                                    sign,
+                                    format_etag(sign, false).as_str(),
                                 );
                                 resp.header(
                                     CONTENT_DISPOSITION.as_str(),
                                     DEFAULT_CONTENT_DISPOSITION,
                                 );
                                 resp.header(CONTENT_TYPE.as_str(), mime.as_ref());
+                                if encoding.is_some() {
+                                    //批量文件的内容与客户端接受的编码相关
+                                    resp.header(VARY.as_str(), ACCEPT_ENCODING.as_str());
+                                }
                                 if let Some(body) = resp.as_mut_body() {
                                     //将缓存数据写入响应体
                                     body.init();
@@ -229,13 +238,37 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for BatchLoad {
                 }
             }
 
-            //合并解析的所有文件，并根据文件数量构建Http响应
+            //合并解析的所有文件
             dir_vec.append(&mut file_vec);
-            let resp = HttpResponse::new(
+
+            //将有预压缩文件的文件替换为预压缩文件，查找预压缩文件会在文件异步运行时中执行
+            if let (Some(encoding), Some(compressed_id)) = (encoding, compressed_id) {
+                let files = mem::replace(&mut dir_vec, Vec::new());
+                match async_files_call(&self.files_async_runtime, move || precompressed_files(files, encoding, false)).await {
+                    Err(e) => {
+                        //查找预压缩文件错误，则立即中止请求处理，并返回响应
+                        return MiddlewareResult::Throw(e);
+                    }
+                    Ok((files, is_replaced)) => {
+                        dir_vec = files;
+                        if is_replaced {
+                            //已替换了预压缩文件，则使用包含预压缩文件的批量文件的缓存id
+                            files_id = compressed_id;
+                        }
+                    }
+                }
+            }
+
+            //根据文件数量构建Http响应
+            let mut resp = HttpResponse::new(
                 req.get_handle().clone(),
                 req.get_waits().clone(),
                 dir_vec.len(),
             );
+            if encoding.is_some() {
+                //批量文件的内容与客户端接受的编码相关
+                resp.header(VARY.as_str(), ACCEPT_ENCODING.as_str());
+            }
 
             //异步加载所有文件
             match async_load_files(
@@ -378,7 +411,7 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for BatchLoad {
                                             self.is_only_if_cached,
                                             self.max_age,
                                             None,
-                                            sign,
+                                            format_etag(sign, false).as_str(),
                                         );
                                     }
                                 }
@@ -419,10 +452,16 @@ impl BatchLoad {
                 is_store,
                 is_transform,
                 is_only_if_cached,
+                is_precompressed: false,
                 max_age: max_age as u64,
             },
         }
     }
+
+    //设置是否将有预压缩文件的文件替换为客户端接受的预压缩文件，默认不替换，替换后文件名带有编码的扩展名，需要客户端支持解压
+    pub fn set_precompressed(&mut self, is_precompressed: bool) {
+        self.is_precompressed = is_precompressed;
+    }
 }
 
 //解析后缀，失败返回在解析哪个字符时出错
@@ -758,7 +797,11 @@ fn filter_file(suffix: Option<&OsStr>, entry: DirEntry, result: &mut Vec<(u64, P
             _ => (), //后缀名不同，则忽略
         }
     } else {
-        //不过滤
+        //不过滤，但忽略已存在原文件的预压缩文件，预压缩文件只会在客户端接受时替换原文件
+        if is_precompressed_file(&entry.path()) {
+            return;
+        }
+
         if let Ok(meta) = entry.metadata() {
             result.push((meta.len(), entry.path()));
         } else {
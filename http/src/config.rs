@@ -0,0 +1,1654 @@
+use std::fs;
+use std::any::Any;
+use std::sync::Arc;
+use std::path::Path;
+use std::str::FromStr;
+use std::cell::RefCell;
+use std::time::Duration;
+use std::net::{IpAddr, SocketAddr};
+use std::result::Result as GenResult;
+use std::io::{Error, Result, ErrorKind};
+
+use https::{HeaderMap, Method, StatusCode};
+use serde_json::{Map, Value};
+use parking_lot::Mutex;
+use log::warn;
+
+use hash::XHashMap;
+use handler::{Handler, SGenType};
+use r#async::rt::multi_thread::MultiTaskRuntime;
+use tcp::{driver::{Socket, Stream, SocketConfig, SocketDriver},
+          server::{AsyncWaitsHandle, AsyncPortsFactory, PortsAdapter, SocketListener},
+          buffer_pool::WriteBufferPool,
+          connect::TcpSocket,
+          tls_connect::TlsSocket,
+          util::TlsConfig};
+
+use crate::{server::{HttpListenerFactory, HttpLimits},
+            virtual_host::{VirtualHostPool, VirtualHostTab, VirtualHost},
+            gateway::GatewayContext,
+            route::HttpRoute,
+            middleware::{Middleware, MiddlewareChain},
+            cors_handler::CORSHandler,
+            default_parser::DefaultParser,
+            multi_parts::MutilParts,
+            range_load::RangeLoad,
+            file_load::FileLoad,
+            files_load::FilesLoad,
+            batch_load::BatchLoad,
+            upload::UploadFile,
+            port::HttpPort,
+            proxy::{ReverseProxy, BalanceStrategy},
+            auth::{MemoryCredentialStore, BasicAuth, JwtAuth, HmacAuth},
+            cookie::{SameSite, CookieParser},
+            session::{DEFAULT_MAX_MEMORY_SESSIONS, SessionStore, MemorySessionStore, CookieSessionStore, SessionManager},
+            rate_limit::{RateLimitAlgorithm, RateLimitKey, RateLimit},
+            gray_route::{DEFAULT_GRAY_STICKY_MAX_AGE, MAX_GRAY_PERCENT, GrayUserKey, GrayMatcher, GrayRule, GrayRules, GrayRouter},
+            static_cache::StaticCache,
+            response::ResponseHandler,
+            util::IpRange};
+
+/*
+* 默认的Http连接保持时长，单位毫秒
+*/
+const DEFAULT_KEEP_ALIVE: usize = 10000;
+
+/*
+* 默认的静态资源缓存整理间隔，单位毫秒
+*/
+const DEFAULT_CACHE_COLLECT_INTERVAL: u64 = 10000;
+
+/*
+* 默认的Tls会话缓存的最大容量
+*/
+const DEFAULT_TLS_SESSION_SIZE: usize = 512;
+
+/*
+* 默认的跨域请求允许的方法
+*/
+const DEFAULT_CORS_METHODS: &[&str] = &["OPTIONS", "GET", "POST"];
+
+/*
+* 默认的多部分请求体分析的块大小
+*/
+const DEFAULT_MULTI_PARTS_BLOCK_SIZE: usize = 8 * 1024 * 1024;
+
+/*
+* 默认的文件加载的缓存有效时长，单位秒
+*/
+const DEFAULT_FILE_MAX_AGE: usize = 10;
+
+/*
+* 默认的认证域
+*/
+const DEFAULT_AUTH_REALM: &str = "Restricted";
+
+/*
+* 配置文件中所有中间件链共用的Http中间件
+*/
+pub type HttpMiddleware<S> = Arc<dyn Middleware<S, AsyncWaitsHandle, GatewayContext>>;
+
+/*
+* 配置文件中路由使用的Http中间件链
+*/
+pub type HttpChain<S> = Arc<MiddlewareChain<S, AsyncWaitsHandle, GatewayContext>>;
+
+/*
+* Http端口中间件使用的异步请求处理器
+*/
+pub type PortHandler<S> = Arc<dyn Handler<
+    A = SocketAddr,
+    B = Arc<HeaderMap>,
+    C = Arc<RefCell<XHashMap<String, SGenType>>>,
+    D = ResponseHandler<S>,
+    E = (),
+    F = (),
+    G = (),
+    H = (),
+    HandleResult = ()
+>>;
+
+/*
+* 已构建的共享中间件表，键为中间件名，Http和Https监听器使用同一个共享中间件
+*/
+type SharedMiddlewares = Mutex<XHashMap<String, Arc<dyn Any + Send + Sync>>>;
+
+/*
+* 中间件工厂，根据中间件配置和构建上下文构建中间件
+*/
+pub type MiddlewareFactory<S> = Arc<dyn Fn(&MiddlewareConfig, &BuildContext<'_, S>) -> Result<HttpMiddleware<S>> + Send + Sync + 'static>;
+
+/*
+* 服务器配置，可以从Toml或Json文件加载，两种格式的结构相同，中间件选项中时长的单位与中间件的设置方法相同，其它时长单位为毫秒
+* server:      连接选项
+* listeners:   监听器列表，设置了tls的监听器为Https监听器
+* caches:      静态资源缓存表，键为缓存名
+* middlewares: 中间件表，键为中间件名，type为中间件类型，其它字段为中间件选项
+* chains:      中间件链表，键为中间件链名，值为按顺序执行的中间件名列表，同名中间件在所有中间件链中共享
+* hosts:       虚拟主机列表，每个虚拟主机包括主机名列表、路由列表和错误页列表
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerConfig {
+    pub server:         ServerOption,           //连接选项
+    pub listeners:      Vec<ListenerConfig>,    //监听器列表
+    pub caches:         Vec<CacheConfig>,       //静态资源缓存列表
+    pub middlewares:    Vec<MiddlewareConfig>,  //中间件列表
+    pub chains:         Vec<ChainConfig>,       //中间件链列表
+    pub hosts:          Vec<HostConfig>,        //虚拟主机列表
+}
+
+impl ServerConfig {
+    //从指定文件加载服务器配置，根据扩展名选择Toml或Json格式
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = match fs::read_to_string(path) {
+            Err(e) => {
+                return Err(Error::new(e.kind(), format!("load server config failed, file: {:?}, reason: {:?}", path, e)));
+            },
+            Ok(text) => text,
+        };
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => ServerConfig::from_toml_str(text.as_str()),
+            Some("json") => ServerConfig::from_json_str(text.as_str()),
+            _ => {
+                Err(Error::new(ErrorKind::InvalidInput, format!("load server config failed, file: {:?}, reason: unsupported extension, expected .toml or .json", path)))
+            },
+        }
+    }
+
+    //从Toml文本加载服务器配置
+    pub fn from_toml_str(text: &str) -> Result<Self> {
+        match toml::from_str::<Value>(text) {
+            Err(e) => {
+                Err(Error::new(ErrorKind::InvalidData, format!("parse server config failed, format: toml, reason: {}", e)))
+            },
+            Ok(value) => ServerConfig::from_value(&value),
+        }
+    }
+
+    //从Json文本加载服务器配置
+    pub fn from_json_str(text: &str) -> Result<Self> {
+        match serde_json::from_str::<Value>(text) {
+            Err(e) => {
+                Err(Error::new(ErrorKind::InvalidData, format!("parse server config failed, format: json, reason: {}", e)))
+            },
+            Ok(value) => ServerConfig::from_value(&value),
+        }
+    }
+
+    //从Json值加载服务器配置，会检查未知字段、字段类型和所有名称的引用
+    pub fn from_value(value: &Value) -> Result<Self> {
+        let root = match value.as_object() {
+            None => return Err(config_error("$", "expected table")),
+            Some(map) => Fields { path: String::new(), map },
+        };
+        root.check_keys(&["server", "listeners", "caches", "middlewares", "chains", "hosts"])?;
+
+        let server = match root.get_table("server")? {
+            None => ServerOption::default(),
+            Some(fields) => parse_server(&fields)?,
+        };
+
+        let mut listeners = Vec::new();
+        for fields in root.require_table_list("listeners")? {
+            let listener = parse_listener(&fields)?;
+            if listeners.iter().any(|other: &ListenerConfig| other.port == listener.port) {
+                return Err(fields.error("port", &format!("duplicate port {}", listener.port)));
+            }
+            listeners.push(listener);
+        }
+
+        let mut caches = Vec::new();
+        if let Some(table) = root.get_table("caches")? {
+            for (name, fields) in table.tables()? {
+                caches.push(parse_cache(name, &fields)?);
+            }
+        }
+
+        let mut middlewares = Vec::new();
+        if let Some(table) = root.get_table("middlewares")? {
+            for (name, fields) in table.tables()? {
+                let kind = fields.require_str("type")?.to_string();
+                let mut options = fields.map.clone();
+                options.remove("type");
+                middlewares.push(MiddlewareConfig {
+                    name: name.to_string(),
+                    kind,
+                    options,
+                });
+            }
+        }
+
+        let mut chains = Vec::new();
+        if let Some(table) = root.get_table("chains")? {
+            for name in table.map.keys() {
+                let names = table.require_str_list(name)?;
+                if names.is_empty() {
+                    return Err(table.error(name, "expected non-empty middleware list"));
+                }
+
+                for (index, middleware) in names.iter().enumerate() {
+                    if !middlewares.iter().any(|config: &MiddlewareConfig| &config.name == middleware) {
+                        return Err(config_error(&format!("{}[{}]", table.path(name), index),
+                                                &format!("undefined middleware {:?}", middleware)));
+                    }
+                }
+
+                chains.push(ChainConfig {
+                    name: name.to_string(),
+                    middlewares: names,
+                });
+            }
+        }
+
+        let mut hosts: Vec<HostConfig> = Vec::new();
+        for fields in root.require_table_list("hosts")? {
+            let host = parse_host(&fields, &chains)?;
+            for name in &host.names {
+                if hosts.iter().any(|other| other.names.contains(name)) {
+                    return Err(fields.error("names", &format!("duplicate host name {:?}", name)));
+                }
+            }
+            if host.is_default && hosts.iter().any(|other| other.is_default) {
+                return Err(fields.error("default", "duplicate default host"));
+            }
+            hosts.push(host);
+        }
+
+        Ok(ServerConfig {
+            server,
+            listeners,
+            caches,
+            middlewares,
+            chains,
+            hosts,
+        })
+    }
+
+    //获取指定名称的中间件配置
+    pub fn get_middleware(&self, name: &str) -> Option<&MiddlewareConfig> {
+        self.middlewares.iter().find(|config| config.name == name)
+    }
+
+    //获取指定名称的中间件链配置
+    pub fn get_chain(&self, name: &str) -> Option<&ChainConfig> {
+        self.chains.iter().find(|config| config.name == name)
+    }
+}
+
+/*
+* 连接选项
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerOption {
+    pub ip:                     String,         //监听地址，为::时同时在ipv4和ipv6上监听
+    pub recv_buffer_size:       usize,          //连接接收缓冲区大小
+    pub send_buffer_size:       usize,          //连接发送缓冲区大小
+    pub read_buffer_capacity:   usize,          //连接读缓冲容量
+    pub write_buffer_capacity:  usize,          //连接写缓冲容量
+    pub buffer_pool_capacity:   usize,          //写缓冲池容量
+    pub buffer_pool_init:       usize,          //写缓冲池初始写缓冲数量
+    pub buffer_iolist_capacity: usize,          //写缓冲的初始IO列表容量
+    pub init_capacity:          usize,          //连接池初始容量
+    pub stack_size:             usize,          //线程堆栈大小
+    pub event_size:             usize,          //同时处理的事件数
+    pub timeout:                Option<usize>,  //事件轮询超时时长，配置为0表示不超时
+}
+
+impl Default for ServerOption {
+    fn default() -> Self {
+        ServerOption {
+            ip: "0.0.0.0".to_string(),
+            recv_buffer_size: 16384,
+            send_buffer_size: 16384,
+            read_buffer_capacity: 16384,
+            write_buffer_capacity: 16,
+            buffer_pool_capacity: 10000,
+            buffer_pool_init: 10,
+            buffer_iolist_capacity: 3,
+            init_capacity: 1024,
+            stack_size: 1024 * 1024,
+            event_size: 1024,
+            timeout: Some(10),
+        }
+    }
+}
+
+/*
+* 监听器配置
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListenerConfig {
+    pub port:       u16,                //监听端口
+    pub keep_alive: usize,              //Http连接保持时长，单位毫秒
+    pub limits:     HttpLimits,         //Http请求限制
+    pub tls:        Option<TlsOption>,  //Tls选项，为空表示Http监听器
+}
+
+/*
+* Tls选项，列表使用逗号分隔
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsOption {
+    pub cert:                   String, //服务器证书路径
+    pub key:                    String, //服务器私钥路径
+    pub client_auth:            String, //授权客户端证书路径
+    pub is_client_auth:         bool,   //是否强制进行客户端身份认证
+    pub ocsp:                   String, //服务器OCSP响应文件路径
+    pub suites:                 String, //密码套件名称列表
+    pub versions:               String, //Tls版本名称列表
+    pub session_size:           usize,  //Tls会话缓存的最大容量
+    pub is_tickets:             bool,   //是否分配客户端会话票据
+    pub alpns:                  String, //支持的ALPN协议名称列表
+}
+
+impl TlsOption {
+    //构建服务器的Tls配置
+    pub fn build(&self) -> GenResult<TlsConfig, String> {
+        TlsConfig::new_server(self.client_auth.as_str(),
+                              self.is_client_auth,
+                              self.cert.as_str(),
+                              self.key.as_str(),
+                              self.ocsp.as_str(),
+                              self.suites.as_str(),
+                              self.versions.as_str(),
+                              self.session_size,
+                              self.is_tickets,
+                              self.alpns.as_str())
+    }
+}
+
+/*
+* 静态资源缓存配置
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheConfig {
+    pub name:               String, //缓存名
+    pub max_size:           usize,  //缓存最大大小
+    pub max_len:            usize,  //缓存最大数量
+    pub collect_interval:   u64,    //缓存整理间隔，单位毫秒，为0表示不整理
+    pub is_watch:           bool,   //是否监听缓存文件的修改
+}
+
+/*
+* 中间件配置，选项不包括中间件类型
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct MiddlewareConfig {
+    pub name:       String,             //中间件名
+    pub kind:       String,             //中间件类型
+    pub options:    Map<String, Value>, //中间件选项
+}
+
+impl MiddlewareConfig {
+    //构建指定选项的错误，错误中包括选项在配置文件中的路径
+    pub fn error(&self, key: &str, reason: &str) -> Error {
+        self.fields().error(key, reason)
+    }
+
+    //检查是否有未知的选项
+    pub fn check_keys(&self, keys: &[&str]) -> Result<()> {
+        self.fields().check_keys(keys)
+    }
+
+    //获取指定选项的值
+    pub fn get_value(&self, key: &str) -> Option<&Value> {
+        self.options.get(key)
+    }
+
+    //获取指定的字符串选项
+    pub fn get_str(&self, key: &str) -> Result<Option<&str>> {
+        self.fields().get_str(key)
+    }
+
+    //获取指定的非空字符串选项，不存在则返回错误
+    pub fn require_str(&self, key: &str) -> Result<&str> {
+        self.fields().require_str(key)
+    }
+
+    //获取指定的非负整数选项
+    pub fn get_u64(&self, key: &str) -> Result<Option<u64>> {
+        self.fields().get_u64(key)
+    }
+
+    //获取指定的布尔选项
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>> {
+        self.fields().get_bool(key)
+    }
+
+    //获取指定的字符串列表选项
+    pub fn get_str_list(&self, key: &str) -> Result<Option<Vec<String>>> {
+        self.fields().get_str_list(key)
+    }
+
+    //获取选项字段表
+    fn fields(&self) -> Fields {
+        Fields {
+            path: format!("middlewares.{}", self.name),
+            map: &self.options,
+        }
+    }
+}
+
+/*
+* 中间件链配置
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainConfig {
+    pub name:           String,         //中间件链名
+    pub middlewares:    Vec<String>,    //按顺序执行的中间件名列表
+}
+
+/*
+* 虚拟主机配置
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostConfig {
+    pub names:          Vec<String>,            //主机名列表，以*.开始的为通配主机名
+    pub is_default:     bool,                   //是否是默认虚拟主机
+    pub routes:         Vec<RouteConfig>,       //路由列表
+    pub error_pages:    Vec<ErrorPageConfig>,   //错误页列表
+}
+
+/*
+* 路由配置
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteConfig {
+    pub path:       String,         //路由路径
+    pub methods:    Vec<Method>,    //路由方法列表
+    pub chain:      String,         //处理请求的中间件链名
+}
+
+/*
+* 错误页配置
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorPageConfig {
+    pub status:     StatusCode, //状态码
+    pub mime:       String,     //错误页的类型
+    pub template:   String,     //错误页模板
+}
+
+/*
+* 中间件构建上下文
+*/
+pub struct BuildContext<'a, S: Socket> {
+    caches:                 &'a XHashMap<String, Arc<StaticCache>>, //静态资源缓存表
+    files_async_runtime:    Option<&'a MultiTaskRuntime<()>>,       //文件异步运行时
+    proxy_async_runtime:    Option<&'a MultiTaskRuntime<()>>,       //反向代理异步运行时
+    handlers:               &'a XHashMap<String, PortHandler<S>>,   //异步请求处理器表
+    middlewares:            &'a SharedMiddlewares,                  //已构建的共享中间件表
+}
+
+impl<'a, S: Socket> BuildContext<'a, S> {
+    //获取指定名称的静态资源缓存
+    pub fn get_cache(&self, name: &str) -> Option<Arc<StaticCache>> {
+        self.caches.get(name).cloned()
+    }
+
+    //获取文件异步运行时
+    pub fn get_files_runtime(&self) -> Option<MultiTaskRuntime<()>> {
+        self.files_async_runtime.cloned()
+    }
+
+    //获取反向代理异步运行时
+    pub fn get_proxy_runtime(&self) -> Option<MultiTaskRuntime<()>> {
+        self.proxy_async_runtime.cloned()
+    }
+
+    //获取指定名称的异步请求处理器
+    pub fn get_handler(&self, name: &str) -> Option<PortHandler<S>> {
+        self.handlers.get(name).cloned()
+    }
+
+    //获取指定中间件配置的共享中间件，未构建或类型不同则构建，同名中间件只会共享第一次构建的中间件
+    pub fn shared<T, F>(&self, config: &MiddlewareConfig, build: F) -> Result<Arc<T>>
+        where T: Any + Send + Sync,
+              F: FnOnce() -> Result<T> {
+        if let Some(middleware) = self.middlewares.lock().get(&config.name).cloned() {
+            if let Ok(middleware) = middleware.downcast::<T>() {
+                return Ok(middleware);
+            }
+        }
+
+        let middleware = Arc::new(build()?);
+        let shared: Arc<dyn Any + Send + Sync> = middleware.clone();
+        self.middlewares.lock().entry(config.name.clone()).or_insert(shared);
+        Ok(middleware)
+    }
+}
+
+/*
+* 中间件注册表，按中间件类型注册中间件工厂，构建时会注册所有内置的中间件类型
+*/
+pub struct MiddlewareRegistry<S: Socket> {
+    factories:  XHashMap<String, MiddlewareFactory<S>>, //中间件工厂表
+    handlers:   XHashMap<String, PortHandler<S>>,       //Http端口中间件使用的异步请求处理器表
+}
+
+impl<S: Socket> MiddlewareRegistry<S> {
+    //构建中间件注册表
+    pub fn new() -> Self {
+        let mut registry = MiddlewareRegistry {
+            factories: XHashMap::default(),
+            handlers: XHashMap::default(),
+        };
+
+        registry.register_shared("cors", build_cors::<S>);
+        registry.register_shared("parser", build_parser::<S>);
+        registry.register_shared("multi_parts", build_multi_parts::<S>);
+        registry.register_shared("range_load", build_range_load::<S>);
+        registry.register_shared("file_load", build_file_load::<S>);
+        registry.register_shared("files_load", build_files_load::<S>);
+        registry.register_shared("batch_load", build_batch_load::<S>);
+        registry.register_shared("upload", build_upload::<S>);
+        registry.register("port", build_port::<S>);
+        registry.register_shared("proxy", build_proxy::<S>);
+        registry.register_shared("basic_auth", build_basic_auth::<S>);
+        registry.register_shared("jwt_auth", build_jwt_auth::<S>);
+        registry.register_shared("hmac_auth", build_hmac_auth::<S>);
+        registry.register_shared("cookie", build_cookie::<S>);
+        registry.register_shared("session", build_session::<S>);
+        registry.register_shared("rate_limit", build_rate_limit::<S>);
+        registry.register_shared("gray", build_gray::<S>);
+
+        registry
+    }
+
+    //注册指定类型的中间件工厂，返回上次注册的中间件工厂，可以替换内置的中间件类型
+    pub fn register<F>(&mut self, kind: &str, factory: F) -> Option<MiddlewareFactory<S>>
+        where F: Fn(&MiddlewareConfig, &BuildContext<'_, S>) -> Result<HttpMiddleware<S>> + Send + Sync + 'static {
+        self.factories.insert(kind.to_string(), Arc::new(factory))
+    }
+
+    //注册指定类型的共享中间件工厂，返回上次注册的中间件工厂，同名中间件只构建一次，并在Http和Https监听器间共享
+    pub fn register_shared<T, F>(&mut self, kind: &str, factory: F) -> Option<MiddlewareFactory<S>>
+        where T: Middleware<S, AsyncWaitsHandle, GatewayContext> + Send + Sync + 'static,
+              F: Fn(&MiddlewareConfig, &BuildContext<'_, S>) -> Result<T> + Send + Sync + 'static {
+        self.register(kind, move |config, context| {
+            let middleware: HttpMiddleware<S> = context.shared(config, || factory(config, context))?;
+            Ok(middleware)
+        })
+    }
+
+    //判断是否注册了指定类型的中间件工厂
+    pub fn contains(&self, kind: &str) -> bool {
+        self.factories.contains_key(kind)
+    }
+
+    //设置Http端口中间件使用的指定名称的异步请求处理器
+    pub fn set_handler(&mut self, name: &str, handler: PortHandler<S>) -> Option<PortHandler<S>> {
+        self.handlers.insert(name.to_string(), handler)
+    }
+
+    //根据中间件配置构建中间件
+    pub fn build(&self, config: &MiddlewareConfig, context: &BuildContext<S>) -> Result<HttpMiddleware<S>> {
+        match self.factories.get(&config.kind) {
+            None => Err(config.error("type", &format!("unknown middleware type {:?}", config.kind))),
+            Some(factory) => factory(config, context),
+        }
+    }
+}
+
+/*
+* 服务器引导，根据服务器配置构建虚拟主机表，并绑定Http和Https监听器，共享中间件在Http和Https监听器间只构建一次
+*/
+pub struct ServerBootstrap {
+    config:                 ServerConfig,                           //服务器配置
+    caches:                 XHashMap<String, Arc<StaticCache>>,     //静态资源缓存表
+    files_async_runtime:    Option<MultiTaskRuntime<()>>,           //文件异步运行时
+    proxy_async_runtime:    Option<MultiTaskRuntime<()>>,           //反向代理异步运行时
+    middlewares:            SharedMiddlewares,                      //已构建的共享中间件表
+}
+
+impl ServerBootstrap {
+    //构建服务器引导，会构建配置中的所有静态资源缓存，并启动缓存的整理
+    pub fn new(config: ServerConfig) -> Self {
+        let mut caches = XHashMap::default();
+        for cache_config in &config.caches {
+            let cache = Arc::new(StaticCache::new(cache_config.max_size, cache_config.max_len));
+            cache.set_watch(cache_config.is_watch);
+            if cache_config.collect_interval > 0 {
+                StaticCache::run_collect(cache.clone(), cache_config.name.clone(), cache_config.collect_interval);
+            }
+            caches.insert(cache_config.name.clone(), cache);
+        }
+
+        ServerBootstrap {
+            config,
+            caches,
+            files_async_runtime: None,
+            proxy_async_runtime: None,
+            middlewares: Mutex::new(XHashMap::default()),
+        }
+    }
+
+    //从指定文件加载服务器配置，并构建服务器引导
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(ServerBootstrap::new(ServerConfig::load(path)?))
+    }
+
+    //设置文件加载、文件上传等中间件使用的文件异步运行时
+    pub fn set_files_runtime(&mut self, files_async_runtime: MultiTaskRuntime<()>) {
+        self.files_async_runtime = Some(files_async_runtime);
+    }
+
+    //设置反向代理使用的异步运行时，反向代理会在其中阻塞的与上游服务器交换，所以需要使用独立的有限线程数的异步运行时
+    pub fn set_proxy_runtime(&mut self, proxy_async_runtime: MultiTaskRuntime<()>) {
+        self.proxy_async_runtime = Some(proxy_async_runtime);
+    }
+
+    //获取服务器配置
+    pub fn get_config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    //获取指定名称的静态资源缓存
+    pub fn get_cache(&self, name: &str) -> Option<&Arc<StaticCache>> {
+        self.caches.get(name)
+    }
+
+    //获取指定名称的已构建的共享中间件，未构建或类型不同则返回空，用于在运行时调整中间件，例如替换灰度路由中间件的规则表
+    pub fn get_middleware<T: Any + Send + Sync>(&self, name: &str) -> Option<Arc<T>> {
+        self.middlewares.lock().get(name).cloned().and_then(|middleware| middleware.downcast::<T>().ok())
+    }
+
+    //构建虚拟主机表，只构建被路由使用的中间件链和中间件，同名中间件只构建一次，共享中间件在多次构建间也只构建一次
+    pub fn build_hosts<S: Socket>(&self, registry: &MiddlewareRegistry<S>)
+                                  -> Result<VirtualHostTab<S, AsyncWaitsHandle, HttpChain<S>>> {
+        let context = BuildContext {
+            caches: &self.caches,
+            files_async_runtime: self.files_async_runtime.as_ref(),
+            proxy_async_runtime: self.proxy_async_runtime.as_ref(),
+            handlers: &registry.handlers,
+            middlewares: &self.middlewares,
+        };
+
+        let mut middlewares: XHashMap<String, HttpMiddleware<S>> = XHashMap::default();
+        let mut chains: XHashMap<String, HttpChain<S>> = XHashMap::default();
+        for chain_config in &self.config.chains {
+            if !self.config.hosts.iter().any(|host| host.routes.iter().any(|route| route.chain == chain_config.name)) {
+                warn!("!!!> Unused Http Middleware Chain, name: {:?}", chain_config.name);
+                continue;
+            }
+
+            let mut chain = MiddlewareChain::new();
+            for name in &chain_config.middlewares {
+                let middleware = match middlewares.get(name) {
+                    Some(middleware) => middleware.clone(),
+                    None => {
+                        let config = match self.config.get_middleware(name) {
+                            None => {
+                                return Err(config_error(&format!("chains.{}", chain_config.name),
+                                                        &format!("undefined middleware {:?}", name)));
+                            },
+                            Some(config) => config,
+                        };
+
+                        let middleware = registry.build(config, &context)?;
+                        middlewares.insert(name.clone(), middleware.clone());
+                        middleware
+                    },
+                };
+                chain.push_back(middleware);
+            }
+            chain.finish();
+            chains.insert(chain_config.name.clone(), Arc::new(chain));
+        }
+
+        for config in &self.config.middlewares {
+            if !middlewares.contains_key(&config.name) {
+                warn!("!!!> Unused Http Middleware, name: {:?}", config.name);
+            }
+        }
+
+        let mut hosts = VirtualHostTab::new();
+        for (index, host_config) in self.config.hosts.iter().enumerate() {
+            let mut route = HttpRoute::new();
+            for (route_index, route_config) in host_config.routes.iter().enumerate() {
+                let chain = match chains.get(&route_config.chain) {
+                    None => {
+                        return Err(config_error(&format!("hosts[{}].routes[{}].chain", index, route_index),
+                                                &format!("undefined chain {:?}", route_config.chain)));
+                    },
+                    Some(chain) => chain,
+                };
+
+                route.at(route_config.path.as_str());
+                for method in &route_config.methods {
+                    route.method(method.clone(), chain.clone());
+                }
+            }
+
+            let mut host = VirtualHost::with(route);
+            for page in &host_config.error_pages {
+                host.set_error_template(page.status, page.mime.as_str(), page.template.as_str())?;
+            }
+
+            for name in &host_config.names {
+                hosts.add(name.as_str(), host.clone())?;
+            }
+            if host_config.is_default {
+                hosts.set_default(host)?;
+            }
+        }
+
+        Ok(hosts)
+    }
+
+    //绑定所有Http监听器，没有Http监听器则返回空
+    pub fn bind_tcp(&self, registry: &MiddlewareRegistry<TcpSocket>)
+                    -> Result<Option<SocketDriver<TcpSocket, PortsAdapter<TcpSocket>>>> {
+        let listeners: Vec<&ListenerConfig> = self.config.listeners.iter().filter(|listener| listener.tls.is_none()).collect();
+        if listeners.is_empty() {
+            return Ok(None);
+        }
+
+        let ports: Vec<u16> = listeners.iter().map(|listener| listener.port).collect();
+        let config = SocketConfig::new(self.config.server.ip.as_str(), ports.as_slice());
+        self.bind(registry, listeners, config).map(Some)
+    }
+
+    //绑定所有Https监听器，没有Https监听器则返回空
+    pub fn bind_tls(&self, registry: &MiddlewareRegistry<TlsSocket>)
+                    -> Result<Option<SocketDriver<TlsSocket, PortsAdapter<TlsSocket>>>> {
+        let mut listeners = Vec::new();
+        let mut ports = Vec::new();
+        for (index, listener) in self.config.listeners.iter().enumerate() {
+            if let Some(tls) = &listener.tls {
+                match tls.build() {
+                    Err(e) => {
+                        return Err(config_error(&format!("listeners[{}].tls", index), e.as_str()));
+                    },
+                    Ok(tls_config) => {
+                        listeners.push(listener);
+                        ports.push((listener.port, tls_config));
+                    },
+                }
+            }
+        }
+        if listeners.is_empty() {
+            return Ok(None);
+        }
+
+        let config = SocketConfig::with_tls(self.config.server.ip.as_str(), ports.as_slice());
+        self.bind(registry, listeners, config).map(Some)
+    }
+
+    //为指定的监听器构建端口服务，并绑定连接监听器
+    fn bind<S: Socket + Stream>(&self,
+                                registry: &MiddlewareRegistry<S>,
+                                listeners: Vec<&ListenerConfig>,
+                                mut config: SocketConfig) -> Result<SocketDriver<S, PortsAdapter<S>>> {
+        let hosts = self.build_hosts(registry)?;
+
+        let mut factory = AsyncPortsFactory::<S>::new();
+        for listener in listeners {
+            let mut service = HttpListenerFactory::<S, _>::with_hosts(hosts.clone(), listener.keep_alive);
+            service.set_limits(listener.limits);
+            factory.bind(listener.port, Box::new(service));
+        }
+
+        let option = &self.config.server;
+        config.set_option(option.recv_buffer_size,
+                          option.send_buffer_size,
+                          option.read_buffer_capacity,
+                          option.write_buffer_capacity);
+        let buffer = WriteBufferPool::new(option.buffer_pool_capacity,
+                                          option.buffer_pool_init,
+                                          option.buffer_iolist_capacity)?;
+
+        SocketListener::bind(factory,
+                             buffer,
+                             config,
+                             option.init_capacity,
+                             option.stack_size,
+                             option.event_size,
+                             option.timeout)
+    }
+}
+
+/*
+* 配置文件中指定路径的字段表
+*/
+struct Fields<'a> {
+    path:   String,                 //字段表的路径
+    map:    &'a Map<String, Value>, //字段表
+}
+
+impl<'a> Fields<'a> {
+    //获取指定字段的路径
+    fn path(&self, key: &str) -> String {
+        if self.path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", self.path, key)
+        }
+    }
+
+    //构建字段表的错误
+    fn fail(&self, reason: &str) -> Error {
+        config_error(self.path.as_str(), reason)
+    }
+
+    //构建指定字段的错误
+    fn error(&self, key: &str, reason: &str) -> Error {
+        config_error(self.path(key).as_str(), reason)
+    }
+
+    //检查是否有未知的字段
+    fn check_keys(&self, keys: &[&str]) -> Result<()> {
+        for key in self.map.keys() {
+            if !keys.contains(&key.as_str()) {
+                return Err(self.error(key, "unknown field"));
+            }
+        }
+
+        Ok(())
+    }
+
+    //获取指定的字符串字段
+    fn get_str(&self, key: &str) -> Result<Option<&'a str>> {
+        match self.map.get(key) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value.as_str())),
+            Some(_) => Err(self.error(key, "expected string")),
+        }
+    }
+
+    //获取指定的非空字符串字段，不存在则返回错误
+    fn require_str(&self, key: &str) -> Result<&'a str> {
+        match self.get_str(key)? {
+            None => Err(self.error(key, "missing field")),
+            Some("") => Err(self.error(key, "expected non-empty string")),
+            Some(value) => Ok(value),
+        }
+    }
+
+    //获取指定的非负整数字段
+    fn get_u64(&self, key: &str) -> Result<Option<u64>> {
+        match self.map.get(key) {
+            None => Ok(None),
+            Some(value) => {
+                match value.as_u64() {
+                    None => Err(self.error(key, "expected non-negative integer")),
+                    Some(value) => Ok(Some(value)),
+                }
+            },
+        }
+    }
+
+    //获取指定的正整数字段，不存在则使用默认值
+    fn get_positive(&self, key: &str, default: usize) -> Result<usize> {
+        match self.get_u64(key)? {
+            None => Ok(default),
+            Some(0) => Err(self.error(key, "expected positive integer")),
+            Some(value) => Ok(value as usize),
+        }
+    }
+
+    //获取指定的正整数字段，不存在则返回错误
+    fn require_positive(&self, key: &str) -> Result<usize> {
+        match self.get_u64(key)? {
+            None => Err(self.error(key, "missing field")),
+            Some(0) => Err(self.error(key, "expected positive integer")),
+            Some(value) => Ok(value as usize),
+        }
+    }
+
+    //获取指定的端口字段
+    fn get_port(&self, key: &str) -> Result<Option<u16>> {
+        match self.get_u64(key)? {
+            None => Ok(None),
+            Some(port) if port > 0 && port <= u16::max_value() as u64 => Ok(Some(port as u16)),
+            Some(_) => Err(self.error(key, "expected port in 1..65535")),
+        }
+    }
+
+    //获取指定的布尔字段
+    fn get_bool(&self, key: &str) -> Result<Option<bool>> {
+        match self.map.get(key) {
+            None => Ok(None),
+            Some(Value::Bool(value)) => Ok(Some(*value)),
+            Some(_) => Err(self.error(key, "expected boolean")),
+        }
+    }
+
+    //获取指定的字符串列表字段
+    fn get_str_list(&self, key: &str) -> Result<Option<Vec<String>>> {
+        let values = match self.map.get(key) {
+            None => return Ok(None),
+            Some(Value::Array(values)) => values,
+            Some(_) => return Err(self.error(key, "expected string array")),
+        };
+
+        let mut list = Vec::with_capacity(values.len());
+        for (index, value) in values.iter().enumerate() {
+            match value {
+                Value::String(value) => list.push(value.clone()),
+                _ => return Err(config_error(&format!("{}[{}]", self.path(key), index), "expected string")),
+            }
+        }
+
+        Ok(Some(list))
+    }
+
+    //获取指定的字符串列表字段，不存在则返回错误
+    fn require_str_list(&self, key: &str) -> Result<Vec<String>> {
+        match self.get_str_list(key)? {
+            None => Err(self.error(key, "missing field")),
+            Some(list) => Ok(list),
+        }
+    }
+
+    //获取指定的地址段列表字段
+    fn get_ip_ranges(&self, key: &str) -> Result<Vec<IpRange>> {
+        let mut ranges = Vec::new();
+        for (index, value) in self.get_str_list(key)?.unwrap_or_default().iter().enumerate() {
+            match IpRange::from_str(value) {
+                Err(e) => return Err(config_error(&format!("{}[{}]", self.path(key), index), &e.to_string())),
+                Ok(range) => ranges.push(range),
+            }
+        }
+
+        Ok(ranges)
+    }
+
+    //获取指定的子字段表
+    fn get_table(&self, key: &str) -> Result<Option<Fields<'a>>> {
+        match self.map.get(key) {
+            None => Ok(None),
+            Some(Value::Object(map)) => Ok(Some(Fields { path: self.path(key), map })),
+            Some(_) => Err(self.error(key, "expected table")),
+        }
+    }
+
+    //获取指定的子字段表列表，不存在则返回空列表
+    fn get_table_list(&self, key: &str) -> Result<Vec<Fields<'a>>> {
+        let values = match self.map.get(key) {
+            None => return Ok(Vec::new()),
+            Some(Value::Array(values)) => values,
+            Some(_) => return Err(self.error(key, "expected table array")),
+        };
+
+        let mut list = Vec::with_capacity(values.len());
+        for (index, value) in values.iter().enumerate() {
+            let path = format!("{}[{}]", self.path(key), index);
+            match value {
+                Value::Object(map) => list.push(Fields { path, map }),
+                _ => return Err(config_error(path.as_str(), "expected table")),
+            }
+        }
+
+        Ok(list)
+    }
+
+    //获取指定的非空子字段表列表，不存在则返回错误
+    fn require_table_list(&self, key: &str) -> Result<Vec<Fields<'a>>> {
+        let list = self.get_table_list(key)?;
+        if list.is_empty() {
+            return Err(self.error(key, "expected non-empty table array"));
+        }
+
+        Ok(list)
+    }
+
+    //获取所有字段的子字段表
+    fn tables(&self) -> Result<Vec<(&'a str, Fields<'a>)>> {
+        let mut list = Vec::with_capacity(self.map.len());
+        for (key, value) in self.map {
+            match value {
+                Value::Object(map) => list.push((key.as_str(), Fields { path: self.path(key), map })),
+                _ => return Err(self.error(key, "expected table")),
+            }
+        }
+
+        Ok(list)
+    }
+}
+
+//构建配置错误
+fn config_error(path: &str, reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("invalid server config, path: {:?}, reason: {}", path, reason))
+}
+
+//分析连接选项
+fn parse_server(fields: &Fields) -> Result<ServerOption> {
+    fields.check_keys(&["ip", "recv_buffer_size", "send_buffer_size", "read_buffer_capacity", "write_buffer_capacity",
+        "buffer_pool_capacity", "buffer_pool_init", "buffer_iolist_capacity", "init_capacity", "stack_size", "event_size", "timeout"])?;
+
+    let default = ServerOption::default();
+    let ip = fields.get_str("ip")?.unwrap_or(default.ip.as_str()).to_string();
+    if IpAddr::from_str(ip.as_str()).is_err() {
+        return Err(fields.error("ip", &format!("invalid ip address {:?}", ip)));
+    }
+
+    let timeout = match fields.get_u64("timeout")? {
+        None => default.timeout,
+        Some(0) => None,
+        Some(timeout) => Some(timeout as usize),
+    };
+
+    Ok(ServerOption {
+        ip,
+        recv_buffer_size: fields.get_positive("recv_buffer_size", default.recv_buffer_size)?,
+        send_buffer_size: fields.get_positive("send_buffer_size", default.send_buffer_size)?,
+        read_buffer_capacity: fields.get_positive("read_buffer_capacity", default.read_buffer_capacity)?,
+        write_buffer_capacity: fields.get_positive("write_buffer_capacity", default.write_buffer_capacity)?,
+        buffer_pool_capacity: fields.get_positive("buffer_pool_capacity", default.buffer_pool_capacity)?,
+        buffer_pool_init: fields.get_positive("buffer_pool_init", default.buffer_pool_init)?,
+        buffer_iolist_capacity: fields.get_positive("buffer_iolist_capacity", default.buffer_iolist_capacity)?,
+        init_capacity: fields.get_positive("init_capacity", default.init_capacity)?,
+        stack_size: fields.get_positive("stack_size", default.stack_size)?,
+        event_size: fields.get_positive("event_size", default.event_size)?,
+        timeout,
+    })
+}
+
+//分析监听器配置
+fn parse_listener(fields: &Fields) -> Result<ListenerConfig> {
+    fields.check_keys(&["port", "keep_alive", "limits", "tls"])?;
+
+    let port = match fields.get_port("port")? {
+        None => return Err(fields.error("port", "missing field")),
+        Some(port) => port,
+    };
+
+    let mut limits = HttpLimits::default();
+    if let Some(table) = fields.get_table("limits")? {
+        table.check_keys(&["max_request_line", "max_header_bytes", "max_headers", "header_timeout", "max_body"])?;
+        limits.max_request_line = table.get_positive("max_request_line", limits.max_request_line)?;
+        limits.max_header_bytes = table.get_positive("max_header_bytes", limits.max_header_bytes)?;
+        limits.max_headers = table.get_positive("max_headers", limits.max_headers)?;
+        limits.header_timeout = table.get_positive("header_timeout", limits.header_timeout)?;
+        limits.max_body = table.get_u64("max_body")?.map(|max_body| max_body as usize);
+        if limits.max_request_line > limits.max_header_bytes {
+            return Err(table.error("max_request_line", "greater than max_header_bytes"));
+        }
+    }
+
+    let tls = match fields.get_table("tls")? {
+        None => None,
+        Some(table) => {
+            table.check_keys(&["cert", "key", "client_auth", "is_client_auth", "ocsp", "suites", "versions", "session_size", "is_tickets", "alpns"])?;
+            let is_client_auth = table.get_bool("is_client_auth")?.unwrap_or(false);
+            let client_auth = table.get_str("client_auth")?.unwrap_or("").to_string();
+            if is_client_auth && client_auth.is_empty() {
+                return Err(table.error("client_auth", "required by is_client_auth"));
+            }
+
+            Some(TlsOption {
+                cert: table.require_str("cert")?.to_string(),
+                key: table.require_str("key")?.to_string(),
+                client_auth,
+                is_client_auth,
+                ocsp: table.get_str("ocsp")?.unwrap_or("").to_string(),
+                suites: table.get_str("suites")?.unwrap_or("").to_string(),
+                versions: table.get_str("versions")?.unwrap_or("").to_string(),
+                session_size: table.get_u64("session_size")?.map_or(DEFAULT_TLS_SESSION_SIZE, |size| size as usize),
+                is_tickets: table.get_bool("is_tickets")?.unwrap_or(false),
+                alpns: table.get_str("alpns")?.unwrap_or("").to_string(),
+            })
+        },
+    };
+
+    Ok(ListenerConfig {
+        port,
+        keep_alive: fields.get_positive("keep_alive", DEFAULT_KEEP_ALIVE)?,
+        limits,
+        tls,
+    })
+}
+
+//分析静态资源缓存配置
+fn parse_cache(name: &str, fields: &Fields) -> Result<CacheConfig> {
+    fields.check_keys(&["max_size", "max_len", "collect_interval", "is_watch"])?;
+
+    Ok(CacheConfig {
+        name: name.to_string(),
+        max_size: fields.require_positive("max_size")?,
+        max_len: fields.require_positive("max_len")?,
+        collect_interval: fields.get_u64("collect_interval")?.unwrap_or(DEFAULT_CACHE_COLLECT_INTERVAL),
+        is_watch: fields.get_bool("is_watch")?.unwrap_or(false),
+    })
+}
+
+//分析虚拟主机配置
+fn parse_host(fields: &Fields, chains: &[ChainConfig]) -> Result<HostConfig> {
+    fields.check_keys(&["names", "default", "routes", "error_pages"])?;
+
+    let names = fields.get_str_list("names")?.unwrap_or_default();
+    let is_default = fields.get_bool("default")?.unwrap_or(false);
+    if names.is_empty() && !is_default {
+        return Err(fields.error("names", "expected non-empty host names for non-default host"));
+    }
+    if let Some(index) = names.iter().position(|name| name.is_empty()) {
+        return Err(config_error(&format!("{}[{}]", fields.path("names"), index), "expected non-empty string"));
+    }
+
+    let mut routes: Vec<RouteConfig> = Vec::new();
+    for route in fields.require_table_list("routes")? {
+        route.check_keys(&["path", "methods", "chain"])?;
+
+        let path = route.require_str("path")?;
+        if !path.starts_with('/') {
+            return Err(route.error("path", "expected path starting with /"));
+        }
+
+        let mut methods = Vec::new();
+        for (index, method) in route.require_str_list("methods")?.iter().enumerate() {
+            match Method::from_str(method.to_ascii_uppercase().as_str()) {
+                Err(_) => {
+                    return Err(config_error(&format!("{}[{}]", route.path("methods"), index),
+                                            &format!("invalid method {:?}", method)));
+                },
+                Ok(method) => {
+                    if methods.contains(&method) || routes.iter().any(|other| other.path == path && other.methods.contains(&method)) {
+                        return Err(config_error(&format!("{}[{}]", route.path("methods"), index),
+                                                &format!("duplicate route {} {}", method, path)));
+                    }
+                    methods.push(method);
+                },
+            }
+        }
+        if methods.is_empty() {
+            return Err(route.error("methods", "expected non-empty method list"));
+        }
+
+        let chain = route.require_str("chain")?;
+        if !chains.iter().any(|config| config.name == chain) {
+            return Err(route.error("chain", &format!("undefined chain {:?}", chain)));
+        }
+
+        routes.push(RouteConfig {
+            path: path.to_string(),
+            methods,
+            chain: chain.to_string(),
+        });
+    }
+
+    let mut error_pages = Vec::new();
+    for page in fields.get_table_list("error_pages")? {
+        page.check_keys(&["status", "mime", "template"])?;
+
+        let status = match page.get_u64("status")? {
+            None => return Err(page.error("status", "missing field")),
+            Some(status) => {
+                match StatusCode::from_u16(status as u16) {
+                    Ok(code) if status < 1000 && (code.is_client_error() || code.is_server_error()) => code,
+                    _ => return Err(page.error("status", &format!("invalid error status {}", status))),
+                }
+            },
+        };
+
+        error_pages.push(ErrorPageConfig {
+            status,
+            mime: page.get_str("mime")?.unwrap_or("text/html").to_string(),
+            template: page.require_str("template")?.to_string(),
+        });
+    }
+
+    Ok(HostConfig {
+        names,
+        is_default,
+        routes,
+        error_pages,
+    })
+}
+
+//获取中间件需要的文件异步运行时
+fn require_files_runtime<S: Socket>(config: &MiddlewareConfig, context: &BuildContext<S>) -> Result<MultiTaskRuntime<()>> {
+    match context.get_files_runtime() {
+        None => Err(config.error("type", "files async runtime not set")),
+        Some(runtime) => Ok(runtime),
+    }
+}
+
+//构建跨域请求处理器
+fn build_cors<S: Socket>(config: &MiddlewareConfig, _context: &BuildContext<S>) -> Result<CORSHandler> {
+    let fields = config.fields();
+    fields.check_keys(&["methods", "any_origin_max_age", "origins"])?;
+
+    let methods = fields.get_str_list("methods")?
+        .unwrap_or_else(|| DEFAULT_CORS_METHODS.iter().map(|method| method.to_string()).collect());
+    let any_origin_max_age = fields.get_u64("any_origin_max_age")?.map(|max_age| max_age as usize);
+    let handler = CORSHandler::new(methods.join(", "), any_origin_max_age);
+
+    for origin in fields.get_table_list("origins")? {
+        origin.check_keys(&["scheme", "host", "port", "methods", "headers", "max_age"])?;
+
+        let scheme = origin.get_str("scheme")?.unwrap_or("http");
+        let port = match origin.get_port("port")? {
+            Some(port) => port,
+            None if scheme == "https" => 443,
+            None => 80,
+        };
+        let methods = origin.get_str_list("methods")?.unwrap_or_else(|| methods.clone());
+        let headers = origin.get_str_list("headers")?.unwrap_or_default();
+        let max_age = origin.get_u64("max_age")?.map(|max_age| max_age as usize);
+        if let Err(e) = handler.allow_origin(scheme.to_string(),
+                                             origin.require_str("host")?.to_string(),
+                                             port,
+                                             methods.as_slice(),
+                                             headers.as_slice(),
+                                             max_age) {
+            return Err(origin.fail(&e.to_string()));
+        }
+    }
+
+    Ok(handler)
+}
+
+//构建默认的请求体分析器
+fn build_parser<S: Socket>(config: &MiddlewareConfig, _context: &BuildContext<S>) -> Result<DefaultParser> {
+    let fields = config.fields();
+    fields.check_keys(&["min_plain_limit", "level"])?;
+
+    let min_plain_limit = fields.get_u64("min_plain_limit")?.map_or(128, |limit| limit as usize);
+    let level = fields.get_u64("level")?.map(|level| level as u32);
+    Ok(DefaultParser::with(min_plain_limit, level))
+}
+
+//构建多部分请求体分析器，设置了临时目录则流式分析
+fn build_multi_parts<S: Socket>(config: &MiddlewareConfig, context: &BuildContext<S>) -> Result<MutilParts> {
+    let fields = config.fields();
+    fields.check_keys(&["block_size", "spool_dir", "part_limit", "total_limit", "max_parts"])?;
+
+    let block_size = fields.get_positive("block_size", DEFAULT_MULTI_PARTS_BLOCK_SIZE)?;
+    let mut multi_parts = match fields.get_str("spool_dir")? {
+        None => MutilParts::with(block_size),
+        Some(dir) => MutilParts::with_spool(block_size, require_files_runtime(config, context)?, dir)?,
+    };
+    //未配置的限制使用默认限制
+    if let Some(limit) = fields.get_u64("part_limit")? {
+        multi_parts.set_part_limit(Some(limit as usize));
+    }
+    if let Some(limit) = fields.get_u64("total_limit")? {
+        multi_parts.set_total_limit(Some(limit as usize));
+    }
+    if let Some(max) = fields.get_u64("max_parts")? {
+        multi_parts.set_max_parts(Some(max as usize));
+    }
+
+    Ok(multi_parts)
+}
+
+//构建范围加载器，设置了根目录则直接从文件中加载范围
+fn build_range_load<S: Socket>(config: &MiddlewareConfig, context: &BuildContext<S>) -> Result<RangeLoad> {
+    let fields = config.fields();
+    fields.check_keys(&["dir"])?;
+
+    match fields.get_str("dir")? {
+        None => Ok(RangeLoad::new()),
+        Some(dir) => Ok(RangeLoad::with_files(require_files_runtime(config, context)?, dir)),
+    }
+}
+
+//分析文件加载器的选项，返回根目录、静态资源缓存、是否缓存、是否存储、是否转换、是否只使用缓存和缓存有效时长
+fn parse_load_options<S: Socket>(config: &MiddlewareConfig, context: &BuildContext<S>, keys: &[&str])
+                                 -> Result<(String, Option<Arc<StaticCache>>, bool, bool, bool, bool, usize)> {
+    let fields = config.fields();
+    fields.check_keys(keys)?;
+
+    let cache = match fields.get_str("cache")? {
+        None => None,
+        Some(name) => {
+            match context.get_cache(name) {
+                None => return Err(fields.error("cache", &format!("undefined cache {:?}", name))),
+                Some(cache) => Some(cache),
+            }
+        },
+    };
+
+    Ok((fields.require_str("dir")?.to_string(),
+        cache,
+        fields.get_bool("is_cache")?.unwrap_or(true),
+        fields.get_bool("is_store")?.unwrap_or(true),
+        fields.get_bool("is_transform")?.unwrap_or(true),
+        fields.get_bool("is_only_if_cached")?.unwrap_or(false),
+        fields.get_u64("max_age")?.map_or(DEFAULT_FILE_MAX_AGE, |max_age| max_age as usize)))
+}
+
+//构建文件加载器
+fn build_file_load<S: Socket>(config: &MiddlewareConfig, context: &BuildContext<S>) -> Result<FileLoad> {
+    let (dir, cache, is_cache, is_store, is_transform, is_only_if_cached, max_age)
+        = parse_load_options(config, context, &["dir", "cache", "is_cache", "is_store", "is_transform", "is_only_if_cached", "max_age", "is_precompressed"])?;
+
+    let mut load = FileLoad::new(require_files_runtime(config, context)?, dir, cache, is_cache, is_store, is_transform, is_only_if_cached, max_age);
+    if let Some(is_precompressed) = config.get_bool("is_precompressed")? {
+        load.set_precompressed(is_precompressed);
+    }
+
+    Ok(load)
+}
+
+//构建文件批量加载器
+fn build_files_load<S: Socket>(config: &MiddlewareConfig, context: &BuildContext<S>) -> Result<FilesLoad> {
+    let (dir, cache, is_cache, is_store, is_transform, is_only_if_cached, max_age)
+        = parse_load_options(config, context, &["dir", "cache", "is_cache", "is_store", "is_transform", "is_only_if_cached", "max_age", "is_precompressed"])?;
+
+    let mut load = FilesLoad::new(require_files_runtime(config, context)?, dir, cache, is_cache, is_store, is_transform, is_only_if_cached, max_age);
+    if let Some(is_precompressed) = config.get_bool("is_precompressed")? {
+        load.set_precompressed(is_precompressed);
+    }
+
+    Ok(load)
+}
+
+//构建改进的文件批量加载器
+fn build_batch_load<S: Socket>(config: &MiddlewareConfig, context: &BuildContext<S>) -> Result<BatchLoad> {
+    let (dir, cache, is_cache, is_store, is_transform, is_only_if_cached, max_age)
+        = parse_load_options(config, context, &["dir", "cache", "is_cache", "is_store", "is_transform", "is_only_if_cached", "max_age", "is_precompressed"])?;
+
+    let mut load = BatchLoad::new(require_files_runtime(config, context)?, dir, cache, is_cache, is_store, is_transform, is_only_if_cached, max_age);
+    if let Some(is_precompressed) = config.get_bool("is_precompressed")? {
+        load.set_precompressed(is_precompressed);
+    }
+
+    Ok(load)
+}
+
+//构建文件上传处理器
+fn build_upload<S: Socket>(config: &MiddlewareConfig, context: &BuildContext<S>) -> Result<UploadFile> {
+    config.check_keys(&["dir"])?;
+
+    let dir = config.require_str("dir")?;
+    Ok(UploadFile::new(require_files_runtime(config, context)?, dir))
+}
+
+//构建Http端口中间件
+fn build_port<S: Socket>(config: &MiddlewareConfig, context: &BuildContext<S>) -> Result<HttpMiddleware<S>> {
+    config.check_keys(&["handler", "gray"])?;
+
+    let name = config.require_str("handler")?;
+    match context.get_handler(name) {
+        None => Err(config.error("handler", &format!("undefined handler {:?}", name))),
+        Some(handler) => {
+            let gray = config.get_u64("gray")?.map(|gray| gray as usize);
+            Ok(Arc::new(HttpPort::with_handler(gray, handler)))
+        },
+    }
+}
+
+//构建反向代理
+fn build_proxy<S: Socket>(config: &MiddlewareConfig, context: &BuildContext<S>) -> Result<ReverseProxy> {
+    let fields = config.fields();
+    fields.check_keys(&["upstreams", "strategy", "preserve_host", "retries", "trusted_proxies", "max_exchanges"])?;
+
+    let mut upstreams = Vec::new();
+    for upstream in fields.require_table_list("upstreams")? {
+        upstream.check_keys(&["addr", "weight"])?;
+        upstreams.push((upstream.require_str("addr")?, upstream.get_positive("weight", 1)?));
+    }
+
+    let strategy = match fields.get_str("strategy")?.unwrap_or("round_robin") {
+        "round_robin" => BalanceStrategy::RoundRobin,
+        "least_connections" => BalanceStrategy::LeastConnections,
+        "ip_hash" => BalanceStrategy::IpHash,
+        strategy => return Err(fields.error("strategy", &format!("unknown strategy {:?}", strategy))),
+    };
+
+    let runtime = match context.get_proxy_runtime() {
+        None => return Err(config.error("type", "proxy async runtime not set")),
+        Some(runtime) => runtime,
+    };
+    let mut proxy = match ReverseProxy::new(runtime, upstreams.as_slice(), strategy) {
+        Err(e) => return Err(fields.error("upstreams", &e.to_string())),
+        Ok(proxy) => proxy,
+    };
+    if let Some(preserve) = fields.get_bool("preserve_host")? {
+        proxy.set_preserve_host(preserve);
+    }
+    if let Some(retries) = fields.get_u64("retries")? {
+        proxy.set_retries(retries as usize);
+    }
+    if let Some(max) = fields.get_u64("max_exchanges")? {
+        proxy.set_max_exchanges(max as usize);
+    }
+    proxy.set_trusted_proxies(fields.get_ip_ranges("trusted_proxies")?);
+
+    Ok(proxy)
+}
+
+//构建Basic认证中间件，用户表的键为用户名，值为密码
+fn build_basic_auth<S: Socket>(config: &MiddlewareConfig, _context: &BuildContext<S>) -> Result<BasicAuth> {
+    let fields = config.fields();
+    fields.check_keys(&["realm", "users"])?;
+
+    let users = match fields.get_table("users")? {
+        None => return Err(fields.error("users", "missing field")),
+        Some(users) => users,
+    };
+    if users.map.is_empty() {
+        return Err(users.fail("expected non-empty user table"));
+    }
+
+    let store = MemoryCredentialStore::new();
+    for user in users.map.keys() {
+        if let Err(e) = store.add_user(user.as_str(), users.require_str(user)?) {
+            return Err(users.fail(&e.to_string()));
+        }
+    }
+
+    Ok(BasicAuth::new(fields.get_str("realm")?.unwrap_or(DEFAULT_AUTH_REALM), Box::new(store)))
+}
+
+//构建Bearer令牌认证中间件，密钥可以从JWKS文件加载，也可以直接配置HS256密钥
+fn build_jwt_auth<S: Socket>(config: &MiddlewareConfig, _context: &BuildContext<S>) -> Result<JwtAuth> {
+    let fields = config.fields();
+    fields.check_keys(&["realm", "audience", "issuer", "leeway", "principal_claim", "jwks", "hs256_keys"])?;
+
+    let mut auth = JwtAuth::new(fields.get_str("realm")?.unwrap_or(DEFAULT_AUTH_REALM));
+    auth.set_audience(fields.get_str("audience")?.map(|audience| audience.to_string()));
+    auth.set_issuer(fields.get_str("issuer")?.map(|issuer| issuer.to_string()));
+    if let Some(leeway) = fields.get_u64("leeway")? {
+        auth.set_leeway(leeway);
+    }
+    if let Some(claim) = fields.get_str("principal_claim")? {
+        auth.set_principal_claim(claim);
+    }
+
+    if let Some(path) = fields.get_str("jwks")? {
+        if let Err(e) = auth.load_jwks(path) {
+            return Err(fields.error("jwks", &e.to_string()));
+        }
+    }
+    for key in fields.get_table_list("hs256_keys")? {
+        key.check_keys(&["kid", "secret"])?;
+        auth.add_hs256_key(key.get_str("kid")?, key.require_str("secret")?.as_bytes());
+    }
+    if auth.keys_len() == 0 {
+        return Err(fields.fail("no verification keys, expected jwks or hs256_keys"));
+    }
+
+    Ok(auth)
+}
+
+//构建Hmac签名认证中间件，密钥表的键为密钥id，值为密钥
+fn build_hmac_auth<S: Socket>(config: &MiddlewareConfig, _context: &BuildContext<S>) -> Result<HmacAuth> {
+    let fields = config.fields();
+    fields.check_keys(&["max_skew", "max_nonces", "keys"])?;
+
+    let mut auth = HmacAuth::new();
+    if let Some(max_skew) = fields.get_u64("max_skew")? {
+        auth.set_max_skew(max_skew);
+    }
+    if let Some(max_nonces) = fields.get_u64("max_nonces")? {
+        auth.set_max_nonces(max_nonces as usize);
+    }
+
+    let keys = match fields.get_table("keys")? {
+        None => return Err(fields.error("keys", "missing field")),
+        Some(keys) => keys,
+    };
+    if keys.map.is_empty() {
+        return Err(keys.fail("expected non-empty key table"));
+    }
+
+    for id in keys.map.keys() {
+        auth.add_key(id.as_str(), keys.require_str(id)?.as_bytes());
+    }
+
+    Ok(auth)
+}
+
+//构建Cookie解析器
+fn build_cookie<S: Socket>(config: &MiddlewareConfig, _context: &BuildContext<S>) -> Result<CookieParser> {
+    config.check_keys(&[])?;
+
+    Ok(CookieParser)
+}
+
+//构建会话中间件，存储为memory或cookie，cookie存储设置了key则加密，否则使用secret签名
+fn build_session<S: Socket>(config: &MiddlewareConfig, _context: &BuildContext<S>) -> Result<SessionManager> {
+    let fields = config.fields();
+    fields.check_keys(&["store", "secret", "key", "cookie_name", "path", "domain", "max_age", "http_only", "secure", "same_site", "max_sessions"])?;
+
+    let store: Arc<dyn SessionStore> = match fields.get_str("store")?.unwrap_or("memory") {
+        "memory" => Arc::new(MemorySessionStore::with_max_sessions(fields.get_positive("max_sessions", DEFAULT_MAX_MEMORY_SESSIONS)?)),
+        "cookie" => {
+            match fields.get_str("key")? {
+                None => Arc::new(CookieSessionStore::signed(fields.require_str("secret")?.as_bytes())),
+                Some(key) => {
+                    let key = match base64::decode(key) {
+                        Err(e) => return Err(fields.error("key", &format!("invalid base64, {}", e))),
+                        Ok(key) => key,
+                    };
+                    match CookieSessionStore::encrypted(key.as_slice()) {
+                        Err(e) => return Err(fields.error("key", &e.to_string())),
+                        Ok(store) => Arc::new(store),
+                    }
+                },
+            }
+        },
+        store => return Err(fields.error("store", &format!("unknown session store {:?}", store))),
+    };
+
+    let mut manager = SessionManager::new(store);
+    if let Some(name) = fields.get_str("cookie_name")? {
+        manager.set_cookie_name(name);
+    }
+    if let Some(path) = fields.get_str("path")? {
+        manager.set_path(path);
+    }
+    manager.set_domain(fields.get_str("domain")?.map(|domain| domain.to_string()));
+    if let Some(max_age) = fields.get_u64("max_age")? {
+        manager.set_max_age(max_age);
+    }
+    if let Some(http_only) = fields.get_bool("http_only")? {
+        manager.set_http_only(http_only);
+    }
+    if let Some(secure) = fields.get_bool("secure")? {
+        manager.set_secure(secure);
+    }
+    if let Some(same_site) = fields.get_str("same_site")? {
+        manager.set_same_site(Some(parse_same_site(&fields, same_site)?));
+    }
+
+    Ok(manager)
+}
+
+//分析Cookie的跨站请求策略
+fn parse_same_site(fields: &Fields, same_site: &str) -> Result<SameSite> {
+    match same_site.to_ascii_lowercase().as_str() {
+        "strict" => Ok(SameSite::Strict),
+        "lax" => Ok(SameSite::Lax),
+        "none" => Ok(SameSite::None),
+        _ => Err(fields.error("same_site", &format!("unknown same site {:?}", same_site))),
+    }
+}
+
+//构建限流中间件，窗口时长单位为毫秒，限流键为remote_addr、route、principal或header:请求头名
+fn build_rate_limit<S: Socket>(config: &MiddlewareConfig, _context: &BuildContext<S>) -> Result<RateLimit> {
+    let fields = config.fields();
+    fields.check_keys(&["algorithm", "limit", "window", "keys", "trusted_proxies", "max_keys"])?;
+
+    let algorithm = match fields.get_str("algorithm")?.unwrap_or("token_bucket") {
+        "token_bucket" => RateLimitAlgorithm::TokenBucket,
+        "sliding_window" => RateLimitAlgorithm::SlidingWindow,
+        algorithm => return Err(fields.error("algorithm", &format!("unknown algorithm {:?}", algorithm))),
+    };
+
+    let mut keys = Vec::new();
+    for (index, key) in fields.get_str_list("keys")?.unwrap_or_default().iter().enumerate() {
+        let key = match key.as_str() {
+            "remote_addr" => RateLimitKey::RemoteAddr,
+            "route" => RateLimitKey::Route,
+            "principal" => RateLimitKey::Principal,
+            key if key.starts_with("header:") && key.len() > 7 => RateLimitKey::Header(key[7..].to_ascii_lowercase()),
+            _ => {
+                return Err(config_error(&format!("{}[{}]", fields.path("keys"), index),
+                                        &format!("unknown rate limit key {:?}", key)));
+            },
+        };
+        keys.push(key);
+    }
+
+    let mut rate_limit = RateLimit::new(algorithm,
+                                        fields.require_positive("limit")? as u64,
+                                        Duration::from_millis(fields.require_positive("window")? as u64));
+    if !keys.is_empty() {
+        rate_limit.set_keys(keys);
+    }
+    rate_limit.set_trusted_proxies(fields.get_ip_ranges("trusted_proxies")?);
+    if let Some(max_keys) = fields.get_u64("max_keys")? {
+        rate_limit.set_max_keys(max_keys as usize);
+    }
+
+    Ok(rate_limit)
+}
+
+//构建灰度路由中间件，每条规则只能设置header、cookie、percent或ip_ranges中的一种匹配条件，设置了sticky_cookie则必须设置签名的sticky_secret
+fn build_gray<S: Socket>(config: &MiddlewareConfig, _context: &BuildContext<S>) -> Result<GrayRouter> {
+    let fields = config.fields();
+    fields.check_keys(&["default", "rules", "sticky_cookie", "sticky_secret", "sticky_max_age", "trusted_proxies"])?;
+
+    let mut rules = GrayRules::new(fields.get_u64("default")?.map(|gray| gray as usize));
+    for rule in fields.get_table_list("rules")? {
+        rule.check_keys(&["gray", "header", "cookie", "value", "percent", "key", "ip_ranges"])?;
+
+        let gray = match rule.get_u64("gray")? {
+            None => return Err(rule.error("gray", "missing field")),
+            Some(gray) => gray as usize,
+        };
+
+        let conditions = ["header", "cookie", "percent", "ip_ranges"].iter().filter(|key| rule.map.contains_key(**key)).count();
+        if conditions != 1 {
+            return Err(rule.fail("expected exactly one of header, cookie, percent or ip_ranges"));
+        }
+
+        let matcher = if let Some(name) = rule.get_str("header")? {
+            GrayMatcher::Header(name.to_ascii_lowercase(), rule.require_str("value")?.to_string())
+        } else if let Some(name) = rule.get_str("cookie")? {
+            GrayMatcher::Cookie(name.to_string(), rule.require_str("value")?.to_string())
+        } else if let Some(percent) = rule.get_u64("percent")? {
+            if percent > MAX_GRAY_PERCENT as u64 {
+                return Err(rule.error("percent", &format!("expected percent in 0..{}", MAX_GRAY_PERCENT)));
+            }
+
+            let key = match rule.get_str("key")?.unwrap_or("principal") {
+                "principal" => GrayUserKey::Principal,
+                key if key.starts_with("header:") && key.len() > 7 => GrayUserKey::Header(key[7..].to_ascii_lowercase()),
+                key if key.starts_with("cookie:") && key.len() > 7 => GrayUserKey::Cookie(key[7..].to_string()),
+                key => return Err(rule.error("key", &format!("unknown gray user key {:?}", key))),
+            };
+            GrayMatcher::Percent(key, percent as u32)
+        } else {
+            GrayMatcher::IpRange(rule.get_ip_ranges("ip_ranges")?)
+        };
+
+        rules.add_rule(GrayRule::new(matcher, gray));
+    }
+
+    let mut router = GrayRouter::new(rules);
+    if let Some(name) = fields.get_str("sticky_cookie")? {
+        router.set_sticky(Some(name.to_string()),
+                          fields.require_str("sticky_secret")?.as_bytes(),
+                          fields.get_u64("sticky_max_age")?.unwrap_or(DEFAULT_GRAY_STICKY_MAX_AGE));
+    }
+    router.set_trusted_proxies(fields.get_ip_ranges("trusted_proxies")?);
+
+    Ok(router)
+}
@@ -1,18 +1,39 @@
 use std::io::{Error, Result, ErrorKind};
 
-use https::{status::StatusCode,
-            header::{CONTENT_LENGTH, HeaderValue}};
+use https::{Version,
+            status::StatusCode,
+            header::{CONNECTION, CONTENT_LENGTH, TRANSFER_ENCODING, HeaderValue}};
 
-use tcp::{driver::{Socket, SocketHandle, AsyncIOWait},
+use tcp::{driver::{Socket, SocketHandle, AsyncIOWait, PendSocket},
           buffer_pool::WriteBuffer,
           util::{SocketContext, SocketEvent}};
 
 use crate::{service::{ServiceFactory, HttpService},
             request::HttpRequest,
             packet::DEFAULT_READ_READY_HTTP_REQUEST_BYTE_LEN,
-            util::{HttpSender, HttpReceiver, channel}};
+            util::{HttpSender, HttpReceiver, HttpRecvResult, channel}};
 use crate::response::HttpResponse;
 
+/*
+* 分块传输编码名
+*/
+const CHUNKED_TRANSFER_ENCODING: &str = "chunked";
+
+/*
+* 分块传输编码的结束块
+*/
+const LAST_CHUNK: &[u8] = b"0\r\n\r\n";
+
+/*
+* 关闭连接的连接头值
+*/
+const CONNECTION_CLOSE: &str = "close";
+
+/*
+* 保持连接的连接头值
+*/
+const CONNECTION_KEEP_ALIVE: &str = "keep-alive";
+
 /*
 * Http连接
 */
@@ -20,6 +41,7 @@ pub struct HttpConnect<S: Socket, W: AsyncIOWait, HS: HttpService<S, W>> {
     handle:         SocketHandle<S>,        //当前连接的Tcp连接句柄
     waits:          W,                      //异步任务等待队列
     service:        HS,                     //当前连接的服务
+    host:           String,                 //当前连接的服务所属虚拟主机的主机名
     keep_alive:     usize,                  //连接保持时
 }
 
@@ -36,10 +58,27 @@ impl<S: Socket, W: AsyncIOWait, HS: HttpService<S, W>> HttpConnect<S, W, HS> {
             handle,
             waits,
             service,
+            host: String::new(),
             keep_alive,
         }
     }
 
+    //获取当前连接的服务所属虚拟主机的主机名
+    pub fn get_host(&self) -> &str {
+        self.host.as_str()
+    }
+
+    //设置当前连接的服务所属虚拟主机的主机名
+    pub fn set_host(&mut self, host: &str) {
+        self.host = host.to_string();
+    }
+
+    //替换为指定虚拟主机的服务，用于保持连接时后续请求的主机与当前服务的主机不同
+    pub fn set_service(&mut self, host: &str, service: HS) {
+        self.host = host.to_string();
+        self.service = service;
+    }
+
     //分配一个用于发送的写缓冲区
     pub fn alloc(&self) -> Option<WriteBuffer> {
         match self.handle.alloc() {
@@ -106,12 +145,34 @@ impl<S: Socket, W: AsyncIOWait, HS: HttpService<S, W>> HttpConnect<S, W, HS> {
     //运行连接上的服务
     pub async fn run_service(&mut self, req: HttpRequest<S, W>) {
         self.update_timeout(); //在调用服务前，更新当前Http连接的超时时长
+        let is_http10 = *req.version() == Version::HTTP_10;
+        let is_close = is_close_request(&req);
         match self.service.call(req).await {
             Err(e) => {
                 //服务调用异常
                 let resp = HttpResponse::empty(self.handle.clone(), self.waits.clone());
                 self.throw(resp, StatusCode::INTERNAL_SERVER_ERROR, e.into());
             },
+            Ok(resp) if resp.is_stream() => {
+                //服务调用完成，且为流式响应
+                match self.reply_stream(resp, is_http10, is_close).await {
+                    Err(e) => {
+                        //回应错误，因为已发送响应头，则立即关闭当前Http连接
+                        self.close(Err(e));
+                    },
+                    Ok(true) => {
+                        //回应成功，且需要关闭连接，则在发送完成后关闭当前Http连接
+                        self.close(Ok(()));
+                    },
+                    Ok(false) => {
+                        //回应成功，则继续读当前Http连接的后续请求
+                        if let Err(e) = self.handle.read_ready(DEFAULT_READ_READY_HTTP_REQUEST_BYTE_LEN) {
+                            //继续读失败，则立即关闭Http连接
+                            self.close(Err(e));
+                        }
+                    },
+                }
+            },
             Ok(resp) => {
                 //服务调用完成
                 if let Ok(Some(mut buf)) = self.handle.alloc() {
@@ -136,4 +197,122 @@ impl<S: Socket, W: AsyncIOWait, HS: HttpService<S, W>> HttpConnect<S, W, HS> {
             },
         }
     }
-}
\ No newline at end of file
+
+    //异步回应流式Http响应，立即发送响应启始行和响应头，再按生成顺序发送响应体块
+    //每次发送后会等待发送完成，再获取后续响应体块，以适应对端的接收速度，未设置响应体长度时，将使用分块传输
+    //Http/1.0不支持分块传输，未设置响应体长度时，以关闭连接结束响应体，返回发送完成后是否需要关闭连接
+    async fn reply_stream(&self, mut resp: HttpResponse<S, W>, is_http10: bool, mut is_close: bool) -> Result<bool> {
+        let has_len = resp.contains_header(CONTENT_LENGTH);
+        let is_chunked = !has_len && !is_http10;
+        if is_chunked {
+            resp.header(TRANSFER_ENCODING.as_str(), CHUNKED_TRANSFER_ENCODING);
+        } else if !has_len {
+            //Http/1.0且未设置响应体长度，则只能由关闭连接结束响应体
+            is_close = true;
+        }
+
+        if is_close {
+            //需要关闭连接，则通知对端
+            resp.remove_header(CONNECTION);
+            resp.header(CONNECTION.as_str(), CONNECTION_CLOSE);
+        }
+
+        let mut bufs = vec![resp.to_head()];
+        if let Some(mut body) = resp.take_body() {
+            loop {
+                match body.next_stream().await {
+                    HttpRecvResult::Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                        //当前Http连接已被其它事件唤醒，则继续等待后续响应体块
+                        PendSocket::pending(self.handle.get_token().clone(), self.waits.clone()).await;
+                    },
+                    HttpRecvResult::Err(e) => {
+                        //获取响应体块错误
+                        return Err(e);
+                    },
+                    HttpRecvResult::Ok(bins) => {
+                        //获取到后续响应体块，则发送，并等待发送完成
+                        append_stream(&mut bufs, bins, is_chunked);
+                        self.send_stream(bufs).await?;
+                        bufs = Vec::new();
+                    },
+                    HttpRecvResult::Fin(bins) => {
+                        //获取到最后的响应体块，则发送，并结束流式响应
+                        append_stream(&mut bufs, bins, is_chunked);
+                        break;
+                    },
+                }
+            }
+        }
+
+        if is_chunked {
+            bufs.push(LAST_CHUNK.to_vec());
+        }
+        self.send_stream(bufs).await?;
+        Ok(is_close)
+    }
+
+    //异步发送流式响应的数据，并等待发送完成
+    async fn send_stream(&self, bufs: Vec<Vec<u8>>) -> Result<()> {
+        if bufs.is_empty() {
+            return Ok(());
+        }
+
+        match self.handle.alloc() {
+            Ok(Some(mut buf)) => {
+                for bin in bufs {
+                    buf.get_iolist_mut().push_back(bin.into());
+                }
+                self.reply(buf)?;
+                self.update_timeout(); //每次发送后，更新当前Http连接的超时时长
+
+                //挂起当前Http连接，并在发送完成后被唤醒
+                PendSocket::pending(self.handle.get_token().clone(), self.waits.clone()).await;
+                if self.handle.is_closed() {
+                    return Err(Error::new(ErrorKind::BrokenPipe, "http stream response failed, reason: connect closed"));
+                }
+
+                Ok(())
+            },
+            Ok(None) => {
+                Err(Error::new(ErrorKind::Other, "http stream response failed, reason: alloc write buffer failed"))
+            },
+            Err(e) => Err(e),
+        }
+    }
+}
+
+//判断Http请求是否要求回应后关闭连接，Http/1.0的请求只有声明保持连接时才不关闭
+fn is_close_request<S: Socket, W: AsyncIOWait>(req: &HttpRequest<S, W>) -> bool {
+    let mut is_close = *req.version() == Version::HTTP_10;
+    for value in req.headers().get_all(CONNECTION).iter() {
+        if let Ok(str) = value.to_str() {
+            for token in str.split(',') {
+                let token = token.trim();
+                if token.eq_ignore_ascii_case(CONNECTION_CLOSE) {
+                    return true;
+                } else if token.eq_ignore_ascii_case(CONNECTION_KEEP_ALIVE) {
+                    is_close = false;
+                }
+            }
+        }
+    }
+
+    is_close
+}
+
+//将响应体块加入流式响应的发送缓冲，分块传输时需要对响应体块编码，并忽略空块
+fn append_stream(bufs: &mut Vec<Vec<u8>>, bins: Vec<Vec<u8>>, is_chunked: bool) {
+    for bin in bins {
+        if bin.is_empty() {
+            continue;
+        }
+
+        if is_chunked {
+            bufs.push(format!("{:X}\r\n", bin.len()).into_bytes());
+            bufs.push(bin);
+            bufs.push(b"\r\n".to_vec());
+        } else {
+            bufs.push(bin);
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use std::fmt;
+use std::time::SystemTime;
+use std::io::{Error, Result, ErrorKind};
+
+use https::header::COOKIE;
+use futures::future::{FutureExt, BoxFuture};
+use httpdate::fmt_http_date;
+
+use hash::XHashMap;
+use tcp::driver::{Socket, AsyncIOWait};
+
+use crate::{gateway::GatewayContext,
+            middleware::{MiddlewareResult, Middleware},
+            request::HttpRequest,
+            response::HttpResponse};
+
+/*
+* Cookie的SameSite属性
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SameSite {
+    Strict, //只允许同站请求携带
+    Lax,    //允许同站请求和跨站的顶级导航携带
+    None,   //允许跨站请求携带，必须同时设置Secure
+}
+
+impl SameSite {
+    //获取属性值
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/*
+* Set-Cookie响应头的值，格式化时SameSite=None会强制设置Secure
+*/
+#[derive(Debug, Clone)]
+pub struct SetCookie {
+    name:       String,             //Cookie名
+    value:      String,             //Cookie值
+    path:       Option<String>,     //Cookie路径
+    domain:     Option<String>,     //Cookie域
+    max_age:    Option<u64>,        //Cookie有效时长，单位秒
+    expires:    Option<SystemTime>, //Cookie过期时间
+    http_only:  bool,               //是否禁止脚本访问
+    secure:     bool,               //是否只允许安全连接携带
+    same_site:  Option<SameSite>,   //跨站请求策略
+}
+
+impl fmt::Display for SetCookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age)?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", fmt_http_date(expires))?;
+        }
+        if self.secure || self.same_site == Some(SameSite::None) {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site.as_str())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl SetCookie {
+    //构建Set-Cookie，Cookie名必须是令牌，Cookie值不允许包括空白、双引号、逗号、分号和反斜杠
+    pub fn new(name: &str, value: &str) -> Result<Self> {
+        if name.is_empty() || !name.bytes().all(is_token_byte) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("build set cookie failed, name: {:?}, reason: invalid name", name)));
+        }
+
+        if !value.bytes().all(is_cookie_octet) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("build set cookie failed, name: {:?}, reason: invalid value", name)));
+        }
+
+        Ok(SetCookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            http_only: false,
+            secure: false,
+            same_site: None,
+        })
+    }
+
+    //构建移除指定Cookie的Set-Cookie，路径和域需要与设置时相同
+    pub fn removal(name: &str) -> Result<Self> {
+        let mut cookie = SetCookie::new(name, "")?;
+        cookie.max_age(0).expires(SystemTime::UNIX_EPOCH);
+        Ok(cookie)
+    }
+
+    //获取Cookie名
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    //获取Cookie值
+    pub fn value(&self) -> &str {
+        self.value.as_str()
+    }
+
+    //设置Cookie路径
+    pub fn path(&mut self, path: &str) -> &mut Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    //设置Cookie域
+    pub fn domain(&mut self, domain: &str) -> &mut Self {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    //设置Cookie有效时长，单位秒
+    pub fn max_age(&mut self, max_age: u64) -> &mut Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    //设置Cookie过期时间
+    pub fn expires(&mut self, expires: SystemTime) -> &mut Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    //设置是否禁止脚本访问
+    pub fn http_only(&mut self, http_only: bool) -> &mut Self {
+        self.http_only = http_only;
+        self
+    }
+
+    //设置是否只允许安全连接携带
+    pub fn secure(&mut self, secure: bool) -> &mut Self {
+        self.secure = secure;
+        self
+    }
+
+    //设置跨站请求策略
+    pub fn same_site(&mut self, same_site: SameSite) -> &mut Self {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+/*
+* Http请求的Cookie解析器，将Cookie写入网关上下文
+*/
+pub struct CookieParser;
+
+unsafe impl Send for CookieParser {}
+unsafe impl Sync for CookieParser {}
+
+impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for CookieParser {
+    fn request<'a>(&'a self, context: &'a mut GatewayContext, req: HttpRequest<S, W>)
+                   -> BoxFuture<'a, MiddlewareResult<S, W>> {
+        let future = async move {
+            load_request_cookies(context, &req);
+            MiddlewareResult::ContinueRequest(req)
+        };
+        future.boxed()
+    }
+
+    fn response<'a>(&'a self, context: &'a mut GatewayContext, req: HttpRequest<S, W>, resp: HttpResponse<S, W>)
+                    -> BoxFuture<'a, MiddlewareResult<S, W>> {
+        let future = async move {
+            MiddlewareResult::ContinueResponse((req, resp))
+        };
+        future.boxed()
+    }
+}
+
+/*
+* 解析Cookie请求头的值，同名Cookie只保留第一个，会移除Cookie值两边的双引号
+*/
+pub fn parse_cookies(value: &str) -> Vec<(String, String)> {
+    let mut cookies: Vec<(String, String)> = Vec::new();
+    for pair in value.split(';') {
+        let pair = pair.trim();
+        let index = match pair.find('=') {
+            None => continue,
+            Some(index) => index,
+        };
+
+        let name = pair[..index].trim();
+        let mut value = pair[index + 1..].trim();
+        if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+            value = &value[1..value.len() - 1];
+        }
+
+        if name.is_empty() || cookies.iter().any(|(key, _)| key == name) {
+            //忽略无效或重复的Cookie
+            continue;
+        }
+
+        cookies.push((name.to_string(), value.to_string()));
+    }
+
+    cookies
+}
+
+/*
+* 将Http请求的所有Cookie写入网关上下文，已写入过则忽略
+*/
+pub fn load_request_cookies<S: Socket, W: AsyncIOWait>(context: &mut GatewayContext, req: &HttpRequest<S, W>) {
+    if context.as_cookies().is_some() {
+        return;
+    }
+
+    let mut cookies = XHashMap::default();
+    for value in req.headers().get_all(COOKIE) {
+        if let Ok(value) = value.to_str() {
+            for (name, value) in parse_cookies(value) {
+                cookies.entry(name).or_insert(value);
+            }
+        }
+    }
+    context.set_cookies(Some(cookies));
+}
+
+//是否是令牌字符
+fn is_token_byte(b: u8) -> bool {
+    match b {
+        b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => true,
+        _ => b.is_ascii_alphanumeric(),
+    }
+}
+
+//是否是Cookie值允许的字符
+fn is_cookie_octet(b: u8) -> bool {
+    match b {
+        0x21 | 0x23..=0x2b | 0x2d..=0x3a | 0x3c..=0x5b | 0x5d..=0x7e => true,
+        _ => false,
+    }
+}
@@ -4,8 +4,9 @@ use std::io::{Error, Result, ErrorKind, Write};
 
 use url::form_urlencoded;
 use mime::{APPLICATION, WWW_FORM_URLENCODED, JSON, OCTET_STREAM, TEXT, CHARSET, UTF_8, Mime};
-use https::{header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, CONTENT_LENGTH}, StatusCode};
+use https::{header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, CONTENT_LENGTH, ETAG, VARY}, StatusCode};
 use flate2::{Compression, FlushCompress, Compress, Status, write::GzEncoder};
+use brotli::CompressorWriter;
 use serde_json::{Result as JsonResult, Map, Value};
 use futures::future::{FutureExt, BoxFuture};
 use crossbeam_channel::{Sender, Receiver, unbounded, TryRecvError};
@@ -25,6 +26,28 @@ use hash::XHashMap;
 */
 pub const DEFLATE_ENCODING: &str = "deflate";
 pub const GZIP_ENCODING: &str = "gzip";
+pub const BROTLI_ENCODING: &str = "br";
+pub const ZSTD_ENCODING: &str = "zstd";
+pub const IDENTITY_ENCODING: &str = "identity";
+
+/*
+* 支持的响应体编码，按服务器的优先顺序排列，客户端接受的权重相同时，选择靠前的编码
+*/
+#[cfg(feature = "zstd")]
+pub const SUPPORTED_ENCODINGS: &[&str] = &[BROTLI_ENCODING, ZSTD_ENCODING, GZIP_ENCODING, DEFLATE_ENCODING];
+#[cfg(not(feature = "zstd"))]
+pub const SUPPORTED_ENCODINGS: &[&str] = &[BROTLI_ENCODING, GZIP_ENCODING, DEFLATE_ENCODING];
+
+/*
+* 任意编码
+*/
+const ANY_ENCODING: &str = "*";
+
+/*
+* brotli编码的缓冲区大小和窗口大小
+*/
+const BROTLI_BUFFER_SIZE: usize = 4096;
+const BROTLI_LG_WINDOW_SIZE: u32 = 22;
 
 /*
 * Http请求和响应的默认分析器，处理Http请求的默认头和Http响应的默认头
@@ -115,104 +138,62 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for DefaultPars
                 return MiddlewareResult::ContinueResponse((req, response));
             }
 
-            if let Some(accept_encoding) = req.headers().get(ACCEPT_ENCODING) {
-                if let Ok(value) = accept_encoding.to_str() {
-                    for val in value.split(',') {
-                        if let Some(encoding) = val.trim().split(';').next() {
-                            match encoding.trim() {
-                                DEFLATE_ENCODING => {
-                                    //接受deflate编码
-                                    if let Some(body) = response.as_mut_body() {
-                                        if body.len().is_none() || body.len().unwrap() < self.min_plain_limit {
-                                            //响应体明文数据过小，则忽略编码
-                                            break;
-                                        }
-
-                                        match self.deflate_consumer.try_recv() {
-                                            Err(ref e) if e.is_disconnected() => {
-                                                //编码器通道错误，则立即抛出错误
-                                                return MiddlewareResult::Throw(Error::new(ErrorKind::Other, format!("http response body deflate encode failed, reason: {:?}", e)));
-                                            },
-                                            Err(_) => {
-                                                //没有空闲编码器，则创建新的编码器
-                                                if let Some(input) = body.as_slice() {
-                                                    let mut deflate = new_deflate(self.level);
-                                                    let mut output = Vec::with_capacity(input.len());
-                                                    unsafe { output.set_len(output.capacity()); }
-                                                    if let Err(e) = encode_deflate(&mut deflate, input, &mut output, self.flush) {
-                                                        //编码错误，则立即抛出错误
-                                                        return MiddlewareResult::Throw(e);
-                                                    }
-
-                                                    //编码成功，则替换当前响应体，设置响应头，并将创建的编码器加入空闲编码器队列中
-                                                    body.reset(output.as_slice());
-                                                    response.header(CONTENT_ENCODING.as_str(), DEFLATE_ENCODING);
-                                                    response.header(CONTENT_LENGTH.as_str(), deflate.total_out().to_string().as_str());
-                                                    deflate.reset();
-                                                    produce_deflate(self.deflate_producor.clone(), deflate);
-                                                }
-                                            },
-                                            Ok(mut deflate) => {
-                                                //有空闲编码器，则开始编码
-                                                if let Some(input) = body.as_slice() {
-                                                    let cap = (input.len() as f64 * 0.75) as usize;
-                                                    let mut output = Vec::with_capacity(input.len());
-                                                    unsafe { output.set_len(output.capacity()); }
-                                                    if let Err(e) = encode_deflate(&mut deflate, input, &mut output, self.flush) {
-                                                        //编码错误，则立即抛出错误
-                                                        return MiddlewareResult::Throw(e);
-                                                    }
-
-                                                    //编码成功，则替换当前响应体，设置响应头，并将使用后的编码器放入空闲编码器队列中
-                                                    body.reset(output.as_slice());
-                                                    response.header(CONTENT_ENCODING.as_str(), DEFLATE_ENCODING);
-                                                    response.header(CONTENT_LENGTH.as_str(), deflate.total_out().to_string().as_str());
-                                                    deflate.reset();
-                                                    produce_deflate(self.deflate_producor.clone(), deflate);
-                                                }
-                                            },
-                                        }
-                                    }
-
-                                    //已编码，则中止其它类型的编码
-                                    break;
-                                },
-                                GZIP_ENCODING => {
-                                    //接受gzip编码
-                                    if let Some(body) = response.as_mut_body() {
-                                        if body.len().is_none() || body.len().unwrap() < self.min_plain_limit {
-                                            //响应体明文数据过小，则忽略编码
-                                            break;
-                                        }
-
-                                        if let Some(input) = body.as_slice() {
-                                            let gzip = new_gzip(Vec::new(), self.level);
-                                            match encode_gzip(gzip, input) {
-                                                Err(e) => {
-                                                    //编码错误，则立即抛出错误
-                                                    return MiddlewareResult::Throw(e);
-                                                },
-                                                Ok(output) => {
-                                                    //编码成功，则替换当前响应体，设置响应头
-                                                    body.reset(output.as_slice());
-                                                    response.header(CONTENT_ENCODING.as_str(), GZIP_ENCODING);
-                                                    response.header(CONTENT_LENGTH.as_str(), output.len().to_string().as_str());
-                                                },
-                                            }
-                                        }
-                                    }
-
-                                    //已编码，则中止其它类型的编码
-                                    break;
-                                },
-                                _ => {
-                                    //服务器不支持客户端接受的编码，则继续
-                                    continue;
-                                }
-                            }
+            if response.is_stream() {
+                //本次Http响应为流式响应，则忽略编码和内容长度，由Http连接在发送时处理
+                return MiddlewareResult::ContinueResponse((req, response));
+            }
+
+            let encoding = if response.contains_header(CONTENT_ENCODING) {
+                //本次Http响应已编码，例如预压缩的静态资源，则忽略编码
+                None
+            } else if response.get_status() == StatusCode::PARTIAL_CONTENT.as_u16() {
+                //本次Http响应为范围响应，编码会使响应体与内容范围不匹配，则忽略编码
+                None
+            } else {
+                match response.as_body().unwrap().len() {
+                    Some(body_len) if body_len >= self.min_plain_limit => {
+                        //响应体明文数据满足压缩要求，则响应内容会因客户端接受的编码而不同
+                        response.header(VARY.as_str(), ACCEPT_ENCODING.as_str());
+                        req.headers()
+                            .get(ACCEPT_ENCODING)
+                            .and_then(|value| value.to_str().ok())
+                            .and_then(|value| negotiate_encoding(value, SUPPORTED_ENCODINGS))
+                    },
+                    _ => None, //响应体明文数据过小，则忽略编码
+                }
+            };
+
+            if let Some(encoding) = encoding {
+                if let Some(body) = response.as_mut_body() {
+                    if let Some(input) = body.as_slice() {
+                        match self.encode(encoding, input) {
+                            Err(e) => {
+                                //编码错误，则立即抛出错误
+                                return MiddlewareResult::Throw(e);
+                            },
+                            Ok(output) => {
+                                //编码成功，则替换当前响应体，设置响应头
+                                body.reset(output.as_slice());
+                                response.header(CONTENT_ENCODING.as_str(), encoding);
+                                response.header(CONTENT_LENGTH.as_str(), output.len().to_string().as_str());
+                            },
                         }
                     }
                 }
+
+                if response.contains_header(CONTENT_ENCODING) {
+                    //编码后的响应体与原资源的字节不同，则将强实体标签改为弱实体标签
+                    let weak_etag = response.get_headers()
+                        .get(ETAG)
+                        .and_then(|value| value.to_str().ok())
+                        .filter(|etag| !etag.starts_with("W/"))
+                        .map(|etag| format!("W/{}", etag));
+                    if let Some(weak_etag) = weak_etag {
+                        response
+                            .remove_header(ETAG)
+                            .header(ETAG.as_str(), weak_etag.as_str());
+                    }
+                }
             }
 
             //继续响应处理
@@ -261,6 +242,95 @@ impl DefaultParser {
             deflate_consumer,
         }
     }
+
+    //使用指定的编码对响应体进行编码
+    fn encode(&self, encoding: &str, input: &[u8]) -> Result<Vec<u8>> {
+        match encoding {
+            DEFLATE_ENCODING => {
+                //优先使用空闲编码器，没有空闲编码器，则创建新的编码器
+                let mut deflate = match self.deflate_consumer.try_recv() {
+                    Err(ref e) if e.is_disconnected() => {
+                        //编码器通道错误，则立即返回错误
+                        return Err(Error::new(ErrorKind::Other, format!("http response body deflate encode failed, reason: {:?}", e)));
+                    },
+                    Err(_) => new_deflate(self.level),
+                    Ok(deflate) => deflate,
+                };
+
+                let mut output = Vec::with_capacity(input.len());
+                unsafe { output.set_len(output.capacity()); }
+                let result = encode_deflate(&mut deflate, input, &mut output, self.flush);
+
+                //将使用后的编码器放入空闲编码器队列中
+                deflate.reset();
+                produce_deflate(self.deflate_producor.clone(), deflate)?;
+                result.map(|_| output)
+            },
+            GZIP_ENCODING => {
+                encode_gzip(new_gzip(Vec::new(), self.level), input)
+            },
+            BROTLI_ENCODING => {
+                encode_brotli(input, self.level.level())
+            },
+            #[cfg(feature = "zstd")]
+            ZSTD_ENCODING => {
+                zstd::stream::encode_all(input, self.level.level() as i32)
+            },
+            _ => {
+                Err(Error::new(ErrorKind::Other, format!("http response body encode failed, encoding: {:?}, reason: unsupported encoding", encoding)))
+            },
+        }
+    }
+}
+
+//解析Accept-Encoding的值，返回小写的编码名和权重，未指定权重时为1
+pub fn parse_accept_encoding(value: &str) -> Vec<(String, f32)> {
+    let mut encodings = Vec::new();
+    for item in value.split(',') {
+        let mut params = item.split(';');
+        let encoding = match params.next() {
+            Some(encoding) if !encoding.trim().is_empty() => encoding.trim().to_ascii_lowercase(),
+            _ => continue,
+        };
+
+        let mut q = 1.0;
+        for param in params {
+            let mut pair = param.splitn(2, '=');
+            if let (Some(key), Some(value)) = (pair.next(), pair.next()) {
+                if key.trim().eq_ignore_ascii_case("q") {
+                    //无效的权重，则认为不接受
+                    q = value.trim().parse::<f32>().unwrap_or(0.0).max(0.0).min(1.0);
+                }
+            }
+        }
+        encodings.push((encoding, q));
+    }
+
+    encodings
+}
+
+//根据Accept-Encoding的值，从按服务器优先顺序排列的编码中，选择客户端权重最大的编码，选择不编码时返回空
+pub fn negotiate_encoding(accept: &str, supported: &[&'static str]) -> Option<&'static str> {
+    let encodings = parse_accept_encoding(accept);
+    let weight = |name: &str| {
+        encodings.iter().find(|(encoding, _)| encoding == name).map(|(_, q)| *q)
+    };
+    let any = weight(ANY_ENCODING);
+
+    let mut selected: Option<(&'static str, f32)> = None;
+    for encoding in supported {
+        let q = weight(*encoding).or(any).unwrap_or(0.0);
+        if q > 0.0 && selected.map_or(true, |(_, max)| q > max) {
+            selected = Some((*encoding, q));
+        }
+    }
+
+    //未明确指定或由任意编码指定不编码的权重时，不编码总是可以接受
+    let identity = weight(IDENTITY_ENCODING).or(any).unwrap_or(1.0);
+    match selected {
+        Some((encoding, q)) if q >= identity => Some(encoding),
+        _ => None,
+    }
 }
 
 //创建指定压缩级别的deflate编码器
@@ -317,6 +387,13 @@ fn encode_deflate(deflate: &mut Compress, input: &[u8], output: &mut Vec<u8>, fl
     }
 }
 
+//进行brotli编码，压缩级别会转换为brotli的质量
+fn encode_brotli(input: &[u8], level: u32) -> Result<Vec<u8>> {
+    let mut brotli = CompressorWriter::new(Vec::with_capacity(input.len() / 2), BROTLI_BUFFER_SIZE, level.min(11), BROTLI_LG_WINDOW_SIZE);
+    brotli.write_all(input)?;
+    Ok(brotli.into_inner()) //获取输出时会结束编码
+}
+
 //进行gzip编码
 fn encode_gzip(mut gzip: GzEncoder<Vec<u8>>, input: &[u8]) -> Result<Vec<u8>> {
     if let Err(e) = gzip.write_all(input) {
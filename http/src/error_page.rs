@@ -0,0 +1,178 @@
+use std::sync::Arc;
+use std::io::{Error, Result, ErrorKind};
+
+use https::{StatusCode, header::{CONTENT_TYPE, CONTENT_LENGTH}};
+
+use hash::XHashMap;
+use tcp::driver::{Socket, AsyncIOWait};
+
+use crate::{gateway::GatewayContext,
+            middleware::{MiddlewareResult, Middleware},
+            request::HttpRequest,
+            response::HttpResponse};
+
+/*
+* 错误页模板中的占位符
+*/
+const STATUS_PLACEHOLDER: &str = "{status}";
+const REASON_PLACEHOLDER: &str = "{reason}";
+const PATH_PLACEHOLDER: &str = "{path}";
+
+/*
+* 默认错误页的Mime
+*/
+const DEFAULT_ERROR_PAGE_MIME: &str = "text/plain; charset=utf-8";
+
+/*
+* Http错误页
+*/
+pub enum ErrorPage<S: Socket, W: AsyncIOWait> {
+    Template(String, String),                               //错误页模板，包括Mime和模板内容，模板中的{status}、{reason}和{path}会被替换
+    Handler(Arc<dyn Middleware<S, W, GatewayContext>>),     //错误页处理器，处理器返回的响应未设置状态码时，会使用错误的状态码
+}
+
+impl<S: Socket, W: AsyncIOWait> Clone for ErrorPage<S, W> {
+    fn clone(&self) -> Self {
+        match self {
+            ErrorPage::Template(mime, template) => ErrorPage::Template(mime.clone(), template.clone()),
+            ErrorPage::Handler(handler) => ErrorPage::Handler(handler.clone()),
+        }
+    }
+}
+
+/*
+* Http错误页表，用于为虚拟主机定制指定状态码的错误响应
+*/
+pub struct ErrorPages<S: Socket, W: AsyncIOWait> {
+    pages:  XHashMap<u16, ErrorPage<S, W>>, //状态码和错误页表
+}
+
+unsafe impl<S: Socket, W: AsyncIOWait> Send for ErrorPages<S, W> {}
+unsafe impl<S: Socket, W: AsyncIOWait> Sync for ErrorPages<S, W> {}
+
+impl<S: Socket, W: AsyncIOWait> Clone for ErrorPages<S, W> {
+    fn clone(&self) -> Self {
+        ErrorPages {
+            pages: self.pages.clone(),
+        }
+    }
+}
+
+/*
+* Http错误页表同步方法
+*/
+impl<S: Socket, W: AsyncIOWait> ErrorPages<S, W> {
+    //构建Http错误页表
+    pub fn new() -> Self {
+        ErrorPages {
+            pages: XHashMap::default(),
+        }
+    }
+
+    //获取错误页数量
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    //获取指定状态码的错误页
+    pub fn get(&self, status: StatusCode) -> Option<&ErrorPage<S, W>> {
+        self.pages.get(&status.as_u16())
+    }
+
+    //设置指定状态码的错误页，返回上一个错误页，只允许设置客户端错误和服务器端错误的状态码
+    pub fn set(&mut self, status: StatusCode, page: ErrorPage<S, W>) -> Result<Option<ErrorPage<S, W>>> {
+        if !status.is_client_error() && !status.is_server_error() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("set error page failed, status: {:?}, reason: invalid error status", status)));
+        }
+
+        Ok(self.pages.insert(status.as_u16(), page))
+    }
+
+    //移除指定状态码的错误页
+    pub fn remove(&mut self, status: StatusCode) -> Option<ErrorPage<S, W>> {
+        self.pages.remove(&status.as_u16())
+    }
+
+    //使用指定状态码的错误页模板生成错误页，返回Mime和错误页内容，没有错误页模板时返回默认的错误页
+    pub fn render(&self, status: StatusCode, path: &str) -> (String, Vec<u8>) {
+        if let Some(ErrorPage::Template(mime, template)) = self.get(status) {
+            return (mime.clone(), render_template(template, status, path).into_bytes());
+        }
+
+        default_page(status)
+    }
+}
+
+/*
+* Http错误页表异步方法
+*/
+impl<S: Socket, W: AsyncIOWait> ErrorPages<S, W> {
+    //为指定的请求生成指定状态码的错误响应
+    pub async fn reply(&self, context: &mut GatewayContext, req: HttpRequest<S, W>, status: StatusCode) -> Result<HttpResponse<S, W>> {
+        if let Some(ErrorPage::Handler(handler)) = self.get(status) {
+            //由错误页处理器生成错误响应
+            let mut resp = match handler.request(context, req).await {
+                MiddlewareResult::Break(resp) => resp,
+                MiddlewareResult::Finish((req, resp)) => {
+                    match handler.response(context, req, resp).await {
+                        MiddlewareResult::Break(resp) => resp,
+                        MiddlewareResult::Finish((_, resp)) => resp,
+                        MiddlewareResult::Throw(e) => return Err(e),
+                        _ => return Err(Error::new(ErrorKind::Other, "invalid error page result")),
+                    }
+                },
+                MiddlewareResult::Throw(e) => return Err(e),
+                _ => return Err(Error::new(ErrorKind::Other, "invalid error page result")),
+            };
+
+            if resp.get_status() == StatusCode::default().as_u16() {
+                //错误页处理器未设置状态码，则使用错误的状态码
+                resp.status(status.as_u16());
+            }
+
+            return Ok(resp);
+        }
+
+        let (mime, body) = self.render(status, req.url().path());
+        let mut resp = HttpResponse::new(req.get_handle().clone(), req.get_waits().clone(), 1);
+        resp.status(status.as_u16());
+        resp.header(CONTENT_TYPE.as_str(), &mime);
+        resp.header(CONTENT_LENGTH.as_str(), &body.len().to_string());
+        if let Some(resp_body) = resp.as_mut_body() {
+            resp_body.init();
+            resp_body.push(&body);
+        }
+
+        Ok(resp)
+    }
+}
+
+//生成指定状态码的默认错误页，返回Mime和错误页内容
+pub fn default_page(status: StatusCode) -> (String, Vec<u8>) {
+    let body = format!("{} {}", status.as_u16(), status.canonical_reason().unwrap_or(""));
+    (DEFAULT_ERROR_PAGE_MIME.to_string(), body.into_bytes())
+}
+
+//使用指定的状态码和请求路径替换模板中的占位符，请求路径会进行Html转义
+pub fn render_template(template: &str, status: StatusCode, path: &str) -> String {
+    template.replace(STATUS_PLACEHOLDER, status.as_str())
+        .replace(REASON_PLACEHOLDER, status.canonical_reason().unwrap_or(""))
+        .replace(PATH_PLACEHOLDER, &escape_html(path))
+}
+
+//Html转义
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
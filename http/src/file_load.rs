@@ -5,8 +5,12 @@ use std::str::FromStr;
 use std::sync::Arc;
 
 use futures::future::{BoxFuture, FutureExt, MapErr};
+use httpdate::fmt_http_date;
 use https::{
-    header::{CONTENT_LENGTH, CONTENT_TYPE},
+    header::{
+        ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MATCH,
+        IF_NONE_MATCH, LAST_MODIFIED, VARY,
+    },
     StatusCode,
 };
 use log::warn;
@@ -26,10 +30,10 @@ use crate::{
     request::HttpRequest,
     response::HttpResponse,
     static_cache::{
-        is_modified, is_unmodified, request_get_cache, set_cache_resp_headers, CacheRes,
-        StaticCache,
+        check_cache_preconditions, check_preconditions, content_sign, format_etag,
+        request_get_cache, set_cache_resp_headers, CacheRes, Precondition, StaticCache,
     },
-    util::{trim_path, HttpRecvResult},
+    util::{async_files_call, precompressed_path, trim_path, HttpRecvResult},
 };
 
 /*
@@ -50,6 +54,8 @@ pub struct FileLoad {
     //设置是否允许客户端更改前端资源内容
     is_only_if_cached: bool,
     //设置是否要求代理有缓存，则只由代理向客户端提供资源
+    is_precompressed: bool,
+    //设置是否优先加载客户端接受的预压缩文件
     max_age: u64, //缓存有效时长
 }
 
@@ -70,6 +76,26 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for FileLoad {
             let mut file_path = self.root.to_path_buf();
             file_path.extend(&normalize_path(Path::new(req.url().path())));
 
+            //选择客户端接受的预压缩文件，例如foo.js.br或foo.js.gz，预压缩文件使用原文件的Mime，查找预压缩文件会在文件异步运行时中执行
+            let accept = req
+                .headers()
+                .get(ACCEPT_ENCODING)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string());
+            let mut encoding = None;
+            let mut mime_path = None;
+            if self.is_precompressed && accept.is_some() {
+                let path = file_path.clone();
+                let accept_copy = accept.clone();
+                if let Ok(Some((enc, path))) = async_files_call(&self.files_async_runtime, move || {
+                    precompressed_path(&path, accept_copy.as_ref().map(|value| value.as_str()))
+                }).await {
+                    encoding = Some(enc);
+                    mime_path = Some(file_path);
+                    file_path = path;
+                }
+            }
+
             let mut file_path_id = Atom::from("");
             if let Some(path) = file_path.to_str() {
                 file_path_id = Atom::from(path);
@@ -83,33 +109,27 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for FileLoad {
 
             //访问指定文件的内存缓存
             if let Some(cache) = &self.cache {
-                //设置了文件缓存
-                match is_unmodified(cache.as_ref(), &req, None, file_path_id.clone()) {
-                    Err(e) => {
-                        return MiddlewareResult::Throw(e);
-                    }
-                    Ok(None) => (), //忽略这个判断
-                    Ok(Some(false)) => {
-                        //验证指定文件的缓存已修改，则立即返回指定错误
+                //设置了文件缓存，则根据缓存验证条件请求，预压缩文件使用弱实体标签
+                match check_cache_preconditions(
+                    cache.as_ref(),
+                    &req,
+                    None,
+                    file_path_id.clone(),
+                    encoding.is_some(),
+                ) {
+                    (Precondition::Pass, _) => (), //验证通过，则继续
+                    (Precondition::Failed, _) => {
+                        //前置条件失败，则立即返回指定错误
                         resp.status(StatusCode::PRECONDITION_FAILED.as_u16());
                         resp.header(CONTENT_LENGTH.as_str(), "0");
                         return MiddlewareResult::Break(resp);
                     }
-                    Ok(Some(true)) => {
-                        //验证指定文件的缓存未修改，则立即返回
-                        resp.status(StatusCode::NOT_MODIFIED.as_u16());
-                        return MiddlewareResult::Break(resp);
-                    }
-                }
-
-                match is_modified(cache.as_ref(), &req, None, file_path_id.clone()) {
-                    Err(e) => {
-                        return MiddlewareResult::Throw(e);
-                    }
-                    Ok(true) => (), //验证指定文件的缓存已修改，则继续
-                    Ok(false) => {
+                    (Precondition::NotModified, etag) => {
                         //验证指定文件的缓存未修改，则立即返回
                         resp.status(StatusCode::NOT_MODIFIED.as_u16());
+                        if let Some(etag) = etag {
+                            resp.header(ETAG.as_str(), etag.as_str());
+                        }
                         return MiddlewareResult::Break(resp);
                     }
                 }
@@ -132,9 +152,14 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for FileLoad {
                                     self.is_only_if_cached,
                                     max_age,
                                     Some(last_modified),
-                                    sign,
+                                    format_etag(sign, encoding.is_some()).as_str(),
                                 );
                                 resp.header(CONTENT_TYPE.as_str(), mime.as_ref());
+                                if let Some(encoding) = encoding {
+                                    //缓存的是预压缩文件，则设置响应体编码
+                                    resp.header(CONTENT_ENCODING.as_str(), encoding);
+                                    resp.header(VARY.as_str(), ACCEPT_ENCODING.as_str());
+                                }
                                 if let Some(body) = resp.as_mut_body() {
                                     //将缓存数据写入响应体
                                     body.init();
@@ -181,10 +206,32 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for FileLoad {
                     resp.header(CONTENT_LENGTH.as_str(), "0");
                     return MiddlewareResult::Break(resp);
                 }
-                Some(path) => {
+                Some(mut path) => {
+                    if self.is_precompressed && encoding.is_none() && accept.is_some() {
+                        //请求路径为目录，则选择默认文件的预压缩文件
+                        let default_path = path.clone();
+                        let accept_copy = accept.clone();
+                        if let Ok(Some((enc, compressed_path))) = async_files_call(&self.files_async_runtime, move || {
+                            precompressed_path(&default_path, accept_copy.as_ref().map(|value| value.as_str()))
+                        }).await {
+                            if let Some(compressed_path_str) = compressed_path.to_str() {
+                                encoding = Some(enc);
+                                file_path_id = Atom::from(compressed_path_str);
+                                mime_path = Some(path);
+                                path = compressed_path;
+                            }
+                        }
+                    }
+
+                    if let Some(encoding) = encoding {
+                        //加载的是预压缩文件，则设置响应体编码
+                        resp.header(CONTENT_ENCODING.as_str(), encoding);
+                        resp.header(VARY.as_str(), ACCEPT_ENCODING.as_str());
+                    }
+
                     //文件存在，则异步加载指定文件，并根据文件扩展名，设置Mime
                     let file_mime;
-                    if let Some(mime) = mime_guess::from_path(path.as_path()).first() {
+                    if let Some(mime) = mime_guess::from_path(mime_path.as_ref().unwrap_or(&path).as_path()).first() {
                         //解析出文件的Mime
                         file_mime = mime;
                         resp.header(CONTENT_TYPE.as_str(), file_mime.as_ref());
@@ -194,8 +241,30 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for FileLoad {
                         resp.header(CONTENT_TYPE.as_str(), file_mime.as_ref());
                     }
 
+                    let last_modified = fs::metadata(&path).and_then(|meta| meta.modified()).ok();
+                    if !req.headers().contains_key(IF_MATCH)
+                        && !req.headers().contains_key(IF_NONE_MATCH)
+                    {
+                        //没有实体标签的条件，则根据文件的最近修改时间验证条件请求，否则在加载文件后验证
+                        match check_preconditions(req.headers(), req.method(), None, last_modified) {
+                            Precondition::Pass => (),
+                            Precondition::Failed => {
+                                resp.status(StatusCode::PRECONDITION_FAILED.as_u16());
+                                resp.header(CONTENT_LENGTH.as_str(), "0");
+                                return MiddlewareResult::Break(resp);
+                            }
+                            Precondition::NotModified => {
+                                resp.status(StatusCode::NOT_MODIFIED.as_u16());
+                                if let Some(last_modified) = last_modified {
+                                    resp.header(LAST_MODIFIED.as_str(), fmt_http_date(last_modified).as_str());
+                                }
+                                return MiddlewareResult::Break(resp);
+                            }
+                        }
+                    }
+
                     //缓存加载的文件名、文件的Mime和文件最近修改时间到网关上下文的参数表中
-                    if let Ok(last_modified) = meta.modified() {
+                    if let Some(last_modified) = last_modified {
                         context.set_cache_args(Some((
                             file_path_id.to_string(),
                             file_mime,
@@ -267,48 +336,85 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for FileLoad {
                 }
             }
 
-            if self.is_store {
-                //需要缓存文件
-                if let Some(body) = response.as_body() {
-                    //响应有响应体
-                    if let Some(body_bin) = body.as_slice() {
-                        //有文件内容
-                        if let Some(cache) = &self.cache {
-                            //需要缓存加载的文件
-                            if let Some((file_path, mime, last_modified)) = context.get_cache_args()
-                            {
-                                match cache.insert(
-                                    None,
-                                    self.max_age,
-                                    last_modified.clone(),
-                                    mime,
+            let file_bin = match response.as_body().and_then(|body| body.as_slice()) {
+                Some(bin) if response.get_status() == StatusCode::OK.as_u16() => {
+                    Some(Arc::new(Vec::from(bin)))
+                }
+                _ => None,
+            };
+            if let (Some(bin), Some((file_path, mime, last_modified))) =
+                (file_bin, context.get_cache_args())
+            {
+                //加载文件成功，则根据文件内容生成实体标签，预压缩文件使用弱实体标签
+                let is_weak = response.contains_header(CONTENT_ENCODING);
+                let mut sign = None;
+                if self.is_store {
+                    //需要缓存文件
+                    if let Some(cache) = &self.cache {
+                        //需要缓存加载的文件
+                        match cache.insert(
+                            None,
+                            self.max_age,
+                            last_modified.clone(),
+                            mime,
+                            true,
+                            Atom::from(file_path.clone()),
+                            bin.clone(),
+                        ) {
+                            Err(e) => {
+                                //缓存指定文件错误
+                                warn!("!!!> File Load Ok, But Cache Failed, file: {:?}, reason: {:?}", file_path, e);
+                            }
+                            Ok((cache_sign, _)) => {
+                                //缓存指定文件成功，则设置响应的缓存头
+                                set_cache_resp_headers(
+                                    &mut response,
                                     false,
-                                    Atom::from(file_path.clone()),
-                                    Arc::new(Vec::from(body_bin)),
-                                ) {
-                                    Err(e) => {
-                                        //缓存指定文件错误
-                                        warn!("!!!> File Load Ok, But Cache Failed, file: {:?}, reason: {:?}", file_path, e);
-                                    }
-                                    Ok((sign, _)) => {
-                                        //缓存指定文件成功，则设置响应的缓存头
-                                        set_cache_resp_headers(
-                                            &mut response,
-                                            false,
-                                            self.is_cache,
-                                            self.is_store,
-                                            self.is_transform,
-                                            self.is_only_if_cached,
-                                            self.max_age,
-                                            Some(last_modified),
-                                            sign,
-                                        );
-                                    }
-                                }
+                                    self.is_cache,
+                                    self.is_store,
+                                    self.is_transform,
+                                    self.is_only_if_cached,
+                                    self.max_age,
+                                    Some(last_modified),
+                                    format_etag(cache_sign, is_weak).as_str(),
+                                );
+                                sign = Some(cache_sign);
                             }
                         }
                     }
                 }
+
+                let etag = match sign {
+                    Some(sign) => format_etag(sign, is_weak),
+                    None => {
+                        //未缓存文件，则只设置验证头
+                        let etag = format_etag(content_sign(bin.as_slice()), is_weak);
+                        response
+                            .header(ETAG.as_str(), etag.as_str())
+                            .header(LAST_MODIFIED.as_str(), fmt_http_date(last_modified).as_str());
+                        etag
+                    }
+                };
+
+                //根据实体标签和最近修改时间验证条件请求
+                let status = match check_preconditions(
+                    req.headers(),
+                    req.method(),
+                    Some(etag.as_str()),
+                    Some(last_modified),
+                ) {
+                    Precondition::Pass => None,
+                    Precondition::NotModified => Some(StatusCode::NOT_MODIFIED),
+                    Precondition::Failed => Some(StatusCode::PRECONDITION_FAILED),
+                };
+                if let Some(status) = status {
+                    if let Some(body) = response.as_mut_body() {
+                        body.reset(&[]);
+                    }
+                    response
+                        .remove_header(CONTENT_LENGTH)
+                        .status(status.as_u16());
+                }
             }
 
             //继续响应处理
@@ -342,14 +448,20 @@ impl FileLoad {
                 is_store,
                 is_transform,
                 is_only_if_cached,
+                is_precompressed: true,
                 max_age: max_age as u64,
             },
         }
     }
+
+    //设置是否优先加载客户端接受的预压缩文件，默认加载
+    pub fn set_precompressed(&mut self, is_precompressed: bool) {
+        self.is_precompressed = is_precompressed;
+    }
 }
 
 //标准化路径
-fn normalize_path(path: &Path) -> PathBuf {
+pub(crate) fn normalize_path(path: &Path) -> PathBuf {
     path.components()
         .fold(PathBuf::new(), |mut result, p| match p {
             Component::Normal(x) => {
@@ -1,5 +1,6 @@
 use std::ffi::OsStr;
 use std::fs::DirEntry;
+use std::mem;
 use std::io::{Error, ErrorKind, Result};
 use std::path::{Component, Path, PathBuf};
 use std::result::Result as GenResult;
@@ -10,7 +11,7 @@ use std::sync::Arc;
 
 use futures::future::{BoxFuture, FutureExt, MapErr};
 use https::{
-    header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE},
+    header::{ACCEPT_ENCODING, CONTENT_DISPOSITION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG, VARY},
     StatusCode,
 };
 use log::warn;
@@ -18,15 +19,16 @@ use mime::APPLICATION_OCTET_STREAM;
 use path_absolutize::Absolutize;
 
 use crate::{
+    default_parser::{negotiate_encoding, GZIP_ENCODING},
     gateway::GatewayContext,
     middleware::{Middleware, MiddlewareResult},
     request::HttpRequest,
     response::HttpResponse,
     static_cache::{
-        is_modified, is_unmodified, request_get_cache, set_cache_resp_headers, CacheRes,
-        StaticCache,
+        check_cache_preconditions, format_etag, request_get_cache, set_cache_resp_headers,
+        CacheRes, Precondition, StaticCache,
     },
-    util::{trim_path, HttpRecvResult},
+    util::{async_files_call, is_precompressed_file, precompressed_files, trim_path, HttpRecvResult},
 };
 use async_file::file::{AsyncFile, AsyncFileOptions};
 use atom::Atom;
@@ -58,6 +60,8 @@ pub struct FilesLoad {
     //设置是否允许客户端更改前端资源内容
     is_only_if_cached: bool,
     //设置是否要求代理有缓存，则只由代理向客户端提供资源
+    is_precompressed: bool,
+    //设置是否优先加载客户端接受的gzip预压缩文件
     max_age: u64, //缓存有效时长
 }
 
@@ -81,48 +85,49 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for FilesLoad {
             if let Some(SGenType::Str(f)) = context.as_params().borrow().get("f") {
                 fs = f.clone();
             }
-            let files_id = Atom::from(ds.to_string() + "&" + fs.as_str());
+            let mut files_id = Atom::from(ds.to_string() + "&" + fs.as_str());
+
+            //批量文件会按顺序直接拼接，只有gzip允许将多个压缩成员拼接为一个合法的压缩流，所以只选择gzip预压缩文件
+            let encoding = if self.is_precompressed {
+                req.headers()
+                    .get(ACCEPT_ENCODING)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|accept| negotiate_encoding(accept, &[GZIP_ENCODING]))
+            } else {
+                None
+            };
+            let compressed_id = encoding.map(|enc| Atom::from(files_id.to_string() + "&" + enc));
 
             //访问指定的批量文件的内存缓存
             if let Some(cache) = &self.cache {
-                //设置了文件缓存
-                match is_unmodified(cache.as_ref(), &req, None, files_id.clone()) {
-                    Err(e) => {
-                        return MiddlewareResult::Throw(e);
-                    }
-                    Ok(None) => (), //忽略这个判断
-                    Ok(Some(false)) => {
-                        //验证指定文件的缓存已修改，则立即返回指定错误
+                //设置了文件缓存，客户端接受且已缓存预压缩的批量文件，则优先使用
+                let (cache_id, cache_encoding) = match &compressed_id {
+                    Some(id) if cache.contains(None, id.clone()) => (id.clone(), encoding),
+                    _ => (files_id.clone(), None),
+                };
+                match check_cache_preconditions(cache.as_ref(), &req, None, cache_id.clone(), cache_encoding.is_some()) {
+                    (Precondition::Pass, _) => (), //验证通过，则继续
+                    (Precondition::Failed, _) => {
+                        //前置条件失败，则立即返回指定错误
                         let mut resp =
                             HttpResponse::new(req.get_handle().clone(), req.get_waits().clone(), 1);
                         resp.status(StatusCode::PRECONDITION_FAILED.as_u16());
                         resp.header(CONTENT_LENGTH.as_str(), "0");
                         return MiddlewareResult::Break(resp);
                     }
-                    Ok(Some(true)) => {
-                        //验证指定文件的缓存未修改，则立即返回
-                        let mut resp =
-                            HttpResponse::new(req.get_handle().clone(), req.get_waits().clone(), 1);
-                        resp.status(StatusCode::NOT_MODIFIED.as_u16());
-                        return MiddlewareResult::Break(resp);
-                    }
-                }
-
-                match is_modified(cache.as_ref(), &req, None, files_id.clone()) {
-                    Err(e) => {
-                        return MiddlewareResult::Throw(e);
-                    }
-                    Ok(true) => (), //验证指定文件的缓存已修改，则继续
-                    Ok(false) => {
+                    (Precondition::NotModified, etag) => {
                         //验证指定文件的缓存未修改，则立即返回
                         let mut resp =
                             HttpResponse::new(req.get_handle().clone(), req.get_waits().clone(), 1);
                         resp.status(StatusCode::NOT_MODIFIED.as_u16());
+                        if let Some(etag) = etag {
+                            resp.header(ETAG.as_str(), etag.as_str());
+                        }
                         return MiddlewareResult::Break(resp);
                     }
                 }
 
-                match request_get_cache(cache.as_ref(), &req, None, files_id.clone()) {
+                match request_get_cache(cache.as_ref(), &req, None, cache_id) {
                     Err(e) => {
                         //获取指定文件的缓存错误，则立即抛出错误
                         return MiddlewareResult::Throw(e);
@@ -145,13 +150,18 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for FilesLoad {
                                     self.is_only_if_cached,
                                     max_age,
                                     None,
-                                    sign,
+                                    format_etag(sign, cache_encoding.is_some()).as_str(),
                                 );
                                 resp.header(
                                     CONTENT_DISPOSITION.as_str(),
                                     DEFAULT_CONTENT_DISPOSITION,
                                 );
                                 resp.header(CONTENT_TYPE.as_str(), mime.as_ref());
+                                if let Some(encoding) = cache_encoding {
+                                    //缓存的是预压缩的批量文件，则设置响应体编码
+                                    resp.header(CONTENT_ENCODING.as_str(), encoding);
+                                    resp.header(VARY.as_str(), ACCEPT_ENCODING.as_str());
+                                }
                                 if let Some(body) = resp.as_mut_body() {
                                     //将缓存数据写入响应体
                                     body.init();
@@ -217,13 +227,40 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for FilesLoad {
                 }
             }
 
-            //合并解析的所有文件，并根据文件数量构建Http响应
+            //合并解析的所有文件
             dir_vec.append(&mut file_vec);
-            let resp = HttpResponse::new(
+
+            //所有文件都有gzip预压缩文件，则加载预压缩文件，查找预压缩文件会在文件异步运行时中执行
+            let mut is_compressed = false;
+            if let (Some(encoding), Some(compressed_id)) = (encoding, compressed_id) {
+                let files = mem::replace(&mut dir_vec, Vec::new());
+                match async_files_call(&self.files_async_runtime, move || precompressed_files(files, encoding, true)).await {
+                    Err(e) => {
+                        //查找预压缩文件错误，则立即中止请求处理，并返回响应
+                        return MiddlewareResult::Throw(e);
+                    }
+                    Ok((files, is_all)) => {
+                        dir_vec = files;
+                        if is_all {
+                            //已替换为预压缩文件，则使用预压缩的批量文件的缓存id
+                            is_compressed = true;
+                            files_id = compressed_id;
+                        }
+                    }
+                }
+            }
+
+            //根据文件数量构建Http响应
+            let mut resp = HttpResponse::new(
                 req.get_handle().clone(),
                 req.get_waits().clone(),
                 dir_vec.len(),
             );
+            if is_compressed {
+                //加载的是预压缩文件，则设置响应体编码
+                resp.header(CONTENT_ENCODING.as_str(), GZIP_ENCODING);
+                resp.header(VARY.as_str(), ACCEPT_ENCODING.as_str());
+            }
 
             //异步加载所有文件
             match async_load_files(self.files_async_runtime.clone(), &resp, dir_vec) {
@@ -351,7 +388,8 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for FilesLoad {
                                         warn!("!!!> Files Load Ok, But Cache Failed, file: {:?}, reason: {:?}", files_id, e);
                                     }
                                     Ok((sign, _)) => {
-                                        //缓存指定的批量文件成功，则设置响应的缓存头
+                                        //缓存指定的批量文件成功，则设置响应的缓存头，预压缩的批量文件使用弱实体标签
+                                        let is_weak = response.contains_header(CONTENT_ENCODING);
                                         set_cache_resp_headers(
                                             &mut response,
                                             false,
@@ -361,7 +399,7 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for FilesLoad {
                                             self.is_only_if_cached,
                                             self.max_age,
                                             None,
-                                            sign,
+                                            format_etag(sign, is_weak).as_str(),
                                         );
                                     }
                                 }
@@ -402,10 +440,16 @@ impl FilesLoad {
                 is_store,
                 is_transform,
                 is_only_if_cached,
+                is_precompressed: true,
                 max_age: max_age as u64,
             },
         }
     }
+
+    //设置是否优先加载客户端接受的gzip预压缩文件，默认加载，只有所有文件都有预压缩文件时才会加载
+    pub fn set_precompressed(&mut self, is_precompressed: bool) {
+        self.is_precompressed = is_precompressed;
+    }
 }
 
 //解析后缀，失败返回在解析哪个字符时出错
@@ -741,7 +785,11 @@ fn filter_file(suffix: Option<&OsStr>, entry: DirEntry, result: &mut Vec<(u64, P
             _ => (), //后缀名不同，则忽略
         }
     } else {
-        //不过滤
+        //不过滤，但忽略已存在原文件的预压缩文件，预压缩文件只会在客户端接受时整体替换原文件
+        if is_precompressed_file(&entry.path()) {
+            return;
+        }
+
         if let Ok(meta) = entry.metadata() {
             result.push((meta.len(), entry.path()));
         } else {
@@ -1,20 +1,26 @@
 use std::sync::Arc;
 use std::cell::RefCell;
-use std::time::SystemTime;
+use std::time::{Instant, SystemTime};
 use std::result::Result as GenResult;
 use std::io::{Error, Result, ErrorKind};
 
 use mime::Mime;
 use bytes::BufMut;
 use futures::future::{FutureExt, BoxFuture};
+use https::{StatusCode, header::ALLOW};
 
 use hash::XHashMap;
+use atom::Atom;
 use handler::SGenType;
 use tcp::driver::{Socket, AsyncIOWait};
 
 use crate::{service::{HttpService, ServiceFactory},
             route::{RouterTab, HttpRoute},
             middleware::{MiddlewareResult, Middleware},
+            error_page::ErrorPages,
+            multi_parts::SpooledFile,
+            auth::Principal,
+            session::Session,
             request::HttpRequest,
             response::HttpResponse};
 
@@ -30,6 +36,13 @@ pub struct GatewayContext {
     files_len:  usize,                                      //Http批量加载文件数量
     attrs:      XHashMap<String, SGenType>,                 //Http连接属性表
     part_buf:   Option<Vec<u8>>,                            //Http连接的请求体未解析部分缓冲
+    files:      XHashMap<String, Arc<SpooledFile>>,         //Http连接的请求体中已写入临时文件的文件部分
+    principal:  Option<Principal>,                          //当前Http请求的已认证主体
+    cookies:    Option<XHashMap<String, String>>,           //当前Http请求的Cookie表，未解析则为空
+    session:    Option<Session>,                            //当前Http请求的会话
+    gray:       Option<usize>,                              //当前Http请求选择的灰度，为空则使用Http端口中间件的灰度
+    start_time: Option<Instant>,                            //当前Http请求的开始处理时间
+    route:      Option<Atom>,                               //当前Http请求匹配的路由，为空表示未匹配路由
 }
 
 unsafe impl Send for GatewayContext {}
@@ -46,6 +59,13 @@ impl GatewayContext {
             files_len: 0,
             attrs: XHashMap::default(),
             part_buf: None,
+            files: XHashMap::default(),
+            principal: None,
+            cookies: None,
+            session: None,
+            gray: None,
+            start_time: None,
+            route: None,
         }
     }
 
@@ -59,6 +79,11 @@ impl GatewayContext {
         self.params.borrow_mut().clear();
     }
 
+    //为当前请求使用新的请求参数表，不影响其它请求持有的请求参数表
+    pub fn reset_params(&mut self) {
+        self.params = Arc::new(RefCell::new(XHashMap::default()));
+    }
+
     //获取请求体已解析部分的只读引用
     pub fn as_parts(&self) -> &XHashMap<String, SGenType> {
         &self.parts
@@ -74,6 +99,81 @@ impl GatewayContext {
         self.parts.clear();
     }
 
+    //获取请求体中指定表单参数名的临时文件
+    pub fn get_file(&self, name: &str) -> Option<&Arc<SpooledFile>> {
+        self.files.get(name)
+    }
+
+    //写入请求体中的临时文件，返回同名的上一个临时文件
+    pub fn insert_file(&mut self, file: SpooledFile) -> Option<Arc<SpooledFile>> {
+        self.files.insert(file.name().to_string(), Arc::new(file))
+    }
+
+    //移除请求体中指定表单参数名的临时文件
+    pub fn take_file(&mut self, name: &str) -> Option<Arc<SpooledFile>> {
+        self.files.remove(name)
+    }
+
+    //清空请求体中的临时文件，未持久化的临时文件会被移除
+    pub fn clear_files(&mut self) {
+        self.files.clear();
+    }
+
+    //获取当前Http请求的已认证主体
+    pub fn get_principal(&self) -> Option<&Principal> {
+        self.principal.as_ref()
+    }
+
+    //设置当前Http请求的已认证主体
+    pub fn set_principal(&mut self, principal: Option<Principal>) {
+        self.principal = principal;
+    }
+
+    //获取当前Http请求的Cookie表，未解析则返回空
+    pub fn as_cookies(&self) -> Option<&XHashMap<String, String>> {
+        self.cookies.as_ref()
+    }
+
+    //获取当前Http请求的指定Cookie值
+    pub fn get_cookie(&self, name: &str) -> Option<&String> {
+        self.cookies.as_ref().and_then(|cookies| cookies.get(name))
+    }
+
+    //设置当前Http请求的Cookie表
+    pub fn set_cookies(&mut self, cookies: Option<XHashMap<String, String>>) {
+        self.cookies = cookies;
+    }
+
+    //获取当前Http请求的会话的只读引用
+    pub fn get_session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    //获取当前Http请求的会话的可写引用
+    pub fn get_session_mut(&mut self) -> Option<&mut Session> {
+        self.session.as_mut()
+    }
+
+    //设置当前Http请求的会话
+    pub fn set_session(&mut self, session: Option<Session>) {
+        self.session = session;
+    }
+
+    //移除当前Http请求的会话
+    pub fn take_session(&mut self) -> Option<Session> {
+        self.session.take()
+    }
+
+    //获取当前Http请求选择的灰度
+    pub fn get_gray(&self) -> Option<usize> {
+        self.gray
+    }
+
+    //设置当前Http请求选择的灰度
+    pub fn set_gray(&mut self, gray: Option<usize>) {
+        self.gray = gray;
+    }
+
     //获取Http请求缓存参数
     pub fn get_cache_args(&self) -> Option<(String, Mime, SystemTime)> {
         if let Some((file_path, mime, last_modified)) = &self.cache_args {
@@ -88,6 +188,26 @@ impl GatewayContext {
         self.cache_args = args;
     }
 
+    //获取当前Http请求的开始处理时间
+    pub fn get_start_time(&self) -> Option<Instant> {
+        self.start_time
+    }
+
+    //设置当前Http请求的开始处理时间
+    pub fn set_start_time(&mut self, time: Option<Instant>) {
+        self.start_time = time;
+    }
+
+    //获取当前Http请求匹配的路由，例如/user/:id
+    pub fn get_route(&self) -> Option<&str> {
+        self.route.as_ref().map(|route| route.as_ref())
+    }
+
+    //设置当前Http请求匹配的路由
+    pub fn set_route(&mut self, route: Option<Atom>) {
+        self.route = route;
+    }
+
     //获取Http批量加载文件大小
     pub fn get_files_size(&self) -> u64 {
         self.files_size
@@ -152,8 +272,9 @@ impl GatewayContext {
 * Http网关，每个Http连接和一个Http网关绑定
 */
 pub struct HttpGateway<S: Socket, W: AsyncIOWait, H: Middleware<S, W, GatewayContext>> {
-    context:    GatewayContext,                     //上下文
-    router_tab: RouterTab<S, W, GatewayContext, H>, //路由器表
+    context:        GatewayContext,                     //上下文
+    router_tab:     RouterTab<S, W, GatewayContext, H>, //路由器表
+    error_pages:    Arc<ErrorPages<S, W>>,              //错误页表
 }
 
 unsafe impl<S: Socket, W: AsyncIOWait, H: Middleware<S, W, GatewayContext>> Send for HttpGateway<S, W, H> {}
@@ -164,14 +285,27 @@ impl<S: Socket, W: AsyncIOWait, H: Middleware<S, W, GatewayContext>> HttpService
     type Future = BoxFuture<'static, GenResult<HttpResponse<S, W>, Self::Error>>;
 
     fn call(&mut self, req: HttpRequest<S, W>) -> Self::Future {
-        let middleware = self.router_tab.match_route(req.method(), req.url().path());
+        let middleware = self.router_tab.match_route_pattern(req.method(), req.url().path());
+        let allow = if middleware.is_none() {
+            //路由失败，则获取匹配请求路径的其它方法
+            self.router_tab.allow_methods(req.url().path())
+        } else {
+            Vec::new()
+        };
+        let error_pages = self.error_pages.clone();
         let mut context = self.context.clone();
+        context.set_start_time(Some(Instant::now())); //每次请求处理前，记录请求的开始处理时间
 
         let future = async move {
-            if let Some(ware) = middleware {
+            if let Some((ware, params, route)) = middleware {
                 //路由到指定方法和路径的Http请求处理器
-                context.clear_params(); //每次请求处理前，清空网关上下文内的请求参数表
+                context.reset_params(); //每次请求处理前，使用新的请求参数表，同一网关上并发处理的请求不共享请求参数表
+                for (key, value) in params {
+                    //将路由中命名参数捕获的参数写入请求参数表
+                    context.as_params().borrow_mut().insert(key, SGenType::Str(value));
+                }
                 context.set_cache_args(None); //每次请求处理前，重置网关上下文内的缓存参数
+                context.set_route(Some(route));
                 match ware.request(&mut context, req).await {
                     MiddlewareResult::Break(resp) => {
                         //中止请求处理，则立即返回响应
@@ -208,8 +342,23 @@ impl<S: Socket, W: AsyncIOWait, H: Middleware<S, W, GatewayContext>> HttpService
                     },
                 }
             } else {
-                //路由错误，则立即返回错误原因
-                Err(Error::new(ErrorKind::Other, "invalid route"))
+                //路由错误，则回应错误页
+                context.reset_params();
+                context.set_cache_args(None);
+                context.set_route(None);
+                if allow.is_empty() {
+                    //没有匹配请求路径的路由
+                    error_pages.reply(&mut context, req, StatusCode::NOT_FOUND).await
+                } else {
+                    //有匹配请求路径的路由，但请求方法不匹配
+                    match error_pages.reply(&mut context, req, StatusCode::METHOD_NOT_ALLOWED).await {
+                        Err(e) => Err(e),
+                        Ok(mut resp) => {
+                            resp.header(ALLOW.as_str(), &allow.join(", "));
+                            Ok(resp)
+                        },
+                    }
+                }
             }
         };
         future.boxed()
@@ -219,9 +368,15 @@ impl<S: Socket, W: AsyncIOWait, H: Middleware<S, W, GatewayContext>> HttpService
 impl<S: Socket, W: AsyncIOWait, H: Middleware<S, W, GatewayContext>> HttpGateway<S, W, H> {
     //创建指定路由器表的Http路由服务
     pub fn with(tab: RouterTab<S, W, GatewayContext, H>) -> Self {
+        Self::with_error_pages(tab, Arc::new(ErrorPages::new()))
+    }
+
+    //创建指定路由器表和错误页表的Http路由服务
+    pub fn with_error_pages(tab: RouterTab<S, W, GatewayContext, H>, error_pages: Arc<ErrorPages<S, W>>) -> Self {
         HttpGateway {
             context: GatewayContext::new(),
             router_tab: tab,
+            error_pages,
         }
     }
 }
\ No newline at end of file
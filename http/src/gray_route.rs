@@ -0,0 +1,319 @@
+use std::sync::Arc;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use https::{HeaderMap, header::SET_COOKIE};
+use futures::future::{FutureExt, BoxFuture};
+use parking_lot::RwLock;
+use ring::hmac;
+
+use hash::XHashMap;
+use tcp::driver::{Socket, AsyncIOWait};
+
+use crate::{gateway::GatewayContext,
+            middleware::{MiddlewareResult, Middleware},
+            cookie::{SameSite, SetCookie, load_request_cookies},
+            request::HttpRequest,
+            response::HttpResponse,
+            util::{IpRange, client_ip}};
+
+/*
+* 默认的灰度粘滞Cookie有效时长，单位秒
+*/
+pub const DEFAULT_GRAY_STICKY_MAX_AGE: u64 = 86400;
+
+/*
+* 灰度百分比的最大值
+*/
+pub const MAX_GRAY_PERCENT: u32 = 100;
+
+/*
+* 灰度用户标识
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum GrayUserKey {
+    Header(String), //指定请求头的值
+    Cookie(String), //指定Cookie的值
+    Principal,      //网关上下文中的已认证主体名
+}
+
+/*
+* 灰度规则的匹配条件
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum GrayMatcher {
+    Header(String, String),     //指定请求头等于指定值
+    Cookie(String, String),     //指定Cookie等于指定值
+    Percent(GrayUserKey, u32),  //用户标识的哈希值落在指定百分比内，没有用户标识则不匹配
+    IpRange(Vec<IpRange>),      //客户端地址在任意一个地址段内
+}
+
+/*
+* 灰度规则
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrayRule {
+    matcher:    GrayMatcher,    //匹配条件
+    gray:       usize,          //匹配后选择的灰度
+}
+
+impl GrayRule {
+    //构建灰度规则，百分比超过100时按100处理
+    pub fn new(matcher: GrayMatcher, gray: usize) -> Self {
+        let matcher = match matcher {
+            GrayMatcher::Percent(key, percent) => GrayMatcher::Percent(key, percent.min(MAX_GRAY_PERCENT)),
+            matcher => matcher,
+        };
+
+        GrayRule {
+            matcher,
+            gray,
+        }
+    }
+
+    //获取匹配条件
+    pub fn matcher(&self) -> &GrayMatcher {
+        &self.matcher
+    }
+
+    //获取匹配后选择的灰度
+    pub fn gray(&self) -> usize {
+        self.gray
+    }
+}
+
+/*
+* 灰度规则表，按顺序匹配，第一个匹配的规则决定灰度，没有匹配的规则则使用默认灰度
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrayRules {
+    rules:      Vec<GrayRule>,  //灰度规则列表
+    default:    Option<usize>,  //默认灰度，为空则使用Http端口中间件的灰度
+}
+
+impl GrayRules {
+    //构建灰度规则表
+    pub fn new(default: Option<usize>) -> Self {
+        GrayRules {
+            rules: Vec::new(),
+            default,
+        }
+    }
+
+    //增加灰度规则
+    pub fn add_rule(&mut self, rule: GrayRule) {
+        self.rules.push(rule);
+    }
+
+    //获取灰度规则列表
+    pub fn rules(&self) -> &[GrayRule] {
+        self.rules.as_slice()
+    }
+
+    //获取默认灰度
+    pub fn default_gray(&self) -> Option<usize> {
+        self.default
+    }
+
+    //判断指定灰度是否是规则表可以选择的灰度
+    pub fn is_valid(&self, gray: usize) -> bool {
+        self.default == Some(gray) || self.rules.iter().any(|rule| rule.gray == gray)
+    }
+
+    //根据请求头、Cookie、已认证主体名和客户端地址选择灰度
+    pub fn select(&self,
+                  headers: &HeaderMap,
+                  cookies: Option<&XHashMap<String, String>>,
+                  principal: Option<&str>,
+                  ip: &IpAddr) -> Option<usize> {
+        for rule in &self.rules {
+            let is_match = match &rule.matcher {
+                GrayMatcher::Header(name, value) => {
+                    headers.get_all(name.as_str()).iter().any(|v| v.as_bytes() == value.as_bytes())
+                },
+                GrayMatcher::Cookie(name, value) => {
+                    cookies.and_then(|cookies| cookies.get(name)) == Some(value)
+                },
+                GrayMatcher::Percent(key, percent) => {
+                    let user = match key {
+                        GrayUserKey::Header(name) => headers.get(name.as_str()).and_then(|value| value.to_str().ok()),
+                        GrayUserKey::Cookie(name) => cookies.and_then(|cookies| cookies.get(name)).map(|value| value.as_str()),
+                        GrayUserKey::Principal => principal,
+                    };
+
+                    match user {
+                        Some(user) if !user.is_empty() => gray_bucket(user) < *percent,
+                        _ => false,
+                    }
+                },
+                GrayMatcher::IpRange(ranges) => {
+                    ranges.iter().any(|range| range.contains(ip))
+                },
+            };
+
+            if is_match {
+                return Some(rule.gray);
+            }
+        }
+
+        self.default
+    }
+}
+
+/*
+* 灰度路由中间件，为每个请求选择灰度，并通过网关上下文传递给Http端口中间件，规则表可以在运行时替换
+*/
+pub struct GrayRouter {
+    rules:              RwLock<Arc<GrayRules>>,         //灰度规则表
+    sticky_cookie:      Option<(String, hmac::Key)>,    //灰度粘滞Cookie名和签名密钥，为空则不粘滞
+    sticky_max_age:     u64,                            //灰度粘滞Cookie有效时长，单位秒
+    trusted_proxies:    Vec<IpRange>,                   //可信代理地址段列表
+}
+
+unsafe impl Send for GrayRouter {}
+unsafe impl Sync for GrayRouter {}
+
+impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for GrayRouter {
+    fn request<'a>(&'a self, context: &'a mut GatewayContext, req: HttpRequest<S, W>)
+                   -> BoxFuture<'a, MiddlewareResult<S, W>> {
+        let future = async move {
+            load_request_cookies(context, &req);
+            let rules = self.get_rules();
+
+            //粘滞的灰度签名有效、未过期，且仍然可以被当前规则表选择时，优先使用粘滞的灰度
+            let sticky = self.sticky_cookie
+                .as_ref()
+                .and_then(|(name, _)| context.get_cookie(name.as_str()))
+                .and_then(|value| self.verify_sticky(value.as_str()))
+                .filter(|gray| rules.is_valid(*gray));
+
+            let gray = match sticky {
+                Some(gray) => Some(gray),
+                None => {
+                    let ip = client_ip(req.headers(), req.get_handle().get_remote().ip(), &self.trusted_proxies);
+                    rules.select(req.headers(),
+                                 context.as_cookies(),
+                                 context.get_principal().map(|principal| principal.name()),
+                                 &ip)
+                },
+            };
+
+            context.set_gray(gray);
+            MiddlewareResult::ContinueRequest(req)
+        };
+        future.boxed()
+    }
+
+    fn response<'a>(&'a self, context: &'a mut GatewayContext, req: HttpRequest<S, W>, resp: HttpResponse<S, W>)
+                    -> BoxFuture<'a, MiddlewareResult<S, W>> {
+        let mut response = resp;
+        let future = async move {
+            if let Some((name, _)) = &self.sticky_cookie {
+                let current = context.get_cookie(name.as_str()).cloned();
+                let cookie = match context.get_gray() {
+                    Some(gray) if current.as_ref().and_then(|value| self.verify_sticky(value.as_str())) != Some(gray) => {
+                        //选择的灰度与粘滞的灰度不同，或粘滞Cookie无效，则更新粘滞Cookie
+                        self.sign_sticky(gray).and_then(|value| SetCookie::new(name.as_str(), value.as_str()).ok()).map(|mut cookie| {
+                            cookie.max_age(self.sticky_max_age);
+                            cookie
+                        })
+                    },
+                    None if current.is_some() => {
+                        //没有选择灰度，则移除粘滞Cookie
+                        SetCookie::removal(name.as_str()).ok()
+                    },
+                    _ => None,
+                };
+
+                if let Some(mut cookie) = cookie {
+                    cookie.path("/").http_only(true).same_site(SameSite::Lax);
+                    response.header(SET_COOKIE.as_str(), cookie.to_string().as_str());
+                }
+            }
+
+            MiddlewareResult::ContinueResponse((req, response))
+        };
+        future.boxed()
+    }
+}
+
+impl GrayRouter {
+    //构建灰度路由中间件
+    pub fn new(rules: GrayRules) -> Self {
+        GrayRouter {
+            rules: RwLock::new(Arc::new(rules)),
+            sticky_cookie: None,
+            sticky_max_age: DEFAULT_GRAY_STICKY_MAX_AGE,
+            trusted_proxies: Vec::new(),
+        }
+    }
+
+    //设置灰度粘滞Cookie名、签名密钥和有效时长，名称为空则不粘滞
+    pub fn set_sticky(&mut self, cookie: Option<String>, secret: &[u8], max_age: u64) {
+        self.sticky_cookie = cookie.map(|name| (name, hmac::Key::new(hmac::HMAC_SHA256, secret)));
+        self.sticky_max_age = max_age;
+    }
+
+    //生成指定灰度的粘滞Cookie值，格式为"灰度.过期时间.签名"，使用Hmac-Sha256签名，未设置粘滞则返回空
+    pub fn sign_sticky(&self, gray: usize) -> Option<String> {
+        let (_, key) = self.sticky_cookie.as_ref()?;
+        let payload = format!("{}.{}", gray, now_secs() + self.sticky_max_age);
+        let sign = base64::encode_config(hmac::sign(key, payload.as_bytes()).as_ref(), base64::URL_SAFE_NO_PAD);
+        Some(payload + "." + sign.as_str())
+    }
+
+    //验证粘滞Cookie值，返回粘滞的灰度，签名无效或已过期则返回空
+    pub fn verify_sticky(&self, value: &str) -> Option<usize> {
+        let (_, key) = self.sticky_cookie.as_ref()?;
+        let index = value.rfind('.')?;
+        let sign = base64::decode_config(&value[index + 1..], base64::URL_SAFE_NO_PAD).ok()?;
+        hmac::verify(key, value[..index].as_bytes(), sign.as_slice()).ok()?;
+
+        let mut parts = value[..index].splitn(2, '.');
+        let gray = parts.next()?.parse::<usize>().ok()?;
+        let expire = parts.next()?.parse::<u64>().ok()?;
+        if expire <= now_secs() {
+            //粘滞Cookie已过期
+            return None;
+        }
+
+        Some(gray)
+    }
+
+    //设置可信代理地址段列表
+    pub fn set_trusted_proxies(&mut self, trusted_proxies: Vec<IpRange>) {
+        self.trusted_proxies = trusted_proxies;
+    }
+
+    //获取当前的灰度规则表
+    pub fn get_rules(&self) -> Arc<GrayRules> {
+        self.rules.read().clone()
+    }
+
+    //替换灰度规则表，返回上一个灰度规则表，正在处理的请求继续使用上一个灰度规则表
+    pub fn set_rules(&self, rules: GrayRules) -> Arc<GrayRules> {
+        let mut current = self.rules.write();
+        let last = current.clone();
+        *current = Arc::new(rules);
+        last
+    }
+}
+
+/*
+* 获取用户标识的灰度桶，范围为[0, 100)，同一用户标识在不同进程中的灰度桶相同
+*/
+pub fn gray_bucket(user: &str) -> u32 {
+    //Fnv-1a哈希
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in user.as_bytes() {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    (hash % MAX_GRAY_PERCENT as u64) as u32
+}
+
+//获取当前系统时间，单位秒
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
@@ -0,0 +1,1253 @@
+use std::mem;
+use std::future::Future;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use std::collections::{BTreeMap, VecDeque};
+use std::result::Result as GenResult;
+use std::io::{Error, Result, ErrorKind};
+
+use bytes::BufMut;
+use futures::future::{FutureExt, BoxFuture, poll_fn};
+use hpack::{Decoder, Encoder};
+use https::{Version, StatusCode,
+            header::{HeaderName, HeaderValue, HeaderMap, HOST, COOKIE, LOCATION, CONNECTION, CONTENT_LENGTH, TRANSFER_ENCODING, UPGRADE, TE}};
+use log::warn;
+
+use hash::XHashMap;
+use tcp::{driver::{Socket, AsyncIOWait, SocketHandle, AsyncReadTask, PendSocket},
+          util::SocketEvent};
+
+use crate::{virtual_host::VirtualHostPool,
+            service::{ServiceFactory, HttpService},
+            server::HttpLimits,
+            request::HttpRequest,
+            response::{HttpResponse, RespBody},
+            util::HttpRecvResult,
+            h2_frame::{HTTP2_PREFACE, HTTP2_CLEARTEXT_UPGRADE, HTTP2_SETTINGS_HEADER, FRAME_HEAD_LEN, DEFAULT_INITIAL_WINDOW_SIZE, MAX_WINDOW_SIZE,
+                       FLAG_END_STREAM, FLAG_ACK, FLAG_END_HEADERS, FLAG_PRIORITY,
+                       FrameType, FrameHead, Http2ErrorCode, Http2Settings,
+                       settings_frame, settings_ack_frame, ping_ack_frame, window_update_frame, rst_stream_frame, goaway_frame, data_frame, headers_frames,
+                       strip_padding, into_error, read_u32}};
+
+/*
+* Http2连接允许的最大并发流数量
+*/
+const DEFAULT_MAX_CONCURRENT_STREAMS: u32 = 100;
+
+/*
+* Http2连接在重置统计时长内允许对端重置的最大流数量，超过则关闭连接
+*/
+const MAX_RESET_STREAMS: u32 = 200;
+
+/*
+* Http2连接的重置统计时长，单位毫秒
+*/
+const RESET_STREAMS_INTERVAL: u64 = 30000;
+
+/*
+* Http2连接的最小接收流控窗口大小，1MB
+*/
+const DEFAULT_RECV_WINDOW_SIZE: u32 = 1024 * 1024;
+
+/*
+* Http2连接在未限制请求体时使用的请求体长度限制，同时也是接收流控窗口大小，16MB
+*/
+const DEFAULT_MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/*
+* Http2连接缓存的主机服务的最大数量，超过后的主机的请求使用不缓存的服务
+*/
+const MAX_CACHED_SERVICES: usize = 16;
+
+/*
+* Http2连接允许的最大头块长度，256KB
+*/
+const MAX_HEADER_BLOCK_LIMIT: usize = 256 * 1024;
+
+/*
+* Http2连接发送缓冲的刷新长度，64KB
+*/
+const FLUSH_OUT_BUFFER_LEN: usize = 64 * 1024;
+
+/*
+* Http2中不允许使用的连接相关头
+*/
+const KEEP_ALIVE_HEADER: &str = "keep-alive";
+const PROXY_CONNECTION_HEADER: &str = "proxy-connection";
+
+/*
+* Http2中允许的TE头的值
+*/
+const TE_TRAILERS: &str = "trailers";
+
+/*
+* Http2明文升级的响应
+*/
+const SWITCHING_PROTOCOLS_RESPONSE: &[u8] = b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n";
+
+/*
+* Http2连接错误
+*/
+enum Http2Error {
+    Connect(Http2ErrorCode, String),    //连接错误，会关闭连接
+    Stream(u32, Http2ErrorCode),        //流错误，只会重置流
+    Io(Error),                          //Io错误，会立即关闭连接
+}
+
+impl From<Error> for Http2Error {
+    fn from(e: Error) -> Self {
+        Http2Error::Io(e)
+    }
+}
+
+/*
+* Http2流的异步任务结果
+*/
+enum Http2Task<S: Socket, W: AsyncIOWait> {
+    Response(GenResult<HttpResponse<S, W>, Error>),                 //服务已返回响应
+    Body(RespBody<S, W>, HttpRecvResult<Vec<Vec<u8>>>),             //流式响应体已生成后续块
+}
+
+/*
+* Http2连接的事件
+*/
+enum Http2Event<S: Socket, W: AsyncIOWait> {
+    Readed(Result<()>),         //已接收后续数据
+    Task(u32, Http2Task<S, W>), //指定流的异步任务已完成
+}
+
+/*
+* Http2流
+*/
+struct Http2Stream {
+    method:         String,     //请求方法
+    url:            String,     //请求的Url
+    authority:      String,     //请求的主机
+    headers:        HeaderMap,  //请求头
+    body:           Vec<u8>,    //请求体
+    recv_len:       u32,        //已接收但未被消费的流控长度
+    send_window:    i64,        //发送流控窗口
+    is_end:         bool,       //对端是否已结束发送
+    data:           VecDeque<Vec<u8>>,  //待发送的响应体块
+    is_fin:         bool,       //响应体是否已全部生成
+}
+
+/*
+* Http2连接，连接上的多个流会复用同一个Tcp连接，已接收完成的请求会交给虚拟主机的服务并发处理，处理期间会继续接收和处理连接上的帧
+*/
+pub struct Http2Connect<S: Socket, W: AsyncIOWait, P: VirtualHostPool<S, W>> {
+    handle:         SocketHandle<S>,                                                                            //Http2连接的Tcp连接句柄
+    waits:          W,                                                                                          //异步任务等待队列
+    hosts:          P,                                                                                          //虚拟主机池
+    services:       XHashMap<String, <<P as VirtualHostPool<S, W>>::Host as ServiceFactory<S, W>>::Service>,   //主机名和服务表
+    keep_alive:     usize,                                                                                      //连接保持时长
+    limits:         HttpLimits,                                                                                 //Http请求限制
+    decoder:        Decoder<'static>,                                                                           //头解压器
+    encoder:        Encoder<'static>,                                                                           //头压缩器
+    local:          Http2Settings,                                                                              //本端设置
+    remote:         Http2Settings,                                                                              //对端设置
+    buf:            Vec<u8>,                                                                                    //未解析的接收数据
+    is_preface:     bool,                                                                                       //是否已收到连接序言
+    is_settings:    bool,                                                                                       //是否已收到对端的首个设置帧
+    block:          Option<(u32, bool, Vec<u8>)>,                                                               //未完成的头块，包括流id，是否结束流和头块片段
+    streams:        BTreeMap<u32, Http2Stream>,                                                                 //活动流表
+    ready:          VecDeque<u32>,                                                                              //已接收完成，等待处理的流
+    upgrade:        Option<HttpRequest<S, W>>,                                                                  //明文升级时的首个请求
+    tasks:          XHashMap<u32, BoxFuture<'static, Http2Task<S, W>>>,                                         //正在处理的流的异步任务，流被移除时会丢弃任务
+    resets:         (u32, Instant),                                                                             //统计时长内对端重置的流数量和统计开始时间
+    bodies:         XHashMap<u32, RespBody<S, W>>,                                                              //等待已生成的块发送完成后，继续生成的流式响应体
+    recv_window:    i64,                                                                                        //连接的接收流控窗口
+    send_window:    i64,                                                                                        //连接的发送流控窗口
+    last_stream_id: u32,                                                                                        //最后接收的流id
+    is_goaway:      bool,                                                                                       //对端是否已关闭连接
+    out:            Vec<Vec<u8>>,                                                                               //待发送的帧
+}
+
+unsafe impl<S: Socket, W: AsyncIOWait, P: VirtualHostPool<S, W>> Send for Http2Connect<S, W, P> {}
+unsafe impl<S: Socket, W: AsyncIOWait, P: VirtualHostPool<S, W>> Sync for Http2Connect<S, W, P> {}
+
+/*
+* Http2连接同步方法
+*/
+impl<S: Socket, W: AsyncIOWait, P: VirtualHostPool<S, W>> Http2Connect<S, W, P> {
+    //构建指定Tcp连接句柄、异步任务等待队列、虚拟主机池、连接保持时长和Http请求限制的Http2连接，请求头的限制会通过设置通知对端
+    pub fn new(handle: SocketHandle<S>, waits: W, hosts: P, keep_alive: usize, mut limits: HttpLimits) -> Self {
+        let mut local = Http2Settings::default();
+        local.enable_push = false;
+        local.max_concurrent_streams = Some(DEFAULT_MAX_CONCURRENT_STREAMS);
+        local.max_header_list_size = Some(limits.max_header_bytes as u32);
+
+        //请求体被消费后才会归还接收流控窗口，所以接收流控窗口需要可以容纳允许的最大请求体，同时也限制了连接上缓冲的请求体总长度
+        //未限制请求体时，使用默认的请求体限制，避免接收流控窗口过大
+        let limit = *limits.max_body.get_or_insert(DEFAULT_MAX_BODY_SIZE);
+        let recv_window = (limit as u64).max(DEFAULT_RECV_WINDOW_SIZE as u64).min(MAX_WINDOW_SIZE as u64) as u32;
+        local.initial_window_size = recv_window;
+
+        //首先发送本端设置，并扩大连接的接收流控窗口
+        let out = vec![settings_frame(&local), window_update_frame(0, recv_window - DEFAULT_INITIAL_WINDOW_SIZE)];
+
+        Http2Connect {
+            handle,
+            waits,
+            hosts,
+            services: XHashMap::default(),
+            keep_alive,
+            limits,
+            decoder: Decoder::new(),
+            encoder: Encoder::new(),
+            local,
+            remote: Http2Settings::default(),
+            buf: Vec::new(),
+            is_preface: false,
+            is_settings: false,
+            block: None,
+            streams: BTreeMap::new(),
+            ready: VecDeque::new(),
+            upgrade: None,
+            tasks: XHashMap::default(),
+            resets: (0, Instant::now()),
+            bodies: XHashMap::default(),
+            recv_window: recv_window as i64,
+            send_window: DEFAULT_INITIAL_WINDOW_SIZE as i64,
+            last_stream_id: 0,
+            is_goaway: false,
+            out,
+        }
+    }
+
+    //使用明文升级的首个请求和对端设置初始化Http2连接，首个请求会作为流1处理
+    pub fn upgrade(&mut self, request: HttpRequest<S, W>, settings: &[u8]) -> Result<()> {
+        let payload = match base64::decode_config(settings, base64::URL_SAFE_NO_PAD) {
+            Err(e) => {
+                return Err(Error::new(ErrorKind::InvalidData, format!("http2 upgrade failed, reason: {:?}", e)));
+            },
+            Ok(payload) => payload,
+        };
+
+        if let Err(code) = self.remote.decode(&payload) {
+            return Err(into_error(code, "invalid http2 settings"));
+        }
+
+        let authority = match request.headers().get(HOST).and_then(|value| value.to_str().ok()) {
+            None => {
+                return Err(Error::new(ErrorKind::InvalidData, "http2 upgrade failed, reason: host header not exist"));
+            },
+            Some(host) => host.to_string(),
+        };
+
+        //升级的请求已由Http1.1接收完成，则流1处于对端已结束发送的状态
+        self.streams.insert(1, Http2Stream {
+            method: request.method().to_string(),
+            url: request.url().to_string(),
+            authority,
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+            recv_len: 0,
+            send_window: self.remote.initial_window_size as i64,
+            is_end: true,
+            data: VecDeque::new(),
+            is_fin: false,
+        });
+        self.ready.push_back(1);
+        self.upgrade = Some(request);
+        self.last_stream_id = 1;
+        self.out.insert(0, SWITCHING_PROTOCOLS_RESPONSE.to_vec());
+
+        Ok(())
+    }
+
+    //更新Http2连接的超时时长
+    fn update_timeout(&self) {
+        let mut event = SocketEvent::empty();
+        event.set::<usize>(self.keep_alive);
+        self.handle.set_timeout(self.keep_alive, event);
+    }
+
+    //解析并处理所有已接收的完整帧
+    fn process_frames(&mut self) -> GenResult<(), Http2Error> {
+        if !self.is_preface {
+            //检查连接序言
+            if self.buf.len() < HTTP2_PREFACE.len() {
+                if HTTP2_PREFACE.starts_with(&self.buf) {
+                    //连接序言不完整，则继续接收
+                    return Ok(());
+                }
+
+                return Err(Http2Error::Connect(Http2ErrorCode::ProtocolError, "invalid preface".to_string()));
+            }
+
+            if !self.buf.starts_with(HTTP2_PREFACE) {
+                return Err(Http2Error::Connect(Http2ErrorCode::ProtocolError, "invalid preface".to_string()));
+            }
+
+            self.buf.drain(..HTTP2_PREFACE.len());
+            self.is_preface = true;
+        }
+
+        let mut offset = 0;
+        let result = loop {
+            let head = match FrameHead::parse(&self.buf[offset..]) {
+                None => break Ok(()), //帧头不完整
+                Some(head) => head,
+            };
+
+            if head.len > self.local.max_frame_size as usize {
+                break Err(Http2Error::Connect(Http2ErrorCode::FrameSizeError, format!("frame too large, len: {}", head.len)));
+            }
+
+            if self.buf.len() - offset < FRAME_HEAD_LEN + head.len {
+                //帧负载不完整
+                break Ok(());
+            }
+
+            let start = offset + FRAME_HEAD_LEN;
+            let payload = self.buf[start..start + head.len].to_vec();
+            offset = start + head.len;
+
+            match self.process_frame(head, &payload) {
+                Err(Http2Error::Stream(id, code)) => {
+                    //流错误，则重置流，并继续处理后续帧
+                    self.reset_stream(id, code);
+                },
+                Err(e) => break Err(e),
+                Ok(_) => (),
+            }
+        };
+
+        self.buf.drain(..offset);
+        result
+    }
+
+    //处理帧
+    fn process_frame(&mut self, head: FrameHead, payload: &[u8]) -> GenResult<(), Http2Error> {
+        if !self.is_settings && head.kind != FrameType::Settings {
+            //连接序言后的首个帧必须是设置帧
+            return Err(Http2Error::Connect(Http2ErrorCode::ProtocolError, "first frame must be settings".to_string()));
+        }
+
+        if let Some((id, _, _)) = &self.block {
+            if head.kind != FrameType::Continuation || head.stream_id != *id {
+                //头块未完成时，只允许接收同一个流的头块后续帧
+                return Err(Http2Error::Connect(Http2ErrorCode::ProtocolError, "expect continuation".to_string()));
+            }
+        }
+
+        match head.kind {
+            FrameType::Data => self.on_data(head, payload),
+            FrameType::Headers => self.on_headers(head, payload),
+            FrameType::Priority => {
+                if head.stream_id == 0 {
+                    return Err(Http2Error::Connect(Http2ErrorCode::ProtocolError, "invalid priority stream".to_string()));
+                }
+
+                if head.len != 5 {
+                    return Err(Http2Error::Stream(head.stream_id, Http2ErrorCode::FrameSizeError));
+                }
+
+                //忽略优先级
+                Ok(())
+            },
+            FrameType::RstStream => {
+                if head.stream_id == 0 || head.stream_id > self.last_stream_id {
+                    return Err(Http2Error::Connect(Http2ErrorCode::ProtocolError, "invalid reset stream".to_string()));
+                }
+
+                if head.len != 4 {
+                    return Err(Http2Error::Connect(Http2ErrorCode::FrameSizeError, "invalid reset stream".to_string()));
+                }
+
+                //对端重置流，则移除流，并丢弃流的异步任务，正在处理的请求和正在发送的响应会中止
+                if self.remove_stream(head.stream_id).is_some() {
+                    self.count_reset()?;
+                }
+                Ok(())
+            },
+            FrameType::Settings => {
+                if head.stream_id != 0 {
+                    return Err(Http2Error::Connect(Http2ErrorCode::ProtocolError, "invalid settings stream".to_string()));
+                }
+
+                if head.has_flag(FLAG_ACK) {
+                    if head.len != 0 {
+                        return Err(Http2Error::Connect(Http2ErrorCode::FrameSizeError, "invalid settings ack".to_string()));
+                    }
+
+                    return Ok(());
+                }
+
+                let last_window_size = self.remote.initial_window_size as i64;
+                if let Err(code) = self.remote.decode(payload) {
+                    return Err(Http2Error::Connect(code, "invalid settings".to_string()));
+                }
+
+                //对端的初始流控窗口大小改变，则调整所有流的发送流控窗口
+                let delta = self.remote.initial_window_size as i64 - last_window_size;
+                for stream in self.streams.values_mut() {
+                    stream.send_window += delta;
+                    if stream.send_window > MAX_WINDOW_SIZE as i64 {
+                        return Err(Http2Error::Connect(Http2ErrorCode::FlowControlError, "window overflow".to_string()));
+                    }
+                }
+
+                self.is_settings = true;
+                self.out.push(settings_ack_frame());
+                Ok(())
+            },
+            FrameType::PushPromise => {
+                //客户端不允许推送
+                Err(Http2Error::Connect(Http2ErrorCode::ProtocolError, "client push promise".to_string()))
+            },
+            FrameType::Ping => {
+                if head.stream_id != 0 {
+                    return Err(Http2Error::Connect(Http2ErrorCode::ProtocolError, "invalid ping stream".to_string()));
+                }
+
+                if head.len != 8 {
+                    return Err(Http2Error::Connect(Http2ErrorCode::FrameSizeError, "invalid ping".to_string()));
+                }
+
+                if !head.has_flag(FLAG_ACK) {
+                    self.out.push(ping_ack_frame(payload));
+                }
+                Ok(())
+            },
+            FrameType::GoAway => {
+                if head.stream_id != 0 {
+                    return Err(Http2Error::Connect(Http2ErrorCode::ProtocolError, "invalid goaway stream".to_string()));
+                }
+
+                if head.len < 8 {
+                    return Err(Http2Error::Connect(Http2ErrorCode::FrameSizeError, "invalid goaway".to_string()));
+                }
+
+                //对端关闭连接，则处理完已接收的流后关闭连接
+                self.is_goaway = true;
+                Ok(())
+            },
+            FrameType::WindowUpdate => {
+                if head.len != 4 {
+                    return Err(Http2Error::Connect(Http2ErrorCode::FrameSizeError, "invalid window update".to_string()));
+                }
+
+                let increment = (read_u32(payload) & MAX_WINDOW_SIZE) as i64;
+                if head.stream_id == 0 {
+                    if increment == 0 {
+                        return Err(Http2Error::Connect(Http2ErrorCode::ProtocolError, "invalid window increment".to_string()));
+                    }
+
+                    self.send_window += increment;
+                    if self.send_window > MAX_WINDOW_SIZE as i64 {
+                        return Err(Http2Error::Connect(Http2ErrorCode::FlowControlError, "window overflow".to_string()));
+                    }
+                } else if let Some(stream) = self.streams.get_mut(&head.stream_id) {
+                    if increment == 0 {
+                        return Err(Http2Error::Stream(head.stream_id, Http2ErrorCode::ProtocolError));
+                    }
+
+                    stream.send_window += increment;
+                    if stream.send_window > MAX_WINDOW_SIZE as i64 {
+                        return Err(Http2Error::Stream(head.stream_id, Http2ErrorCode::FlowControlError));
+                    }
+                }
+
+                Ok(())
+            },
+            FrameType::Continuation => {
+                match self.block.take() {
+                    None => {
+                        Err(Http2Error::Connect(Http2ErrorCode::ProtocolError, "unexpected continuation".to_string()))
+                    },
+                    Some((id, end_stream, mut block)) => {
+                        block.put_slice(payload);
+                        if block.len() > MAX_HEADER_BLOCK_LIMIT {
+                            return Err(Http2Error::Connect(Http2ErrorCode::EnhanceYourCalm, "header block too large".to_string()));
+                        }
+
+                        if head.has_flag(FLAG_END_HEADERS) {
+                            return self.on_header_block(id, end_stream, block);
+                        }
+
+                        self.block = Some((id, end_stream, block));
+                        Ok(())
+                    },
+                }
+            },
+            FrameType::Unknown(_) => {
+                //忽略未知帧
+                Ok(())
+            },
+        }
+    }
+
+    //处理数据帧
+    fn on_data(&mut self, head: FrameHead, payload: &[u8]) -> GenResult<(), Http2Error> {
+        if head.stream_id == 0 {
+            return Err(Http2Error::Connect(Http2ErrorCode::ProtocolError, "invalid data stream".to_string()));
+        }
+
+        let (start, end) = match strip_padding(&head, payload) {
+            Err(code) => return Err(Http2Error::Connect(code, "invalid data padding".to_string())),
+            Ok(range) => range,
+        };
+
+        //数据帧的全部长度都受流控限制
+        let len = head.len as u32;
+        self.recv_window -= len as i64;
+        if self.recv_window < 0 {
+            return Err(Http2Error::Connect(Http2ErrorCode::FlowControlError, "recv window overflow".to_string()));
+        }
+
+        let body_limit = self.limits.max_body;
+        let stream_window = self.local.initial_window_size;
+        match self.streams.get_mut(&head.stream_id) {
+            None => {
+                if head.stream_id > self.last_stream_id {
+                    //空闲流不允许接收数据帧
+                    return Err(Http2Error::Connect(Http2ErrorCode::ProtocolError, "data on idle stream".to_string()));
+                }
+
+                //忽略已关闭流的数据帧，数据已被丢弃，则立即归还连接的接收流控窗口
+                self.refill(len);
+                Ok(())
+            },
+            Some(stream) => {
+                //流的数据在请求体被消费后，才归还连接的接收流控窗口
+                stream.recv_len += len;
+                if stream.is_end {
+                    return Err(Http2Error::Stream(head.stream_id, Http2ErrorCode::StreamClosed));
+                }
+
+                if stream.recv_len > stream_window {
+                    //对端未遵守流的接收流控窗口
+                    return Err(Http2Error::Stream(head.stream_id, Http2ErrorCode::FlowControlError));
+                }
+
+                if let Some(limit) = body_limit {
+                    if stream.body.len() + (end - start) > limit {
+                        //请求体超过限制
+                        self.reply_status(head.stream_id, StatusCode::PAYLOAD_TOO_LARGE);
+                        return Ok(());
+                    }
+                }
+
+                stream.body.put_slice(&payload[start..end]);
+                if head.has_flag(FLAG_END_STREAM) {
+                    //请求已接收完成
+                    stream.is_end = true;
+                    self.ready.push_back(head.stream_id);
+                }
+
+                Ok(())
+            },
+        }
+    }
+
+    //处理头帧
+    fn on_headers(&mut self, head: FrameHead, payload: &[u8]) -> GenResult<(), Http2Error> {
+        if head.stream_id == 0 {
+            return Err(Http2Error::Connect(Http2ErrorCode::ProtocolError, "invalid headers stream".to_string()));
+        }
+
+        let (mut start, end) = match strip_padding(&head, payload) {
+            Err(code) => return Err(Http2Error::Connect(code, "invalid headers padding".to_string())),
+            Ok(range) => range,
+        };
+
+        if head.has_flag(FLAG_PRIORITY) {
+            //忽略优先级
+            if end - start < 5 {
+                return Err(Http2Error::Connect(Http2ErrorCode::FrameSizeError, "invalid headers priority".to_string()));
+            }
+            start += 5;
+        }
+
+        let end_stream = head.has_flag(FLAG_END_STREAM);
+        let block = payload[start..end].to_vec();
+        if head.has_flag(FLAG_END_HEADERS) {
+            return self.on_header_block(head.stream_id, end_stream, block);
+        }
+
+        self.block = Some((head.stream_id, end_stream, block));
+        Ok(())
+    }
+
+    //处理完整的头块
+    fn on_header_block(&mut self, id: u32, end_stream: bool, block: Vec<u8>) -> GenResult<(), Http2Error> {
+        //无论流是否有效，都需要解压头块，以保证与对端的头压缩表一致，解压后的头列表超过限制时，只继续解压而不再保存头
+        let max_header_bytes = self.limits.max_header_bytes;
+        let max_headers = self.limits.max_headers;
+        let mut fields = Vec::new();
+        let mut size = 0; //头列表大小，每个头额外计入32字节
+        let mut count = 0; //普通头数量
+        let mut is_too_large = false;
+        if let Err(e) = self.decoder.decode_with_cb(&block, |key, value| {
+            size += key.len() + value.len() + 32;
+            if !key.starts_with(b":") {
+                count += 1;
+            }
+
+            if size > max_header_bytes || count > max_headers {
+                is_too_large = true;
+            } else if !is_too_large {
+                fields.push((key.into_owned(), value.into_owned()));
+            }
+        }) {
+            return Err(Http2Error::Connect(Http2ErrorCode::CompressionError, format!("{:?}", e)));
+        }
+
+        if let Some(stream) = self.streams.get_mut(&id) {
+            //已存在的流，则只允许接收结束流的尾部头
+            if stream.is_end || !end_stream {
+                return Err(Http2Error::Stream(id, Http2ErrorCode::ProtocolError));
+            }
+
+            if is_too_large {
+                //尾部头超过限制
+                self.reply_status(id, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+                return Ok(());
+            }
+
+            for (key, value) in fields {
+                if key.starts_with(b":") {
+                    return Err(Http2Error::Stream(id, Http2ErrorCode::ProtocolError));
+                }
+
+                if let (Ok(key), Ok(value)) = (HeaderName::from_bytes(&key), HeaderValue::from_bytes(&value)) {
+                    stream.headers.append(key, value);
+                }
+            }
+
+            stream.is_end = true;
+            self.ready.push_back(id);
+            return Ok(());
+        }
+
+        if id % 2 == 0 || id <= self.last_stream_id {
+            //客户端只允许使用递增的奇数流id
+            return Err(Http2Error::Connect(Http2ErrorCode::ProtocolError, format!("invalid stream id, id: {}", id)));
+        }
+        self.last_stream_id = id;
+
+        if self.is_goaway {
+            //对端已关闭连接，则忽略新的流
+            return Ok(());
+        }
+
+        if self.streams.len() >= DEFAULT_MAX_CONCURRENT_STREAMS as usize {
+            //流的异步任务会与流一起移除，所以活动流数量包括了正在处理的请求
+            return Err(Http2Error::Stream(id, Http2ErrorCode::RefusedStream));
+        }
+
+        if is_too_large {
+            //请求头超过限制，对端未结束发送时，通知对端不需要继续发送
+            self.reply_status(id, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+            if !end_stream {
+                self.out.push(rst_stream_frame(id, Http2ErrorCode::NoError));
+            }
+            return Ok(());
+        }
+
+        let mut stream = match parse_request_headers(fields) {
+            None => return Err(Http2Error::Stream(id, Http2ErrorCode::ProtocolError)),
+            Some(stream) => stream,
+        };
+        stream.send_window = self.remote.initial_window_size as i64;
+        stream.is_end = end_stream;
+
+        let content_len = stream.headers
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+        self.streams.insert(id, stream);
+
+        if let (Some(limit), Some(len)) = (self.limits.max_body, content_len) {
+            if len > limit {
+                //声明的请求体长度超过限制
+                self.reply_status(id, StatusCode::PAYLOAD_TOO_LARGE);
+                return Ok(());
+            }
+        }
+
+        if end_stream {
+            //没有请求体，则请求已接收完成
+            self.ready.push_back(id);
+        }
+
+        Ok(())
+    }
+
+    //重置指定的流
+    fn reset_stream(&mut self, id: u32, code: Http2ErrorCode) {
+        self.out.push(rst_stream_frame(id, code));
+        self.remove_stream(id);
+    }
+
+    //统计对端重置的流，统计时长内重置过多时关闭连接，避免对端快速创建并重置流，使服务器持续处理已重置的请求
+    fn count_reset(&mut self) -> GenResult<(), Http2Error> {
+        let now = Instant::now();
+        if now.duration_since(self.resets.1) >= Duration::from_millis(RESET_STREAMS_INTERVAL) {
+            self.resets = (0, now);
+        }
+
+        self.resets.0 += 1;
+        if self.resets.0 > MAX_RESET_STREAMS {
+            return Err(Http2Error::Connect(Http2ErrorCode::EnhanceYourCalm, "too many reset streams".to_string()));
+        }
+
+        Ok(())
+    }
+
+    //移除指定的流，丢弃流的异步任务，并归还流上未被消费的接收流控窗口
+    fn remove_stream(&mut self, id: u32) -> Option<Http2Stream> {
+        self.tasks.remove(&id);
+        self.bodies.remove(&id);
+        let stream = self.streams.remove(&id);
+        if let Some(stream) = &stream {
+            self.refill(stream.recv_len);
+        }
+
+        stream
+    }
+
+    //归还连接的接收流控窗口
+    fn refill(&mut self, len: u32) {
+        if len > 0 {
+            self.recv_window += len as i64;
+            self.out.push(window_update_frame(0, len));
+        }
+    }
+
+    //在指定的流上回应指定状态码的空响应，并关闭流
+    fn reply_status(&mut self, id: u32, status: StatusCode) {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("0"));
+        self.push_headers(id, status.as_u16(), &headers, true);
+
+        if let Some(stream) = self.remove_stream(id) {
+            if !stream.is_end {
+                //对端未结束发送，则通知对端不需要继续发送
+                self.out.push(rst_stream_frame(id, Http2ErrorCode::NoError));
+            }
+        }
+    }
+
+    //压缩响应头，并加入发送缓冲
+    fn push_headers(&mut self, id: u32, status: u16, headers: &HeaderMap, end_stream: bool) {
+        let mut fields: Vec<(Vec<u8>, Vec<u8>)> = Vec::with_capacity(headers.len() + 1);
+        fields.push((b":status".to_vec(), status.to_string().into_bytes()));
+        for (key, value) in headers.iter() {
+            if is_connection_header(key) {
+                //忽略连接相关头
+                continue;
+            }
+
+            fields.push((key.as_str().as_bytes().to_vec(), value.as_bytes().to_vec()));
+        }
+
+        let block = self.encoder.encode(fields.iter().map(|(key, value)| (&key[..], &value[..])));
+        for frame in headers_frames(id, &block, end_stream, self.remote.max_frame_size as usize) {
+            self.out.push(frame);
+        }
+    }
+
+    //获取发送缓冲的长度
+    fn out_len(&self) -> usize {
+        self.out.iter().map(|frame| frame.len()).sum()
+    }
+
+    //将已接收完成的流交给对应虚拟主机的服务处理，服务的异步任务会与连接上的帧处理并发执行
+    fn dispatch(&mut self, id: u32) -> GenResult<(), Http2Error> {
+        let (authority, request, recv_len) = match self.streams.get_mut(&id) {
+            None => return Ok(()), //流已被重置
+            Some(stream) => {
+                let request = if id == 1 && self.upgrade.is_some() {
+                    //明文升级时的首个请求
+                    self.upgrade.take()
+                } else {
+                    let headers = mem::replace(&mut stream.headers, HeaderMap::new());
+                    let body = mem::replace(&mut stream.body, Vec::new());
+                    HttpRequest::with_body(self.handle.clone(), self.waits.clone(), &stream.method, &stream.url, Version::HTTP_2, headers, body)
+                };
+
+                //请求体已被消费
+                let recv_len = mem::replace(&mut stream.recv_len, 0);
+                match request {
+                    None => return Err(Http2Error::Stream(id, Http2ErrorCode::ProtocolError)), //请求的Url无效
+                    Some(request) => (stream.authority.clone(), request, recv_len),
+                }
+            },
+        };
+
+        //请求体已交给请求，则归还连接的接收流控窗口
+        self.refill(recv_len);
+
+        //检查请求的主机是否需要重定向
+        let path = match request.url().query() {
+            None => request.url().path().to_string(),
+            Some(query) => request.url().path().to_string() + "?" + query,
+        };
+        if let Some((status, location)) = self.hosts.redirect(&authority, self.handle.is_security(), &path) {
+            if let Ok(value) = HeaderValue::from_str(&location) {
+                let mut headers = HeaderMap::new();
+                headers.insert(LOCATION, value);
+                headers.insert(CONTENT_LENGTH, HeaderValue::from_static("0"));
+                self.push_headers(id, status.as_u16(), &headers, true);
+                self.remove_stream(id);
+                return Ok(());
+            }
+        }
+
+        //获取请求的主机对应的服务，只缓存有限数量的主机的服务，避免对端使用大量不同的主机名耗尽内存
+        let future = if let Some(service) = self.services.get_mut(&authority) {
+            service.call(request)
+        } else {
+            match self.hosts.get(&authority) {
+                None => {
+                    //请求的主机不存在
+                    let status = self.hosts.unknown_host_status();
+                    self.reply_status(id, status);
+                    return Ok(());
+                },
+                Some(host) => {
+                    let mut service = host.new_service();
+                    let future = service.call(request);
+                    if self.services.len() < MAX_CACHED_SERVICES {
+                        self.services.insert(authority.clone(), service);
+                    }
+                    future
+                },
+            }
+        };
+
+        self.tasks.insert(id, async move {
+            let result = match future.await {
+                Err(e) => Err(e.into()),
+                Ok(resp) => Ok(resp),
+            };
+
+            Http2Task::Response(result)
+        }.boxed());
+
+        Ok(())
+    }
+
+    //异步生成指定流的流式响应体的后续块，任务由Http2连接轮询，所以任意流的响应体块就绪时都会唤醒Http2连接，并重新轮询所有流的任务
+    fn poll_body(&mut self, id: u32, mut body: RespBody<S, W>) {
+        let token = self.handle.get_token().clone();
+        let waits = self.waits.clone();
+
+        self.tasks.insert(id, async move {
+            let result = loop {
+                match body.next_stream().await {
+                    HttpRecvResult::Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                        //响应体块未就绪，则等待Http2连接被唤醒后继续获取
+                        PendSocket::pending(token.clone(), waits.clone()).await;
+                    },
+                    result => break result,
+                }
+            };
+
+            Http2Task::Body(body, result)
+        }.boxed());
+    }
+
+    //处理已完成的流的异步任务
+    fn on_task(&mut self, id: u32, task: Http2Task<S, W>) {
+        if !self.streams.contains_key(&id) {
+            //流已被重置，则忽略任务结果，并中止流式响应体的生成
+            return;
+        }
+
+        match task {
+            Http2Task::Response(Err(e)) => {
+                //服务调用异常，则回应服务器内部错误
+                warn!("!!!> Http2 Service Failed, stream: {}, reason: {:?}", id, e);
+                self.reply_status(id, StatusCode::INTERNAL_SERVER_ERROR);
+            },
+            Http2Task::Response(Ok(resp)) => {
+                self.on_response(id, resp);
+            },
+            Http2Task::Body(_body, HttpRecvResult::Err(e)) => {
+                //获取响应体块错误，则重置流
+                warn!("!!!> Http2 Stream Response Failed, stream: {}, reason: {:?}", id, e);
+                self.reset_stream(id, Http2ErrorCode::InternalError);
+            },
+            Http2Task::Body(body, HttpRecvResult::Ok(bins)) => {
+                //已生成部分响应体块，则在已生成的块发送完成后，继续生成后续块
+                if let Some(stream) = self.streams.get_mut(&id) {
+                    stream.data.extend(bins);
+                }
+                self.bodies.insert(id, body);
+            },
+            Http2Task::Body(_body, HttpRecvResult::Fin(bins)) => {
+                if let Some(stream) = self.streams.get_mut(&id) {
+                    stream.data.extend(bins);
+                    stream.is_fin = true;
+                }
+            },
+        }
+    }
+
+    //在指定的流上发送响应头，响应体会按流控窗口异步发送
+    fn on_response(&mut self, id: u32, mut resp: HttpResponse<S, W>) {
+        let status = resp.get_status();
+        let headers = resp.get_headers();
+        let is_stream = resp.is_stream();
+        let body = resp.take_body();
+
+        if !is_stream {
+            //数据块响应
+            let bin = body.and_then(|body| body.into_bin()).unwrap_or(Vec::new());
+            if bin.is_empty() {
+                self.push_headers(id, status, &headers, true);
+                self.remove_stream(id);
+                return;
+            }
+
+            self.push_headers(id, status, &headers, false);
+            if let Some(stream) = self.streams.get_mut(&id) {
+                stream.data.push_back(bin);
+                stream.is_fin = true;
+            }
+            return;
+        }
+
+        //流式响应
+        self.push_headers(id, status, &headers, false);
+        match body {
+            None => {
+                if let Some(stream) = self.streams.get_mut(&id) {
+                    stream.is_fin = true;
+                }
+            },
+            Some(body) => {
+                self.poll_body(id, body);
+            },
+        }
+    }
+
+    //按流控窗口将所有流的待发送响应体块加入发送缓冲，发送缓冲已满时返回真
+    fn write_streams(&mut self) -> bool {
+        let max_frame_size = self.remote.max_frame_size as i64;
+        let mut out_len = self.out_len();
+        let mut finished = Vec::new(); //已发送完响应的流
+        let mut drained = Vec::new(); //已发送完已生成的块，需要继续生成后续块的流
+        let mut is_full = false;
+
+        'streams: for (id, stream) in self.streams.iter_mut() {
+            let mut is_end = false;
+            while let Some(bin_len) = stream.data.front().map(|bin| bin.len()) {
+                if bin_len == 0 {
+                    //忽略空块
+                    stream.data.pop_front();
+                    continue;
+                }
+
+                if out_len >= FLUSH_OUT_BUFFER_LEN {
+                    //发送缓冲已满，则等待发送完成后继续
+                    is_full = true;
+                    break 'streams;
+                }
+
+                let window = self.send_window.min(stream.send_window).min(max_frame_size);
+                if window <= 0 {
+                    //流控窗口已耗尽，则等待对端更新流控窗口
+                    break;
+                }
+
+                let len = (window as usize).min(bin_len);
+                is_end = stream.is_fin && stream.data.len() == 1 && len == bin_len;
+                self.out.push(data_frame(*id, &stream.data[0][..len], is_end));
+                self.send_window -= len as i64;
+                stream.send_window -= len as i64;
+                out_len += FRAME_HEAD_LEN + len;
+
+                if len == bin_len {
+                    stream.data.pop_front();
+                } else {
+                    stream.data[0].drain(..len);
+                }
+            }
+
+            if stream.data.is_empty() {
+                if stream.is_fin {
+                    if !is_end {
+                        //空数据帧不受流控限制，用于结束流
+                        self.out.push(data_frame(*id, &[], true));
+                    }
+                    finished.push(*id);
+                } else if self.bodies.contains_key(id) {
+                    drained.push(*id);
+                }
+            }
+        }
+
+        for id in finished {
+            self.remove_stream(id);
+        }
+
+        for id in drained {
+            if let Some(body) = self.bodies.remove(&id) {
+                self.poll_body(id, body);
+            }
+        }
+
+        is_full
+    }
+}
+
+/*
+* Http2连接异步方法
+*/
+impl<S: Socket, W: AsyncIOWait, P: VirtualHostPool<S, W>> Http2Connect<S, W, P> {
+    //运行Http2连接，会持续接收并处理连接上的帧，直到连接关闭
+    pub async fn run(mut self, preffix: Vec<u8>) {
+        self.buf = preffix;
+
+        loop {
+            self.update_timeout(); //处理帧前，更新当前Http2连接的超时时长
+
+            let mut result = self.process_frames();
+            while result.is_ok() {
+                //将所有已接收完成的流交给服务处理，不会等待服务处理完成
+                if let Some(id) = self.ready.pop_front() {
+                    match self.dispatch(id) {
+                        Err(Http2Error::Stream(id, code)) => {
+                            //流错误，则重置流，并继续处理后续已接收完成的流
+                            self.reset_stream(id, code);
+                        },
+                        Err(e) => result = Err(e),
+                        Ok(_) => (),
+                    }
+                } else {
+                    break;
+                }
+            }
+
+            while result.is_ok() {
+                //按流控窗口发送所有流的响应
+                let is_full = self.write_streams();
+                result = self.flush().await;
+                if !is_full {
+                    break;
+                }
+            }
+
+            match result {
+                Err(Http2Error::Connect(code, reason)) => {
+                    //连接错误，则通知对端后关闭当前Http2连接
+                    return self.goaway(code, reason).await;
+                },
+                Err(Http2Error::Io(e)) => {
+                    //Io错误，则立即关闭当前Http2连接
+                    self.handle.close(Err(e));
+                    return;
+                },
+                _ => (),
+            }
+
+            if self.is_goaway && self.ready.is_empty() && self.streams.is_empty() {
+                //对端已关闭连接，且已处理完所有流
+                self.handle.close(Ok(()));
+                return;
+            }
+
+            match self.wait().await {
+                Http2Event::Readed(Err(e)) => {
+                    //读错误，则立即关闭当前Http2连接
+                    self.handle.close(Err(e));
+                    return;
+                },
+                Http2Event::Readed(Ok(_)) => (),
+                Http2Event::Task(id, task) => {
+                    self.on_task(id, task);
+                },
+            }
+        }
+    }
+
+    //等待接收后续数据，或任意流的异步任务完成，等待期间不会阻塞其它流
+    async fn wait(&mut self) -> Http2Event<S, W> {
+        let mut read = Box::pin(AsyncReadTask::async_read(self.handle.clone(), self.waits.clone(), 0));
+        let buf = &mut self.buf;
+        let tasks = &mut self.tasks;
+
+        poll_fn(move |cx| {
+            if let Poll::Ready(result) = read.as_mut().poll(cx) {
+                return Poll::Ready(match result {
+                    Err(e) => Http2Event::Readed(Err(e)),
+                    Ok(bin) => {
+                        buf.put_slice(bin);
+                        Http2Event::Readed(Ok(()))
+                    },
+                });
+            }
+
+            //所有流的任务都使用Http2连接的唤醒器轮询，避免同一个连接令牌只能保存一个唤醒器时，丢失其它流的唤醒
+            let mut ready = None;
+            for (id, task) in tasks.iter_mut() {
+                if let Poll::Ready(task) = task.as_mut().poll(cx) {
+                    ready = Some((*id, task));
+                    break;
+                }
+            }
+
+            if let Some((id, task)) = ready {
+                tasks.remove(&id);
+                return Poll::Ready(Http2Event::Task(id, task));
+            }
+
+            Poll::Pending
+        }).await
+    }
+
+    //发送所有待发送的帧，并等待发送完成
+    async fn flush(&mut self) -> GenResult<(), Http2Error> {
+        if self.out.is_empty() {
+            return Ok(());
+        }
+
+        match self.handle.alloc() {
+            Ok(Some(mut buf)) => {
+                for frame in self.out.drain(..) {
+                    buf.get_iolist_mut().push_back(frame.into());
+                }
+
+                if let Some(buf_handle) = buf.finish() {
+                    self.handle.write_ready(buf_handle)?;
+                    self.update_timeout(); //每次发送后，更新当前Http2连接的超时时长
+
+                    //挂起当前Http2连接，并在发送完成后被唤醒
+                    PendSocket::pending(self.handle.get_token().clone(), self.waits.clone()).await;
+                    if self.handle.is_closed() {
+                        return Err(Http2Error::Io(Error::new(ErrorKind::BrokenPipe, "http2 send failed, reason: connect closed")));
+                    }
+                }
+
+                Ok(())
+            },
+            Ok(None) => {
+                Err(Http2Error::Io(Error::new(ErrorKind::Other, "http2 send failed, reason: alloc write buffer failed")))
+            },
+            Err(e) => Err(Http2Error::Io(e)),
+        }
+    }
+
+    //关闭当前Http2连接
+    async fn goaway(mut self, code: Http2ErrorCode, reason: String) {
+        self.out.push(goaway_frame(self.last_stream_id, code, reason.as_bytes()));
+        let _ = self.flush().await;
+        self.handle.close(Err(into_error(code, &reason)));
+    }
+}
+
+//解析请求头，构建Http2流
+fn parse_request_headers(fields: Vec<(Vec<u8>, Vec<u8>)>) -> Option<Http2Stream> {
+    let mut method = None;
+    let mut scheme = None;
+    let mut authority = None;
+    let mut path = None;
+    let mut cookies = Vec::new();
+    let mut headers = HeaderMap::new();
+    let mut is_regular = false;
+
+    for (key, value) in fields {
+        if key.starts_with(b":") {
+            if is_regular {
+                //伪头必须在普通头之前
+                return None;
+            }
+
+            let value = String::from_utf8(value).ok()?;
+            match &key[..] {
+                b":method" => method = Some(value),
+                b":scheme" => scheme = Some(value),
+                b":authority" => authority = Some(value),
+                b":path" => path = Some(value),
+                _ => return None,
+            }
+            continue;
+        }
+
+        is_regular = true;
+        let key = HeaderName::from_bytes(&key).ok()?;
+        if is_connection_header(&key) {
+            if key != TE || value != TE_TRAILERS.as_bytes() {
+                //不允许使用连接相关头
+                return None;
+            }
+        }
+
+        if key == COOKIE {
+            //合并拆分的Cookie头
+            cookies.push(value);
+            continue;
+        }
+
+        headers.append(key, HeaderValue::from_bytes(&value).ok()?);
+    }
+
+    if !cookies.is_empty() {
+        headers.insert(COOKIE, HeaderValue::from_bytes(&cookies.join(&b"; "[..])).ok()?);
+    }
+
+    let authority = match authority {
+        Some(authority) => authority,
+        None => headers.get(HOST)?.to_str().ok()?.to_string(),
+    };
+    if !headers.contains_key(HOST) {
+        //为兼容Http1.1的中间件，则设置主机头
+        headers.insert(HOST, HeaderValue::from_str(&authority).ok()?);
+    }
+
+    let path = path?;
+    let url = scheme? + "://" + &authority + &path;
+    Some(Http2Stream {
+        method: method?,
+        url,
+        authority,
+        headers,
+        body: Vec::new(),
+        recv_len: 0,
+        send_window: DEFAULT_INITIAL_WINDOW_SIZE as i64,
+        is_end: false,
+        data: VecDeque::new(),
+        is_fin: false,
+    })
+}
+
+//判断是否是连接相关头
+fn is_connection_header(key: &HeaderName) -> bool {
+    key == CONNECTION
+        || key == TRANSFER_ENCODING
+        || key == UPGRADE
+        || key == TE
+        || key == KEEP_ALIVE_HEADER
+        || key == PROXY_CONNECTION_HEADER
+}
+
+//获取明文升级为Http2的请求携带的对端设置，不是明文升级请求或请求有请求体时返回空
+pub fn upgrade_settings<S: Socket, W: AsyncIOWait>(request: &HttpRequest<S, W>) -> Option<Vec<u8>> {
+    if request.get_handle().is_security() || request.is_chunked() {
+        //安全连接只允许通过应用层协议协商使用Http2，且不支持有请求体的升级
+        return None;
+    }
+
+    if let Some(len) = request.headers().get(CONTENT_LENGTH) {
+        if len.as_bytes() != b"0" {
+            return None;
+        }
+    }
+
+    let is_upgrade = request.headers()
+        .get_all(UPGRADE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|protocol| protocol.trim().eq_ignore_ascii_case(HTTP2_CLEARTEXT_UPGRADE));
+    if !is_upgrade {
+        return None;
+    }
+
+    request.headers().get(HTTP2_SETTINGS_HEADER).map(|value| value.as_bytes().to_vec())
+}
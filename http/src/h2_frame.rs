@@ -0,0 +1,438 @@
+use std::result::Result as GenResult;
+use std::io::{Error, Result, ErrorKind};
+
+use bytes::BufMut;
+
+/*
+* Http2连接序言
+*/
+pub const HTTP2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/*
+* Http2的应用层协议名
+*/
+pub const HTTP2_ALPN: &[u8] = b"h2";
+
+/*
+* Http2明文升级的协议名
+*/
+pub const HTTP2_CLEARTEXT_UPGRADE: &str = "h2c";
+
+/*
+* Http2明文升级时，携带设置的请求头
+*/
+pub const HTTP2_SETTINGS_HEADER: &str = "http2-settings";
+
+/*
+* Http2帧头长度
+*/
+pub const FRAME_HEAD_LEN: usize = 9;
+
+/*
+* Http2默认的最大帧负载长度和最大帧负载长度限制
+*/
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 16384;
+pub const MAX_FRAME_SIZE_LIMIT: u32 = 16777215;
+
+/*
+* Http2默认的流控窗口大小和最大流控窗口大小
+*/
+pub const DEFAULT_INITIAL_WINDOW_SIZE: u32 = 65535;
+pub const MAX_WINDOW_SIZE: u32 = 0x7fffffff;
+
+/*
+* Http2帧标志
+*/
+pub const FLAG_END_STREAM: u8 = 0x1;
+pub const FLAG_ACK: u8 = 0x1;
+pub const FLAG_END_HEADERS: u8 = 0x4;
+pub const FLAG_PADDED: u8 = 0x8;
+pub const FLAG_PRIORITY: u8 = 0x20;
+
+/*
+* Http2设置项标识
+*/
+const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+/*
+* Http2帧类型
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameType {
+    Data,           //数据帧
+    Headers,        //头帧
+    Priority,       //优先级帧
+    RstStream,      //重置流帧
+    Settings,       //设置帧
+    PushPromise,    //推送承诺帧
+    Ping,           //心跳帧
+    GoAway,         //关闭连接帧
+    WindowUpdate,   //流控窗口更新帧
+    Continuation,   //头块后续帧
+    Unknown(u8),    //未知帧
+}
+
+impl From<u8> for FrameType {
+    fn from(kind: u8) -> Self {
+        match kind {
+            0x0 => FrameType::Data,
+            0x1 => FrameType::Headers,
+            0x2 => FrameType::Priority,
+            0x3 => FrameType::RstStream,
+            0x4 => FrameType::Settings,
+            0x5 => FrameType::PushPromise,
+            0x6 => FrameType::Ping,
+            0x7 => FrameType::GoAway,
+            0x8 => FrameType::WindowUpdate,
+            0x9 => FrameType::Continuation,
+            kind => FrameType::Unknown(kind),
+        }
+    }
+}
+
+impl From<FrameType> for u8 {
+    fn from(kind: FrameType) -> Self {
+        match kind {
+            FrameType::Data => 0x0,
+            FrameType::Headers => 0x1,
+            FrameType::Priority => 0x2,
+            FrameType::RstStream => 0x3,
+            FrameType::Settings => 0x4,
+            FrameType::PushPromise => 0x5,
+            FrameType::Ping => 0x6,
+            FrameType::GoAway => 0x7,
+            FrameType::WindowUpdate => 0x8,
+            FrameType::Continuation => 0x9,
+            FrameType::Unknown(kind) => kind,
+        }
+    }
+}
+
+/*
+* Http2错误码
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Http2ErrorCode {
+    NoError,            //正常关闭
+    ProtocolError,      //协议错误
+    InternalError,      //内部错误
+    FlowControlError,   //流控错误
+    SettingsTimeout,    //设置超时
+    StreamClosed,       //流已关闭
+    FrameSizeError,     //帧长度错误
+    RefusedStream,      //拒绝流
+    Cancel,             //取消流
+    CompressionError,   //头压缩错误
+    ConnectError,       //连接错误
+    EnhanceYourCalm,    //对端负载过高
+    InadequateSecurity, //安全性不足
+    Http11Required,     //需要使用Http1.1
+    Unknown(u32),       //未知错误
+}
+
+impl From<u32> for Http2ErrorCode {
+    fn from(code: u32) -> Self {
+        match code {
+            0x0 => Http2ErrorCode::NoError,
+            0x1 => Http2ErrorCode::ProtocolError,
+            0x2 => Http2ErrorCode::InternalError,
+            0x3 => Http2ErrorCode::FlowControlError,
+            0x4 => Http2ErrorCode::SettingsTimeout,
+            0x5 => Http2ErrorCode::StreamClosed,
+            0x6 => Http2ErrorCode::FrameSizeError,
+            0x7 => Http2ErrorCode::RefusedStream,
+            0x8 => Http2ErrorCode::Cancel,
+            0x9 => Http2ErrorCode::CompressionError,
+            0xa => Http2ErrorCode::ConnectError,
+            0xb => Http2ErrorCode::EnhanceYourCalm,
+            0xc => Http2ErrorCode::InadequateSecurity,
+            0xd => Http2ErrorCode::Http11Required,
+            code => Http2ErrorCode::Unknown(code),
+        }
+    }
+}
+
+impl From<Http2ErrorCode> for u32 {
+    fn from(code: Http2ErrorCode) -> Self {
+        match code {
+            Http2ErrorCode::NoError => 0x0,
+            Http2ErrorCode::ProtocolError => 0x1,
+            Http2ErrorCode::InternalError => 0x2,
+            Http2ErrorCode::FlowControlError => 0x3,
+            Http2ErrorCode::SettingsTimeout => 0x4,
+            Http2ErrorCode::StreamClosed => 0x5,
+            Http2ErrorCode::FrameSizeError => 0x6,
+            Http2ErrorCode::RefusedStream => 0x7,
+            Http2ErrorCode::Cancel => 0x8,
+            Http2ErrorCode::CompressionError => 0x9,
+            Http2ErrorCode::ConnectError => 0xa,
+            Http2ErrorCode::EnhanceYourCalm => 0xb,
+            Http2ErrorCode::InadequateSecurity => 0xc,
+            Http2ErrorCode::Http11Required => 0xd,
+            Http2ErrorCode::Unknown(code) => code,
+        }
+    }
+}
+
+/*
+* Http2帧头
+*/
+#[derive(Debug, Clone)]
+pub struct FrameHead {
+    pub len:        usize,      //帧负载长度
+    pub kind:       FrameType,  //帧类型
+    pub flags:      u8,         //帧标志
+    pub stream_id:  u32,        //流id
+}
+
+impl FrameHead {
+    //解析帧头，数据不足时返回空
+    pub fn parse(bin: &[u8]) -> Option<Self> {
+        if bin.len() < FRAME_HEAD_LEN {
+            return None;
+        }
+
+        Some(FrameHead {
+            len: ((bin[0] as usize) << 16) | ((bin[1] as usize) << 8) | bin[2] as usize,
+            kind: FrameType::from(bin[3]),
+            flags: bin[4],
+            stream_id: read_u32(&bin[5..9]) & MAX_WINDOW_SIZE,
+        })
+    }
+
+    //判断是否有指定的帧标志
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+}
+
+/*
+* Http2设置
+*/
+#[derive(Debug, Clone)]
+pub struct Http2Settings {
+    pub header_table_size:      u32,            //头压缩表大小
+    pub enable_push:            bool,           //是否允许服务器推送
+    pub max_concurrent_streams: Option<u32>,    //最大并发流数量，为空表示不限制
+    pub initial_window_size:    u32,            //流的初始流控窗口大小
+    pub max_frame_size:         u32,            //最大帧负载长度
+    pub max_header_list_size:   Option<u32>,    //最大头列表大小，为空表示不限制
+}
+
+impl Default for Http2Settings {
+    fn default() -> Self {
+        Http2Settings {
+            header_table_size: 4096,
+            enable_push: true,
+            max_concurrent_streams: None,
+            initial_window_size: DEFAULT_INITIAL_WINDOW_SIZE,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_header_list_size: None,
+        }
+    }
+}
+
+impl Http2Settings {
+    //解析设置帧负载，并更新当前设置
+    pub fn decode(&mut self, payload: &[u8]) -> GenResult<(), Http2ErrorCode> {
+        if payload.len() % 6 != 0 {
+            return Err(Http2ErrorCode::FrameSizeError);
+        }
+
+        for item in payload.chunks(6) {
+            let id = ((item[0] as u16) << 8) | item[1] as u16;
+            let value = read_u32(&item[2..6]);
+            match id {
+                SETTINGS_HEADER_TABLE_SIZE => self.header_table_size = value,
+                SETTINGS_ENABLE_PUSH => {
+                    if value > 1 {
+                        return Err(Http2ErrorCode::ProtocolError);
+                    }
+                    self.enable_push = value == 1;
+                },
+                SETTINGS_MAX_CONCURRENT_STREAMS => self.max_concurrent_streams = Some(value),
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    if value > MAX_WINDOW_SIZE {
+                        return Err(Http2ErrorCode::FlowControlError);
+                    }
+                    self.initial_window_size = value;
+                },
+                SETTINGS_MAX_FRAME_SIZE => {
+                    if value < DEFAULT_MAX_FRAME_SIZE || value > MAX_FRAME_SIZE_LIMIT {
+                        return Err(Http2ErrorCode::ProtocolError);
+                    }
+                    self.max_frame_size = value;
+                },
+                SETTINGS_MAX_HEADER_LIST_SIZE => self.max_header_list_size = Some(value),
+                _ => (), //忽略未知的设置项
+            }
+        }
+
+        Ok(())
+    }
+
+    //将当前设置序列化为设置帧负载
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        put_setting(&mut buf, SETTINGS_HEADER_TABLE_SIZE, self.header_table_size);
+        put_setting(&mut buf, SETTINGS_ENABLE_PUSH, self.enable_push as u32);
+        if let Some(max) = self.max_concurrent_streams {
+            put_setting(&mut buf, SETTINGS_MAX_CONCURRENT_STREAMS, max);
+        }
+        put_setting(&mut buf, SETTINGS_INITIAL_WINDOW_SIZE, self.initial_window_size);
+        put_setting(&mut buf, SETTINGS_MAX_FRAME_SIZE, self.max_frame_size);
+        if let Some(max) = self.max_header_list_size {
+            put_setting(&mut buf, SETTINGS_MAX_HEADER_LIST_SIZE, max);
+        }
+
+        buf
+    }
+}
+
+//序列化指定类型、标志、流id和负载的帧
+pub fn encode_frame(kind: FrameType, flags: u8, stream_id: u32, payload: &[u8]) -> Vec<u8> {
+    let len = payload.len();
+    let mut buf = Vec::with_capacity(FRAME_HEAD_LEN + len);
+
+    buf.put_u8((len >> 16) as u8);
+    buf.put_u8((len >> 8) as u8);
+    buf.put_u8(len as u8);
+    buf.put_u8(kind.into());
+    buf.put_u8(flags);
+    buf.put_u32(stream_id & MAX_WINDOW_SIZE);
+    buf.put_slice(payload);
+
+    buf
+}
+
+//序列化设置帧
+pub fn settings_frame(settings: &Http2Settings) -> Vec<u8> {
+    encode_frame(FrameType::Settings, 0, 0, &settings.encode())
+}
+
+//序列化设置确认帧
+pub fn settings_ack_frame() -> Vec<u8> {
+    encode_frame(FrameType::Settings, FLAG_ACK, 0, &[])
+}
+
+//序列化心跳确认帧
+pub fn ping_ack_frame(payload: &[u8]) -> Vec<u8> {
+    encode_frame(FrameType::Ping, FLAG_ACK, 0, payload)
+}
+
+//序列化流控窗口更新帧
+pub fn window_update_frame(stream_id: u32, increment: u32) -> Vec<u8> {
+    encode_frame(FrameType::WindowUpdate, 0, stream_id, &(increment & MAX_WINDOW_SIZE).to_be_bytes())
+}
+
+//序列化重置流帧
+pub fn rst_stream_frame(stream_id: u32, code: Http2ErrorCode) -> Vec<u8> {
+    let code: u32 = code.into();
+    encode_frame(FrameType::RstStream, 0, stream_id, &code.to_be_bytes())
+}
+
+//序列化关闭连接帧
+pub fn goaway_frame(last_stream_id: u32, code: Http2ErrorCode, debug: &[u8]) -> Vec<u8> {
+    let code: u32 = code.into();
+    let mut payload = Vec::with_capacity(8 + debug.len());
+    payload.put_u32(last_stream_id & MAX_WINDOW_SIZE);
+    payload.put_u32(code);
+    payload.put_slice(debug);
+
+    encode_frame(FrameType::GoAway, 0, 0, &payload)
+}
+
+//序列化数据帧
+pub fn data_frame(stream_id: u32, bin: &[u8], end_stream: bool) -> Vec<u8> {
+    let flags = if end_stream {
+        FLAG_END_STREAM
+    } else {
+        0
+    };
+
+    encode_frame(FrameType::Data, flags, stream_id, bin)
+}
+
+//序列化头块，头块超过最大帧负载长度时，会拆分为头帧和多个头块后续帧
+pub fn headers_frames(stream_id: u32, block: &[u8], end_stream: bool, max_frame_size: usize) -> Vec<Vec<u8>> {
+    let mut frames = Vec::new();
+    let mut chunks = block.chunks(max_frame_size.max(1)).peekable();
+    let mut kind = FrameType::Headers;
+    let mut flags = if end_stream {
+        FLAG_END_STREAM
+    } else {
+        0
+    };
+
+    if block.is_empty() {
+        frames.push(encode_frame(kind, flags | FLAG_END_HEADERS, stream_id, &[]));
+        return frames;
+    }
+
+    while let Some(chunk) = chunks.next() {
+        if chunks.peek().is_none() {
+            //最后一个头块片段
+            flags |= FLAG_END_HEADERS;
+        }
+
+        frames.push(encode_frame(kind, flags, stream_id, chunk));
+        kind = FrameType::Continuation;
+        flags = 0;
+    }
+
+    frames
+}
+
+//移除帧负载的填充，返回实际负载
+pub fn strip_padding(head: &FrameHead, payload: &[u8]) -> GenResult<(usize, usize), Http2ErrorCode> {
+    if !head.has_flag(FLAG_PADDED) {
+        return Ok((0, payload.len()));
+    }
+
+    if payload.is_empty() {
+        return Err(Http2ErrorCode::FrameSizeError);
+    }
+
+    let pad_len = payload[0] as usize;
+    if pad_len >= payload.len() {
+        //填充长度必须小于帧负载长度
+        return Err(Http2ErrorCode::ProtocolError);
+    }
+
+    Ok((1, payload.len() - pad_len))
+}
+
+//判断接收的数据是否是Http2连接，协商了Http2应用层协议或以Http2连接序言开始的连接是Http2连接，数据不足以判断时返回空
+pub fn is_http2(alpn: Option<&[u8]>, bin: &[u8]) -> Option<bool> {
+    if alpn == Some(HTTP2_ALPN) {
+        return Some(true);
+    }
+
+    if bin.len() < HTTP2_PREFACE.len() && HTTP2_PREFACE.starts_with(bin) {
+        return None;
+    }
+
+    Some(bin.starts_with(HTTP2_PREFACE))
+}
+
+//将Http2错误码转换为错误
+pub fn into_error(code: Http2ErrorCode, reason: &str) -> Error {
+    Error::new(ErrorKind::Other, format!("http2 connect failed, code: {:?}, reason: {}", code, reason))
+}
+
+//读取大端序的32位无符号整数
+pub fn read_u32(bin: &[u8]) -> u32 {
+    ((bin[0] as u32) << 24) | ((bin[1] as u32) << 16) | ((bin[2] as u32) << 8) | bin[3] as u32
+}
+
+//写入设置项
+fn put_setting(buf: &mut Vec<u8>, id: u16, value: u32) {
+    buf.put_u16(id);
+    buf.put_u32(value);
+}
@@ -14,11 +14,15 @@ extern crate https;
 extern crate httparse;
 extern crate httpdate;
 extern crate serde_json;
+extern crate toml;
 extern crate futures;
 extern crate parking_lot;
 extern crate crossbeam_channel;
 extern crate base64;
 extern crate flate2;
+extern crate brotli;
+#[cfg(feature = "zstd")]
+extern crate zstd;
 extern crate bytes;
 extern crate path_absolutize;
 extern crate log;
@@ -33,6 +37,8 @@ extern crate gray;
 extern crate file;
 extern crate atom;
 extern crate adler32;
+extern crate hpack;
+extern crate ring;
 
 pub mod server;
 pub mod acceptor;
@@ -42,6 +48,7 @@ pub mod gateway;
 pub mod service;
 pub mod route;
 pub mod middleware;
+pub mod error_page;
 pub mod cors_handler;
 pub mod default_parser;
 pub mod multi_parts;
@@ -51,6 +58,17 @@ pub mod files_load;
 pub mod batch_load;
 pub mod upload;
 pub mod port;
+pub mod sse;
+pub mod h2_frame;
+pub mod h2_connect;
+pub mod proxy;
+pub mod access_log;
+pub mod auth;
+pub mod cookie;
+pub mod session;
+pub mod rate_limit;
+pub mod gray_route;
+pub mod config;
 pub mod static_cache;
 pub mod request;
 pub mod response;
@@ -2,6 +2,10 @@ use std::sync::Arc;
 use std::str::FromStr;
 use std::convert::TryFrom;
 use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::fs::{create_dir_all, rename, copy, remove_file};
+use std::time::{SystemTime, Duration, UNIX_EPOCH};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::result::Result as GenResult;
 use std::io::{Error, Result, ErrorKind, Write};
 
@@ -21,16 +25,18 @@ use mime::{MULTIPART,
            Name};
 use twoway::{find_bytes, rfind_bytes};
 use httparse::{EMPTY_HEADER, Result as ParseResult, Status, parse_headers};
-use https::{header::{CONTENT_TYPE, CONTENT_DISPOSITION, HeaderName, HeaderValue}, StatusCode};
-use futures::future::{FutureExt, BoxFuture};
+use https::{header::{CONTENT_TYPE, CONTENT_DISPOSITION, CONNECTION, HeaderName, HeaderValue}, StatusCode};
+use futures::{SinkExt, channel::{mpsc, oneshot}, future::{FutureExt, BoxFuture}};
 
 use tcp::driver::{Socket, AsyncIOWait};
 use handler::SGenType;
+use r#async::rt::multi_thread::MultiTaskRuntime;
 
 use crate::{gateway::GatewayContext,
             middleware::{MiddlewareResult, Middleware},
             request::HttpRequest,
             response::HttpResponse,
+            upload::{spawn_part_writer, wait_part_writer, async_remove_part},
             util::HttpRecvResult};
 
 /*
@@ -74,11 +80,55 @@ const X_MSDOWNLOAD_MIME_SUBTYPE: &str = "x-msdownload";
 const MULTI_PARTS_NAME_PARAM: &str = "name";
 const MULTI_PARTS_FILE_NAME_PARAM: &str = "filename";
 
+/*
+* 流式分析时，单个部分头的最大长度
+*/
+const MAX_STREAM_PART_HEADER_SIZE: usize = 8 * 1024;
+
+/*
+* 多部分请求的部分头结束符
+*/
+const MULTI_PARTS_HEADER_END: &[u8] = b"\r\n\r\n";
+
+/*
+* 过滤后的文件名的最大长度
+*/
+const MAX_FILE_NAME_LEN: usize = 255;
+
+/*
+* 文件名中需要过滤的字符集
+*/
+const FILE_NAME_FILTER_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+
+/*
+* 流式分析时，单个部分的体数据的默认最大长度
+*/
+pub const DEFAULT_MULTI_PARTS_PART_LIMIT: usize = 64 * 1024 * 1024;
+
+/*
+* 流式分析时，所有部分的体数据的默认最大总长度
+*/
+pub const DEFAULT_MULTI_PARTS_TOTAL_LIMIT: usize = 256 * 1024 * 1024;
+
+/*
+* 流式分析时，默认的最大部分数量
+*/
+pub const DEFAULT_MULTI_PARTS_MAX_PARTS: usize = 128;
+
+/*
+* 临时文件计数，用于生成临时文件名
+*/
+static SPOOLED_FILE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
 /*
 * Http请求的多部分请求体分析器
 */
 pub struct MutilParts {
-    block_size: usize,  //每次读取的请求体块大小
+    block_size:     usize,                                  //每次读取的请求体块大小
+    spool:          Option<(MultiTaskRuntime<()>, PathBuf)>,//流式分析时，写入临时文件的异步文件运行时和临时目录
+    part_limit:     Option<usize>,                          //流式分析时，单个部分的体数据的最大长度
+    total_limit:    Option<usize>,                          //流式分析时，所有部分的体数据的最大总长度
+    max_parts:      Option<usize>,                          //流式分析时，最大部分数量
 }
 
 unsafe impl Send for MutilParts {}
@@ -96,6 +146,28 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for MutilParts
                         if mime.type_() == MULTIPART && mime.subtype() == FORM_DATA {
                             //当前请求体使用了多部分请求体，则分析，并写入参数表
                             if let Some(param) = mime.get_param(BOUNDARY) {
+                                if let Some(spool) = &self.spool {
+                                    //流式分析多部分请求体，文件部分写入临时文件
+                                    return match spool_parts(context, &mut request, param.as_str(), block_size, spool, self.part_limit, self.total_limit, self.max_parts).await {
+                                        Err(SpoolError::Other(e)) => {
+                                            MiddlewareResult::Throw(e)
+                                        },
+                                        Err(SpoolError::TooLarge) => {
+                                            //请求体超过限制，则立即返回响应，并关闭连接
+                                            let mut resp = HttpResponse::new(request.get_handle().clone(), request.get_waits().clone(), 1);
+                                            if let Some(body) = resp.as_mut_body() {
+                                                body.init();
+                                            }
+                                            resp.status(StatusCode::PAYLOAD_TOO_LARGE.as_u16())
+                                                .header(CONNECTION.as_str(), "close");
+                                            MiddlewareResult::Break(resp)
+                                        },
+                                        Ok(_) => {
+                                            MiddlewareResult::ContinueRequest(request)
+                                        },
+                                    };
+                                }
+
                                 //获取本次Http多部分请求体的分隔符
                                 let boundary_str = (MULTI_PARTS_COMMON_PREFIX_SUFFIX_BIN.to_string() + param.as_str() + MULTI_PARTS_LINE_BREAK);
                                 let boundary = boundary_str.as_bytes();
@@ -150,8 +222,523 @@ impl MutilParts {
     pub fn with(block_size: usize) -> Self {
         MutilParts {
             block_size,
+            spool: None,
+            part_limit: Some(DEFAULT_MULTI_PARTS_PART_LIMIT),
+            total_limit: Some(DEFAULT_MULTI_PARTS_TOTAL_LIMIT),
+            max_parts: Some(DEFAULT_MULTI_PARTS_MAX_PARTS),
+        }
+    }
+
+    //构建指定块大小和临时目录的流式Http多部分请求体分析器，文件部分会写入临时目录下的临时文件
+    pub fn with_spool<P: Into<PathBuf>>(block_size: usize,
+                                        files_async_runtime: MultiTaskRuntime<()>,
+                                        dir: P) -> Result<Self> {
+        let dir = dir.into();
+        if !dir.exists() {
+            if let Err(e) = create_dir_all(&dir) {
+                return Err(Error::new(ErrorKind::Other, format!("create multi parts spool failed, dir: {:?}, reason: {:?}", dir, e)));
+            }
         }
+
+        Ok(MutilParts {
+            block_size,
+            spool: Some((files_async_runtime, dir)),
+            part_limit: Some(DEFAULT_MULTI_PARTS_PART_LIMIT),
+            total_limit: Some(DEFAULT_MULTI_PARTS_TOTAL_LIMIT),
+            max_parts: Some(DEFAULT_MULTI_PARTS_MAX_PARTS),
+        })
+    }
+
+    //设置流式分析时，单个部分的体数据的最大长度，为空表示不限制
+    pub fn set_part_limit(&mut self, limit: Option<usize>) {
+        self.part_limit = limit;
     }
+
+    //设置流式分析时，所有部分的体数据的最大总长度，为空表示不限制
+    pub fn set_total_limit(&mut self, limit: Option<usize>) {
+        self.total_limit = limit;
+    }
+
+    //设置流式分析时，最大部分数量，为空表示不限制
+    pub fn set_max_parts(&mut self, max: Option<usize>) {
+        self.max_parts = max;
+    }
+}
+
+/*
+* 流式多部分请求体中的部分头
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartHead {
+    pub name:           String,         //表单参数名
+    pub is_file:        bool,           //是否是文件部分
+    pub filename:       Option<String>, //已过滤的客户端文件名
+    pub content_type:   Option<String>, //体数据类型
+}
+
+/*
+* 流式多部分请求体的分析事件
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum PartEvent {
+    Begin(PartHead),    //部分开始
+    Data(Vec<u8>),      //部分的体数据
+    End,                //部分结束
+}
+
+/*
+* 流式多部分请求体的分析状态
+*/
+enum ParseState {
+    Preamble,   //首个分隔符之前
+    Delimiter,  //分隔符之后
+    Headers,    //部分头
+    Body,       //部分的体数据
+    Finished,   //已分析到结尾分隔符
+}
+
+/*
+* 流式多部分请求体分析器，只缓冲可能是分隔符前缀的数据，部分的体数据会在分析后立即返回
+*/
+pub struct MultiPartsParser {
+    delimiter:  Vec<u8>,    //部分分隔符，包括前导的换行符
+    buf:        Vec<u8>,    //未分析的数据
+    state:      ParseState, //分析状态
+}
+
+impl MultiPartsParser {
+    //构建指定分隔符的流式多部分请求体分析器
+    pub fn new(boundary: &str) -> Self {
+        MultiPartsParser {
+            delimiter: [MULTI_PARTS_LINE_BREAK, MULTI_PARTS_COMMON_PREFIX_SUFFIX_BIN, boundary].concat().into_bytes(),
+            buf: Vec::from(MULTI_PARTS_LINE_BREAK.as_bytes()), //首个分隔符前没有换行符，则补充换行符，以统一所有分隔符
+            state: ParseState::Preamble,
+        }
+    }
+
+    //判断是否已分析到结尾分隔符
+    pub fn is_finished(&self) -> bool {
+        if let ParseState::Finished = self.state {
+            return true;
+        }
+
+        false
+    }
+
+    //分析后续的请求体数据，返回本次分析产生的事件
+    pub fn feed(&mut self, bin: &[u8]) -> Result<Vec<PartEvent>> {
+        self.buf.extend_from_slice(bin);
+
+        let mut events = Vec::new();
+        loop {
+            match self.state {
+                ParseState::Preamble => {
+                    if let Some(index) = find_bytes(&self.buf[..], &self.delimiter[..]) {
+                        self.buf.drain(..index + self.delimiter.len());
+                        self.state = ParseState::Delimiter;
+                    } else {
+                        //忽略首个分隔符之前的数据
+                        let len = self.buf.len().saturating_sub(self.delimiter.len() - 1);
+                        self.buf.drain(..len);
+                        break;
+                    }
+                },
+                ParseState::Delimiter => {
+                    if self.buf.len() < 2 {
+                        break;
+                    }
+
+                    if self.buf.starts_with(MULTI_PARTS_LINE_BREAK.as_bytes()) {
+                        //后续还有部分
+                        self.buf.drain(..2);
+                        self.state = ParseState::Headers;
+                    } else if self.buf.starts_with(MULTI_PARTS_COMMON_PREFIX_SUFFIX_BIN.as_bytes()) {
+                        //结尾分隔符，则忽略后续的数据
+                        self.buf.clear();
+                        self.state = ParseState::Finished;
+                    } else {
+                        return Err(Error::new(ErrorKind::InvalidData, "parse multi parts failed, reason: invalid boundary"));
+                    }
+                },
+                ParseState::Headers => {
+                    if self.buf.starts_with(MULTI_PARTS_LINE_BREAK.as_bytes()) {
+                        return Err(Error::new(ErrorKind::InvalidData, "parse multi parts headers failed, reason: empty headers"));
+                    }
+
+                    if let Some(index) = find_bytes(&self.buf[..], MULTI_PARTS_HEADER_END) {
+                        let head = parse_part_head(&self.buf[..index + MULTI_PARTS_HEADER_END.len()])?;
+                        self.buf.drain(..index + MULTI_PARTS_HEADER_END.len());
+                        events.push(PartEvent::Begin(head));
+                        self.state = ParseState::Body;
+                    } else {
+                        if self.buf.len() > MAX_STREAM_PART_HEADER_SIZE {
+                            return Err(Error::new(ErrorKind::InvalidData, format!("parse multi parts headers failed, len: {}, reason: headers too large", self.buf.len())));
+                        }
+                        break;
+                    }
+                },
+                ParseState::Body => {
+                    if let Some(index) = find_bytes(&self.buf[..], &self.delimiter[..]) {
+                        if index > 0 {
+                            events.push(PartEvent::Data(self.buf[..index].to_vec()));
+                        }
+                        self.buf.drain(..index + self.delimiter.len());
+                        events.push(PartEvent::End);
+                        self.state = ParseState::Delimiter;
+                    } else {
+                        //保留可能是分隔符前缀的数据，其余数据作为体数据返回
+                        let len = self.buf.len().saturating_sub(self.delimiter.len() - 1);
+                        if len > 0 {
+                            events.push(PartEvent::Data(self.buf.drain(..len).collect()));
+                        }
+                        break;
+                    }
+                },
+                ParseState::Finished => {
+                    self.buf.clear();
+                    break;
+                },
+            }
+        }
+
+        Ok(events)
+    }
+}
+
+//获取流式分析时，文件部分的客户端文件名在网关上下文的部分数据中的键，例如content部分的键为content.filename
+pub fn part_filename_key(name: &str) -> String {
+    format!("{}.{}", name, MULTI_PARTS_FILE_NAME_PARAM)
+}
+
+//过滤客户端文件名，只保留最后一级文件名，并移除控制字符和路径中的特殊字符，过滤后为空则返回空
+pub fn sanitize_filename(name: &str) -> Option<String> {
+    let name = name.rsplit(|c| c == '/' || c == '\\').next().unwrap_or("");
+    let filtered: String = name
+        .chars()
+        .filter(|c| !c.is_control() && !FILE_NAME_FILTER_CHARS.contains(c))
+        .collect();
+
+    let mut filtered = filtered.trim_matches(|c| c == ' ' || c == '.').to_string();
+    if filtered.is_empty() {
+        return None;
+    }
+
+    while filtered.len() > MAX_FILE_NAME_LEN {
+        filtered.pop();
+    }
+    Some(filtered)
+}
+
+/*
+* 流式分析时写入临时文件的文件部分，未持久化的临时文件会在释放时移除
+*/
+pub struct SpooledFile {
+    files_async_runtime:    MultiTaskRuntime<()>,   //异步文件运行时
+    head:                   PartHead,               //部分头
+    path:                   PathBuf,                //临时文件路径
+    size:                   u64,                    //文件大小
+    persisted:              AtomicBool,             //是否已持久化
+}
+
+unsafe impl Send for SpooledFile {}
+unsafe impl Sync for SpooledFile {}
+
+impl Drop for SpooledFile {
+    fn drop(&mut self) {
+        if !self.persisted.load(Ordering::Relaxed) {
+            //未持久化，则移除临时文件
+            async_remove_part(self.files_async_runtime.clone(), self.path.clone());
+        }
+    }
+}
+
+impl SpooledFile {
+    //获取表单参数名
+    pub fn name(&self) -> &str {
+        self.head.name.as_str()
+    }
+
+    //获取已过滤的客户端文件名
+    pub fn filename(&self) -> Option<&str> {
+        self.head.filename.as_ref().map(|name| name.as_str())
+    }
+
+    //获取文件类型
+    pub fn content_type(&self) -> Option<&str> {
+        self.head.content_type.as_ref().map(|mime| mime.as_str())
+    }
+
+    //获取临时文件路径
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    //获取文件大小
+    pub fn len(&self) -> u64 {
+        self.size
+    }
+
+    //判断是否已持久化
+    pub fn is_persisted(&self) -> bool {
+        self.persisted.load(Ordering::Relaxed)
+    }
+
+    //在异步文件运行时中将临时文件移动到指定路径，移动后不会再被移除，只允许持久化一次
+    pub async fn persist<P: Into<PathBuf>>(&self, to: P) -> Result<()> {
+        if self.persisted.swap(true, Ordering::SeqCst) {
+            return Err(Error::new(ErrorKind::Other, format!("persist spooled file failed, file: {:?}, reason: already persisted", self.path)));
+        }
+
+        let from = self.path.clone();
+        let to = to.into();
+        let (sender, receiver) = oneshot::channel();
+        if let Err(e) = self.files_async_runtime.spawn(self.files_async_runtime.alloc(), async move {
+            let _ = sender.send(move_file(&from, &to));
+        }) {
+            self.persisted.store(false, Ordering::SeqCst);
+            return Err(Error::new(ErrorKind::Other, format!("persist spooled file failed, file: {:?}, reason: {:?}", self.path, e)));
+        }
+
+        match receiver.await {
+            Err(e) => {
+                self.persisted.store(false, Ordering::SeqCst);
+                Err(Error::new(ErrorKind::Other, format!("persist spooled file failed, file: {:?}, reason: {:?}", self.path, e)))
+            },
+            Ok(Err(e)) => {
+                self.persisted.store(false, Ordering::SeqCst);
+                Err(e)
+            },
+            Ok(Ok(())) => Ok(()),
+        }
+    }
+}
+
+/*
+* 流式分析时的错误
+*/
+enum SpoolError {
+    TooLarge,       //超过长度或数量限制
+    Other(Error),   //其它错误
+}
+
+impl From<Error> for SpoolError {
+    fn from(e: Error) -> Self {
+        SpoolError::Other(e)
+    }
+}
+
+/*
+* 流式分析时正在接收的部分
+*/
+enum SpoolingPart {
+    Field(PartHead, Vec<u8>),                                                       //写入内存的部分
+    File(PartHead, PathBuf, u64, mpsc::Sender<Vec<u8>>, oneshot::Receiver<Result<u64>>), //写入临时文件的部分
+}
+
+//流式分析多部分请求体，普通部分写入网关上下文的部分数据中，文件部分写入临时文件后，将临时文件路径写入部分数据中，并将临时文件写入网关上下文
+async fn spool_parts<S: Socket, W: AsyncIOWait>(context: &mut GatewayContext,
+                                                req: &mut HttpRequest<S, W>,
+                                                boundary: &str,
+                                                block_size: usize,
+                                                spool: &(MultiTaskRuntime<()>, PathBuf),
+                                                part_limit: Option<usize>,
+                                                total_limit: Option<usize>,
+                                                max_parts: Option<usize>) -> GenResult<(), SpoolError> {
+    let mut current = None;
+    let r = spool_parts_body(context, req, boundary, block_size, spool, part_limit, total_limit, max_parts, &mut current).await;
+
+    if let Some(SpoolingPart::File(_, path, _, sender, result)) = current.take() {
+        //中止时有未完成的文件部分，则等待写入结束后移除临时文件
+        drop(sender);
+        let _ = wait_part_writer(result).await;
+        async_remove_part(spool.0.clone(), path);
+    }
+
+    r
+}
+
+//流式读取并分析多部分请求体
+async fn spool_parts_body<S: Socket, W: AsyncIOWait>(context: &mut GatewayContext,
+                                                     req: &mut HttpRequest<S, W>,
+                                                     boundary: &str,
+                                                     block_size: usize,
+                                                     spool: &(MultiTaskRuntime<()>, PathBuf),
+                                                     part_limit: Option<usize>,
+                                                     total_limit: Option<usize>,
+                                                     max_parts: Option<usize>,
+                                                     current: &mut Option<SpoolingPart>) -> GenResult<(), SpoolError> {
+    let (files_async_runtime, dir) = spool;
+    let mut parser = MultiPartsParser::new(boundary);
+    let mut total = 0;
+    let mut count = 0;
+    while let Some(bin) = req.next_body(block_size).await {
+        for event in parser.feed(bin)? {
+            match event {
+                PartEvent::Begin(head) => {
+                    count += 1;
+                    if max_parts.map(|max| count > max).unwrap_or(false) {
+                        //部分数量超过限制
+                        return Err(SpoolError::TooLarge);
+                    }
+
+                    if head.is_file {
+                        //文件部分，则写入临时文件
+                        let path = new_spool_path(dir);
+                        let (sender, result) = spawn_part_writer(files_async_runtime.clone(), path.clone(), 0)?;
+                        *current = Some(SpoolingPart::File(head, path, 0, sender, result));
+                    } else {
+                        *current = Some(SpoolingPart::Field(head, Vec::new()));
+                    }
+                },
+                PartEvent::Data(data) => {
+                    total += data.len();
+                    if let Some(limit) = total_limit {
+                        if total > limit {
+                            return Err(SpoolError::TooLarge);
+                        }
+                    }
+
+                    match current {
+                        Some(SpoolingPart::Field(_, buf)) => {
+                            if part_limit.map(|limit| buf.len() + data.len() > limit).unwrap_or(false) {
+                                return Err(SpoolError::TooLarge);
+                            }
+                            buf.extend_from_slice(&data[..]);
+                        },
+                        Some(SpoolingPart::File(_, _, size, sender, _)) => {
+                            *size += data.len() as u64;
+                            if part_limit.map(|limit| *size > limit as u64).unwrap_or(false) {
+                                return Err(SpoolError::TooLarge);
+                            }
+                            if sender.send(data).await.is_err() {
+                                //写入已失败，则获取写入错误
+                                if let Some(SpoolingPart::File(_, _, _, _, result)) = current.take() {
+                                    wait_part_writer(result).await?;
+                                }
+                                return Err(SpoolError::Other(Error::new(ErrorKind::Other, "spool multi parts failed, reason: writer closed")));
+                            }
+                        },
+                        None => (),
+                    }
+                },
+                PartEvent::End => {
+                    match current.take() {
+                        Some(SpoolingPart::Field(head, buf)) => {
+                            let value = match head.content_type.as_ref().and_then(|mime| Mime::from_str(mime).ok()) {
+                                Some(ref mime) if is_binary_mime(mime) => SGenType::Bin(buf),
+                                _ => SGenType::Str(String::from_utf8_lossy(&buf[..]).to_string()),
+                            };
+                            context.as_mut_parts().insert(head.name, value);
+                        },
+                        Some(SpoolingPart::File(head, path, size, sender, result)) => {
+                            drop(sender);
+                            if let Err(e) = wait_part_writer(result).await {
+                                async_remove_part(files_async_runtime.clone(), path);
+                                return Err(SpoolError::Other(e));
+                            }
+
+                            let parts = context.as_mut_parts();
+                            parts.insert(head.name.clone(), SGenType::Str(path.to_string_lossy().to_string()));
+                            if let Some(filename) = &head.filename {
+                                parts.insert(part_filename_key(&head.name), SGenType::Str(filename.clone()));
+                            }
+                            context.insert_file(SpooledFile {
+                                files_async_runtime: files_async_runtime.clone(),
+                                head,
+                                path,
+                                size,
+                                persisted: AtomicBool::new(false),
+                            });
+                        },
+                        None => (),
+                    }
+                },
+            }
+        }
+    }
+
+    if !parser.is_finished() {
+        return Err(SpoolError::Other(Error::new(ErrorKind::UnexpectedEof, "parse multi parts failed, reason: body not finished")));
+    }
+
+    Ok(())
+}
+
+//移动文件，无法直接移动时，例如跨文件系统，则复制后移除
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if rename(from, to).is_err() {
+        if let Err(e) = copy(from, to) {
+            return Err(Error::new(ErrorKind::Other, format!("persist spooled file failed, file: {:?}, to: {:?}, reason: {:?}", from, to, e)));
+        }
+        let _ = remove_file(from);
+    }
+
+    Ok(())
+}
+
+//生成临时文件路径
+fn new_spool_path(dir: &Path) -> PathBuf {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0));
+    dir.join(format!("{:x}{:04x}.part", now.as_nanos(), SPOOLED_FILE_COUNT.fetch_add(1, Ordering::Relaxed) & 0xffff))
+}
+
+//解析流式多部分请求体中的部分头
+fn parse_part_head(bin: &[u8]) -> Result<PartHead> {
+    let mut headers = [EMPTY_HEADER; MAX_MUTIL_PARTS_HTTP_HEADER_LIMIT];
+    let headers = match parse_headers(bin, &mut headers) {
+        Err(e) => {
+            return Err(Error::new(ErrorKind::InvalidData, format!("parse multi parts headers failed, reason: {:?}", e)));
+        },
+        Ok(Status::Complete((_, headers))) => headers,
+        Ok(Status::Partial) => {
+            return Err(Error::new(ErrorKind::InvalidData, "parse multi parts headers failed, reason: part not enough"));
+        },
+    };
+
+    let mut name = None;
+    let mut filename = None;
+    let mut content_type = None;
+    for header in headers {
+        let value = String::from_utf8_lossy(header.value);
+        if header.name.eq_ignore_ascii_case(CONTENT_DISPOSITION.as_str()) {
+            for param in value.split(MULTI_PARTS_PARAM_SPILT_CHAR) {
+                let mut pair = param.splitn(2, MULTI_PARTS_PAIR_SPILT_CHAR);
+                let k = pair.next().unwrap_or("").trim_matches(MULTI_PARTS_FILTER_CHARS);
+                if let Some(v) = pair.next() {
+                    let v = v.trim_matches(MULTI_PARTS_FILTER_CHARS);
+                    match k {
+                        MULTI_PARTS_NAME_PARAM => name = Some(v.to_string()),
+                        MULTI_PARTS_FILE_NAME_PARAM => filename = Some(sanitize_filename(v)),
+                        _ => continue,
+                    }
+                }
+            }
+        } else if header.name.eq_ignore_ascii_case(CONTENT_TYPE.as_str()) {
+            content_type = Some(value.trim().to_string());
+        }
+    }
+
+    match name {
+        None => Err(Error::new(ErrorKind::InvalidData, "parse multi parts headers failed, reason: empty name")),
+        Some(name) => Ok(PartHead {
+            name,
+            is_file: filename.is_some(),
+            filename: filename.and_then(|name| name),
+            content_type,
+        }),
+    }
+}
+
+//判断指定类型的体数据是否是二进制数据
+fn is_binary_mime(mime: &Mime) -> bool {
+    mime.type_() == AUDIO
+        || mime.type_() == VIDEO
+        || mime.type_() == IMAGE
+        || mime.type_() == FONT
+        || (mime.type_() == APPLICATION
+        && (mime.subtype() == OCTET_STREAM || mime.subtype() == PDF || mime.subtype().as_str() == X_MSDOWNLOAD_MIME_SUBTYPE))
 }
 
 //解析二进制数据，从中分析出多部分请求中的部分，中间数据存储在当前Http连接的网关上下文中，返回false，表示没有分析到完整的部分，需要继续读取数据后再次解析，否则返回已解析的部分
@@ -281,12 +868,7 @@ fn parse_part_headers_body<'a>(context: &'a mut GatewayContext, part: &'a [u8])
                                         //表示当前部分的体数据有指定类型
                                         if let Some(name_key) = name_key.take() {
                                             if let Ok(mime) = Mime::from_str(value_str) {
-                                                if mime.type_() == AUDIO
-                                                    || mime.type_() == VIDEO
-                                                    || mime.type_() == IMAGE
-                                                    || mime.type_() == FONT
-                                                    || (mime.type_() == APPLICATION
-                                                    && (mime.subtype() == OCTET_STREAM || mime.subtype() == PDF || mime.subtype().as_str() == X_MSDOWNLOAD_MIME_SUBTYPE)) {
+                                                if is_binary_mime(&mime) {
                                                     //写入二进制的体数据
                                                     context_parts.insert(name_key, SGenType::Bin(Vec::from(&part[offset..(part.len() - MULTI_PARTS_LINE_BREAK.len())])));
                                                 } else {
@@ -20,7 +20,8 @@ use log::warn;
 
 use tcp::driver::{Socket, AsyncIOWait, SocketHandle, AsyncReadTask, AsyncWriteTask};
 
-use crate::{util::{DEFAULT_SUPPORT_HTTP_VERSION, HttpSender, HttpReceiver, channel}};
+use crate::{util::{DEFAULT_SUPPORT_HTTP_VERSION, HttpSender, HttpReceiver, channel},
+            server::HttpLimits};
 use tcp::connect::TcpSocket;
 use tcp::server::AsyncWaitsHandle;
 
@@ -29,6 +30,16 @@ use tcp::server::AsyncWaitsHandle;
 */
 pub const DEFAULT_READ_READY_HTTP_REQUEST_BYTE_LEN: usize = 0;
 
+/*
+* 分块请求体中，块长度行或尾部头行的最大长度
+*/
+const MAX_CHUNKED_LINE_LIMIT: usize = 4096;
+
+/*
+* 分块请求体中，尾部头的最大数量
+*/
+const MAX_CHUNKED_TRAILER_LIMIT: usize = 32;
+
 /*
 * 上行请求头
 */
@@ -38,19 +49,32 @@ unsafe impl Send for UpStreamHeader {}
 unsafe impl Sync for UpStreamHeader {}
 
 impl UpStreamHeader {
-    //读请求，并解析报文头
+    //读请求，并解析报文头，超过限制则回应错误，并关闭当前Http连接
     pub fn read_header<'h, 'b, S, W>(handle: SocketHandle<S>,
                                       waits: W,
                                       buf: &'b [u8],
                                       req: &mut Request<'h, 'b>,
-                                      headers: &mut HeaderMap) -> Option<usize>
+                                      headers: &mut HeaderMap,
+                                      limits: &HttpLimits) -> Option<usize>
         where 'b: 'h,
               S: Socket,
               W: AsyncIOWait {
+        let line_len = buf.iter().position(|b| *b == b'\n').unwrap_or(buf.len());
+        if line_len > limits.max_request_line {
+            //请求行过长
+            reply_status(&handle, StatusCode::URI_TOO_LONG, Error::new(ErrorKind::Other, format!("http server parse header failed, len: {}, limit: {}, reason: request line too long", line_len, limits.max_request_line)));
+            return None;
+        }
+
         match req.parse(buf) {
+            Err(httparse::Error::TooManyHeaders) => {
+                //Http头数量过多
+                reply_status(&handle, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE, Error::new(ErrorKind::Other, "http server parse header failed, reason: too many headers"));
+                return None;
+            },
             Err(e) => {
                 //解析Http头错误
-                handle.close(Err(Error::new(ErrorKind::Other, format!("http server parse header failed, reason: {:?}", e))));
+                reply_status(&handle, StatusCode::BAD_REQUEST, Error::new(ErrorKind::Other, format!("http server parse header failed, reason: {:?}", e)));
                 return None;
             },
             Ok(ref status) if status.is_partial() => {
@@ -61,6 +85,11 @@ impl UpStreamHeader {
                         handle.close(Err(Error::new(ErrorKind::Other, format!("http server parse header failed, version: {}, reason: not support http version", ver))));
                         return None;
                     },
+                    _ if buf.len() > limits.max_header_bytes => {
+                        //头数据不完整，且已超过限制
+                        reply_status(&handle, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE, Error::new(ErrorKind::Other, format!("http server parse header failed, len: {}, limit: {}, reason: header too large", buf.len(), limits.max_header_bytes)));
+                        return None;
+                    },
                     _ => {
                         //头数据不完整，则继续读
                         return None;
@@ -70,6 +99,12 @@ impl UpStreamHeader {
             Ok(status) => {
                 //全部头数据已到达，则继续读取，并解析体数据
                 if let Status::Complete(len) = status {
+                    if len > limits.max_header_bytes {
+                        //头数据超过限制
+                        reply_status(&handle, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE, Error::new(ErrorKind::Other, format!("http server parse header failed, len: {}, limit: {}, reason: header too large", len, limits.max_header_bytes)));
+                        return None;
+                    }
+
                     if let Err(e) = fill_headers(headers, req) {
                         handle.close(Err(e));
                         return None;
@@ -108,4 +143,239 @@ pub fn fill_headers<'h, 'b>(headers: &mut HeaderMap, req: & mut Request<'h, 'b>)
     }
 
     Ok(count)
-}
\ No newline at end of file
+}
+
+/*
+* 分块解码器状态
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ChunkedState {
+    Size,           //等待块长度行
+    Data(usize),    //等待块数据，值为当前块的剩余长度
+    DataEnd,        //等待块数据后的换行
+    Trailer,        //等待尾部头或结束空行
+    Done,           //分块数据已结束
+}
+
+/*
+* Http分块传输编码的解码器，支持块扩展和尾部头
+*/
+pub struct ChunkedDecoder {
+    state:      ChunkedState,   //解码状态
+    raw:        Vec<u8>,        //未解码的原始数据
+    trailers:   HeaderMap,      //已解码的尾部头
+}
+
+unsafe impl Send for ChunkedDecoder {}
+unsafe impl Sync for ChunkedDecoder {}
+
+impl ChunkedDecoder {
+    //构建分块解码器
+    pub fn new() -> Self {
+        ChunkedDecoder {
+            state: ChunkedState::Size,
+            raw: Vec::new(),
+            trailers: HeaderMap::new(),
+        }
+    }
+
+    //判断分块数据是否已结束
+    pub fn is_done(&self) -> bool {
+        self.state == ChunkedState::Done
+    }
+
+    //获取已解码的尾部头，只有分块数据结束后才会返回
+    pub fn trailers(&self) -> Option<&HeaderMap> {
+        if self.is_done() {
+            return Some(&self.trailers);
+        }
+
+        None
+    }
+
+    //在未解码的原始数据尾部，增加数据
+    pub fn push(&mut self, bin: &[u8]) {
+        self.raw.put_slice(bin);
+    }
+
+    //取出分块数据结束后剩余的原始数据
+    pub fn take_remaining(&mut self) -> Vec<u8> {
+        if self.is_done() {
+            return std::mem::replace(&mut self.raw, Vec::new());
+        }
+
+        Vec::new()
+    }
+
+    //解码已到达的原始数据，并将解码后的数据写入输出缓冲区的尾部，输出缓冲区长度达到指定的最大长度后会暂停解码
+    pub fn decode(&mut self, out: &mut Vec<u8>, max: usize) -> Result<()> {
+        let mut offset = 0; //已解码的原始数据偏移
+        loop {
+            match self.state {
+                ChunkedState::Size => {
+                    match find_line(&self.raw[offset..]) {
+                        None => {
+                            //块长度行不完整
+                            if self.raw.len() - offset > MAX_CHUNKED_LINE_LIMIT {
+                                return Err(Error::new(ErrorKind::InvalidData, "decode chunked failed, reason: chunk size line too long"));
+                            }
+                            break;
+                        },
+                        Some(end) => {
+                            let size = parse_chunk_size(&self.raw[offset..offset + end])?;
+                            offset += end + 2;
+                            if size == 0 {
+                                //最后一个块，则开始解码尾部头
+                                self.state = ChunkedState::Trailer;
+                            } else {
+                                self.state = ChunkedState::Data(size);
+                            }
+                        },
+                    }
+                },
+                ChunkedState::Data(remaining) => {
+                    let available = self.raw.len() - offset;
+                    let free = max.saturating_sub(out.len());
+                    if available == 0 || free == 0 {
+                        //没有可解码的块数据，或输出缓冲区已满
+                        break;
+                    }
+
+                    let len = remaining.min(available).min(free);
+                    out.put_slice(&self.raw[offset..offset + len]);
+                    offset += len;
+                    if len == remaining {
+                        //当前块数据已解码完成
+                        self.state = ChunkedState::DataEnd;
+                    } else {
+                        self.state = ChunkedState::Data(remaining - len);
+                    }
+                },
+                ChunkedState::DataEnd => {
+                    if self.raw.len() - offset < 2 {
+                        //块数据后的换行不完整
+                        break;
+                    }
+
+                    if &self.raw[offset..offset + 2] != b"\r\n" {
+                        return Err(Error::new(ErrorKind::InvalidData, "decode chunked failed, reason: invalid chunk data end"));
+                    }
+                    offset += 2;
+                    self.state = ChunkedState::Size;
+                },
+                ChunkedState::Trailer => {
+                    match find_line(&self.raw[offset..]) {
+                        None => {
+                            //尾部头行不完整
+                            if self.raw.len() - offset > MAX_CHUNKED_LINE_LIMIT {
+                                return Err(Error::new(ErrorKind::InvalidData, "decode chunked failed, reason: trailer line too long"));
+                            }
+                            break;
+                        },
+                        Some(0) => {
+                            //结束空行，则分块数据已结束
+                            offset += 2;
+                            self.state = ChunkedState::Done;
+                        },
+                        Some(end) => {
+                            if self.trailers.len() >= MAX_CHUNKED_TRAILER_LIMIT {
+                                return Err(Error::new(ErrorKind::InvalidData, "decode chunked failed, reason: too many trailers"));
+                            }
+
+                            let (key, value) = parse_trailer(&self.raw[offset..offset + end])?;
+                            self.trailers.append(key, value);
+                            offset += end + 2;
+                        },
+                    }
+                },
+                ChunkedState::Done => break,
+            }
+        }
+
+        self.raw.drain(..offset);
+        Ok(())
+    }
+}
+
+//查找行尾的换行，返回行的长度
+fn find_line(bin: &[u8]) -> Option<usize> {
+    bin.windows(2).position(|w| w == b"\r\n")
+}
+
+//解析块长度行，忽略块扩展
+fn parse_chunk_size(line: &[u8]) -> Result<usize> {
+    let size = match line.iter().position(|b| *b == b';') {
+        Some(index) => &line[..index],
+        None => line,
+    };
+
+    match std::str::from_utf8(size) {
+        Ok(str) if !str.trim().is_empty() => {
+            usize::from_str_radix(str.trim(), 16)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, format!("decode chunked failed, size: {:?}, reason: {:?}", str, e)))
+        },
+        _ => {
+            Err(Error::new(ErrorKind::InvalidData, "decode chunked failed, reason: invalid chunk size"))
+        },
+    }
+}
+
+//解析尾部头行
+fn parse_trailer(line: &[u8]) -> Result<(HeaderName, HeaderValue)> {
+    if let Some(index) = line.iter().position(|b| *b == b':') {
+        if let Ok(key) = HeaderName::from_bytes(&line[..index]) {
+            let value = &line[index + 1..];
+            let start = value.iter().position(|b| *b != b' ' && *b != b'\t').unwrap_or(value.len());
+            let end = value.iter().rposition(|b| *b != b' ' && *b != b'\t').map_or(start, |i| i + 1);
+            if let Ok(value) = HeaderValue::from_bytes(&value[start..end]) {
+                return Ok((key, value));
+            }
+        }
+    }
+
+    Err(Error::new(ErrorKind::InvalidData, format!("decode chunked failed, trailer: {:?}, reason: invalid trailer", String::from_utf8_lossy(line))))
+}
+
+//回应指定状态码的错误，并关闭当前Http连接，用于在Http请求交给服务前拒绝请求
+pub fn reply_status<S: Socket>(handle: &SocketHandle<S>, status: StatusCode, reason: Error) -> Result<()> {
+    let body = reason.to_string().into_bytes();
+    reply_page(handle, status, reason, None, body)
+}
+
+//回应指定状态码的错误页，并关闭当前Http连接，用于在Http请求交给服务前使用虚拟主机的错误页拒绝请求
+pub fn reply_page<S: Socket>(handle: &SocketHandle<S>, status: StatusCode, reason: Error, mime: Option<&str>, body: Vec<u8>) -> Result<()> {
+    let headers = if let Some(mime) = mime {
+        format!("Content-Type: {}\r\n", mime)
+    } else {
+        String::new()
+    };
+    write_status(handle, status, headers, body);
+
+    handle.close(Err(reason))
+}
+
+//回应指定状态码和目标地址的重定向，并关闭当前Http连接，用于在Http请求交给服务前重定向请求
+pub fn reply_redirect<S: Socket>(handle: &SocketHandle<S>, status: StatusCode, location: &str) -> Result<()> {
+    write_status(handle, status, format!("Location: {}\r\n", location), Vec::new());
+
+    handle.close(Ok(()))
+}
+
+//发送指定状态码、附加响应头和响应体的响应，响应会要求对端关闭连接
+fn write_status<S: Socket>(handle: &SocketHandle<S>, status: StatusCode, headers: String, body: Vec<u8>) {
+    if let Ok(Some(mut buf)) = handle.alloc() {
+        let head = format!("HTTP/1.1 {} {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                           status.as_u16(),
+                           status.canonical_reason().unwrap_or(""),
+                           headers,
+                           body.len());
+        buf.get_iolist_mut().push_back(head.into_bytes().into());
+        buf.get_iolist_mut().push_back(body.into());
+
+        if let Some(buf_handle) = buf.finish() {
+            if let Err(e) = handle.write_ready(buf_handle) {
+                warn!("!!!> Http Reply Status Failed, status: {:?}, reason: {:?}", status, e);
+            }
+        }
+    }
+}
@@ -17,6 +17,8 @@ use hash::XHashMap;
 
 use crate::{gateway::GatewayContext,
             middleware::{MiddlewareResult, Middleware},
+            auth::PRINCIPAL_PARAM,
+            session::SESSION_PARAM,
             request::HttpRequest,
             response::{ResponseHandler, HttpResponse},
             util::HttpRecvResult};
@@ -82,7 +84,7 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for HttpPort<S>
         let future = async move {
             //处理请求
             let uid = req.get_handle().get_uid(); //获取当前http连接的唯一id
-            let gray = self.get_gray(); //获取当前灰度
+            let gray = context.get_gray().or_else(|| self.get_gray()); //获取当前请求选择的灰度，未选择则使用端口的灰度
             let remote_addr = req.get_handle().get_remote().clone(); //获取当前http连接的对端地址
             let headers = req.share_headers(); //获取当前http请求头
             let args = context.as_params().clone(); //获取http请求参数或请求体
@@ -103,6 +105,19 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for HttpPort<S>
                 }
             }
 
+            //检查是否有已认证的主体
+            if let Some(principal) = context.get_principal() {
+                //有已认证的主体，则填充到参数中
+                args.borrow_mut().insert(PRINCIPAL_PARAM.to_string(), SGenType::Str(principal.name().to_string()));
+            }
+
+            //检查是否有会话
+            if let Some(session) = context.get_session() {
+                //有会话，则将会话数据填充到参数中
+                let data = serde_json::to_string(session.as_data()).unwrap_or_default();
+                args.borrow_mut().insert(SESSION_PARAM.to_string(), SGenType::Str(data));
+            }
+
             let resp = HttpResponse::new(req.get_handle().clone(), req.get_waits().clone(), 1);
             if let Some(resp_handler) = resp.get_response_handler() {
                 let http_gray = HttpGray {
@@ -136,6 +151,25 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for HttpPort<S>
                             for (_index, bin) in bodys {
                                 body_bufs.push(bin);
                             }
+
+                            if body.is_stream() {
+                                //当前响应为流式响应，则将已获取的响应体块交给Http连接发送，并立即继续响应处理
+                                for buf in body_bufs {
+                                    body.pend(buf);
+                                }
+                                break;
+                            }
+                        },
+                        HttpRecvResult::Fin(bodys) if body.is_stream() => {
+                            //获取到的是流式响应的响应体块的尾部，则将已获取的响应体块交给Http连接发送，并立即继续响应处理
+                            for buf in body_bufs {
+                                body.pend(buf);
+                            }
+                            for (_index, bin) in bodys {
+                                body.pend(bin);
+                            }
+                            body.pend_fin();
+                            break;
                         },
                         HttpRecvResult::Fin(bodys) => {
                             //获取到的是Http响应体块的尾部，处理后退出循环
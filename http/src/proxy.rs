@@ -0,0 +1,1249 @@
+use std::thread;
+use std::sync::{Arc, Weak};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+use std::io::{Error, Result, ErrorKind, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use regex::Regex;
+use bytes::BufMut;
+use httparse::{EMPTY_HEADER, Response, Status};
+use https::{Method, StatusCode,
+            header::{HeaderName, HeaderValue, HeaderMap,
+                     HOST, CONNECTION, CONTENT_LENGTH, TRANSFER_ENCODING, TE, TRAILER, UPGRADE, EXPECT,
+                     PROXY_AUTHENTICATE, PROXY_AUTHORIZATION, VIA}};
+use futures::{SinkExt, StreamExt,
+              channel::{mpsc, oneshot},
+              future::{FutureExt, BoxFuture}};
+use parking_lot::Mutex;
+use log::warn;
+
+use tcp::driver::{Socket, AsyncIOWait};
+use r#async::rt::multi_thread::MultiTaskRuntime;
+
+use crate::{gateway::GatewayContext,
+            middleware::{MiddlewareResult, Middleware},
+            request::HttpRequest,
+            response::{ResponseHandler, HttpResponse},
+            packet::ChunkedDecoder,
+            util::{IpRange, forwarded_for}};
+
+/*
+* 代理时转发的请求头
+*/
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+
+/*
+* 逐跳头，不会被代理转发
+*/
+const KEEP_ALIVE_HEADER: &str = "keep-alive";
+const PROXY_CONNECTION_HEADER: &str = "proxy-connection";
+
+/*
+* 分块传输编码名
+*/
+const CHUNKED_TRANSFER_ENCODING: &str = "chunked";
+
+/*
+* 分块传输的结束块
+*/
+const LAST_CHUNK: &[u8] = b"0\r\n\r\n";
+
+/*
+* 默认的代理标识，用于Via头
+*/
+const DEFAULT_PROXY_NAME: &str = "pi_http";
+
+/*
+* 上游响应头的最大数量
+*/
+const MAX_UPSTREAM_HEADER_LIMIT: usize = 64;
+
+/*
+* 上游响应头的最大长度，64KB
+*/
+const MAX_UPSTREAM_HEAD_LEN: usize = 64 * 1024;
+
+/*
+* 代理转发的默认块大小，16KB
+*/
+const DEFAULT_PROXY_BLOCK_SIZE: usize = 16 * 1024;
+
+/*
+* 代理请求体的缓冲块数量，缓冲满后会暂停读取客户端请求体，直到上游交换发送
+*/
+const PROXY_REQ_BODY_BUFFER_LEN: usize = 2;
+
+/*
+* 代理响应体的缓冲块数量，缓冲满后会暂停读取上游响应体，直到客户端接收
+*/
+const PROXY_RESP_BODY_BUFFER_LEN: usize = 8;
+
+/*
+* 代理默认的超时时长，单位毫秒
+*/
+const DEFAULT_CONNECT_TIMEOUT: u64 = 3000;
+const DEFAULT_READ_TIMEOUT: u64 = 30000;
+const DEFAULT_WRITE_TIMEOUT: u64 = 30000;
+
+/*
+* 代理默认的最大并发交换数量，超过后的请求会立即回应服务不可用，避免慢速的上游服务器占满代理异步运行时
+*/
+const DEFAULT_MAX_EXCHANGES: usize = 256;
+
+/*
+* 上游服务器默认的被动健康检查参数，连续失败指定次数后，在指定时长内不会被选择，单位毫秒
+*/
+const DEFAULT_MAX_FAILS: usize = 3;
+const DEFAULT_FAIL_TIMEOUT: u64 = 10000;
+
+/*
+* 上游服务器默认的空闲连接参数，单位毫秒
+*/
+const DEFAULT_MAX_IDLE: usize = 32;
+const DEFAULT_IDLE_TIMEOUT: u64 = 60000;
+
+/*
+* 上游服务器的负载均衡策略
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BalanceStrategy {
+    RoundRobin,         //按权重轮询
+    LeastConnections,   //最少活动连接
+    IpHash,             //按客户端地址散列，相同的客户端会尽量选择相同的上游服务器
+}
+
+/*
+* 请求路径的重写规则，按增加顺序依次执行
+*/
+#[derive(Debug, Clone)]
+pub enum PathRewrite {
+    StripPrefix(String),    //移除指定的路径前缀
+    AddPrefix(String),      //增加指定的路径前缀
+    Replace(Regex, String), //使用正则表达式替换路径，替换内容支持$1或${name}形式的捕获引用
+}
+
+impl PathRewrite {
+    //重写指定的路径
+    pub fn rewrite(&self, path: &str) -> String {
+        match self {
+            PathRewrite::StripPrefix(prefix) => {
+                if path.starts_with(prefix.as_str()) {
+                    let path = &path[prefix.len()..];
+                    if path.starts_with('/') {
+                        path.to_string()
+                    } else {
+                        "/".to_string() + path
+                    }
+                } else {
+                    path.to_string()
+                }
+            },
+            PathRewrite::AddPrefix(prefix) => {
+                prefix.trim_end_matches('/').to_string() + path
+            },
+            PathRewrite::Replace(regex, replacement) => {
+                regex.replace(path, replacement.as_str()).into_owned()
+            },
+        }
+    }
+}
+
+/*
+* 上游服务器的主动健康检查配置
+*/
+#[derive(Debug, Clone)]
+pub struct HealthCheck {
+    interval:   u64,            //检查间隔时长，单位毫秒
+    timeout:    u64,            //检查超时时长，单位毫秒
+    path:       Option<String>, //检查的请求路径，为空表示只检查是否可以连接
+    rise:       usize,          //不可用的上游服务器连续检查成功指定次数后，恢复为可用
+    fall:       usize,          //可用的上游服务器连续检查失败指定次数后，标记为不可用
+}
+
+impl HealthCheck {
+    //构建指定检查间隔时长和超时时长的健康检查配置，单位毫秒
+    pub fn new(interval: u64, timeout: u64) -> Self {
+        HealthCheck {
+            interval,
+            timeout,
+            path: None,
+            rise: 2,
+            fall: 3,
+        }
+    }
+
+    //设置检查的请求路径，上游服务器回应2xx或3xx时表示检查成功
+    pub fn set_path(&mut self, path: &str) -> &mut Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    //设置恢复为可用和标记为不可用需要的连续检查次数
+    pub fn set_threshold(&mut self, rise: usize, fall: usize) -> &mut Self {
+        self.rise = rise.max(1);
+        self.fall = fall.max(1);
+        self
+    }
+}
+
+/*
+* 上游服务器
+*/
+struct Upstream {
+    addr:           SocketAddr,                     //上游服务器地址
+    host:           String,                         //上游服务器的主机名，用于Host头
+    weight:         usize,                          //权重
+    active:         AtomicUsize,                    //活动连接数
+    fails:          AtomicUsize,                    //连续失败次数
+    down_until:     Mutex<Option<Instant>>,         //被动健康检查标记的不可用截止时间
+    is_down:        AtomicBool,                     //主动健康检查是否标记为不可用
+    checks:         AtomicUsize,                    //主动健康检查的连续成功或失败次数
+    idle:           Mutex<Vec<(TcpStream, Instant)>>, //空闲连接，包括连接和放入空闲连接的时间
+}
+
+impl Upstream {
+    //判断上游服务器是否可用
+    fn is_available(&self) -> bool {
+        if self.is_down.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        match *self.down_until.lock() {
+            Some(until) => Instant::now() >= until,
+            None => true,
+        }
+    }
+
+    //记录一次失败，连续失败达到指定次数后，在指定时长内标记为不可用
+    fn fail(&self, max_fails: usize, fail_timeout: Duration) {
+        let fails = self.fails.fetch_add(1, Ordering::Relaxed) + 1;
+        if fails >= max_fails {
+            self.fails.store(0, Ordering::Relaxed);
+            *self.down_until.lock() = Some(Instant::now() + fail_timeout);
+            warn!("!!!> Http Proxy Upstream Down, addr: {:?}, fails: {}", self.addr, fails);
+        }
+    }
+
+    //记录一次成功
+    fn success(&self) {
+        self.fails.store(0, Ordering::Relaxed);
+    }
+
+    //取出一个未超时且未被上游服务器关闭的空闲连接
+    fn take_idle(&self, idle_timeout: Duration) -> Option<TcpStream> {
+        let mut idle = self.idle.lock();
+        while let Some((stream, time)) = idle.pop() {
+            if time.elapsed() < idle_timeout && is_alive(&stream) {
+                return Some(stream);
+            }
+        }
+
+        None
+    }
+
+    //放入空闲连接
+    fn put_idle(&self, stream: TcpStream, max_idle: usize) {
+        let mut idle = self.idle.lock();
+        if idle.len() < max_idle {
+            idle.push((stream, Instant::now()));
+        }
+    }
+}
+
+/*
+* 上游服务器的活动连接守护者，销毁时减少活动连接数
+*/
+struct ActiveGuard(Arc<Upstream>);
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/*
+* 上游交换的许可，销毁时减少并发交换数
+*/
+struct ExchangePermit(Arc<AtomicUsize>);
+
+impl Drop for ExchangePermit {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/*
+* 上游服务器组
+*/
+struct UpstreamGroup {
+    upstreams:      Vec<Arc<Upstream>>, //上游服务器列表
+    strategy:       BalanceStrategy,    //负载均衡策略
+    cursor:         AtomicUsize,        //轮询游标
+    max_fails:      usize,              //被动健康检查的最大连续失败次数
+    fail_timeout:   Duration,           //被动健康检查标记的不可用时长
+    max_idle:       usize,              //每个上游服务器的最大空闲连接数
+    idle_timeout:   Duration,           //空闲连接的超时时长
+}
+
+impl UpstreamGroup {
+    //按负载均衡策略选择可用的上游服务器，已尝试的上游服务器会被跳过，所有上游服务器都不可用时返回空
+    fn select(&self, client: &SocketAddr, tried: &[usize]) -> Option<usize> {
+        let candidates: Vec<usize> = (0..self.upstreams.len())
+            .filter(|index| !tried.contains(index) && self.upstreams[*index].is_available())
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+
+        match self.strategy {
+            BalanceStrategy::RoundRobin => {
+                let total: usize = candidates.iter().map(|index| self.upstreams[*index].weight).sum();
+                let mut point = self.cursor.fetch_add(1, Ordering::Relaxed) % total.max(1);
+                for index in &candidates {
+                    let weight = self.upstreams[*index].weight;
+                    if point < weight {
+                        return Some(*index);
+                    }
+                    point -= weight;
+                }
+
+                candidates.first().cloned()
+            },
+            BalanceStrategy::LeastConnections => {
+                candidates.into_iter().min_by_key(|index| {
+                    //按权重比较活动连接数
+                    let upstream = &self.upstreams[*index];
+                    upstream.active.load(Ordering::Relaxed) * 1000 / upstream.weight.max(1)
+                })
+            },
+            BalanceStrategy::IpHash => {
+                let hash = match client.ip() {
+                    std::net::IpAddr::V4(ip) => ip.octets().iter().fold(0usize, |h, b| h.wrapping_mul(31).wrapping_add(*b as usize)),
+                    std::net::IpAddr::V6(ip) => ip.octets().iter().fold(0usize, |h, b| h.wrapping_mul(31).wrapping_add(*b as usize)),
+                };
+                Some(candidates[hash % candidates.len()])
+            },
+        }
+    }
+}
+
+/*
+* 上游响应体的传输方式
+*/
+enum BodyKind {
+    Empty,          //没有响应体
+    Length(usize),  //指定长度的响应体
+    Chunked,        //分块传输的响应体
+    UntilClose,     //直到上游服务器关闭连接的响应体
+}
+
+/*
+* 发送给上游服务器的请求头，主机头在选择上游服务器后生成
+*/
+struct RequestHead {
+    line:   Vec<u8>,        //请求行
+    host:   Option<String>, //转发给上游服务器的客户端主机，为空表示使用上游服务器的主机名
+    fields: Vec<u8>,        //除主机头以外的其它请求头
+}
+
+impl RequestHead {
+    //生成发送给指定上游服务器的请求头
+    fn to_bytes(&self, upstream: &Upstream) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.line.len() + self.fields.len() + 64);
+        buf.put_slice(&self.line);
+        match &self.host {
+            Some(host) => put_header(&mut buf, HOST.as_str(), host.as_bytes()),
+            None => put_header(&mut buf, HOST.as_str(), upstream.host.as_bytes()),
+        }
+        buf.put_slice(&self.fields);
+        buf
+    }
+}
+
+/*
+* 上游服务器的响应头，包括状态码、响应头和是否有响应体
+*/
+type UpstreamHead = (u16, HeaderMap, bool);
+
+/*
+* 上游交换的结果，失败时包括回应给客户端的状态码和错误原因
+*/
+type ExchangeResult<T> = std::result::Result<T, (StatusCode, Error)>;
+
+/*
+* 上游交换，在代理异步运行时中通过阻塞的Tcp连接与上游服务器交换请求和响应，不会阻塞网络线程
+*/
+struct Exchange {
+    group:              Arc<UpstreamGroup>, //上游服务器组
+    head:               RequestHead,        //请求头
+    url:                String,             //请求的Url
+    client:             SocketAddr,         //客户端地址
+    is_chunked:         bool,               //请求体是否分块传输
+    has_body:           bool,               //是否有请求体
+    is_idempotent:      bool,               //是否是幂等的请求
+    is_head:            bool,               //是否是HEAD请求
+    connect_timeout:    Duration,           //连接超时时长
+    read_timeout:       Duration,           //读超时时长
+    write_timeout:      Duration,           //写超时时长
+    retries:            usize,              //失败后尝试其它上游服务器的次数
+    block_size:         usize,              //转发的块大小
+    _permit:            ExchangePermit,     //上游交换的许可，交换结束后释放
+}
+
+impl Exchange {
+    //获取上游服务器的连接，允许复用时优先使用空闲连接，返回连接和是否是复用的连接
+    fn connect(&self, upstream: &Upstream, reuse: bool) -> Result<(TcpStream, bool)> {
+        if reuse {
+            if let Some(stream) = upstream.take_idle(self.group.idle_timeout) {
+                return Ok((stream, true));
+            }
+        }
+
+        let stream = TcpStream::connect_timeout(&upstream.addr, self.connect_timeout)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(self.read_timeout))?;
+        stream.set_write_timeout(Some(self.write_timeout))?;
+        Ok((stream, false))
+    }
+
+    //运行上游交换，请求体由网络线程通过通道发送，响应头通过单次通道返回，响应体在获取响应句柄后以流方式转发
+    async fn run<S: Socket>(self,
+                            mut body: mpsc::Receiver<Option<Vec<u8>>>,
+                            head_sender: oneshot::Sender<ExchangeResult<UpstreamHead>>,
+                            handler_receiver: oneshot::Receiver<ResponseHandler<S>>) {
+        let (stream, body_kind, remaining, keep_alive, guard) = match self.request(&mut body).await {
+            Err(e) => {
+                let _ = head_sender.send(Err(e));
+                return;
+            },
+            Ok((code, headers, stream, body_kind, remaining, keep_alive, guard)) => {
+                let has_body = match body_kind {
+                    BodyKind::Empty => false,
+                    _ => true,
+                };
+                if head_sender.send(Ok((code, headers, has_body))).is_err() {
+                    //客户端已关闭，则放弃上游连接
+                    return;
+                }
+
+                (stream, body_kind, remaining, keep_alive, guard)
+            },
+        };
+
+        if let BodyKind::Empty = body_kind {
+            //没有响应体，则立即回收连接
+            if keep_alive && remaining.is_empty() {
+                guard.0.put_idle(stream, self.group.max_idle);
+            }
+            return;
+        }
+
+        let handler = match handler_receiver.await {
+            Err(_) => return, //无法转发响应体，则放弃上游连接
+            Ok(handler) => handler,
+        };
+
+        match relay_body(&handler, stream, body_kind, remaining, self.block_size) {
+            Err(e) => {
+                //转发响应体失败，因为已发送响应头，则只能中止响应
+                warn!("!!!> Http Proxy Relay Body Failed, url: {:?}, reason: {:?}", self.url, e);
+                let _ = handler.finish();
+            },
+            Ok(stream) => {
+                let _ = handler.finish();
+                if keep_alive {
+                    if let Some(stream) = stream {
+                        guard.0.put_idle(stream, self.group.max_idle);
+                    }
+                }
+            },
+        }
+    }
+
+    //选择上游服务器并发送请求，成功返回状态码、响应头、上游连接、响应体的传输方式、已读取的响应体数据、连接是否可以复用和活动连接守护者
+    async fn request(&self, body: &mut mpsc::Receiver<Option<Vec<u8>>>)
+        -> ExchangeResult<(u16, HeaderMap, TcpStream, BodyKind, Vec<u8>, bool, ActiveGuard)> {
+        let mut tried = Vec::new();
+        let mut last_error = (StatusCode::SERVICE_UNAVAILABLE, Error::new(ErrorKind::NotConnected, "no available upstream"));
+        while tried.len() <= self.retries {
+            let index = match self.group.select(&self.client, &tried) {
+                None => break,
+                Some(index) => index,
+            };
+            tried.push(index);
+            let upstream = self.group.upstreams[index].clone();
+
+            //连接上游服务器
+            let (mut stream, is_reused) = match self.connect(&upstream, true) {
+                Err(e) => {
+                    upstream.fail(self.group.max_fails, self.group.fail_timeout);
+                    last_error = (error_status(&e), e);
+                    continue;
+                },
+                Ok(r) => r,
+            };
+            upstream.active.fetch_add(1, Ordering::Relaxed);
+            let guard = ActiveGuard(upstream.clone());
+
+            //发送请求头，复用的连接可能已被上游服务器关闭，则使用新连接重试
+            let head = self.head.to_bytes(&upstream);
+            if let Err(e) = stream.write_all(&head) {
+                if !is_reused {
+                    upstream.fail(self.group.max_fails, self.group.fail_timeout);
+                    last_error = (error_status(&e), e);
+                    continue;
+                }
+
+                match self.connect(&upstream, false).and_then(|(mut stream, _)| stream.write_all(&head).map(|_| stream)) {
+                    Err(e) => {
+                        upstream.fail(self.group.max_fails, self.group.fail_timeout);
+                        last_error = (error_status(&e), e);
+                        continue;
+                    },
+                    Ok(s) => {
+                        stream = s;
+                    },
+                }
+            }
+
+            //以流方式发送请求体
+            if self.has_body {
+                if let Err(e) = self.send_body(body, &mut stream).await {
+                    //请求体已部分发送，则不允许重试
+                    return Err((error_status(&e), e));
+                }
+            }
+
+            //接收响应头
+            match self.recv_head(&mut stream) {
+                Err(e) => {
+                    upstream.fail(self.group.max_fails, self.group.fail_timeout);
+                    let status = error_status(&e);
+                    if self.has_body || !self.is_idempotent || status == StatusCode::GATEWAY_TIMEOUT {
+                        //已发送请求体、非幂等的请求或上游服务器超时，则不允许重试
+                        return Err((status, e));
+                    }
+
+                    last_error = (status, e);
+                    continue;
+                },
+                Ok((code, headers, body_kind, remaining, keep_alive)) => {
+                    upstream.success();
+                    return Ok((code, headers, stream, body_kind, remaining, keep_alive, guard));
+                },
+            }
+        }
+
+        Err(last_error)
+    }
+
+    //以流方式将网络线程接收的请求体发送给上游服务器，分块传输的请求体会重新分块，通道在请求体结束前关闭表示客户端已关闭
+    async fn send_body(&self, body: &mut mpsc::Receiver<Option<Vec<u8>>>, stream: &mut TcpStream) -> Result<()> {
+        loop {
+            match body.next().await {
+                None => {
+                    return Err(Error::new(ErrorKind::ConnectionAborted, "http proxy send body failed, reason: client closed"));
+                },
+                Some(None) => {
+                    if self.is_chunked {
+                        stream.write_all(LAST_CHUNK)?;
+                    }
+                    return Ok(());
+                },
+                Some(Some(bin)) => {
+                    if self.is_chunked {
+                        stream.write_all(format!("{:x}\r\n", bin.len()).as_bytes())?;
+                        stream.write_all(&bin)?;
+                        stream.write_all(b"\r\n")?;
+                    } else {
+                        stream.write_all(&bin)?;
+                    }
+                },
+            }
+        }
+    }
+
+    //接收上游服务器的响应头，返回状态码、响应头、响应体的传输方式、已读取的响应体数据和连接是否可以复用
+    fn recv_head(&self, stream: &mut TcpStream) -> Result<(u16, HeaderMap, BodyKind, Vec<u8>, bool)> {
+        let mut buf: Vec<u8> = Vec::with_capacity(1024);
+        let mut part = [0u8; 4096];
+        loop {
+            let len = stream.read(&mut part)?;
+            if len == 0 {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "http proxy recv head failed, reason: upstream closed"));
+            }
+            buf.put_slice(&part[..len]);
+
+            let mut headers = [EMPTY_HEADER; MAX_UPSTREAM_HEADER_LIMIT];
+            let mut response = Response::new(&mut headers);
+            let offset = match response.parse(&buf) {
+                Err(e) => {
+                    return Err(Error::new(ErrorKind::InvalidData, format!("http proxy recv head failed, reason: {:?}", e)));
+                },
+                Ok(Status::Partial) => {
+                    if buf.len() > MAX_UPSTREAM_HEAD_LEN {
+                        return Err(Error::new(ErrorKind::InvalidData, "http proxy recv head failed, reason: head too large"));
+                    }
+                    continue;
+                },
+                Ok(Status::Complete(offset)) => offset,
+            };
+
+            let code = response.code.unwrap_or(502);
+            if code >= 100 && code < 200 {
+                //忽略上游服务器的信息响应
+                buf.drain(..offset);
+                continue;
+            }
+
+            let mut map = HeaderMap::new();
+            for header in response.headers.iter() {
+                if let (Ok(key), Ok(value)) = (HeaderName::from_bytes(header.name.as_bytes()), HeaderValue::from_bytes(header.value)) {
+                    map.append(key, value);
+                }
+            }
+
+            //确定响应体的传输方式
+            let body_kind = if self.is_head || code == 204 || code == 304 {
+                BodyKind::Empty
+            } else if map.get_all(TRANSFER_ENCODING).iter().any(|value| value.to_str().map_or(false, |v| v.to_ascii_lowercase().contains(CHUNKED_TRANSFER_ENCODING))) {
+                BodyKind::Chunked
+            } else if let Some(len) = map.get(CONTENT_LENGTH).and_then(|value| value.to_str().ok()).and_then(|value| value.parse::<usize>().ok()) {
+                if len == 0 {
+                    BodyKind::Empty
+                } else {
+                    BodyKind::Length(len)
+                }
+            } else {
+                BodyKind::UntilClose
+            };
+            let keep_alive = response.version == Some(1)
+                && !connection_tokens(&map).iter().any(|token| token == "close")
+                && match body_kind {
+                    BodyKind::UntilClose => false,
+                    _ => true,
+                };
+
+            let remaining = buf.split_off(offset);
+            return Ok((code, map, body_kind, remaining, keep_alive));
+        }
+    }
+}
+
+/*
+* Http反向代理，将请求转发到上游的Http/1.1服务器，请求体和响应体都以流方式转发
+* 上游连接使用带超时的阻塞Tcp连接，所有上游交换都在独立的代理异步运行时中执行，并限制并发交换数，响应体转发受响应体缓冲的背压限制
+*/
+pub struct ReverseProxy {
+    proxy_async_runtime: MultiTaskRuntime<()>,                  //代理异步运行时
+    exchanges:          Arc<AtomicUsize>,                       //当前的并发交换数
+    max_exchanges:      usize,                                  //最大并发交换数
+    group:              Arc<UpstreamGroup>,                     //上游服务器组
+    rewrites:           Vec<PathRewrite>,                       //请求路径的重写规则
+    preserve_host:      bool,                                   //是否将客户端请求的Host头转发给上游服务器
+    name:               String,                                 //代理标识，用于Via头
+    request_headers:    Vec<(HeaderName, Option<HeaderValue>)>, //需要设置或移除的请求头
+    response_headers:   Vec<(HeaderName, Option<HeaderValue>)>, //需要设置或移除的响应头
+    trusted_proxies:    Vec<IpRange>,                           //可信代理地址段列表
+    connect_timeout:    Duration,                               //连接超时时长
+    read_timeout:       Duration,                               //读超时时长
+    write_timeout:      Duration,                               //写超时时长
+    retries:            usize,                                  //失败后尝试其它上游服务器的次数
+    block_size:         usize,                                  //转发的块大小
+}
+
+unsafe impl Send for ReverseProxy {}
+unsafe impl Sync for ReverseProxy {}
+
+impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for ReverseProxy {
+    fn request<'a>(&'a self, _context: &'a mut GatewayContext, mut req: HttpRequest<S, W>)
+                   -> BoxFuture<'a, MiddlewareResult<S, W>> {
+        let future = async move {
+            match self.forward(&mut req).await {
+                Err((status, e)) => {
+                    //转发失败，则回应网关错误
+                    warn!("!!!> Http Proxy Failed, url: {:?}, status: {:?}, reason: {:?}", req.url().as_str(), status, e);
+                    let mut resp = HttpResponse::new(req.get_handle().clone(), req.get_waits().clone(), 1);
+                    resp.status(status.as_u16());
+                    resp.header(CONTENT_LENGTH.as_str(), "0");
+                    MiddlewareResult::Break(resp)
+                },
+                Ok(resp) => {
+                    //转发成功，则继续响应中间件的处理
+                    MiddlewareResult::Finish((req, resp))
+                },
+            }
+        };
+        future.boxed()
+    }
+
+    fn response<'a>(&'a self, _context: &'a mut GatewayContext, req: HttpRequest<S, W>, resp: HttpResponse<S, W>)
+                    -> BoxFuture<'a, MiddlewareResult<S, W>> {
+        let future = async move {
+            MiddlewareResult::ContinueResponse((req, resp))
+        };
+        future.boxed()
+    }
+}
+
+/*
+* Http反向代理同步方法
+*/
+impl ReverseProxy {
+    //构建指定代理异步运行时、上游服务器地址、权重和负载均衡策略的反向代理，地址格式为host:port，与上游服务器的交换都在代理异步运行时中执行
+    //与上游服务器的交换会阻塞所在的线程，所以代理异步运行时不应与文件等其它异步运行时共享
+    pub fn new(proxy_async_runtime: MultiTaskRuntime<()>, upstreams: &[(&str, usize)], strategy: BalanceStrategy) -> Result<Self> {
+        let mut vec = Vec::with_capacity(upstreams.len());
+        for (host, weight) in upstreams {
+            let addr = match host.to_socket_addrs()?.next() {
+                None => {
+                    return Err(Error::new(ErrorKind::AddrNotAvailable, format!("create reverse proxy failed, upstream: {:?}, reason: invalid address", host)));
+                },
+                Some(addr) => addr,
+            };
+
+            vec.push(Arc::new(Upstream {
+                addr,
+                host: host.to_string(),
+                weight: (*weight).max(1),
+                active: AtomicUsize::new(0),
+                fails: AtomicUsize::new(0),
+                down_until: Mutex::new(None),
+                is_down: AtomicBool::new(false),
+                checks: AtomicUsize::new(0),
+                idle: Mutex::new(Vec::new()),
+            }));
+        }
+
+        if vec.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "create reverse proxy failed, reason: empty upstream"));
+        }
+
+        Ok(ReverseProxy {
+            proxy_async_runtime,
+            exchanges: Arc::new(AtomicUsize::new(0)),
+            max_exchanges: DEFAULT_MAX_EXCHANGES,
+            group: Arc::new(UpstreamGroup {
+                upstreams: vec,
+                strategy,
+                cursor: AtomicUsize::new(0),
+                max_fails: DEFAULT_MAX_FAILS,
+                fail_timeout: Duration::from_millis(DEFAULT_FAIL_TIMEOUT),
+                max_idle: DEFAULT_MAX_IDLE,
+                idle_timeout: Duration::from_millis(DEFAULT_IDLE_TIMEOUT),
+            }),
+            rewrites: Vec::new(),
+            preserve_host: false,
+            name: DEFAULT_PROXY_NAME.to_string(),
+            request_headers: Vec::new(),
+            response_headers: Vec::new(),
+            trusted_proxies: Vec::new(),
+            connect_timeout: Duration::from_millis(DEFAULT_CONNECT_TIMEOUT),
+            read_timeout: Duration::from_millis(DEFAULT_READ_TIMEOUT),
+            write_timeout: Duration::from_millis(DEFAULT_WRITE_TIMEOUT),
+            retries: 1,
+            block_size: DEFAULT_PROXY_BLOCK_SIZE,
+        })
+    }
+
+    //增加请求路径的重写规则
+    pub fn add_rewrite(&mut self, rewrite: PathRewrite) {
+        self.rewrites.push(rewrite);
+    }
+
+    //设置是否将客户端请求的Host头转发给上游服务器，否则使用上游服务器的主机名
+    pub fn set_preserve_host(&mut self, preserve: bool) {
+        self.preserve_host = preserve;
+    }
+
+    //设置代理标识，用于Via头
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
+    //设置转发给上游服务器的请求头，值为空表示移除请求头
+    pub fn set_request_header(&mut self, key: &str, value: Option<&str>) -> Result<()> {
+        let header = parse_header(key, value)?;
+        self.request_headers.push(header);
+        Ok(())
+    }
+
+    //设置回应给客户端的响应头，值为空表示移除响应头
+    pub fn set_response_header(&mut self, key: &str, value: Option<&str>) -> Result<()> {
+        let header = parse_header(key, value)?;
+        self.response_headers.push(header);
+        Ok(())
+    }
+
+    //设置可信代理地址段列表，只有对端地址是可信代理时，才会保留客户端请求的X-Forwarded-For头
+    pub fn set_trusted_proxies(&mut self, trusted_proxies: Vec<IpRange>) {
+        self.trusted_proxies = trusted_proxies;
+    }
+
+    //设置连接、读和写的超时时长，单位毫秒
+    pub fn set_timeout(&mut self, connect: u64, read: u64, write: u64) {
+        self.connect_timeout = Duration::from_millis(connect.max(1));
+        self.read_timeout = Duration::from_millis(read.max(1));
+        self.write_timeout = Duration::from_millis(write.max(1));
+    }
+
+    //设置失败后尝试其它上游服务器的次数，有请求体或非幂等的请求在发送请求后失败不会重试
+    pub fn set_retries(&mut self, retries: usize) {
+        self.retries = retries;
+    }
+
+    //设置转发的块大小
+    pub fn set_block_size(&mut self, size: usize) {
+        self.block_size = size.max(1);
+    }
+
+    //设置最大并发交换数，一般不超过代理异步运行时的线程数与每个上游交换允许的等待时长的乘积
+    pub fn set_max_exchanges(&mut self, max: usize) {
+        self.max_exchanges = max.max(1);
+    }
+
+    //设置被动健康检查参数，上游服务器连续失败指定次数后，在指定时长内不会被选择，单位毫秒，需要在启动主动健康检查前设置
+    pub fn set_passive_check(&mut self, max_fails: usize, fail_timeout: u64) -> Result<()> {
+        if let Some(group) = Arc::get_mut(&mut self.group) {
+            group.max_fails = max_fails.max(1);
+            group.fail_timeout = Duration::from_millis(fail_timeout);
+            return Ok(());
+        }
+
+        Err(Error::new(ErrorKind::Other, "set passive check failed, reason: not writable"))
+    }
+
+    //设置每个上游服务器的最大空闲连接数和空闲连接的超时时长，单位毫秒，需要在启动主动健康检查前设置
+    pub fn set_idle(&mut self, max_idle: usize, idle_timeout: u64) -> Result<()> {
+        if let Some(group) = Arc::get_mut(&mut self.group) {
+            group.max_idle = max_idle;
+            group.idle_timeout = Duration::from_millis(idle_timeout);
+            return Ok(());
+        }
+
+        Err(Error::new(ErrorKind::Other, "set idle failed, reason: not writable"))
+    }
+
+    //启动主动健康检查，检查线程会在反向代理被销毁后退出
+    pub fn start_health_check(&self, check: HealthCheck) {
+        let group = Arc::downgrade(&self.group);
+        thread::Builder::new()
+            .name("Http Proxy Health Check".to_string())
+            .spawn(move || health_check_loop(group, check))
+            .expect("start http proxy health check failed");
+    }
+
+    //获取指定上游服务器的可用状态
+    pub fn is_available(&self, upstream: &str) -> Option<bool> {
+        self.group.upstreams
+            .iter()
+            .find(|u| u.host == upstream)
+            .map(|u| u.is_available())
+    }
+
+    //生成发送给上游服务器的请求头
+    fn request_head<S: Socket, W: AsyncIOWait>(&self, req: &HttpRequest<S, W>, path: &str) -> RequestHead {
+        let headers = req.headers();
+        let mut line = Vec::with_capacity(path.len() + 32);
+        line.put_slice(req.method().as_str().as_bytes());
+        line.put_slice(b" ");
+        line.put_slice(path.as_bytes());
+        line.put_slice(b" HTTP/1.1\r\n");
+
+        //确定主机头
+        let client_host = headers.get(HOST).and_then(|value| value.to_str().ok()).unwrap_or("");
+        let host = if self.preserve_host && !client_host.is_empty() {
+            Some(client_host.to_string())
+        } else {
+            None
+        };
+        let mut buf = Vec::with_capacity(512);
+
+        //转发端到端头
+        let hops = connection_tokens(headers);
+        for (key, value) in headers.iter() {
+            if key == HOST || key == EXPECT || is_hop_header(key, &hops) || self.request_headers.iter().any(|(k, _)| k == key) {
+                continue;
+            }
+
+            if key == X_FORWARDED_FOR || key == X_FORWARDED_PROTO || key == X_FORWARDED_HOST {
+                continue;
+            }
+
+            put_header(&mut buf, key.as_str(), value.as_bytes());
+        }
+
+        //设置代理头，只有对端地址是可信代理时，才会保留客户端请求的X-Forwarded-For头
+        let forwarded = forwarded_for(headers, req.get_handle().get_remote().ip(), &self.trusted_proxies);
+        put_header(&mut buf, X_FORWARDED_FOR, forwarded.as_bytes());
+        put_header(&mut buf, X_FORWARDED_PROTO, if req.get_handle().is_security() { b"https" } else { b"http" });
+        if !client_host.is_empty() {
+            put_header(&mut buf, X_FORWARDED_HOST, client_host.as_bytes());
+        }
+        put_header(&mut buf, VIA.as_str(), via_value(headers, &self.name).as_bytes());
+        for (key, value) in &self.request_headers {
+            if let Some(value) = value {
+                put_header(&mut buf, key.as_str(), value.as_bytes());
+            }
+        }
+
+        //设置请求体的传输方式
+        if req.is_chunked() {
+            put_header(&mut buf, TRANSFER_ENCODING.as_str(), CHUNKED_TRANSFER_ENCODING.as_bytes());
+        } else if let Some(len) = headers.get(CONTENT_LENGTH) {
+            put_header(&mut buf, CONTENT_LENGTH.as_str(), len.as_bytes());
+        }
+        put_header(&mut buf, CONNECTION.as_str(), KEEP_ALIVE_HEADER.as_bytes());
+        buf.put_slice(b"\r\n");
+
+        RequestHead {
+            line,
+            host,
+            fields: buf,
+        }
+    }
+}
+
+/*
+* Http反向代理异步方法
+*/
+impl ReverseProxy {
+    //转发请求，成功返回上游服务器的响应，失败返回回应给客户端的状态码和错误原因
+    async fn forward<S: Socket, W: AsyncIOWait>(&self, req: &mut HttpRequest<S, W>) -> ExchangeResult<HttpResponse<S, W>> {
+        //重写请求路径
+        let mut path = req.url().path().to_string();
+        for rewrite in &self.rewrites {
+            path = rewrite.rewrite(&path);
+        }
+        if let Some(query) = req.url().query() {
+            path = path + "?" + query;
+        }
+
+        let has_body = req.is_chunked() || req.headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok())
+            .map_or(false, |len| len > 0);
+        if self.exchanges.fetch_add(1, Ordering::Relaxed) >= self.max_exchanges {
+            //并发交换数已达限制，则立即回应服务不可用
+            self.exchanges.fetch_sub(1, Ordering::Relaxed);
+            return Err((StatusCode::SERVICE_UNAVAILABLE, Error::new(ErrorKind::Other, "http proxy exchange failed, reason: too many exchanges")));
+        }
+        let permit = ExchangePermit(self.exchanges.clone());
+        let is_idempotent = match req.method() {
+            &Method::GET | &Method::HEAD | &Method::OPTIONS | &Method::PUT | &Method::DELETE | &Method::TRACE => true,
+            _ => false,
+        };
+        let exchange = Exchange {
+            group: self.group.clone(),
+            head: self.request_head(req, &path),
+            url: req.url().as_str().to_string(),
+            client: req.get_handle().get_remote().clone(),
+            is_chunked: req.is_chunked(),
+            has_body,
+            is_idempotent,
+            is_head: req.method() == &Method::HEAD,
+            connect_timeout: self.connect_timeout,
+            read_timeout: self.read_timeout,
+            write_timeout: self.write_timeout,
+            retries: self.retries,
+            block_size: self.block_size,
+            _permit: permit,
+        };
+
+        //在代理异步运行时中与上游服务器交换
+        let (mut body_sender, body_receiver) = mpsc::channel::<Option<Vec<u8>>>(PROXY_REQ_BODY_BUFFER_LEN);
+        let (head_sender, head_receiver) = oneshot::channel();
+        let (handler_sender, handler_receiver) = oneshot::channel();
+        if let Err(e) = self.proxy_async_runtime.spawn(self.proxy_async_runtime.alloc(), exchange.run(body_receiver, head_sender, handler_receiver)) {
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, Error::new(ErrorKind::Other, format!("http proxy spawn exchange failed, reason: {:?}", e))));
+        }
+
+        //以流方式将请求体发送给上游交换
+        if has_body {
+            self.send_body(req, &mut body_sender).await;
+        }
+        drop(body_sender);
+
+        let (code, headers, has_resp_body) = match head_receiver.await {
+            Err(_) => {
+                return Err((StatusCode::BAD_GATEWAY, Error::new(ErrorKind::Other, "http proxy recv head failed, reason: exchange canceled")));
+            },
+            Ok(Err(e)) => return Err(e),
+            Ok(Ok(head)) => head,
+        };
+
+        //构建响应，并移除逐跳头
+        let mut resp = HttpResponse::new(req.get_handle().clone(), req.get_waits().clone(), PROXY_RESP_BODY_BUFFER_LEN);
+        resp.status(code);
+        let hops = connection_tokens(&headers);
+        for (key, value) in headers.iter() {
+            if is_hop_header(key, &hops) || key == VIA || self.response_headers.iter().any(|(k, _)| k == key) {
+                continue;
+            }
+
+            if let Ok(value) = value.to_str() {
+                resp.header(key.as_str(), value);
+            }
+        }
+        resp.header(VIA.as_str(), &via_value(&headers, &self.name));
+        for (key, value) in &self.response_headers {
+            if let Some(Ok(value)) = value.as_ref().map(|value| value.to_str()) {
+                resp.header(key.as_str(), value);
+            }
+        }
+
+        //有响应体，则将响应句柄交给上游交换，由上游交换以流方式转发响应体
+        if has_resp_body {
+            if let Some(handler) = resp.get_response_handler() {
+                resp.set_stream(true);
+                let _ = handler_sender.send(handler);
+            }
+        }
+
+        Ok(resp)
+    }
+
+    //以流方式将客户端的请求体发送给上游交换，请求体结束时发送空块，客户端已关闭或上游交换已结束时直接关闭通道
+    async fn send_body<S: Socket, W: AsyncIOWait>(&self, req: &mut HttpRequest<S, W>, sender: &mut mpsc::Sender<Option<Vec<u8>>>) {
+        loop {
+            match req.next_body(self.block_size).await {
+                None => {
+                    if !req.get_handle().is_closed() {
+                        let _ = sender.send(None).await;
+                    }
+                    return;
+                },
+                Some(bin) => {
+                    if sender.send(Some(bin.to_vec())).await.is_err() {
+                        return;
+                    }
+                },
+            }
+        }
+    }
+}
+
+//将上游服务器的响应体写入响应句柄，写入会在响应体缓冲满时阻塞，完成后返回可以复用的连接
+fn relay_body<S: Socket>(handler: &ResponseHandler<S>,
+                         mut stream: TcpStream,
+                         body_kind: BodyKind,
+                         remaining: Vec<u8>,
+                         block_size: usize) -> Result<Option<TcpStream>> {
+    let mut part = vec![0u8; block_size];
+    match body_kind {
+        BodyKind::Empty => Ok(Some(stream)),
+        BodyKind::Length(len) => {
+            let mut left = len;
+            if !remaining.is_empty() {
+                let size = remaining.len().min(left);
+                handler.write(remaining[..size].to_vec())?;
+                left -= size;
+            }
+
+            while left > 0 {
+                let size = stream.read(&mut part[..block_size.min(left)])?;
+                if size == 0 {
+                    return Err(Error::new(ErrorKind::UnexpectedEof, "upstream closed"));
+                }
+
+                handler.write(part[..size].to_vec())?;
+                left -= size;
+            }
+
+            Ok(Some(stream))
+        },
+        BodyKind::Chunked => {
+            let mut decoder = ChunkedDecoder::new();
+            decoder.push(&remaining);
+            loop {
+                let mut out = Vec::new();
+                decoder.decode(&mut out, block_size)?;
+                if !out.is_empty() {
+                    handler.write(out)?;
+                    continue;
+                }
+
+                if decoder.is_done() {
+                    let is_clean = decoder.take_remaining().is_empty();
+                    return Ok(if is_clean {
+                        Some(stream)
+                    } else {
+                        None
+                    });
+                }
+
+                let size = stream.read(&mut part)?;
+                if size == 0 {
+                    return Err(Error::new(ErrorKind::UnexpectedEof, "upstream closed"));
+                }
+                decoder.push(&part[..size]);
+            }
+        },
+        BodyKind::UntilClose => {
+            if !remaining.is_empty() {
+                handler.write(remaining)?;
+            }
+
+            loop {
+                let size = stream.read(&mut part)?;
+                if size == 0 {
+                    return Ok(None);
+                }
+
+                handler.write(part[..size].to_vec())?;
+            }
+        },
+    }
+}
+
+//主动健康检查的循环，上游服务器组被销毁后退出
+fn health_check_loop(group: Weak<UpstreamGroup>, check: HealthCheck) {
+    loop {
+        thread::sleep(Duration::from_millis(check.interval));
+        let group = match group.upgrade() {
+            None => return,
+            Some(group) => group,
+        };
+
+        for upstream in &group.upstreams {
+            let is_ok = check_upstream(upstream, &check).is_ok();
+            let is_down = upstream.is_down.load(Ordering::Relaxed);
+            if is_ok == !is_down {
+                //状态未改变，则重置连续检查次数
+                upstream.checks.store(0, Ordering::Relaxed);
+                continue;
+            }
+
+            let checks = upstream.checks.fetch_add(1, Ordering::Relaxed) + 1;
+            if is_down && checks >= check.rise {
+                //连续检查成功，则恢复为可用
+                upstream.checks.store(0, Ordering::Relaxed);
+                upstream.is_down.store(false, Ordering::Relaxed);
+                upstream.success();
+                *upstream.down_until.lock() = None;
+            } else if !is_down && checks >= check.fall {
+                //连续检查失败，则标记为不可用，并关闭所有空闲连接
+                upstream.checks.store(0, Ordering::Relaxed);
+                upstream.is_down.store(true, Ordering::Relaxed);
+                upstream.idle.lock().clear();
+                warn!("!!!> Http Proxy Health Check Failed, addr: {:?}", upstream.addr);
+            }
+        }
+    }
+}
+
+//检查指定的上游服务器
+fn check_upstream(upstream: &Upstream, check: &HealthCheck) -> Result<()> {
+    let timeout = Duration::from_millis(check.timeout.max(1));
+    let mut stream = TcpStream::connect_timeout(&upstream.addr, timeout)?;
+    let path = match &check.path {
+        None => return Ok(()),
+        Some(path) => path,
+    };
+
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.write_all(format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, upstream.host).as_bytes())?;
+
+    let mut buf = [0u8; 16];
+    let mut len = 0;
+    while len < 12 {
+        let size = stream.read(&mut buf[len..])?;
+        if size == 0 {
+            break;
+        }
+        len += size;
+    }
+
+    //检查状态行，例如HTTP/1.1 200
+    match std::str::from_utf8(&buf[..len]).ok().and_then(|line| line.get(9..12)).and_then(|code| code.parse::<u16>().ok()) {
+        Some(code) if code >= 200 && code < 400 => Ok(()),
+        code => Err(Error::new(ErrorKind::Other, format!("invalid health check status, status: {:?}", code))),
+    }
+}
+
+//判断空闲连接是否未被上游服务器关闭
+fn is_alive(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+
+    let mut buf = [0u8; 1];
+    let is_alive = match stream.peek(&mut buf) {
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock => true,
+        _ => false, //已关闭或有未预期的数据
+    };
+
+    stream.set_nonblocking(false).is_ok() && is_alive
+}
+
+//根据错误获取回应给客户端的状态码
+fn error_status(e: &Error) -> StatusCode {
+    match e.kind() {
+        ErrorKind::TimedOut | ErrorKind::WouldBlock => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::BAD_GATEWAY,
+    }
+}
+
+//获取Connection头中的所有选项
+fn connection_tokens(headers: &HeaderMap) -> Vec<String> {
+    headers.get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|token| token.trim().to_ascii_lowercase())
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+//判断是否是逐跳头
+fn is_hop_header(key: &HeaderName, hops: &[String]) -> bool {
+    key == CONNECTION
+        || key == TRANSFER_ENCODING
+        || key == TE
+        || key == TRAILER
+        || key == UPGRADE
+        || key == PROXY_AUTHENTICATE
+        || key == PROXY_AUTHORIZATION
+        || key == KEEP_ALIVE_HEADER
+        || key == PROXY_CONNECTION_HEADER
+        || hops.iter().any(|hop| hop == key.as_str())
+}
+
+//生成Via头的值
+fn via_value(headers: &HeaderMap, name: &str) -> String {
+    match headers.get(VIA).and_then(|value| value.to_str().ok()) {
+        Some(via) => format!("{}, 1.1 {}", via, name),
+        None => format!("1.1 {}", name),
+    }
+}
+
+//解析需要设置或移除的Http头
+fn parse_header(key: &str, value: Option<&str>) -> Result<(HeaderName, Option<HeaderValue>)> {
+    let key = match HeaderName::from_bytes(key.as_bytes()) {
+        Err(e) => return Err(Error::new(ErrorKind::InvalidInput, format!("invalid header name, key: {:?}, reason: {:?}", key, e))),
+        Ok(key) => key,
+    };
+
+    match value {
+        None => Ok((key, None)),
+        Some(value) => {
+            match HeaderValue::from_str(value) {
+                Err(e) => Err(Error::new(ErrorKind::InvalidInput, format!("invalid header value, value: {:?}, reason: {:?}", value, e))),
+                Ok(value) => Ok((key, Some(value))),
+            }
+        },
+    }
+}
+
+//序列化指定的Http头
+fn put_header(buf: &mut Vec<u8>, key: &str, value: &[u8]) {
+    buf.put_slice(key.as_bytes());
+    buf.put_slice(b": ");
+    buf.put_slice(value);
+    buf.put_slice(b"\r\n");
+}
@@ -1,23 +1,65 @@
-use std::sync::Arc;
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::io::{Error, Result, ErrorKind};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use https::{header::{RANGE, ACCEPT_RANGES, CONTENT_RANGE, CONTENT_LENGTH}, StatusCode};
-use futures::future::{FutureExt, BoxFuture};
+use https::{header::{RANGE, IF_RANGE, ETAG, LAST_MODIFIED, ACCEPT_RANGES, CONTENT_RANGE, CONTENT_LENGTH, CONTENT_TYPE}, Method, StatusCode};
+use futures::{channel::oneshot,
+              future::{FutureExt, BoxFuture}};
+use httpdate::{fmt_http_date, parse_http_date};
+use bytes::BufMut;
+use mime_guess;
 
+use async_file::file::{AsyncFile, AsyncFileOptions};
+use r#async::rt::multi_thread::MultiTaskRuntime;
 use tcp::driver::{Socket, AsyncIOWait};
-use handler::SGenType;
 use log::warn;
 
 use crate::{gateway::GatewayContext,
             middleware::{MiddlewareResult, Middleware},
             request::HttpRequest,
             response::HttpResponse,
-            util::HttpRecvResult};
+            file_load::normalize_path,
+            util::trim_path};
 
 /*
-* Http静态资源范围加载器
+* 范围单位
 */
-pub struct RangeLoad;
+const BYTES_UNIT: &str = "bytes";
+
+/*
+* 单个请求允许的最大范围数量，超过时忽略范围请求，并返回完整的响应体
+*/
+const MAX_RANGES_LIMIT: usize = 64;
+
+/*
+* 从文件中加载范围时，每次读取的块大小，64KB
+*/
+const RANGE_BLOCK_SIZE: usize = 64 * 1024;
+
+/*
+* 从文件中加载范围时，响应体的缓冲块数量，缓冲满后会暂停读取文件，直到客户端接收
+*/
+const RANGE_RESP_BODY_BUFFER_LEN: usize = 8;
+
+/*
+* 多范围响应的Mime
+*/
+const MULTIPART_BYTERANGES_MIME: &str = "multipart/byteranges";
+
+/*
+* 多范围响应的分隔符计数器
+*/
+static BOUNDARY_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/*
+* Http静态资源范围加载器，支持单范围、多范围和后缀范围，并根据If-Range判断是否返回范围
+* 未指定文件根目录时，从已加载的完整响应体中截取范围，否则直接从文件中加载范围
+*/
+pub struct RangeLoad {
+    files:  Option<(MultiTaskRuntime<()>, PathBuf)>,    //异步文件运行时和文件根路径
+}
 
 unsafe impl Send for RangeLoad {}
 unsafe impl Sync for RangeLoad {}
@@ -26,6 +68,17 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for RangeLoad {
     fn request<'a>(&'a self, context: &'a mut GatewayContext, req: HttpRequest<S, W>)
                    -> BoxFuture<'a, MiddlewareResult<S, W>> {
         let future = async move {
+            if let Some((files_async_runtime, root)) = &self.files {
+                //指定了文件根目录，则尝试直接从文件中加载范围
+                if let Some(result) = load_file_ranges(files_async_runtime, root, &req).await {
+                    return match result {
+                        Err(e) => MiddlewareResult::Throw(e),
+                        Ok(resp) if resp.get_status() == StatusCode::RANGE_NOT_SATISFIABLE.as_u16() => MiddlewareResult::Break(resp),
+                        Ok(resp) => MiddlewareResult::Finish((req, resp)),
+                    };
+                }
+            }
+
             //继续请求处理
             MiddlewareResult::ContinueRequest(req)
         };
@@ -36,68 +89,91 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for RangeLoad {
                     -> BoxFuture<'a, MiddlewareResult<S, W>> {
         let mut response = resp;
         let future = async move {
-            if let Some(range_value) = req.headers().get(RANGE) {
-                if let Ok(r) = range_value.to_str() {
-                    if let None = r.find(',') {
-                        //只支持单个指定范围
-                        let str = r.trim().to_lowercase();
-                        let tmp: Vec<&str> = str.split("=").collect();
-                        let range_str = tmp[1].trim();
-                        let mut vec: Vec<&str> = range_str.split("-").collect();
-                        if let Ok(start) = vec[0].parse::<usize>() {
-                            //获取开始范围成功，则获取响应体大小
-                            let mut body_len = 0;
-                            if let Some(body) = response.as_body() {
-                                if let Some(len) = body.len() {
-                                    body_len = len;
-                                }
-                            }
-
-                            let mut is_end = false; //默认未指定结束范围
-                            let mut end = body_len - 1; //未指定结束范围，则默认为所有数据
-                            if let Ok(e) = vec[1].parse::<usize>() {
-                                //已指定结束范围
-                                is_end = true;
-                                end = e;
-                            }
-
-                            if start > end || end >= body_len {
-                                //客户端需要的静态资源范围越界，则立即返回错误
-                                if let Some(body) = response.as_mut_body() {
-                                    body.reset(&[]);
-                                }
-                                response
-                                    .status(StatusCode::RANGE_NOT_SATISFIABLE.as_u16())
-                                    .header(ACCEPT_RANGES.as_str(), "bytes")
-                                    .header(CONTENT_LENGTH.as_str(), "0");
-                                return MiddlewareResult::Break(response);
-                            }
-
-                            if let Some(body) = response.as_mut_body() {
-                                let mut buf = Vec::with_capacity(0);
-                                if let Some(bin) = body.as_slice() {
-                                    buf = Vec::from(&bin[start..(end + 1)]);
-                                }
-                                body.reset(buf.as_slice()); //重置响应体为指定范围的数据
-                            }
-
-                            //设置范围响应状态码和响应头
-                            if !is_end {
-                                //未指定结束范围
-                                response
-                                    .status(StatusCode::PARTIAL_CONTENT.as_u16())
-                                    .header(ACCEPT_RANGES.as_str(), "bytes")
-                                    .header(CONTENT_RANGE.as_str(), ["bytes", " ", range_str, end.to_string().as_str(), "/", body_len.to_string().as_str()].concat().as_str());
-                            } else {
-                                //已指定结束范围
-                                response
-                                    .status(StatusCode::PARTIAL_CONTENT.as_u16())
-                                    .header(ACCEPT_RANGES.as_str(), "bytes")
-                                    .header(CONTENT_RANGE.as_str(), ["bytes", " ", range_str, "/", body_len.to_string().as_str()].concat().as_str());
-                            }
-                        }
-                    }
+            if response.contains_header(CONTENT_RANGE) {
+                //已从文件中以流方式加载范围，则忽略
+                return MiddlewareResult::ContinueResponse((req, response));
+            }
+
+            if req.method() != &Method::GET || response.get_status() != StatusCode::OK.as_u16() {
+                //只处理成功的Get请求
+                return MiddlewareResult::ContinueResponse((req, response));
+            }
+
+            let body_len = match response.as_body().and_then(|body| body.len()) {
+                None => return MiddlewareResult::ContinueResponse((req, response)),
+                Some(len) => len as u64,
+            };
+            response.header(ACCEPT_RANGES.as_str(), BYTES_UNIT);
+
+            let range = match req.headers().get(RANGE).and_then(|value| value.to_str().ok()) {
+                None => return MiddlewareResult::ContinueResponse((req, response)),
+                Some(range) => range.to_string(),
+            };
+
+            if let Some(if_range) = req.headers().get(IF_RANGE).and_then(|value| value.to_str().ok()) {
+                //验证资源未改变时才返回范围，否则返回完整的响应体
+                let headers = response.get_headers();
+                let etag = headers.get(ETAG).and_then(|value| value.to_str().ok());
+                let last_modified = headers.get(LAST_MODIFIED)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| parse_http_date(value).ok());
+                if !if_range_matches(if_range, etag, last_modified) {
+                    return MiddlewareResult::ContinueResponse((req, response));
+                }
+            }
+
+            let ranges = match parse_ranges(&range, body_len) {
+                None => {
+                    //无效的范围，则忽略范围请求
+                    return MiddlewareResult::ContinueResponse((req, response));
+                },
+                Some(ranges) => ranges,
+            };
+
+            if ranges.is_empty() {
+                //客户端需要的静态资源范围越界，则立即返回错误
+                if let Some(body) = response.as_mut_body() {
+                    body.reset(&[]);
+                }
+                response
+                    .remove_header(CONTENT_LENGTH)
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE.as_u16())
+                    .header(CONTENT_RANGE.as_str(), &unsatisfied_content_range(body_len))
+                    .header(CONTENT_LENGTH.as_str(), "0");
+                return MiddlewareResult::Break(response);
+            }
+
+            //重置响应体为指定范围的数据
+            let mime = response.get_headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string());
+            let boundary = new_boundary();
+            let mut buf = Vec::new();
+            if let Some(body) = response.as_mut_body() {
+                if let Some(bin) = body.as_slice() {
+                    buf = if ranges.len() == 1 {
+                        let (start, end) = ranges[0];
+                        Vec::from(&bin[start as usize..(end + 1) as usize])
+                    } else {
+                        let parts = ranges.iter().map(|(start, end)| &bin[*start as usize..(*end + 1) as usize]).collect::<Vec<&[u8]>>();
+                        multipart_body(&ranges, &parts, body_len, mime.as_ref().map(|mime| mime.as_str()), &boundary)
+                    };
                 }
+                body.reset(buf.as_slice());
+            }
+
+            //设置范围响应状态码和响应头
+            response
+                .remove_header(CONTENT_LENGTH)
+                .status(StatusCode::PARTIAL_CONTENT.as_u16())
+                .header(CONTENT_LENGTH.as_str(), buf.len().to_string().as_str());
+            if ranges.len() == 1 {
+                response.header(CONTENT_RANGE.as_str(), &content_range(ranges[0], body_len));
+            } else {
+                response
+                    .remove_header(CONTENT_TYPE)
+                    .header(CONTENT_TYPE.as_str(), &multipart_mime(&boundary));
             }
 
             //继续响应处理
@@ -108,8 +184,345 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for RangeLoad {
 }
 
 impl RangeLoad {
-    //构建指定根目录的文件上传处理器
+    //构建从已加载的完整响应体中截取范围的范围加载器
     pub fn new() -> Self {
-        RangeLoad
+        RangeLoad {
+            files: None,
+        }
+    }
+
+    //构建指定根目录的范围加载器，会直接从文件中加载范围，而不需要加载完整的文件
+    pub fn with_files<P: Into<PathBuf>>(files_async_runtime: MultiTaskRuntime<()>, dir: P) -> Self {
+        match trim_path(dir) {
+            Err(e) => {
+                panic!("Create Http Range Load Failed, reason: {:?}", e);
+            },
+            Ok(root) => {
+                RangeLoad {
+                    files: Some((files_async_runtime, root)),
+                }
+            },
+        }
+    }
+}
+
+//解析Range的值，返回按起始位置排列的闭区间范围，重叠或相邻的范围会被合并，无效的范围返回空，所有范围都越界时返回空范围
+pub fn parse_ranges(value: &str, len: u64) -> Option<Vec<(u64, u64)>> {
+    let mut parts = value.trim().splitn(2, '=');
+    let unit = parts.next()?.trim();
+    if !unit.eq_ignore_ascii_case(BYTES_UNIT) {
+        //不支持的范围单位
+        return None;
+    }
+
+    let specs: Vec<&str> = parts.next()?.split(',').map(|spec| spec.trim()).filter(|spec| !spec.is_empty()).collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES_LIMIT {
+        return None;
+    }
+
+    let mut ranges = Vec::with_capacity(specs.len());
+    for spec in specs {
+        let mut pair = spec.splitn(2, '-');
+        let start = pair.next()?.trim();
+        let end = pair.next()?.trim();
+        if start.is_empty() {
+            //后缀范围，例如bytes=-500
+            let suffix = end.parse::<u64>().ok()?;
+            if suffix > 0 && len > 0 {
+                ranges.push((len.saturating_sub(suffix), len - 1));
+            }
+            continue;
+        }
+
+        let start = start.parse::<u64>().ok()?;
+        let end = if end.is_empty() {
+            None
+        } else {
+            Some(end.parse::<u64>().ok()?)
+        };
+        if let Some(end) = end {
+            if start > end {
+                //无效的范围
+                return None;
+            }
+        }
+
+        if start >= len {
+            //越界的范围，则忽略
+            continue;
+        }
+        ranges.push((start, end.map_or(len - 1, |end| end.min(len - 1))));
+    }
+
+    Some(merge_ranges(ranges))
+}
+
+//合并重叠或相邻的范围，避免重复加载相同的数据
+fn merge_ranges(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort_by_key(|(start, _)| *start);
+
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        if let Some(last) = merged.last_mut() {
+            if start <= last.1 + 1 {
+                last.1 = last.1.max(end);
+                continue;
+            }
+        }
+        merged.push((start, end));
+    }
+
+    merged
+}
+
+//判断If-Range的值是否与当前资源匹配，实体标签使用强比较，日期需要与最近修改时间完全相同
+pub fn if_range_matches(value: &str, etag: Option<&str>, last_modified: Option<SystemTime>) -> bool {
+    let value = value.trim();
+    if value.starts_with("W/") {
+        //弱实体标签不能用于范围请求
+        return false;
+    }
+
+    if value.starts_with('"') {
+        return match etag {
+            Some(etag) if !etag.starts_with("W/") => etag.trim_matches('"') == value.trim_matches('"'),
+            _ => false,
+        };
+    }
+
+    if let Ok(date) = parse_http_date(value) {
+        return match last_modified {
+            Some(last_modified) => unix_secs(last_modified) == unix_secs(date),
+            None => false,
+        };
+    }
+
+    //兼容未加引号的实体标签
+    etag.map_or(false, |etag| etag == value)
+}
+
+//生成多范围响应体
+pub fn multipart_body(ranges: &[(u64, u64)], parts: &[&[u8]], len: u64, mime: Option<&str>, boundary: &str) -> Vec<u8> {
+    let heads = multipart_heads(ranges, len, mime, boundary);
+    let mut buf = Vec::with_capacity(multipart_len(ranges, &heads, boundary) as usize);
+    for (head, part) in heads.iter().zip(parts.iter()) {
+        buf.put_slice(head.as_bytes());
+        buf.put_slice(part);
+    }
+    buf.put_slice(multipart_tail(boundary).as_bytes());
+
+    buf
+}
+
+//生成多范围响应体中每个范围的头
+fn multipart_heads(ranges: &[(u64, u64)], len: u64, mime: Option<&str>, boundary: &str) -> Vec<String> {
+    ranges.iter().enumerate().map(|(index, range)| {
+        let mut head = if index == 0 {
+            String::new()
+        } else {
+            "\r\n".to_string()
+        };
+        head.push_str(&format!("--{}\r\n", boundary));
+        if let Some(mime) = mime {
+            head.push_str(&format!("Content-Type: {}\r\n", mime));
+        }
+        head.push_str(&format!("Content-Range: {}\r\n\r\n", content_range(*range, len)));
+        head
+    }).collect()
+}
+
+//生成多范围响应体的尾
+fn multipart_tail(boundary: &str) -> String {
+    format!("\r\n--{}--\r\n", boundary)
+}
+
+//计算多范围响应体的长度
+fn multipart_len(ranges: &[(u64, u64)], heads: &[String], boundary: &str) -> u64 {
+    let parts_len: u64 = ranges.iter().map(|(start, end)| end - start + 1).sum();
+    let heads_len: u64 = heads.iter().map(|head| head.len() as u64).sum();
+    parts_len + heads_len + multipart_tail(boundary).len() as u64
+}
+
+//生成多范围响应的Mime
+fn multipart_mime(boundary: &str) -> String {
+    format!("{}; boundary={}", MULTIPART_BYTERANGES_MIME, boundary)
+}
+
+//生成多范围响应的分隔符
+fn new_boundary() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("pi_http_{:x}{:04x}", now.as_nanos(), BOUNDARY_COUNTER.fetch_add(1, Ordering::Relaxed) & 0xffff)
+}
+
+//生成指定范围的Content-Range
+fn content_range((start, end): (u64, u64), len: u64) -> String {
+    format!("{} {}-{}/{}", BYTES_UNIT, start, end, len)
+}
+
+//生成范围越界的Content-Range
+fn unsatisfied_content_range(len: u64) -> String {
+    format!("{} */{}", BYTES_UNIT, len)
+}
+
+//获取时间的秒数，Http日期只精确到秒
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+//在文件异步运行时中获取指定文件的元信息
+async fn async_metadata(files_async_runtime: &MultiTaskRuntime<()>, path: PathBuf) -> Result<Metadata> {
+    let (sender, receiver) = oneshot::channel();
+    if let Err(e) = files_async_runtime.spawn(files_async_runtime.alloc(), async move {
+        let _ = sender.send(fs::metadata(&path));
+    }) {
+        return Err(Error::new(ErrorKind::Other, format!("get file metadata failed, reason: {:?}", e)));
+    }
+
+    match receiver.await {
+        Err(_) => Err(Error::new(ErrorKind::Other, "get file metadata failed, reason: task canceled")),
+        Ok(result) => result,
     }
 }
+
+//尝试直接从文件中加载请求的范围，不需要或无法从文件中加载范围时返回空，由后续中间件处理
+async fn load_file_ranges<S: Socket, W: AsyncIOWait>(files_async_runtime: &MultiTaskRuntime<()>,
+                                                     root: &Path,
+                                                     req: &HttpRequest<S, W>) -> Option<Result<HttpResponse<S, W>>> {
+    if req.method() != &Method::GET {
+        return None;
+    }
+    let range = req.headers().get(RANGE).and_then(|value| value.to_str().ok())?;
+
+    let mut path = root.to_path_buf();
+    path.extend(&normalize_path(Path::new(req.url().path())));
+    let meta = async_metadata(files_async_runtime, path.clone()).await.ok().filter(|meta| meta.is_file())?;
+    let last_modified = meta.modified().ok();
+
+    if let Some(if_range) = req.headers().get(IF_RANGE).and_then(|value| value.to_str().ok()) {
+        if if_range.trim().starts_with('"') || if_range.trim().starts_with("W/") {
+            //实体标签由缓存生成，则由后续中间件加载文件后验证
+            return None;
+        }
+
+        if !if_range_matches(if_range, None, last_modified) {
+            //文件已修改，则由后续中间件返回完整的文件
+            return None;
+        }
+    }
+
+    let len = meta.len();
+    let ranges = parse_ranges(range, len)?;
+    let mut resp = HttpResponse::new(req.get_handle().clone(), req.get_waits().clone(), RANGE_RESP_BODY_BUFFER_LEN);
+    resp.header(ACCEPT_RANGES.as_str(), BYTES_UNIT);
+    if let Some(last_modified) = last_modified {
+        resp.header(LAST_MODIFIED.as_str(), fmt_http_date(last_modified).as_str());
+    }
+
+    if ranges.is_empty() {
+        //客户端需要的静态资源范围越界
+        resp.status(StatusCode::RANGE_NOT_SATISFIABLE.as_u16())
+            .header(CONTENT_RANGE.as_str(), &unsatisfied_content_range(len))
+            .header(CONTENT_LENGTH.as_str(), "0");
+        return Some(Ok(resp));
+    }
+
+    let mime = mime_guess::from_path(&path).first_or_text_plain();
+    resp.status(StatusCode::PARTIAL_CONTENT.as_u16());
+    let (heads, tail) = if ranges.len() == 1 {
+        resp.header(CONTENT_TYPE.as_str(), mime.as_ref())
+            .header(CONTENT_RANGE.as_str(), &content_range(ranges[0], len))
+            .header(CONTENT_LENGTH.as_str(), (ranges[0].1 - ranges[0].0 + 1).to_string().as_str());
+        (Vec::new(), String::new())
+    } else {
+        let boundary = new_boundary();
+        let heads = multipart_heads(&ranges, len, Some(mime.as_ref()), &boundary);
+        resp.header(CONTENT_TYPE.as_str(), &multipart_mime(&boundary))
+            .header(CONTENT_LENGTH.as_str(), multipart_len(&ranges, &heads, &boundary).to_string().as_str());
+        (heads, multipart_tail(&boundary))
+    };
+
+    if let Err(e) = async_load_ranges(files_async_runtime.clone(), &resp, path, ranges, heads, tail) {
+        return Some(Err(e));
+    }
+    resp.set_stream(true);
+
+    Some(Ok(resp))
+}
+
+//异步加载指定文件的范围，每个范围按块读取并以流方式写入响应体，多范围时在每个范围前增加范围头，并在最后增加尾
+fn async_load_ranges<S: Socket, W: AsyncIOWait>(files_async_runtime: MultiTaskRuntime<()>,
+                                                resp: &HttpResponse<S, W>,
+                                                path: PathBuf,
+                                                ranges: Vec<(u64, u64)>,
+                                                heads: Vec<String>,
+                                                tail: String) -> Result<()> {
+    let resp_handler = match resp.get_response_handler() {
+        None => {
+            return Err(Error::new(ErrorKind::NotFound, "load file ranges error, reason: invalid response body"));
+        },
+        Some(handler) => handler,
+    };
+
+    let files_async_runtime_copy = files_async_runtime.clone();
+    if let Err(e) = files_async_runtime.spawn(files_async_runtime.alloc(), async move {
+        let file = match AsyncFile::open(files_async_runtime_copy, path.clone(), AsyncFileOptions::OnlyRead).await {
+            Err(e) => {
+                warn!("!!!> Http Async Open File Failed, file: {:?}, reason: {:?}", path, e);
+                if let Err(e) = resp_handler.finish() {
+                    warn!("!!!> Http Body Mut Finish Failed, file: {:?}, reason: {:?}", path, e);
+                }
+                return;
+            },
+            Ok(file) => file,
+        };
+
+        let mut result = Ok(());
+        'ranges: for (index, (start, end)) in ranges.into_iter().enumerate() {
+            if let Some(head) = heads.get(index) {
+                if let Err(e) = resp_handler.write(head.as_bytes().to_vec()) {
+                    result = Err(e);
+                    break 'ranges;
+                }
+            }
+
+            let mut offset = start;
+            while offset <= end {
+                let size = ((end - offset + 1) as usize).min(RANGE_BLOCK_SIZE);
+                match file.read(offset, size).await {
+                    Err(e) => {
+                        result = Err(e);
+                        break 'ranges;
+                    },
+                    Ok(bin) => {
+                        if bin.is_empty() {
+                            //文件已被截断
+                            result = Err(Error::new(ErrorKind::UnexpectedEof, format!("read file range failed, start: {}, end: {}", offset, end)));
+                            break 'ranges;
+                        }
+
+                        offset += bin.len() as u64;
+                        if let Err(e) = resp_handler.write(bin) {
+                            //客户端已关闭，则停止加载
+                            result = Err(e);
+                            break 'ranges;
+                        }
+                    },
+                }
+            }
+        }
+        if result.is_ok() && !tail.is_empty() {
+            result = resp_handler.write(tail.into_bytes());
+        }
+
+        if let Err(e) = result {
+            warn!("!!!> Http Async Load File Ranges Failed, file: {:?}, reason: {:?}", path, e);
+        }
+        if let Err(e) = resp_handler.finish() {
+            warn!("!!!> Http Body Mut Finish Failed, file: {:?}, reason: {:?}", path, e);
+        }
+    }) {
+        warn!("!!!> Http Async Open File Failed, reason: {:?}", e);
+    }
+
+    Ok(())
+}
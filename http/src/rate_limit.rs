@@ -0,0 +1,393 @@
+use std::time::{Duration, Instant};
+use std::collections::VecDeque;
+
+use https::{StatusCode, header::{HeaderName, RETRY_AFTER, CONTENT_LENGTH}};
+use futures::future::{FutureExt, BoxFuture};
+use parking_lot::Mutex;
+
+use hash::XHashMap;
+use handler::SGenType;
+use tcp::driver::{Socket, AsyncIOWait};
+
+use crate::{gateway::GatewayContext,
+            middleware::{MiddlewareResult, Middleware},
+            request::HttpRequest,
+            response::HttpResponse,
+            util::{IpRange, client_ip}};
+
+/*
+* 限流响应头
+*/
+pub const RATE_LIMIT_LIMIT_HEADER: &str = "ratelimit-limit";
+pub const RATE_LIMIT_REMAINING_HEADER: &str = "ratelimit-remaining";
+pub const RATE_LIMIT_RESET_HEADER: &str = "ratelimit-reset";
+pub const RATE_LIMIT_POLICY_HEADER: &str = "ratelimit-policy";
+
+/*
+* 限流结果在网关上下文中的属性名，值为"限制数,剩余数,重置秒数"
+*/
+pub const RATE_LIMIT_ATTR: &str = "rate_limit";
+
+/*
+* 默认的最大限流键数量，达到时会回收空闲的限流键，回收后仍达到则淘汰最早的限流键
+*/
+const DEFAULT_MAX_RATE_LIMIT_KEYS: usize = 100000;
+
+/*
+* 限流算法
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimitAlgorithm {
+    TokenBucket,    //令牌桶，容量为限制数，按限制数/窗口时长的速率补充令牌，允许突发请求
+    SlidingWindow,  //滑动窗口，按上一个窗口的计数加权估算当前滑动窗口内的请求数
+}
+
+/*
+* 限流键
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum RateLimitKey {
+    RemoteAddr,     //客户端地址，对端地址是可信代理时使用X-Forwarded-For中的客户端地址
+    Route,          //请求方法和匹配的路由，未匹配路由则使用请求路径
+    Header(String), //指定请求头的值，请求头不存在则使用客户端地址
+    Principal,      //网关上下文中的已认证主体，未认证则使用客户端地址
+}
+
+/*
+* 限流结果
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitDecision {
+    pub is_allowed:     bool,   //是否允许请求
+    pub limit:          u64,    //窗口内的限制数
+    pub remaining:      u64,    //窗口内的剩余数
+    pub reset:          u64,    //剩余数恢复到限制数的时长，单位秒
+    pub retry_after:    u64,    //被限流时，允许下一次请求前需要等待的时长，单位秒
+}
+
+/*
+* 限流键的状态
+*/
+enum LimitState {
+    Bucket(f64, Instant),       //令牌桶的令牌数和上次补充时间
+    Window(u64, u64, Instant),  //滑动窗口的上一个窗口计数、当前窗口计数和当前窗口开始时间
+}
+
+/*
+* 限流键状态表，按限流键的增加顺序记录限流键，用于在限流键数量达到限制时淘汰最早的限流键
+*/
+struct LimitStates {
+    map:    XHashMap<String, LimitState>,   //限流键状态表
+    order:  VecDeque<String>,               //限流键的增加顺序
+}
+
+impl LimitStates {
+    //设置指定限流键的状态，新的限流键会记录增加顺序
+    fn insert(&mut self, key: &str, state: LimitState) {
+        if self.map.insert(key.to_string(), state).is_none() {
+            self.order.push_back(key.to_string());
+        }
+    }
+
+    //淘汰最早增加的限流键，没有限流键则返回假
+    fn evict(&mut self) -> bool {
+        match self.order.pop_front() {
+            None => false,
+            Some(key) => {
+                self.map.remove(&key);
+                true
+            },
+        }
+    }
+}
+
+/*
+* 限流中间件
+*/
+pub struct RateLimit {
+    algorithm:          RateLimitAlgorithm,                 //限流算法
+    limit:              u64,                                //窗口内的限制数
+    window:             Duration,                           //窗口时长
+    keys:               Vec<RateLimitKey>,                  //限流键列表，多个键会组合为一个限流键
+    trusted_proxies:    Vec<IpRange>,                       //可信代理地址段列表
+    max_keys:           usize,                              //最大限流键数量
+    states:             Mutex<LimitStates>,                 //限流键状态表
+    collected:          Mutex<Option<Instant>>,             //上次因限流键数量达到限制而回收的时间
+}
+
+unsafe impl Send for RateLimit {}
+unsafe impl Sync for RateLimit {}
+
+impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for RateLimit {
+    fn request<'a>(&'a self, context: &'a mut GatewayContext, req: HttpRequest<S, W>)
+                   -> BoxFuture<'a, MiddlewareResult<S, W>> {
+        let future = async move {
+            let key = self.request_key(context, &req);
+            let decision = self.check(key.as_str());
+            if decision.is_allowed {
+                //允许请求，则记录限流结果，并继续请求处理
+                context.set(RATE_LIMIT_ATTR.to_string(),
+                            SGenType::Str(format!("{},{},{}", decision.limit, decision.remaining, decision.reset)));
+                return MiddlewareResult::ContinueRequest(req);
+            }
+
+            //请求被限流
+            let mut resp = HttpResponse::new(req.get_handle().clone(), req.get_waits().clone(), 1);
+            resp.status(StatusCode::TOO_MANY_REQUESTS.as_u16());
+            resp.header(RETRY_AFTER.as_str(), decision.retry_after.to_string().as_str());
+            self.set_headers(&mut resp, decision.limit, decision.remaining, decision.reset);
+            resp.header(CONTENT_LENGTH.as_str(), "0");
+            MiddlewareResult::Break(resp)
+        };
+        future.boxed()
+    }
+
+    fn response<'a>(&'a self, context: &'a mut GatewayContext, req: HttpRequest<S, W>, resp: HttpResponse<S, W>)
+                    -> BoxFuture<'a, MiddlewareResult<S, W>> {
+        let mut response = resp;
+        let future = async move {
+            if let Some(SGenType::Str(value)) = context.get(&RATE_LIMIT_ATTR.to_string()) {
+                if response.contains_header(HeaderName::from_static(RATE_LIMIT_LIMIT_HEADER)) {
+                    //其它限流中间件已设置限流响应头
+                    return MiddlewareResult::ContinueResponse((req, response));
+                }
+
+                //为允许的请求的响应设置限流响应头
+                let values: Vec<u64> = value.split(',').filter_map(|value| value.parse().ok()).collect();
+                if values.len() == 3 {
+                    self.set_headers(&mut response, values[0], values[1], values[2]);
+                }
+            }
+
+            MiddlewareResult::ContinueResponse((req, response))
+        };
+        future.boxed()
+    }
+}
+
+impl RateLimit {
+    //构建限流中间件，默认使用客户端地址作为限流键
+    pub fn new(algorithm: RateLimitAlgorithm, limit: u64, window: Duration) -> Self {
+        RateLimit {
+            algorithm,
+            limit: limit.max(1),
+            window: if window == Duration::from_secs(0) { Duration::from_secs(1) } else { window },
+            keys: vec![RateLimitKey::RemoteAddr],
+            trusted_proxies: Vec::new(),
+            max_keys: DEFAULT_MAX_RATE_LIMIT_KEYS,
+            states: Mutex::new(LimitStates {
+                map: XHashMap::default(),
+                order: VecDeque::new(),
+            }),
+            collected: Mutex::new(None),
+        }
+    }
+
+    //设置限流键列表
+    pub fn set_keys(&mut self, keys: Vec<RateLimitKey>) {
+        self.keys = keys;
+    }
+
+    //设置可信代理地址段列表
+    pub fn set_trusted_proxies(&mut self, trusted_proxies: Vec<IpRange>) {
+        self.trusted_proxies = trusted_proxies;
+    }
+
+    //设置最大限流键数量，至少为1
+    pub fn set_max_keys(&mut self, max_keys: usize) {
+        self.max_keys = max_keys.max(1);
+    }
+
+    //获取当前限流键数量
+    pub fn len(&self) -> usize {
+        self.states.lock().map.len()
+    }
+
+    //检查指定限流键是否允许请求，允许则消耗一次请求
+    pub fn check(&self, key: &str) -> RateLimitDecision {
+        self.check_at(key, Instant::now())
+    }
+
+    //检查指定限流键在指定时间是否允许请求，允许则消耗一次请求
+    pub fn check_at(&self, key: &str, now: Instant) -> RateLimitDecision {
+        let mut states = self.states.lock();
+        if states.map.len() >= self.max_keys && !states.map.contains_key(key) {
+            //限流键数量已达限制，则每个窗口时长最多回收一次空闲的限流键，避免每个新限流键都遍历限流键状态表
+            let mut collected = self.collected.lock();
+            if collected.map_or(true, |time| elapsed(now, time) >= self.window) {
+                collect_states(&mut states, self.limit, self.window, now);
+                *collected = Some(now);
+            }
+
+            //回收后限流键数量仍已达限制，则淘汰最早的限流键，避免大量新的限流键导致其它客户端的请求都被拒绝
+            while states.map.len() >= self.max_keys && states.evict() {}
+        }
+
+        let window = duration_secs(self.window);
+        let limit = self.limit as f64;
+        match self.algorithm {
+            RateLimitAlgorithm::TokenBucket => {
+                let rate = limit / window;
+                let (tokens, last) = match states.map.get(key) {
+                    Some(LimitState::Bucket(tokens, last)) => (*tokens, *last),
+                    _ => (limit, now),
+                };
+                let tokens = (tokens + duration_secs(elapsed(now, last)) * rate).min(limit);
+
+                if tokens >= 1.0 {
+                    let tokens = tokens - 1.0;
+                    states.insert(key, LimitState::Bucket(tokens, now));
+                    RateLimitDecision {
+                        is_allowed: true,
+                        limit: self.limit,
+                        remaining: tokens as u64,
+                        reset: ((limit - tokens) / rate).ceil() as u64,
+                        retry_after: 0,
+                    }
+                } else {
+                    states.insert(key, LimitState::Bucket(tokens, now));
+                    RateLimitDecision {
+                        is_allowed: false,
+                        limit: self.limit,
+                        remaining: 0,
+                        reset: ((limit - tokens) / rate).ceil() as u64,
+                        retry_after: (((1.0 - tokens) / rate).ceil() as u64).max(1),
+                    }
+                }
+            },
+            RateLimitAlgorithm::SlidingWindow => {
+                let (mut prev, mut curr, mut start) = match states.map.get(key) {
+                    Some(LimitState::Window(prev, curr, start)) => (*prev, *curr, *start),
+                    _ => (0, 0, now),
+                };
+
+                //滑动到当前时间所在的窗口
+                let elapsed = duration_secs(elapsed(now, start));
+                if elapsed >= window * 2.0 {
+                    prev = 0;
+                    curr = 0;
+                    start = now;
+                } else if elapsed >= window {
+                    prev = curr;
+                    curr = 0;
+                    start += self.window;
+                }
+
+                let position = duration_secs(elapsed(now, start)) / window;
+                let count = prev as f64 * (1.0 - position) + curr as f64;
+                let reset = (window * (1.0 - position)).ceil() as u64;
+                if count + 1.0 <= limit {
+                    curr += 1;
+                    states.insert(key, LimitState::Window(prev, curr, start));
+                    RateLimitDecision {
+                        is_allowed: true,
+                        limit: self.limit,
+                        remaining: (limit - count - 1.0).floor() as u64,
+                        reset,
+                        retry_after: 0,
+                    }
+                } else {
+                    states.insert(key, LimitState::Window(prev, curr, start));
+                    let retry_after = if curr >= self.limit || prev == 0 {
+                        //当前窗口已满，则需要等待当前窗口结束
+                        reset
+                    } else {
+                        //等待上一个窗口的权重降低到允许一次请求
+                        let position = 1.0 - (limit - 1.0 - curr as f64) / prev as f64;
+                        (window * position - duration_secs(elapsed(now, start))).ceil() as u64
+                    };
+
+                    RateLimitDecision {
+                        is_allowed: false,
+                        limit: self.limit,
+                        remaining: 0,
+                        reset,
+                        retry_after: retry_after.max(1),
+                    }
+                }
+            },
+        }
+    }
+
+    //回收所有空闲的限流键，返回回收的数量
+    pub fn collect(&self) -> usize {
+        collect_states(&mut self.states.lock(), self.limit, self.window, Instant::now())
+    }
+
+    //获取请求的限流键
+    fn request_key<S: Socket, W: AsyncIOWait>(&self, context: &GatewayContext, req: &HttpRequest<S, W>) -> String {
+        let mut key = String::new();
+        for (index, k) in self.keys.iter().enumerate() {
+            if index > 0 {
+                key.push('|');
+            }
+
+            let part = match k {
+                RateLimitKey::RemoteAddr => None,
+                RateLimitKey::Route => {
+                    //使用匹配的路由，避免路径参数不同的请求使用不同的限流键
+                    Some(req.method().as_str().to_string() + " " + context.get_route().unwrap_or(req.url().path()))
+                },
+                RateLimitKey::Header(name) => {
+                    req.headers().get(name.as_str()).and_then(|value| value.to_str().ok()).map(|value| "h:".to_string() + value)
+                },
+                RateLimitKey::Principal => {
+                    context.get_principal().map(|principal| "p:".to_string() + principal.name())
+                },
+            };
+
+            match part {
+                Some(part) => key.push_str(part.as_str()),
+                None => {
+                    //使用客户端地址
+                    let ip = client_ip(req.headers(), req.get_handle().get_remote().ip(), &self.trusted_proxies);
+                    key.push_str(ip.to_string().as_str());
+                },
+            }
+        }
+
+        key
+    }
+
+    //设置限流响应头
+    fn set_headers<S: Socket, W: AsyncIOWait>(&self, resp: &mut HttpResponse<S, W>, limit: u64, remaining: u64, reset: u64) {
+        resp.header(RATE_LIMIT_LIMIT_HEADER, limit.to_string().as_str());
+        resp.header(RATE_LIMIT_REMAINING_HEADER, remaining.to_string().as_str());
+        resp.header(RATE_LIMIT_RESET_HEADER, reset.to_string().as_str());
+        resp.header(RATE_LIMIT_POLICY_HEADER, format!("{};w={}", self.limit, self.window.as_secs().max(1)).as_str());
+    }
+}
+
+//回收所有空闲的限流键，令牌桶已满或滑动窗口内没有请求的限流键为空闲，返回回收的数量
+fn collect_states(states: &mut LimitStates, limit: u64, window: Duration, now: Instant) -> usize {
+    let len = states.map.len();
+    states.map.retain(|_, state| {
+        match state {
+            LimitState::Bucket(tokens, last) => {
+                let rate = limit as f64 / duration_secs(window);
+                *tokens + duration_secs(elapsed(now, *last)) * rate < limit as f64
+            },
+            LimitState::Window(_, _, start) => {
+                elapsed(now, *start) < window * 2
+            },
+        }
+    });
+
+    let map = &states.map;
+    states.order.retain(|key| map.contains_key(key));
+    len - states.map.len()
+}
+
+//获取从指定时间到当前时间的时长，指定时间晚于当前时间则为0
+fn elapsed(now: Instant, since: Instant) -> Duration {
+    if now > since {
+        now.duration_since(since)
+    } else {
+        Duration::from_secs(0)
+    }
+}
+
+//获取时长的秒数
+fn duration_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1_000_000_000.0
+}
@@ -9,10 +9,12 @@ use bytes::BufMut;
 use https::{StatusCode,
             method::Method,
             version::Version,
-            header::{CONTENT_LENGTH, HeaderMap}};
+            header::{CONTENT_LENGTH, TRANSFER_ENCODING, HeaderMap}};
 
 use tcp::driver::{Socket, SocketHandle, AsyncIOWait, AsyncReadTask};
 
+use crate::packet::{ChunkedDecoder, reply_status};
+
 /*
 * 默认的块大小，8KB
 */
@@ -23,6 +25,11 @@ const DEFAULT_BLOCK_SIZE: usize = 8192;
 */
 const MAX_BLOCK_LIMIT: usize = 16 * 1024 * 1024;
 
+/*
+* 分块传输编码名
+*/
+const CHUNKED_TRANSFER_ENCODING: &str = "chunked";
+
 /*
 * Http请求启始行
 */
@@ -70,6 +77,9 @@ pub struct HttpRequest<S: Socket, W: AsyncIOWait> {
     content_len:    Option<usize>,          //Http请求体的实际长度，如果为空，表示本次请求体以流方式传输，否则表示请求体以块方式传输
     body:           Vec<u8>,                //Http请求体
     body_len:       usize,                  //Http请求体的当前长度
+    chunked:        Option<ChunkedDecoder>, //Http请求体的分块解码器，不为空表示本次请求体以分块方式传输
+    recv_len:       usize,                  //已接收的Http请求体总长度
+    body_limit:     Option<usize>,          //Http请求体的总长度限制，为空表示不限制
 }
 
 /*
@@ -89,6 +99,25 @@ impl<S: Socket, W: AsyncIOWait> HttpRequest<S, W> {
             return None;
         }
 
+        //检查本次Http请求体是否以分块方式传输，分块传输时忽略CONTENT_LENGTH头
+        if is_chunked(&headers) {
+            let mut chunked = ChunkedDecoder::new();
+            chunked.push(preffix);
+
+            return Some(HttpRequest {
+                handle,
+                waits,
+                start: start.unwrap(),
+                headers: Arc::new(headers),
+                content_len: None,
+                body: Vec::new(),
+                body_len: 0,
+                chunked: Some(chunked),
+                recv_len: 0,
+                body_limit: None,
+            });
+        }
+
         //初始化本次Http请求的请求体长度
         let mut content_len = None;
         let body_len = preffix.len(); //已读取到的请求体长度
@@ -100,7 +129,7 @@ impl<S: Socket, W: AsyncIOWait> HttpRequest<S, W> {
                         content_len = Some(0);
                     } else {
                         //设置本次请求的未读取到的请求体长度
-                        content_len = Some(len - body_len);
+                        content_len = Some(len.saturating_sub(body_len));
                     }
                 }
             }
@@ -114,6 +143,37 @@ impl<S: Socket, W: AsyncIOWait> HttpRequest<S, W> {
             content_len,
             body: Vec::from(preffix),
             body_len,
+            chunked: None,
+            recv_len: body_len,
+            body_limit: None,
+        })
+    }
+
+    //构建指定的已接收完整请求体的Http请求，用于请求体已由连接接收完成的协议，例如Http2
+    pub fn with_body(handle: SocketHandle<S>,
+                     waits: W,
+                     method: &str,
+                     url: &str,
+                     version: Version,
+                     headers: HeaderMap,
+                     body: Vec<u8>) -> Option<Self> {
+        let start = StartLine::new(method, url, version);
+        if start.is_none() {
+            return None;
+        }
+
+        let body_len = body.len();
+        Some(HttpRequest {
+            handle,
+            waits,
+            start: start.unwrap(),
+            headers: Arc::new(headers),
+            content_len: Some(0),
+            body,
+            body_len,
+            chunked: None,
+            recv_len: body_len,
+            body_limit: None,
         })
     }
 
@@ -157,8 +217,55 @@ impl<S: Socket, W: AsyncIOWait> HttpRequest<S, W> {
         self.headers.clone()
     }
 
-    //设置Http请求体，只有根据CONTENT_LENGTH头将所有数据读取完以后，才允许设置Http请求体
+    //判断Http请求体是否以分块方式传输
+    pub fn is_chunked(&self) -> bool {
+        self.chunked.is_some()
+    }
+
+    //获取Http请求的尾部头，只有分块传输的请求体读取完以后才会返回
+    pub fn trailers(&self) -> Option<&HeaderMap> {
+        if let Some(chunked) = &self.chunked {
+            return chunked.trailers();
+        }
+
+        None
+    }
+
+    //获取Http请求体的总长度限制
+    pub fn get_body_limit(&self) -> Option<usize> {
+        self.body_limit
+    }
+
+    //设置Http请求体的总长度限制，如果CONTENT_LENGTH头声明的请求体长度已超过限制，则返回错误
+    pub fn set_body_limit(&mut self, limit: Option<usize>) -> Result<()> {
+        self.body_limit = limit;
+
+        if let Some(limit) = limit {
+            if let Some(content_len) = self.content_len {
+                let len = self.body.len() + content_len;
+                if len > limit {
+                    return Err(Error::new(ErrorKind::InvalidData, format!("http request body too large, len: {}, limit: {}", len, limit)));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    //设置Http请求体，只有根据CONTENT_LENGTH头或分块传输将所有数据读取完以后，才允许设置Http请求体
     pub fn set_body(&mut self, body: &[u8]) -> bool {
+        if let Some(chunked) = &self.chunked {
+            //本次Http请求的请求体以分块方式传输
+            if !chunked.is_done() {
+                //请求体未读取完成，则返回设置失败
+                return false;
+            }
+
+            self.body.clear();
+            self.body.put_slice(body);
+            return true;
+        }
+
         if let Some(content_len) = self.content_len {
             //本次Http请求有实际长度的请求体
             if content_len > 0 {
@@ -179,8 +286,17 @@ impl<S: Socket, W: AsyncIOWait> HttpRequest<S, W> {
 * Http请求异步方法
 */
 impl<S: Socket, W: AsyncIOWait> HttpRequest<S, W> {
-    //获取Http请求体块的所有数据，块数据会缓存，可以多次读取
+    //获取Http请求体块的所有数据，块数据会缓存，可以多次读取，分块传输的请求体会在解码后缓存
     pub async fn body(&mut self) -> Option<&[u8]> {
+        if self.chunked.is_some() {
+            //本次Http请求，请求体以分块方式传输，则读取并解码所有分块
+            if self.read_chunked(usize::MAX, true).await {
+                return Some(&self.body[..]);
+            }
+
+            return None;
+        }
+
         match self.content_len {
             Some(0) => {
                 //没有剩余的Http块数据需要读取
@@ -198,6 +314,8 @@ impl<S: Socket, W: AsyncIOWait> HttpRequest<S, W> {
                         //读Http体的块数据成功，则更新Http体的当前长度
                         self.body.put(bin);
                         self.body_len += len;
+                        self.recv_len += len;
+                        self.content_len = Some(0);
                         Some(&self.body[..])
                     },
                 }
@@ -219,13 +337,23 @@ impl<S: Socket, W: AsyncIOWait> HttpRequest<S, W> {
             block_size = MAX_BLOCK_LIMIT;
         }
 
+        if self.chunked.is_some() {
+            //本次Http请求，请求体以分块方式传输，则读取并解码不超过块大小的请求体
+            self.body.clear();
+            if self.read_chunked(block_size, false).await && !self.body.is_empty() {
+                return Some(&self.body[..]);
+            }
+
+            return None;
+        }
+
         //检查是否需要返回已读取的到缓冲区内的请求体
         if self.body_len > 0 {
             //当前有部分Http体的流数据，则返回
             let offset = self.body.len() - self.body_len; //获取本次从缓冲区内读数据的偏移
 
             let len;
-            if self.body.len() <= block_size {
+            if self.body_len <= block_size {
                 //当前请求体缓冲区内的数据长度小于等于块大小
                 len = self.body_len; //本次从缓冲区内读取的数据长度
                 self.body_len = 0; //置空Http体的当前长度，防止下次重复读取当前体数据
@@ -235,7 +363,7 @@ impl<S: Socket, W: AsyncIOWait> HttpRequest<S, W> {
                 self.body_len -= block_size; //减去块长度，防止下次重复读取当前体数据
             }
 
-            return Some(&self.body[offset..len]);
+            return Some(&self.body[offset..offset + len]);
         }
 
         //开始异步分块读取块或流数据
@@ -266,8 +394,92 @@ impl<S: Socket, W: AsyncIOWait> HttpRequest<S, W> {
                 None
             },
             Ok(bin) => {
+                self.recv_len += bin.len();
+                if let Some(limit) = self.body_limit {
+                    if self.recv_len > limit {
+                        //已接收的请求体总长度超过限制，则回应错误，并关闭当前Http连接
+                        reply_status(&self.handle,
+                                     StatusCode::PAYLOAD_TOO_LARGE,
+                                     Error::new(ErrorKind::InvalidData, format!("http request body too large, limit: {}", limit)));
+                        return None;
+                    }
+                }
+
                 Some(bin)
             }
         }
     }
+
+    //读取并解码分块传输的请求体，解码后的数据写入请求体缓冲区尾部，直到分块数据结束，或不要求结束时已解码出数据，失败则回应错误并关闭当前Http连接
+    async fn read_chunked(&mut self, max: usize, until_done: bool) -> bool {
+        loop {
+            if let Some(chunked) = &mut self.chunked {
+                let last_len = self.body.len();
+                if let Err(e) = chunked.decode(&mut self.body, max) {
+                    //解码分块数据错误
+                    reply_status(&self.handle, StatusCode::BAD_REQUEST, e);
+                    return false;
+                }
+
+                self.recv_len += self.body.len() - last_len;
+                if let Some(limit) = self.body_limit {
+                    if self.recv_len > limit {
+                        //已接收的请求体总长度超过限制
+                        reply_status(&self.handle,
+                                     StatusCode::PAYLOAD_TOO_LARGE,
+                                     Error::new(ErrorKind::InvalidData, format!("http request body too large, limit: {}", limit)));
+                        return false;
+                    }
+                }
+
+                if chunked.is_done() {
+                    //分块数据已结束，则将多读的后续请求数据交还给连接，由连接继续解析后续请求
+                    let remaining = chunked.take_remaining().len();
+                    if remaining > 0 {
+                        let len = self.handle.unread(remaining);
+                        if len < remaining {
+                            //无法交还全部数据，则立即关闭当前Http连接，避免后续请求被截断
+                            self.handle.close(Err(Error::new(ErrorKind::InvalidData, format!("http request unread pipelined data failed, len: {}, unread: {}", remaining, len))));
+                            return false;
+                        }
+                    }
+
+                    return true;
+                }
+
+                if (!until_done && self.body.len() > last_len) || self.body.len() >= max {
+                    //已解码出数据，或请求体缓冲区已满
+                    return true;
+                }
+            } else {
+                return false;
+            }
+
+            match AsyncReadTask::async_read(self.handle.clone(), self.waits.clone(), 0).await {
+                Err(e) => {
+                    //读Http体的分块数据错误，则立即关闭当前Http连接
+                    self.handle.close(Err(Error::new(ErrorKind::InvalidInput, e)));
+                    return false;
+                },
+                Ok(bin) => {
+                    if let Some(chunked) = &mut self.chunked {
+                        chunked.push(bin);
+                    }
+                },
+            }
+        }
+    }
+}
+
+//判断Http请求头是否声明了分块传输编码，分块传输编码必须是最后一个传输编码
+fn is_chunked(headers: &HeaderMap) -> bool {
+    if let Some(value) = headers.get_all(TRANSFER_ENCODING).iter().last() {
+        if let Ok(str) = value.to_str() {
+            if let Some(encoding) = str.rsplit(',').next() {
+                return encoding.trim().eq_ignore_ascii_case(CHUNKED_TRANSFER_ENCODING);
+            }
+        }
+    }
+
+    false
 }
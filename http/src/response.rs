@@ -1,7 +1,8 @@
+use std::mem;
 use std::sync::Arc;
 use std::str::FromStr;
 use std::io::{Error, Result, ErrorKind};
-use std::sync::atomic::{AtomicU16, AtomicIsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicIsize, Ordering};
 
 use bytes::BufMut;
 use https::{status::StatusCode,
@@ -32,6 +33,9 @@ pub struct StartLine {
 pub struct RespBody<S: Socket, W: AsyncIOWait> {
     consumer:   HttpReceiver<S, W, (u64, Vec<u8>)>, //Http响应体消费者
     buf:        Option<Vec<u8>>,                    //Http响应体缓冲区，不为空表示响应体已准备好
+    stream:     Arc<AtomicBool>,                    //是否是流式响应体
+    pending:    Vec<Vec<u8>>,                       //流式响应体中已接收但未发送的响应体块
+    is_fin:     bool,                               //流式响应体是否已接收完成
 }
 
 /*
@@ -100,6 +104,21 @@ impl<S: Socket, W: AsyncIOWait> RespBody<S, W> {
     pub fn into_bin(self) -> Option<Vec<u8>> {
         self.buf
     }
+
+    //判断是否是流式响应体
+    pub fn is_stream(&self) -> bool {
+        self.stream.load(Ordering::Relaxed)
+    }
+
+    //在流式响应体的待发送缓冲尾部，增加已接收的响应体块
+    pub fn pend(&mut self, bin: Vec<u8>) {
+        self.pending.push(bin);
+    }
+
+    //设置流式响应体已接收完成
+    pub fn pend_fin(&mut self) {
+        self.is_fin = true;
+    }
 }
 
 /*
@@ -114,6 +133,30 @@ impl<S: Socket, W: AsyncIOWait> RespBody<S, W> {
 
         self.consumer.recv().await
     }
+
+    //获取流式响应体的后续响应体块，会优先返回已接收但未发送的响应体块
+    pub async fn next_stream(&mut self) -> HttpRecvResult<Vec<Vec<u8>>> {
+        if !self.pending.is_empty() || self.is_fin {
+            //有已接收但未发送的响应体块，或已接收完成
+            let bufs = mem::replace(&mut self.pending, Vec::new());
+            if self.is_fin {
+                return HttpRecvResult::Fin(bufs);
+            }
+
+            return HttpRecvResult::Ok(bufs);
+        }
+
+        match self.consumer.recv().await {
+            HttpRecvResult::Err(e) => HttpRecvResult::Err(e),
+            HttpRecvResult::Ok(bodys) => {
+                HttpRecvResult::Ok(bodys.into_iter().map(|(_index, bin)| bin).collect())
+            },
+            HttpRecvResult::Fin(bodys) => {
+                self.is_fin = true;
+                HttpRecvResult::Fin(bodys.into_iter().map(|(_index, bin)| bin).collect())
+            },
+        }
+    }
 }
 
 /*
@@ -122,6 +165,7 @@ impl<S: Socket, W: AsyncIOWait> RespBody<S, W> {
 pub struct ResponseHandler<S: Socket> {
     status:     Arc<AtomicU16>,                 //Http响应状态码
     headers:    Arc<Mutex<HeaderMap>>,          //Http响应头
+    stream:     Arc<AtomicBool>,                //是否是流式响应
     producor:   HttpSender<S, (u64, Vec<u8>)>,  //Http响应体生产者
 }
 
@@ -133,6 +177,7 @@ impl<S: Socket> Clone for ResponseHandler<S> {
         ResponseHandler {
             status: self.status.clone(),
             headers: self.headers.clone(),
+            stream: self.stream.clone(),
             producor: self.producor.clone(),
         }
     }
@@ -142,10 +187,12 @@ impl<S: Socket> ResponseHandler<S> {
     //构建Http响应句柄
     pub fn new(status: Arc<AtomicU16>,
                headers: Arc<Mutex<HeaderMap>>,
+               stream: Arc<AtomicBool>,
                producor: HttpSender<S, (u64, Vec<u8>)>) -> Self {
         ResponseHandler {
             status,
             headers,
+            stream,
             producor,
         }
     }
@@ -164,6 +211,27 @@ impl<S: Socket> ResponseHandler<S> {
         }
     }
 
+    //线程安全的将Http响应设置为流式响应，并立即发送响应启始行和响应头，之后写入的响应体块会在写入后立即发送
+    //必须在设置Http状态码和Http响应头以后调用，未设置响应体长度时，将使用分块传输
+    pub fn stream(&self) -> Result<()> {
+        if self.stream.swap(true, Ordering::Relaxed) {
+            //已经是流式响应，则忽略
+            return Ok(());
+        }
+
+        self.producor.send(Some((0, Vec::new())))
+    }
+
+    //判断是否是流式响应
+    pub fn is_stream(&self) -> bool {
+        self.stream.load(Ordering::Relaxed)
+    }
+
+    //线程安全的判断Http响应对应的Http连接是否已关闭，流式响应可以用于判断对端是否已断开
+    pub fn is_closed(&self) -> bool {
+        self.producor.is_closed()
+    }
+
     //线程安全的写入Http响应体，默认序号为0
     pub fn write(&self, body: Vec<u8>) -> Result<()> {
         self.producor.send(Some((0, body)))
@@ -195,19 +263,7 @@ pub struct HttpResponse<S: Socket, W: AsyncIOWait> {
 impl<S: Socket, W: AsyncIOWait> From<HttpResponse<S, W>> for Vec<u8> {
     //Http响应序列化为二进制数据
     fn from(resp: HttpResponse<S, W>) -> Self {
-        let mut buf = Vec::new();
-
-        if let Some(start) = &resp.start {
-            //当前Http响应为数据块响应，则序列化Http响应启始行
-            buf.put(format!("{:?} {}\r\n", &start.version, &start.status.load(Ordering::Relaxed)).as_bytes());
-        }
-
-        //序列化Http响应头
-        for (key, value) in resp.headers.lock().iter() {
-            let slice: &[u8] = key.as_ref();
-            buf.put_slice(&[slice, b":", value.as_bytes(), b"\r\n"].concat());
-        }
-        buf.put_slice(b"\r\n");
+        let mut buf = resp.to_head();
 
         //序列化Http响应体
         if let Some(body) = resp.body {
@@ -253,12 +309,16 @@ impl<S: Socket, W: AsyncIOWait> HttpResponse<S, W> {
             version: Version::HTTP_11,
         });
         let headers = Arc::new(Mutex::new(HeaderMap::new()));
+        let stream = Arc::new(AtomicBool::new(false));
         let (producor, consumer) = channel::<S, W, (u64, Vec<u8>)>(handle.clone(), waits.clone(), size);
         let body = RespBody {
             consumer,
             buf: None,
+            stream: stream.clone(),
+            pending: Vec::new(),
+            is_fin: false,
         };
-        let handler = ResponseHandler::new(status, headers.clone(), producor);
+        let handler = ResponseHandler::new(status, headers.clone(), stream, producor);
 
         HttpResponse {
             handle,
@@ -274,12 +334,16 @@ impl<S: Socket, W: AsyncIOWait> HttpResponse<S, W> {
     pub fn stream(handle: SocketHandle<S>, waits: W, size: usize) -> Self {
         let status = Arc::new(AtomicU16::new(StatusCode::default().as_u16()));
         let headers = Arc::new(Mutex::new(HeaderMap::new()));
+        let stream = Arc::new(AtomicBool::new(false));
         let (producor, consumer) = channel::<S, W, (u64, Vec<u8>)>(handle.clone(), waits.clone(), size);
         let body = RespBody {
             consumer,
             buf: None,
+            stream: stream.clone(),
+            pending: Vec::new(),
+            is_fin: false,
         };
-        let handler = ResponseHandler::new(status, headers.clone(), producor);
+        let handler = ResponseHandler::new(status, headers.clone(), stream, producor);
 
         HttpResponse {
             handle,
@@ -301,6 +365,20 @@ impl<S: Socket, W: AsyncIOWait> HttpResponse<S, W> {
         self.start.as_ref()
     }
 
+    //获取Http响应状态码
+    pub fn get_status(&self) -> u16 {
+        if let Some(start) = &self.start {
+            return start.status.load(Ordering::Relaxed);
+        }
+
+        StatusCode::default().as_u16()
+    }
+
+    //获取Http响应头的副本
+    pub fn get_headers(&self) -> HeaderMap {
+        self.headers.lock().clone()
+    }
+
     //设置Http响应状态码
     pub fn status(&mut self, status_code: u16) -> &mut Self {
         if let Some(start) = &mut self.start {
@@ -326,6 +404,55 @@ impl<S: Socket, W: AsyncIOWait> HttpResponse<S, W> {
         self
     }
 
+    //移除指定的Http响应头
+    pub fn remove_header(&mut self, key: HeaderName) -> &mut Self {
+        self.headers.lock().remove(key);
+
+        self
+    }
+
+    //判断是否是流式响应
+    pub fn is_stream(&self) -> bool {
+        if let Some(body) = &self.body {
+            return body.is_stream();
+        }
+
+        false
+    }
+
+    //设置是否是流式响应，流式响应会在立即发送响应启始行和响应头后，再按生成顺序发送响应体块，只有有响应体的Http响应才允许设置
+    pub fn set_stream(&mut self, stream: bool) -> &mut Self {
+        if let Some(body) = &self.body {
+            body.stream.store(stream, Ordering::Relaxed);
+        }
+
+        self
+    }
+
+    //将Http响应启始行和响应头序列化为二进制数据
+    pub fn to_head(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        if let Some(start) = &self.start {
+            //当前Http响应为数据块响应，则序列化Http响应启始行
+            buf.put(format!("{:?} {}\r\n", &start.version, &start.status.load(Ordering::Relaxed)).as_bytes());
+        }
+
+        //序列化Http响应头
+        for (key, value) in self.headers.lock().iter() {
+            let slice: &[u8] = key.as_ref();
+            buf.put_slice(&[slice, b":", value.as_bytes(), b"\r\n"].concat());
+        }
+        buf.put_slice(b"\r\n");
+
+        buf
+    }
+
+    //取出Http响应体
+    pub fn take_body(&mut self) -> Option<RespBody<S, W>> {
+        self.body.take()
+    }
+
     //获取Http响应体的只读引用
     pub fn as_body(&self) -> Option<&RespBody<S, W>> {
         if let Some(body) = &self.body {
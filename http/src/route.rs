@@ -31,12 +31,51 @@ const REPLACED_DOT: &str = r"\.";
 const REPLACED_SINGLE_STAR: &str = r"([\w \.-])+";
 const REPLACED_DOUBLE_STAR: &str = r"/?([\w \.-]/?)+";
 
+/*
+* 命名参数的前缀，例如/user/:id
+*/
+const NAMED_PARAM_PREFIX: char = ':';
+
+/*
+* 命名通配参数的前缀，例如/files/*path，只允许作为路由的最后一级
+*/
+const NAMED_WILDCARD_PREFIX: char = '*';
+
+/*
+* 命名参数约束的开始和结束字符，例如/user/:id<int>
+*/
+const PARAM_CONSTRAINT_BEGIN: char = '<';
+const PARAM_CONSTRAINT_END: char = '>';
+
+/*
+* 命名参数的内置约束
+*/
+const INT_CONSTRAINT: &str = "int";
+const UUID_CONSTRAINT: &str = "uuid";
+
+/*
+* 命名参数的内置约束对应的正则表达式
+*/
+const DEFAULT_PARAM_REGEX: &str = r"[^/]+";
+const INT_PARAM_REGEX: &str = r"[+-]?\d+";
+const UUID_PARAM_REGEX: &str = r"[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}";
+const WILDCARD_PARAM_REGEX: &str = r".*";
+
+/*
+* 命名参数路由每级的特征值，特征值越大越优先匹配
+*/
+const FIXED_SEGMENT_PRIORITY: u8 = 3;
+const CONSTRAINED_PARAM_PRIORITY: u8 = 2;
+const NAMED_PARAM_PRIORITY: u8 = 1;
+const NAMED_WILDCARD_PRIORITY: u8 = 0;
+
 /*
 * 通配符路由表
 */
 struct WildcardRouter<S: Socket, W: AsyncIOWait, Context: Send + Sync + 'static, Handler: Middleware<S, W, Context>> {
     matchor:    Option<RegexSet>,               //匹配器
     route:      Arc<Vec<Atom>>,                 //路由表
+    patterns:   Arc<Vec<Atom>>,                 //路由表对应的原始路由
     handlers:   Arc<Vec<Arc<Handler>>>,         //处理器列表
     marker:     PhantomData<(S, W, Context)>,
 }
@@ -46,6 +85,7 @@ impl<S: Socket, W: AsyncIOWait, Context: Send + Sync + 'static, Handler: Middlew
         WildcardRouter {
             matchor: self.matchor.clone(), //实际复制了一个正则引擎的共享指针，并构建了一个匹配缓存
             route: self.route.clone(),
+            patterns: self.patterns.clone(),
             handlers: self.handlers.clone(),
             marker: PhantomData,
         }
@@ -58,6 +98,7 @@ impl<S: Socket, W: AsyncIOWait, Context: Send + Sync + 'static, Handler: Middlew
         WildcardRouter {
             matchor: None,
             route: Arc::new(Vec::new()),
+            patterns: Arc::new(Vec::new()),
             handlers: Arc::new(Vec::new()),
             marker: PhantomData,
         }
@@ -68,6 +109,7 @@ impl<S: Socket, W: AsyncIOWait, Context: Send + Sync + 'static, Handler: Middlew
         WildcardRouter {
             matchor: None,
             route: Arc::new(Vec::with_capacity(capacity)),
+            patterns: Arc::new(Vec::with_capacity(capacity)),
             handlers: Arc::new(Vec::with_capacity(capacity)),
             marker: PhantomData,
         }
@@ -78,14 +120,20 @@ impl<S: Socket, W: AsyncIOWait, Context: Send + Sync + 'static, Handler: Middlew
         self.route.len()
     }
 
-    //增加路由条目
-    pub fn add(&mut self, route: Atom, handler: Handler) {
+    //增加路由条目和对应的原始路由
+    pub fn add(&mut self, route: Atom, pattern: Atom, handler: Handler) {
         if let Some(vec) = Arc::get_mut(&mut self.route) {
             vec.push(route);
         } else {
             panic!("add wildcard route error, get mut ref failed");
         }
 
+        if let Some(vec) = Arc::get_mut(&mut self.patterns) {
+            vec.push(pattern);
+        } else {
+            panic!("add wildcard route pattern error, get mut ref failed");
+        }
+
         if let Some(vec) = Arc::get_mut(&mut self.handlers) {
             vec.push(Arc::new(handler));
         } else {
@@ -120,14 +168,135 @@ impl<S: Socket, W: AsyncIOWait, Context: Send + Sync + 'static, Handler: Middlew
         false
     }
 
-    //匹配路由表，匹配成功返回处理器
-    pub fn match_route(&mut self, path: &str) -> Option<Arc<Handler>> {
+    //匹配路由表，匹配成功返回处理器和原始路由
+    pub fn match_route(&mut self, path: &str) -> Option<(Arc<Handler>, Atom)> {
         if let Some(matchor) = &mut self.matchor {
             let indexes: Vec<usize> = matchor.matches(path).into_iter().collect();
             let len = indexes.len();
             if len > 0 {
                 //已匹配，则获取所有匹配项中的最后增加的项
-                return Some(self.handlers[indexes[len - 1]].clone());
+                let index = indexes[len - 1];
+                return Some((self.handlers[index].clone(), self.patterns[index].clone()));
+            }
+        }
+
+        None
+    }
+}
+
+/*
+* 命名参数路由条目
+*/
+struct ParamRoute<Handler> {
+    pattern:    Atom,           //原始路由
+    matchor:    Regex,          //匹配器
+    names:      Vec<String>,    //参数名列表
+    priority:   Vec<u8>,        //路由每级的特征值，用于按路由的具体程度排序
+    wildcard:   bool,           //最后一个参数是否是命名通配参数
+    handler:    Arc<Handler>,   //处理器
+}
+
+/*
+* 命名参数路由表
+*/
+struct ParamRouter<S: Socket, W: AsyncIOWait, Context: Send + Sync + 'static, Handler: Middleware<S, W, Context>> {
+    buf:        Option<Vec<(String, Handler)>>, //未完成的路由条目缓冲
+    routes:     Arc<Vec<ParamRoute<Handler>>>,  //已按优先级排序的路由表
+    marker:     PhantomData<(S, W, Context)>,
+}
+
+impl<S: Socket, W: AsyncIOWait, Context: Send + Sync + 'static, Handler: Middleware<S, W, Context>> Clone for ParamRouter<S, W, Context, Handler> {
+    fn clone(&self) -> Self {
+        ParamRouter {
+            buf: None,
+            routes: self.routes.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<S: Socket, W: AsyncIOWait, Context: Send + Sync + 'static, Handler: Middleware<S, W, Context>> ParamRouter<S, W, Context, Handler> {
+    //构建命名参数路由表
+    pub fn new() -> Self {
+        ParamRouter {
+            buf: Some(Vec::new()),
+            routes: Arc::new(Vec::new()),
+            marker: PhantomData,
+        }
+    }
+
+    //获取路由表长度
+    pub fn len(&self) -> usize {
+        if let Some(buf) = &self.buf {
+            return buf.len() + self.routes.len();
+        }
+
+        self.routes.len()
+    }
+
+    //增加路由条目
+    pub fn add(&mut self, route: String, handler: Handler) {
+        if let Some(buf) = &mut self.buf {
+            buf.push((route, handler));
+        } else {
+            panic!("add param route error, router already finished");
+        }
+    }
+
+    //完成路由表，会编译所有路由条目，并按路由的具体程度排序，完成以后才允许复制路由表
+    pub fn finish(&mut self) -> Result<()> {
+        let buf = match self.buf.take() {
+            None => return Ok(()),
+            Some(buf) => buf,
+        };
+
+        let mut routes = Vec::with_capacity(buf.len());
+        for (route, handler) in buf.into_iter().rev() {
+            //反向编译，以保证相同优先级的路由中，最后增加的优先匹配
+            let (pattern, names, priority) = parse_param_route(&route)?;
+            match Regex::new(&pattern) {
+                Err(e) => {
+                    return Err(Error::new(ErrorKind::Other, format!("finish param router failed, route: {:?}, reason: {:?}", route, e)));
+                },
+                Ok(matchor) => {
+                    let wildcard = priority.last() == Some(&NAMED_WILDCARD_PRIORITY);
+                    routes.push(ParamRoute {
+                        pattern: Atom::from(route),
+                        matchor,
+                        names,
+                        priority,
+                        wildcard,
+                        handler: Arc::new(handler),
+                    });
+                },
+            }
+        }
+
+        //按路由的具体程度降序排序，排序是稳定的
+        routes.sort_by(|x, y| y.priority.cmp(&x.priority));
+        self.routes = Arc::new(routes);
+        Ok(())
+    }
+
+    //判断是否匹配
+    pub fn is_match(&self, path: &str) -> bool {
+        self.routes.iter().any(|route| route.matchor.is_match(path))
+    }
+
+    //匹配路由表，匹配成功返回处理器、捕获的参数和原始路由
+    pub fn match_route(&self, path: &str) -> Option<(Arc<Handler>, Vec<(String, String)>, Atom)> {
+        for route in self.routes.iter() {
+            if let Some(captures) = route.matchor.captures(path) {
+                let mut params = Vec::with_capacity(route.names.len());
+                for (index, name) in route.names.iter().enumerate() {
+                    if let Some(value) = captures.name(name) {
+                        //命名通配参数不解码路径分隔符，以避免改变路径的层级
+                        let is_wildcard = route.wildcard && index == route.names.len() - 1;
+                        params.push((name.clone(), percent_decode(value.as_str(), is_wildcard)));
+                    }
+                }
+
+                return Some((route.handler.clone(), params, route.pattern.clone()));
             }
         }
 
@@ -140,6 +309,7 @@ impl<S: Socket, W: AsyncIOWait, Context: Send + Sync + 'static, Handler: Middlew
 */
 struct Router<S: Socket, W: AsyncIOWait, Context: Send + Sync + 'static, Handler: Middleware<S, W, Context>> {
     fixed:              Arc<XHashMap<Atom, Arc<Handler>>>,          //确定路由表
+    param:              ParamRouter<S, W, Context, Handler>,        //命名参数路由表
     single_wildcard:    WildcardRouter<S, W, Context, Handler>,     //单级通配符路由表
     mutil_wildcard:     WildcardRouter<S, W, Context, Handler>,     //多级通配符路由表
     filter:             Arc<Regex>,                                 //路由过滤器
@@ -149,6 +319,7 @@ impl<S: Socket, W: AsyncIOWait, Context: Send + Sync + 'static, Handler: Middlew
     fn clone(&self) -> Self {
         Router {
             fixed: self.fixed.clone(),
+            param: self.param.clone(),
             single_wildcard: self.single_wildcard.clone(),
             mutil_wildcard: self.mutil_wildcard.clone(),
             filter: self.filter.clone(),
@@ -161,6 +332,7 @@ impl<S: Socket, W: AsyncIOWait, Context: Send + Sync + 'static, Handler: Middlew
     pub fn new() -> Self {
         Router {
             fixed: Arc::new(XHashMap::default()),
+            param: ParamRouter::new(),
             single_wildcard: WildcardRouter::new(),
             mutil_wildcard: WildcardRouter::new(),
             filter: Arc::new(RegexBuilder::new(r"^([^\*])+[\.\*]$").build().ok().unwrap()),
@@ -176,11 +348,18 @@ impl<S: Socket, W: AsyncIOWait, Context: Send + Sync + 'static, Handler: Middlew
 
     //获取路由表长度
     pub fn len(&self) -> usize {
-        self.fixed.len() + self.single_wildcard.len() + self.mutil_wildcard.len()
+        self.fixed.len() + self.param.len() + self.single_wildcard.len() + self.mutil_wildcard.len()
     }
 
     //增加路由条目
     pub fn add(&mut self, mut route: String, handler: Handler) {
+        if is_param_route(&route) {
+            //路由中包含命名参数，则加入命名参数路由表
+            return self.param.add(route, handler);
+        }
+
+        let pattern = Atom::from(route.as_str());
+
         if self.filter.is_match(&route) {
             //优化形如/.../xxx.*的路由
             route = route.replace(".*", "");
@@ -191,12 +370,12 @@ impl<S: Socket, W: AsyncIOWait, Context: Send + Sync + 'static, Handler: Middlew
             let atom = Atom::from(route.replace(DOT_CHAR, REPLACED_DOT)
                 .replace(DOUBLE_STAR_CHAR, REPLACED_DOUBLE_STAR)
                 .replace(SINGLE_STAR_CHAR, REPLACED_SINGLE_STAR));
-            self.mutil_wildcard.add(atom, handler);
+            self.mutil_wildcard.add(atom, pattern, handler);
         } else if  route.contains(SINGLE_STAR_CHAR) {
             //路由中只包含*，则加入单级通配符路由表
             let atom = Atom::from(route.replace(DOT_CHAR, REPLACED_DOT)
                 .replace(SINGLE_STAR_CHAR, REPLACED_SINGLE_STAR));
-            self.single_wildcard.add(atom, handler);
+            self.single_wildcard.add(atom, pattern, handler);
         } else {
             //加入确定路由表
             if let Some(map) = Arc::get_mut(&mut self.fixed) {
@@ -211,6 +390,10 @@ impl<S: Socket, W: AsyncIOWait, Context: Send + Sync + 'static, Handler: Middlew
 
     //完成路由表，完成以后才允许复制路由表
     pub fn finish(&mut self) -> Result<()> {
+        if let Err(e) = self.param.finish() {
+            return Err(e);
+        }
+
         if let Err(e) = self.single_wildcard.finish() {
             return Err(e);
         }
@@ -222,9 +405,9 @@ impl<S: Socket, W: AsyncIOWait, Context: Send + Sync + 'static, Handler: Middlew
         Ok(())
     }
 
-    //判断是否匹配，优先判断确定路由表，再判断单级通配符路由表，最后判断多级通配符路由表
+    //判断是否匹配，优先判断确定路由表，再判断命名参数路由表和单级通配符路由表，最后判断多级通配符路由表
     pub fn is_match(&self, path: &str) -> bool {
-        if !self.fixed.contains_key(&Atom::from(path)) {
+        if !self.fixed.contains_key(&Atom::from(path)) && !self.param.is_match(path) {
             if !self.single_wildcard.is_match(path) {
                 return self.mutil_wildcard.is_match(path);
             }
@@ -233,24 +416,145 @@ impl<S: Socket, W: AsyncIOWait, Context: Send + Sync + 'static, Handler: Middlew
         true
     }
 
-    //匹配路由表，优先判断确定路由表，再判断单级通配符路由表，最后判断多级通配符路由表，匹配成功返回处理器
-    pub fn match_route(&mut self, path: &str) -> Option<Arc<Handler>> {
-        if let Some(handler) = self.fixed.get(&Atom::from(path)) {
-            return Some(handler.clone());
+    //匹配路由表，优先判断确定路由表，再判断命名参数路由表和单级通配符路由表，最后判断多级通配符路由表，匹配成功返回处理器、捕获的参数和原始路由
+    pub fn match_route(&mut self, path: &str) -> Option<(Arc<Handler>, Vec<(String, String)>, Atom)> {
+        let atom = Atom::from(path);
+        if let Some(handler) = self.fixed.get(&atom) {
+            return Some((handler.clone(), Vec::new(), atom));
         }
 
-        if let Some(handler) = self.single_wildcard.match_route(path) {
-            return Some(handler);
+        if let Some(result) = self.param.match_route(path) {
+            return Some(result);
         }
 
-        if let Some(handler) = self.mutil_wildcard.match_route(path) {
-            return Some(handler);
+        if let Some((handler, pattern)) = self.single_wildcard.match_route(path) {
+            return Some((handler, Vec::new(), pattern));
+        }
+
+        if let Some((handler, pattern)) = self.mutil_wildcard.match_route(path) {
+            return Some((handler, Vec::new(), pattern));
         }
 
         None
     }
 }
 
+//判断是否是包含命名参数的路由
+fn is_param_route(route: &str) -> bool {
+    route.split('/').any(|segment| {
+        segment.starts_with(NAMED_PARAM_PREFIX) || is_named_wildcard(segment)
+    })
+}
+
+//判断路由的指定级是否是命名通配参数，**不是命名通配参数
+fn is_named_wildcard(segment: &str) -> bool {
+    let mut chars = segment.chars();
+    if chars.next() != Some(NAMED_WILDCARD_PREFIX) {
+        return false;
+    }
+
+    match chars.next() {
+        Some(c) => c.is_ascii_alphabetic() || c == '_',
+        None => false,
+    }
+}
+
+//判断是否是有效的参数名
+fn is_param_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.chars().next().unwrap().is_ascii_digit()
+}
+
+//解析命名参数路由，返回路由的正则表达式、参数名列表和路由每级的特征值
+fn parse_param_route(route: &str) -> Result<(String, Vec<String>, Vec<u8>)> {
+    let segments: Vec<&str> = route.split('/').collect();
+    let mut patterns = Vec::with_capacity(segments.len());
+    let mut names: Vec<String> = Vec::new();
+    let mut priority = Vec::with_capacity(segments.len());
+
+    for (index, segment) in segments.iter().enumerate() {
+        let (name, pattern, level) = if segment.starts_with(NAMED_PARAM_PREFIX) {
+            //命名参数
+            let param = &segment[1..];
+            match param.find(PARAM_CONSTRAINT_BEGIN) {
+                None => (param, DEFAULT_PARAM_REGEX.to_string(), NAMED_PARAM_PRIORITY),
+                Some(offset) => {
+                    if !param.ends_with(PARAM_CONSTRAINT_END) {
+                        return Err(Error::new(ErrorKind::InvalidInput, format!("parse param route failed, route: {:?}, reason: invalid constraint", route)));
+                    }
+
+                    let constraint = &param[offset + 1..param.len() - 1];
+                    let pattern = match constraint {
+                        INT_CONSTRAINT => INT_PARAM_REGEX.to_string(),
+                        UUID_CONSTRAINT => UUID_PARAM_REGEX.to_string(),
+                        regex => format!("(?:{})", regex), //自定义正则表达式约束
+                    };
+                    (&param[..offset], pattern, CONSTRAINED_PARAM_PRIORITY)
+                },
+            }
+        } else if is_named_wildcard(segment) {
+            //命名通配参数
+            if index != segments.len() - 1 {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("parse param route failed, route: {:?}, reason: wildcard param must be last", route)));
+            }
+
+            (&segment[1..], WILDCARD_PARAM_REGEX.to_string(), NAMED_WILDCARD_PRIORITY)
+        } else {
+            //确定的路由级
+            patterns.push(regex::escape(segment));
+            priority.push(FIXED_SEGMENT_PRIORITY);
+            continue;
+        };
+
+        if !is_param_name(name) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("parse param route failed, route: {:?}, name: {:?}, reason: invalid param name", route, name)));
+        }
+
+        if names.iter().any(|n| n == name) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("parse param route failed, route: {:?}, name: {:?}, reason: duplicate param name", route, name)));
+        }
+
+        patterns.push(format!("(?P<{}>{})", name, pattern));
+        names.push(name.to_string());
+        priority.push(level);
+    }
+
+    Ok(("^".to_string() + &patterns.join("/") + "$", names, priority))
+}
+
+//对路由参数进行百分号解码，保留路径分隔符则不解码%2F，解码后不是有效的Utf8字符串，则返回原值
+fn percent_decode(value: &str, keep_slash: bool) -> String {
+    if !value.contains('%') {
+        return value.to_string();
+    }
+
+    let bin = value.as_bytes();
+    let mut vec = Vec::with_capacity(bin.len());
+    let mut index = 0;
+    while index < bin.len() {
+        if bin[index] == b'%' && index + 2 < bin.len() {
+            if let Ok(hex) = std::str::from_utf8(&bin[index + 1..index + 3]) {
+                if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                    if keep_slash && byte == b'/' {
+                        //保留编码后的路径分隔符
+                        vec.extend_from_slice(&bin[index..index + 3]);
+                    } else {
+                        vec.push(byte);
+                    }
+                    index += 3;
+                    continue;
+                }
+            }
+        }
+
+        vec.push(bin[index]);
+        index += 1;
+    }
+
+    String::from_utf8(vec).unwrap_or(value.to_string())
+}
+
 /*
 * Http路由器表
 */
@@ -315,8 +619,27 @@ impl<S: Socket, W: AsyncIOWait, Context: Send + Sync + 'static, Handler: Middlew
         false
     }
 
-    //匹配路由表
-    pub fn match_route(&mut self, method: &Method, path: &str) -> Option<Arc<Handler>> {
+    //获取匹配指定路径的所有方法，用于在方法不匹配时回应允许的方法
+    pub fn allow_methods(&self, path: &str) -> Vec<String> {
+        let mut methods: Vec<String> = self.map.iter().filter_map(|(method, router)| {
+            if router.is_match(path) {
+                Some(method.as_str().to_string())
+            } else {
+                None
+            }
+        }).collect();
+        methods.sort();
+
+        methods
+    }
+
+    //匹配路由表，匹配成功返回处理器和路由中命名参数捕获的参数
+    pub fn match_route(&mut self, method: &Method, path: &str) -> Option<(Arc<Handler>, Vec<(String, String)>)> {
+        self.match_route_pattern(method, path).map(|(handler, params, _)| (handler, params))
+    }
+
+    //匹配路由表，匹配成功返回处理器、路由中命名参数捕获的参数和匹配的原始路由
+    pub fn match_route_pattern(&mut self, method: &Method, path: &str) -> Option<(Arc<Handler>, Vec<(String, String)>, Atom)> {
         if let Some(router) = self.map.get_mut(method) {
             return router.match_route(path);
         }
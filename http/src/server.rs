@@ -8,7 +8,7 @@ use std::collections::HashMap;
 use std::result::Result as GenResult;
 use std::io::{ErrorKind, Result, Error};
 
-use https::{Version, Response, HeaderMap, header::HOST};
+use https::{Version, Response, HeaderMap, StatusCode, header::HOST};
 use httparse::{EMPTY_HEADER, Request};
 use futures::future::{FutureExt, BoxFuture};
 use bytes::{Buf, BufMut, BytesMut};
@@ -18,14 +18,67 @@ use tcp::{server::AsyncWaitsHandle,
           driver::{Socket, AsyncIOWait,
                    AsyncService, AsyncServiceFactory, SocketStatus,
                    SocketHandle, AsyncReadTask, AsyncWriteTask},
-          util::{IoBytes, SocketContext}};
+          util::{IoBytes, SocketContext, SocketEvent}};
 
 use crate::{acceptor::{MAX_CONNECT_HTTP_HEADER_LIMIT, HttpAcceptor},
             connect::HttpConnect,
             virtual_host::VirtualHostPool,
             service::ServiceFactory,
             request::HttpRequest,
-            packet::UpStreamHeader};
+            packet::{UpStreamHeader, reply_status, reply_page, reply_redirect}};
+
+/*
+* 默认的Http请求行最大长度
+*/
+const DEFAULT_MAX_REQUEST_LINE: usize = 8192;
+
+/*
+* 默认的Http请求头最大长度，包括请求行
+*/
+const DEFAULT_MAX_HEADER_BYTES: usize = 64 * 1024;
+
+/*
+* 默认的读取Http请求头的超时时长，单位毫秒
+*/
+const DEFAULT_HEADER_TIMEOUT: usize = 10000;
+
+/*
+* Http请求限制，请求行过长回应414，请求头过大或过多回应431，读取请求头超时回应408，请求体过大回应413，回应后都会关闭当前Http连接
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HttpLimits {
+    pub max_request_line:   usize,          //请求行最大长度
+    pub max_header_bytes:   usize,          //请求头最大长度，包括请求行
+    pub max_headers:        usize,          //请求头最大数量
+    pub header_timeout:     usize,          //从开始读取请求头到读取完成的超时时长，单位毫秒
+    pub max_body:           Option<usize>,  //请求体的总长度限制，为空表示不限制
+}
+
+impl Default for HttpLimits {
+    fn default() -> Self {
+        HttpLimits {
+            max_request_line: DEFAULT_MAX_REQUEST_LINE,
+            max_header_bytes: DEFAULT_MAX_HEADER_BYTES,
+            max_headers: MAX_CONNECT_HTTP_HEADER_LIMIT,
+            header_timeout: DEFAULT_HEADER_TIMEOUT,
+            max_body: None,
+        }
+    }
+}
+
+/*
+* 读取Http请求头超时的定时器事件
+*/
+pub(crate) struct HeaderTimeout;
+
+/*
+* 设置读取Http请求头的超时定时器，会替换当前Http连接的保持连接定时器
+*/
+pub(crate) fn set_header_timeout<S: Socket>(handle: &SocketHandle<S>, limits: &HttpLimits) {
+    let mut event = SocketEvent::empty();
+    event.set(HeaderTimeout);
+    handle.set_timeout(limits.header_timeout, event);
+}
 
 /*
 * Http连接监听器
@@ -34,6 +87,7 @@ pub struct HttpListener<S: Socket, W: AsyncIOWait, P: VirtualHostPool<S, W>> {
     acceptor:   HttpAcceptor<S, W>, //连接接受器
     hosts:      P,                  //虚拟主机池
     keep_alive: usize,              //Http保持连接时长
+    limits:     HttpLimits,         //Http请求限制
 }
 
 impl<S: Socket, W: AsyncIOWait, P: VirtualHostPool<S, W>> AsyncService<S, W> for HttpListener<S, W, P> {
@@ -45,6 +99,7 @@ impl<S: Socket, W: AsyncIOWait, P: VirtualHostPool<S, W>> AsyncService<S, W> for
         let acceptor = self.acceptor.clone();
         let factory = self.hosts.clone();
         let keep_alive = self.keep_alive;
+        let limits = self.limits;
 
         let future = async move {
             if let SocketStatus::Connected(Err(e)) = status {
@@ -53,13 +108,15 @@ impl<S: Socket, W: AsyncIOWait, P: VirtualHostPool<S, W>> AsyncService<S, W> for
                 return;
             }
 
-            HttpAcceptor::<S, W>::accept(handle, waits, acceptor, factory, keep_alive).await;
+            HttpAcceptor::<S, W>::accept(handle, waits, acceptor, factory, keep_alive, limits).await;
         };
         future.boxed()
     }
 
     fn handle_readed(&self, handle: SocketHandle<S>, waits: W, status: SocketStatus) -> Self::Future {
         //处理Http后续请求
+        let hosts = self.hosts.clone();
+        let limits = self.limits;
         let future = async move {
             if let SocketStatus::Readed(Err(e)) = status {
                 //Tcp读数据失败
@@ -82,6 +139,7 @@ impl<S: Socket, W: AsyncIOWait, P: VirtualHostPool<S, W>> AsyncService<S, W> for
             if let Some(connect) = context.as_mut() {
                 let mut http_request_result = None;
                 let buf = Box::into_raw(Box::new(Vec::<u8>::new())) as usize;
+                set_header_timeout(&handle, &limits); //开始读取请求头前，设置读取请求头的超时定时器
                 loop {
                     match AsyncReadTask::async_read(handle.clone(), waits.clone(), 0).await {
                         Err(e) => {
@@ -91,17 +149,40 @@ impl<S: Socket, W: AsyncIOWait, P: VirtualHostPool<S, W>> AsyncService<S, W> for
                         Ok(bin) => {
                             unsafe { (&mut *(buf as *mut Vec<u8>)).put(bin); }
                             let mut headers = HeaderMap::new();
-                            let mut header = [EMPTY_HEADER; MAX_CONNECT_HTTP_HEADER_LIMIT];
+                            let mut header = vec![EMPTY_HEADER; limits.max_headers];
                             let mut req = Request::new(&mut header);
                             if let Some(body_offset) = UpStreamHeader::read_header(handle.clone(),
                                                                                     waits.clone(),
                                                                                     unsafe { (&*(buf as *mut Vec<u8>)).as_slice() },
                                                                                     &mut req,
-                                                                                    &mut headers) {
+                                                                                    &mut headers,
+                                                                                    &limits) {
                                 //解析成功
                                 let buf = unsafe { *Box::from_raw(buf as *mut Vec<u8>) };
                                 if let Some(value) = headers.get(HOST) {
                                     if let Ok(host_name) = value.to_str() {
+                                        if let Some((status, location)) = hosts.redirect(host_name, handle.is_security(), req.path.unwrap_or("/")) {
+                                            //保持连接的后续请求也需要主机级重定向，则回应重定向，并关闭当前连接
+                                            reply_redirect(&handle, status, &location);
+                                            return;
+                                        }
+
+                                        if !connect.get_host().eq_ignore_ascii_case(host_name) {
+                                            //后续请求的主机与当前服务的主机不同，则替换为后续请求的主机的服务
+                                            match hosts.get(host_name) {
+                                                None => {
+                                                    //后续请求中的主机不存在，则回应主机不存在的错误页，并关闭当前连接
+                                                    let status = hosts.unknown_host_status();
+                                                    let (mime, body) = hosts.error_page(None, status, req.path.unwrap_or("/"));
+                                                    reply_page(&handle, status, Error::new(ErrorKind::Other, format!("http server read failed, host: {:?}, reason: host not exist", host_name)), Some(&mime), body);
+                                                    return;
+                                                },
+                                                Some(host) => {
+                                                    connect.set_service(host_name, host.new_service());
+                                                },
+                                            }
+                                        }
+
                                         if let &Some(method) = &req.method {
                                             if let &Some(path) = &req.path {
                                                 //构建本次Http连接请求
@@ -111,7 +192,21 @@ impl<S: Socket, W: AsyncIOWait, P: VirtualHostPool<S, W>> AsyncService<S, W> for
                                                     "https://".to_string() + host_name + path
                                                 };
 
-                                                if let Some(request) = HttpRequest::new(handle.clone(), waits.clone(), method, &url, Version::HTTP_11, headers, &buf[body_offset..]) {
+                                                //根据请求启始行设置Http版本，Http/1.0需要在回应后关闭连接
+                                                let version = if let Some(0) = req.version {
+                                                    Version::HTTP_10
+                                                } else {
+                                                    Version::HTTP_11
+                                                };
+
+                                                if let Some(mut request) = HttpRequest::new(handle.clone(), waits.clone(), method, &url, version, headers, &buf[body_offset..]) {
+                                                    if let Err(e) = request.set_body_limit(limits.max_body) {
+                                                        //请求的请求体超过限制，则回应虚拟主机的错误页，并关闭当前Tcp连接
+                                                        let (mime, body) = hosts.error_page(request.headers().get(HOST).and_then(|value| value.to_str().ok()), StatusCode::PAYLOAD_TOO_LARGE, request.url().path());
+                                                        reply_page(&handle, StatusCode::PAYLOAD_TOO_LARGE, e, Some(&mime), body);
+                                                        return;
+                                                    }
+
                                                     http_request_result = Some(request);
                                                     break;
                                                 } else {
@@ -122,15 +217,19 @@ impl<S: Socket, W: AsyncIOWait, P: VirtualHostPool<S, W>> AsyncService<S, W> for
                                             }
                                         }
                                     } else {
-                                        //请求的主机头无效，则立即关闭当前连接
-                                        handle.close(Err(Error::new(ErrorKind::Other, "http server read failed, reason: invalid host header")));
+                                        //请求的主机头无效，则回应错误，并关闭当前连接
+                                        reply_status(&handle, StatusCode::BAD_REQUEST, Error::new(ErrorKind::Other, "http server read failed, reason: invalid host header"));
                                         return;
                                     }
                                 } else {
-                                    //请求没有主机头，则立即关闭当前连接
-                                    handle.close(Err(Error::new(ErrorKind::Other, "http server read failed, reason: host header not exist")));
+                                    //请求没有主机头，则回应错误，并关闭当前连接
+                                    reply_status(&handle, StatusCode::BAD_REQUEST, Error::new(ErrorKind::Other, "http server read failed, reason: host header not exist"));
                                     return;
                                 }
+                            } else if handle.is_closed() {
+                                //解析失败，且已回应错误并关闭当前连接，则释放缓冲
+                                unsafe { drop(Box::from_raw(buf as *mut Vec<u8>)); }
+                                return;
                             }
                         }
                     }
@@ -181,6 +280,12 @@ impl<S: Socket, W: AsyncIOWait, P: VirtualHostPool<S, W>> AsyncService<S, W> for
     fn handle_timeouted(&self, handle: SocketHandle<S>, waits: W, status: SocketStatus) -> Self::Future {
         let future = async move {
             if let SocketStatus::Timeout(event) = status {
+                if event.is::<HeaderTimeout>() {
+                    //读取Http请求头超时，则回应超时，并关闭当前Http连接
+                    reply_status(&handle, StatusCode::REQUEST_TIMEOUT, Error::new(ErrorKind::TimedOut, "http server read header failed, reason: read header timeout"));
+                    return;
+                }
+
                 //Http连接超时，则立即关闭当前Http连接
                 handle.close(Ok(()));
                 warn!("!!!> Http Connect Timeout, keep_alive: {:?}, local: {:?}, remote: {:?}", event.get::<usize>(), handle.get_local(), handle.get_remote());
@@ -197,16 +302,33 @@ impl<S: Socket, W: AsyncIOWait, P: VirtualHostPool<S, W>> HttpListener<S, W, P>
             acceptor: HttpAcceptor::default(),
             hosts,
             keep_alive,
+            limits: HttpLimits::default(),
         }
     }
+
+    //获取Http请求限制
+    pub fn get_limits(&self) -> &HttpLimits {
+        &self.limits
+    }
+
+    //设置Http请求限制
+    pub fn set_limits(&mut self, limits: HttpLimits) {
+        self.limits = limits;
+    }
+
+    //设置Http请求体的总长度限制，超过限制的请求将回应413，为空表示不限制
+    pub fn set_body_limit(&mut self, limit: Option<usize>) {
+        self.limits.max_body = limit;
+    }
 }
 
 /*
 * Http连接监听器工厂
 */
 pub struct HttpListenerFactory<S: Socket, P: VirtualHostPool<S, AsyncWaitsHandle>> {
-    hosts:      P,      //虚拟主机池
-    keep_alive: usize,  //Http保持连接时长
+    hosts:      P,              //虚拟主机池
+    keep_alive: usize,          //Http保持连接时长
+    limits:     HttpLimits,     //Http请求限制
     marker:     PhantomData<S>,
 }
 
@@ -217,7 +339,9 @@ impl<S: Socket, P: VirtualHostPool<S, AsyncWaitsHandle>> AsyncServiceFactory for
     type Future = BoxFuture<'static, Self::Out>;
 
     fn new_service(&self) -> Box<dyn AsyncService<Self::Connect, Self::Waits, Out = Self::Out, Future = Self::Future>> {
-        Box::new(HttpListener::with_factory(self.hosts.clone(), self.keep_alive))
+        let mut listener = HttpListener::with_factory(self.hosts.clone(), self.keep_alive);
+        listener.set_limits(self.limits);
+        Box::new(listener)
     }
 }
 
@@ -227,7 +351,23 @@ impl<S: Socket, P: VirtualHostPool<S, AsyncWaitsHandle>> HttpListenerFactory<S,
         HttpListenerFactory {
             hosts,
             keep_alive,
+            limits: HttpLimits::default(),
             marker: PhantomData,
         }
     }
+
+    //获取Http请求限制
+    pub fn get_limits(&self) -> &HttpLimits {
+        &self.limits
+    }
+
+    //设置Http请求限制
+    pub fn set_limits(&mut self, limits: HttpLimits) {
+        self.limits = limits;
+    }
+
+    //设置Http请求体的总长度限制，超过限制的请求将回应413，为空表示不限制
+    pub fn set_body_limit(&mut self, limit: Option<usize>) {
+        self.limits.max_body = limit;
+    }
 }
\ No newline at end of file
@@ -0,0 +1,555 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::io::{Error, Result, ErrorKind};
+
+use ring::{hmac, aead, rand::{SecureRandom, SystemRandom}};
+use https::header::SET_COOKIE;
+use futures::future::{FutureExt, BoxFuture};
+use parking_lot::RwLock;
+use serde_json::{json, Map, Value};
+use log::warn;
+
+use hash::XHashMap;
+use tcp::driver::{Socket, AsyncIOWait};
+
+use crate::{gateway::GatewayContext,
+            middleware::{MiddlewareResult, Middleware},
+            cookie::{SameSite, SetCookie, load_request_cookies},
+            request::HttpRequest,
+            response::HttpResponse};
+
+/*
+* 会话数据在HttpPort请求参数中的参数名，值为会话数据的Json字符串
+*/
+pub const SESSION_PARAM: &str = "session";
+
+/*
+* 默认的会话Cookie名
+*/
+const DEFAULT_SESSION_COOKIE_NAME: &str = "sid";
+
+/*
+* 默认的会话有效时长，单位秒
+*/
+const DEFAULT_SESSION_MAX_AGE: u64 = 1800;
+
+/*
+* 内存会话存储的默认最大会话数量
+*/
+pub const DEFAULT_MAX_MEMORY_SESSIONS: usize = 100_000;
+
+/*
+* 内存会话存储移除已过期会话的间隔时长，单位秒
+*/
+const MEMORY_SESSION_COLLECT_INTERVAL: u64 = 60;
+
+/*
+* 会话id的随机字节数
+*/
+const SESSION_ID_BYTES: usize = 24;
+
+/*
+* 会话Cookie值的最大长度
+*/
+const MAX_SESSION_COOKIE_LEN: usize = 4096;
+
+/*
+* 加密会话Cookie的随机数长度
+*/
+const SESSION_NONCE_LEN: usize = 12;
+
+/*
+* 会话
+*/
+#[derive(Debug, Clone)]
+pub struct Session {
+    id:             String,             //会话id
+    previous_id:    Option<String>,     //重新生成会话id前的会话id
+    data:           Map<String, Value>, //会话数据
+    is_new:         bool,               //是否是新会话
+    is_changed:     bool,               //会话数据是否已修改
+    is_destroyed:   bool,               //会话是否已销毁
+}
+
+impl Session {
+    //构建新会话
+    pub fn new(id: String) -> Self {
+        Session {
+            id,
+            previous_id: None,
+            data: Map::new(),
+            is_new: true,
+            is_changed: false,
+            is_destroyed: false,
+        }
+    }
+
+    //构建已加载的会话
+    pub fn with(id: String, data: Map<String, Value>) -> Self {
+        Session {
+            id,
+            previous_id: None,
+            data,
+            is_new: false,
+            is_changed: false,
+            is_destroyed: false,
+        }
+    }
+
+    //获取会话id
+    pub fn id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    //获取重新生成会话id前的会话id
+    pub fn previous_id(&self) -> Option<&str> {
+        self.previous_id.as_ref().map(|id| id.as_str())
+    }
+
+    //是否是新会话
+    pub fn is_new(&self) -> bool {
+        self.is_new
+    }
+
+    //会话数据是否已修改
+    pub fn is_changed(&self) -> bool {
+        self.is_changed
+    }
+
+    //会话是否已销毁
+    pub fn is_destroyed(&self) -> bool {
+        self.is_destroyed
+    }
+
+    //获取会话数据的只读引用
+    pub fn as_data(&self) -> &Map<String, Value> {
+        &self.data
+    }
+
+    //获取指定键的会话数据
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.data.get(key)
+    }
+
+    //设置指定键的会话数据，返回上一个值
+    pub fn set(&mut self, key: &str, value: Value) -> Option<Value> {
+        self.is_changed = true;
+        self.data.insert(key.to_string(), value)
+    }
+
+    //移除指定键的会话数据，返回被移除的值
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        let value = self.data.remove(key);
+        if value.is_some() {
+            self.is_changed = true;
+        }
+        value
+    }
+
+    //清空会话数据
+    pub fn clear(&mut self) {
+        if !self.data.is_empty() {
+            self.is_changed = true;
+        }
+        self.data.clear();
+    }
+
+    //销毁会话，响应时会移除会话和会话Cookie
+    pub fn destroy(&mut self) {
+        self.data.clear();
+        self.is_destroyed = true;
+    }
+
+    //重新生成会话id并保留会话数据，一般用于登录后防止会话固定攻击
+    pub fn regenerate(&mut self) -> Result<()> {
+        let id = generate_session_id()?;
+        if self.previous_id.is_none() && !self.is_new {
+            self.previous_id = Some(self.id.clone());
+        }
+        self.id = id;
+        self.is_changed = true;
+        Ok(())
+    }
+}
+
+/*
+* 会话存储
+*/
+pub trait SessionStore: Send + Sync + 'static {
+    //根据会话Cookie值加载会话，会话不存在、无效或已过期则返回空
+    fn load(&self, value: &str) -> Option<Session>;
+
+    //保存会话，并返回会话Cookie值
+    fn save(&self, session: &Session, max_age: u64) -> Result<String>;
+
+    //移除指定id的会话
+    fn remove(&self, id: &str);
+}
+
+/*
+* 内存会话存储，会话Cookie值为会话id
+*/
+pub struct MemorySessionStore {
+    sessions:       RwLock<XHashMap<String, (u64, Map<String, Value>)>>, //会话表，值为过期时间和会话数据
+    max_sessions:   usize,      //最大会话数量
+    last_collect:   AtomicU64,  //最近一次移除已过期会话的时间
+}
+
+unsafe impl Send for MemorySessionStore {}
+unsafe impl Sync for MemorySessionStore {}
+
+impl SessionStore for MemorySessionStore {
+    fn load(&self, value: &str) -> Option<Session> {
+        match self.sessions.read().get(value) {
+            Some((expire, data)) if *expire > now_secs() => Some(Session::with(value.to_string(), data.clone())),
+            _ => None,
+        }
+    }
+
+    fn save(&self, session: &Session, max_age: u64) -> Result<String> {
+        let now = now_secs();
+        let mut sessions = self.sessions.write();
+        if !sessions.contains_key(session.id()) {
+            //新会话，则定期或在会话已满时移除已过期的会话
+            let last = self.last_collect.load(Ordering::Relaxed);
+            if now >= last + MEMORY_SESSION_COLLECT_INTERVAL || sessions.len() >= self.max_sessions {
+                self.last_collect.store(now, Ordering::Relaxed);
+                sessions.retain(|_, (expire, _)| *expire > now);
+            }
+
+            if sessions.len() >= self.max_sessions {
+                //移除已过期的会话后仍然已满，则拒绝保存新会话
+                return Err(Error::new(ErrorKind::Other, format!("save memory session failed, max: {}, reason: too many sessions", self.max_sessions)));
+            }
+        }
+
+        sessions.insert(session.id().to_string(), (now + max_age, session.as_data().clone()));
+        Ok(session.id().to_string())
+    }
+
+    fn remove(&self, id: &str) {
+        self.sessions.write().remove(id);
+    }
+}
+
+impl MemorySessionStore {
+    //构建内存会话存储
+    pub fn new() -> Self {
+        Self::with_max_sessions(DEFAULT_MAX_MEMORY_SESSIONS)
+    }
+
+    //构建指定最大会话数量的内存会话存储
+    pub fn with_max_sessions(max_sessions: usize) -> Self {
+        MemorySessionStore {
+            sessions: RwLock::new(XHashMap::default()),
+            max_sessions,
+            last_collect: AtomicU64::new(now_secs()),
+        }
+    }
+
+    //获取会话数量
+    pub fn len(&self) -> usize {
+        self.sessions.read().len()
+    }
+
+    //移除所有已过期的会话，返回移除的数量
+    pub fn collect(&self) -> usize {
+        let now = now_secs();
+        let mut sessions = self.sessions.write();
+        let len = sessions.len();
+        sessions.retain(|_, (expire, _)| *expire > now);
+        len - sessions.len()
+    }
+}
+
+/*
+* 会话Cookie的保护方式
+*/
+enum CookieCipher {
+    Signed(hmac::Key),          //使用Hmac-Sha256签名，会话数据对客户端可见
+    Encrypted(aead::LessSafeKey),//使用Aes-256-Gcm加密
+}
+
+/*
+* Cookie会话存储，会话数据保存在签名或加密的会话Cookie中，移除会话只能使会话Cookie失效
+*/
+pub struct CookieSessionStore {
+    cipher: CookieCipher,   //会话Cookie的保护方式
+    rng:    SystemRandom,   //随机数生成器
+}
+
+unsafe impl Send for CookieSessionStore {}
+unsafe impl Sync for CookieSessionStore {}
+
+impl SessionStore for CookieSessionStore {
+    fn load(&self, value: &str) -> Option<Session> {
+        let payload = match &self.cipher {
+            CookieCipher::Signed(key) => {
+                let index = value.rfind('.')?;
+                let sign = base64::decode_config(&value[index + 1..], base64::URL_SAFE_NO_PAD).ok()?;
+                hmac::verify(key, value[..index].as_bytes(), sign.as_slice()).ok()?;
+                base64::decode_config(&value[..index], base64::URL_SAFE_NO_PAD).ok()?
+            },
+            CookieCipher::Encrypted(key) => {
+                let mut bin = base64::decode_config(value, base64::URL_SAFE_NO_PAD).ok()?;
+                if bin.len() < SESSION_NONCE_LEN + key.algorithm().tag_len() {
+                    return None;
+                }
+
+                let mut nonce = [0u8; SESSION_NONCE_LEN];
+                nonce.copy_from_slice(&bin[..SESSION_NONCE_LEN]);
+                let len = key.open_in_place(aead::Nonce::assume_unique_for_key(nonce), aead::Aad::empty(), &mut bin[SESSION_NONCE_LEN..]).ok()?.len();
+                bin[SESSION_NONCE_LEN..SESSION_NONCE_LEN + len].to_vec()
+            },
+        };
+
+        let mut payload = match serde_json::from_slice::<Value>(payload.as_slice()) {
+            Ok(Value::Object(payload)) => payload,
+            _ => return None,
+        };
+        let expire = payload.get("exp").and_then(|exp| exp.as_u64())?;
+        if expire <= now_secs() {
+            //会话已过期
+            return None;
+        }
+
+        let id = payload.get("id").and_then(|id| id.as_str())?.to_string();
+        match payload.remove("data") {
+            Some(Value::Object(data)) => Some(Session::with(id, data)),
+            _ => None,
+        }
+    }
+
+    fn save(&self, session: &Session, max_age: u64) -> Result<String> {
+        let payload = json!({
+            "id": session.id(),
+            "exp": now_secs() + max_age,
+            "data": session.as_data(),
+        }).to_string();
+
+        let value = match &self.cipher {
+            CookieCipher::Signed(key) => {
+                let payload = base64::encode_config(payload.as_bytes(), base64::URL_SAFE_NO_PAD);
+                let sign = base64::encode_config(hmac::sign(key, payload.as_bytes()).as_ref(), base64::URL_SAFE_NO_PAD);
+                payload + "." + sign.as_str()
+            },
+            CookieCipher::Encrypted(key) => {
+                let mut nonce = [0u8; SESSION_NONCE_LEN];
+                if let Err(e) = self.rng.fill(&mut nonce) {
+                    return Err(Error::new(ErrorKind::Other, format!("save session failed, reason: {:?}", e)));
+                }
+
+                let mut bin = payload.into_bytes();
+                if let Err(e) = key.seal_in_place_append_tag(aead::Nonce::assume_unique_for_key(nonce), aead::Aad::empty(), &mut bin) {
+                    return Err(Error::new(ErrorKind::Other, format!("save session failed, reason: {:?}", e)));
+                }
+
+                let mut buf = Vec::with_capacity(SESSION_NONCE_LEN + bin.len());
+                buf.extend_from_slice(&nonce);
+                buf.extend_from_slice(bin.as_slice());
+                base64::encode_config(buf.as_slice(), base64::URL_SAFE_NO_PAD)
+            },
+        };
+
+        if value.len() > MAX_SESSION_COOKIE_LEN {
+            return Err(Error::new(ErrorKind::InvalidData, format!("save session failed, len: {}, limit: {}, reason: session too large", value.len(), MAX_SESSION_COOKIE_LEN)));
+        }
+
+        Ok(value)
+    }
+
+    fn remove(&self, _id: &str) {}
+}
+
+impl CookieSessionStore {
+    //构建签名的Cookie会话存储
+    pub fn signed(secret: &[u8]) -> Self {
+        CookieSessionStore {
+            cipher: CookieCipher::Signed(hmac::Key::new(hmac::HMAC_SHA256, secret)),
+            rng: SystemRandom::new(),
+        }
+    }
+
+    //构建加密的Cookie会话存储，密钥必须是32字节
+    pub fn encrypted(key: &[u8]) -> Result<Self> {
+        match aead::UnboundKey::new(&aead::AES_256_GCM, key) {
+            Err(e) => {
+                Err(Error::new(ErrorKind::InvalidInput, format!("build cookie session store failed, key len: {}, reason: {:?}", key.len(), e)))
+            },
+            Ok(key) => {
+                Ok(CookieSessionStore {
+                    cipher: CookieCipher::Encrypted(aead::LessSafeKey::new(key)),
+                    rng: SystemRandom::new(),
+                })
+            },
+        }
+    }
+}
+
+/*
+* 会话中间件，请求时加载会话到网关上下文，响应时保存会话并设置会话Cookie，退出请求处理时不会保存会话
+*/
+pub struct SessionManager {
+    store:          Arc<dyn SessionStore>,  //会话存储
+    cookie_name:    String,                 //会话Cookie名
+    path:           String,                 //会话Cookie路径
+    domain:         Option<String>,         //会话Cookie域
+    max_age:        u64,                    //会话有效时长，单位秒，每次响应时会延长
+    http_only:      bool,                   //会话Cookie是否禁止脚本访问
+    secure:         bool,                   //会话Cookie是否只允许安全连接携带
+    same_site:      Option<SameSite>,       //会话Cookie的跨站请求策略
+}
+
+unsafe impl Send for SessionManager {}
+unsafe impl Sync for SessionManager {}
+
+impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for SessionManager {
+    fn request<'a>(&'a self, context: &'a mut GatewayContext, req: HttpRequest<S, W>)
+                   -> BoxFuture<'a, MiddlewareResult<S, W>> {
+        let future = async move {
+            load_request_cookies(context, &req);
+            let session = match context.get_cookie(self.cookie_name.as_str()).and_then(|value| self.store.load(value)) {
+                Some(session) => session,
+                None => {
+                    //会话不存在，则创建新会话
+                    match generate_session_id() {
+                        Err(e) => return MiddlewareResult::Throw(e),
+                        Ok(id) => Session::new(id),
+                    }
+                },
+            };
+
+            context.set_session(Some(session));
+            MiddlewareResult::ContinueRequest(req)
+        };
+        future.boxed()
+    }
+
+    fn response<'a>(&'a self, context: &'a mut GatewayContext, req: HttpRequest<S, W>, resp: HttpResponse<S, W>)
+                    -> BoxFuture<'a, MiddlewareResult<S, W>> {
+        let mut response = resp;
+        let future = async move {
+            let session = match context.take_session() {
+                None => return MiddlewareResult::ContinueResponse((req, response)),
+                Some(session) => session,
+            };
+
+            if let Some(id) = session.previous_id() {
+                //会话id已重新生成，则移除旧会话
+                self.store.remove(id);
+            }
+
+            if session.is_destroyed() {
+                //会话已销毁，则移除会话和会话Cookie
+                self.store.remove(session.id());
+                if context.get_cookie(self.cookie_name.as_str()).is_some() {
+                    if let Ok(mut cookie) = SetCookie::removal(self.cookie_name.as_str()) {
+                        self.set_cookie_attrs(&mut cookie);
+                        response.header(SET_COOKIE.as_str(), cookie.to_string().as_str());
+                    }
+                }
+            } else if !session.is_new() || !session.as_data().is_empty() {
+                //保存非空的会话，并延长会话Cookie的有效时长
+                match self.store.save(&session, self.max_age)
+                    .and_then(|value| SetCookie::new(self.cookie_name.as_str(), value.as_str())) {
+                    Err(e) => {
+                        warn!("!!!> Save Session Failed, id: {:?}, reason: {:?}", session.id(), e);
+                    },
+                    Ok(mut cookie) => {
+                        self.set_cookie_attrs(&mut cookie);
+                        cookie.max_age(self.max_age);
+                        response.header(SET_COOKIE.as_str(), cookie.to_string().as_str());
+                    },
+                }
+            }
+
+            MiddlewareResult::ContinueResponse((req, response))
+        };
+        future.boxed()
+    }
+}
+
+impl SessionManager {
+    //构建会话中间件
+    pub fn new(store: Arc<dyn SessionStore>) -> Self {
+        SessionManager {
+            store,
+            cookie_name: DEFAULT_SESSION_COOKIE_NAME.to_string(),
+            path: "/".to_string(),
+            domain: None,
+            max_age: DEFAULT_SESSION_MAX_AGE,
+            http_only: true,
+            secure: false,
+            same_site: Some(SameSite::Lax),
+        }
+    }
+
+    //获取会话存储
+    pub fn get_store(&self) -> &Arc<dyn SessionStore> {
+        &self.store
+    }
+
+    //设置会话Cookie名
+    pub fn set_cookie_name(&mut self, name: &str) {
+        self.cookie_name = name.to_string();
+    }
+
+    //设置会话Cookie路径
+    pub fn set_path(&mut self, path: &str) {
+        self.path = path.to_string();
+    }
+
+    //设置会话Cookie域
+    pub fn set_domain(&mut self, domain: Option<String>) {
+        self.domain = domain;
+    }
+
+    //设置会话有效时长，单位秒
+    pub fn set_max_age(&mut self, max_age: u64) {
+        self.max_age = max_age;
+    }
+
+    //设置会话Cookie是否禁止脚本访问
+    pub fn set_http_only(&mut self, http_only: bool) {
+        self.http_only = http_only;
+    }
+
+    //设置会话Cookie是否只允许安全连接携带
+    pub fn set_secure(&mut self, secure: bool) {
+        self.secure = secure;
+    }
+
+    //设置会话Cookie的跨站请求策略
+    pub fn set_same_site(&mut self, same_site: Option<SameSite>) {
+        self.same_site = same_site;
+    }
+
+    //设置会话Cookie的属性
+    fn set_cookie_attrs(&self, cookie: &mut SetCookie) {
+        cookie.path(self.path.as_str()).http_only(self.http_only).secure(self.secure);
+        if let Some(domain) = &self.domain {
+            cookie.domain(domain.as_str());
+        }
+        if let Some(same_site) = self.same_site {
+            cookie.same_site(same_site);
+        }
+    }
+}
+
+/*
+* 生成随机的会话id
+*/
+pub fn generate_session_id() -> Result<String> {
+    let mut bin = [0u8; SESSION_ID_BYTES];
+    if let Err(e) = SystemRandom::new().fill(&mut bin) {
+        return Err(Error::new(ErrorKind::Other, format!("generate session id failed, reason: {:?}", e)));
+    }
+
+    Ok(base64::encode_config(&bin, base64::URL_SAFE_NO_PAD))
+}
+
+//获取当前系统时间，单位秒
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
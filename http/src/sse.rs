@@ -0,0 +1,198 @@
+use std::io::{Error, Result, ErrorKind};
+
+use https::{StatusCode,
+            header::{CONTENT_TYPE, CACHE_CONTROL, HeaderMap}};
+
+use tcp::driver::Socket;
+use r#async::rt::multi_thread::MultiTaskRuntime;
+
+use crate::response::ResponseHandler;
+
+/*
+* 服务器推送事件的Mime类型
+*/
+pub const EVENT_STREAM_MIME: &str = "text/event-stream";
+
+/*
+* 服务器推送事件重连时，客户端提交最后事件id的请求头
+*/
+pub const LAST_EVENT_ID_HEADER: &str = "last-event-id";
+
+/*
+* 服务器推送事件的响应头值
+*/
+const NO_CACHE: &str = "no-cache";
+const X_ACCEL_BUFFERING: &str = "x-accel-buffering";
+const NO_BUFFERING: &str = "no";
+
+/*
+* 服务器推送事件
+*/
+#[derive(Debug, Clone, Default)]
+pub struct SseEvent {
+    id:     Option<String>, //事件id
+    event:  Option<String>, //事件名
+    retry:  Option<u64>,    //客户端重连间隔，单位ms
+    data:   String,         //事件数据
+}
+
+impl SseEvent {
+    //构建指定数据的服务器推送事件
+    pub fn new(data: &str) -> Self {
+        SseEvent {
+            id: None,
+            event: None,
+            retry: None,
+            data: data.to_string(),
+        }
+    }
+
+    //设置事件id
+    pub fn id(&mut self, id: &str) -> &mut Self {
+        self.id = Some(id.to_string());
+        self
+    }
+
+    //设置事件名
+    pub fn event(&mut self, event: &str) -> &mut Self {
+        self.event = Some(event.to_string());
+        self
+    }
+
+    //设置客户端重连间隔，单位ms
+    pub fn retry(&mut self, retry: u64) -> &mut Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    //序列化为事件流格式，事件id和事件名中的换行会被移除，多行数据会按\r\n、\r或\n拆分为多个数据字段
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut buf = String::new();
+
+        if let Some(id) = &self.id {
+            buf.push_str("id: ");
+            buf.push_str(&strip_line_break(id));
+            buf.push('\n');
+        }
+
+        if let Some(event) = &self.event {
+            buf.push_str("event: ");
+            buf.push_str(&strip_line_break(event));
+            buf.push('\n');
+        }
+
+        if let Some(retry) = self.retry {
+            buf.push_str("retry: ");
+            buf.push_str(&retry.to_string());
+            buf.push('\n');
+        }
+
+        for line in self.data.split("\r\n").flat_map(|line| line.split(|c: char| c == '\r' || c == '\n')) {
+            buf.push_str("data: ");
+            buf.push_str(line);
+            buf.push('\n');
+        }
+        buf.push('\n');
+
+        buf.into_bytes()
+    }
+}
+
+/*
+* 服务器推送事件响应，基于Http流式响应，可以在Http端口的处理器中通过响应句柄构建
+*/
+pub struct SseResponse<S: Socket> {
+    handler:    ResponseHandler<S>, //Http响应句柄
+}
+
+unsafe impl<S: Socket> Send for SseResponse<S> {}
+unsafe impl<S: Socket> Sync for SseResponse<S> {}
+
+impl<S: Socket> Clone for SseResponse<S> {
+    fn clone(&self) -> Self {
+        SseResponse {
+            handler: self.handler.clone(),
+        }
+    }
+}
+
+impl<S: Socket> SseResponse<S> {
+    //使用指定的Http响应句柄构建服务器推送事件响应，会立即发送响应启始行和响应头
+    pub fn with_handler(handler: ResponseHandler<S>) -> Result<Self> {
+        handler.status(StatusCode::OK.as_u16());
+        handler.header(CONTENT_TYPE.as_str(), EVENT_STREAM_MIME);
+        handler.header(CACHE_CONTROL.as_str(), NO_CACHE);
+        handler.header(X_ACCEL_BUFFERING, NO_BUFFERING);
+        handler.stream()?;
+
+        Ok(SseResponse {
+            handler,
+        })
+    }
+
+    //获取客户端重连时提交的最后事件id
+    pub fn last_event_id(headers: &HeaderMap) -> Option<String> {
+        if let Some(value) = headers.get(LAST_EVENT_ID_HEADER) {
+            if let Ok(id) = value.to_str() {
+                return Some(id.to_string());
+            }
+        }
+
+        None
+    }
+
+    //线程安全的判断客户端是否已断开，已断开时生产者应停止推送
+    pub fn is_closed(&self) -> bool {
+        self.handler.is_closed()
+    }
+
+    //线程安全的推送事件，客户端已断开时返回错误
+    pub fn send(&self, event: &SseEvent) -> Result<()> {
+        self.write(event.to_vec())
+    }
+
+    //线程安全的推送注释，注释会被客户端忽略，一般用于保持连接
+    pub fn comment(&self, comment: &str) -> Result<()> {
+        self.write(format!(":{}\n\n", strip_line_break(comment)).into_bytes())
+    }
+
+    //在指定的异步运行时中按指定间隔推送空注释以保持连接，直到客户端断开或事件流结束，间隔单位ms，需要小于Http连接保持时长
+    pub fn keep_alive(&self, runtime: MultiTaskRuntime<()>, interval: usize) -> Result<()> {
+        let sse = self.clone();
+        let rt = runtime.clone();
+        let future = async move {
+            loop {
+                rt.wait_timeout(interval).await;
+                if sse.comment("").is_err() {
+                    //客户端已断开或事件流已结束，则退出
+                    break;
+                }
+            }
+        };
+
+        if let Err(e) = runtime.spawn(runtime.alloc(), future) {
+            return Err(Error::new(ErrorKind::Other, format!("sse keep alive failed, reason: {:?}", e)));
+        }
+
+        Ok(())
+    }
+
+    //结束事件流
+    pub fn finish(&self) -> Result<()> {
+        self.handler.finish()
+    }
+
+    //写入事件流
+    fn write(&self, bin: Vec<u8>) -> Result<()> {
+        if self.is_closed() {
+            return Err(Error::new(ErrorKind::BrokenPipe, "sse send failed, reason: client disconnected"));
+        }
+
+        self.handler.write(bin)
+    }
+}
+
+//移除字符串中的换行
+fn strip_line_break(str: &str) -> String {
+    str.replace(|c: char| c == '\r' || c == '\n', "")
+}
@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::fs::metadata;
 use std::collections::BTreeMap;
 use std::thread::{Builder, sleep};
 use std::time::{Duration, SystemTime};
@@ -6,9 +7,10 @@ use std::io::{Error, Result, ErrorKind};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use mime::Mime;
-use https::header::{IF_UNMODIFIED_SINCE, IF_MODIFIED_SINCE, LAST_MODIFIED, IF_NONE_MATCH, IF_MATCH, ETAG, CACHE_CONTROL};
+use flate2::Crc;
+use https::{header::{IF_UNMODIFIED_SINCE, IF_MODIFIED_SINCE, LAST_MODIFIED, IF_NONE_MATCH, IF_MATCH, ETAG, CACHE_CONTROL, HeaderName}, HeaderMap, Method};
 use httpdate::{parse_http_date, fmt_http_date};
-use parking_lot::RwLock;
+use parking_lot::{RwLock, Mutex};
 use crossbeam_channel::{Sender, Receiver, unbounded};
 use log::warn;
 
@@ -27,105 +29,127 @@ const REQUEST_NO_STORE_CONTROL_CMD: &str = "no-store";  //不缓存资源
 const REQUEST_MAX_AGE_CONTROL_CMD: &str = "max-age";    //获取过期时间不大于指定秒数的资源
 
 /*
-* 根据Http请求头的验证缓存是否未修改
+* Http条件请求的验证结果
 */
-pub fn is_unmodified<S: Socket, W: AsyncIOWait>(cache: &StaticCache,
-                                                req: &HttpRequest<S, W>,
-                                                owner: Option<Atom>,
-                                                key: Atom) -> Result<Option<bool>> {
-    let mut reply = Ok(None);
-    if let Some(value) = req.headers().get(IF_UNMODIFIED_SINCE.as_str()) {
-        match value.to_str() {
-            Err(e) => {
-                return Err(Error::new(ErrorKind::Other, format!("valid http cache unmodified failed, reason: {:?}", e)));
-            },
-            Ok(date) => {
-                match parse_http_date(date) {
-                    Err(e) => {
-                        return Err(Error::new(ErrorKind::Other, format!("valid http cache unmodified failed, reason: {:?}", e)));
-                    },
-                    Ok(client_last_modified) => {
-                        reply = Ok(Some(cache.is_unmodified(owner.clone(), key.clone(), &client_last_modified)));
-                    },
-                }
-            },
-        }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precondition {
+    Pass,           //验证通过，继续处理请求
+    NotModified,    //资源未修改，应返回304
+    Failed,         //前置条件失败，应返回412
+}
+
+/*
+* 生成指定签名的实体标签，压缩后的资源使用弱实体标签
+*/
+pub fn format_etag(sign: usize, is_weak: bool) -> String {
+    if is_weak {
+        format!("W/\"{:x}\"", sign)
+    } else {
+        format!("\"{:x}\"", sign)
     }
+}
 
-    if let Some(value) = req.headers().get(IF_MATCH.as_str()) {
-        match value.to_str() {
-            Err(e) => {
-                return Err(Error::new(ErrorKind::Other, format!("valid http cache match failed, reason: {:?}", e)));
-            },
-            Ok(client_sign_str) => {
-                if let CacheRes::Cache((_, _, sign, _)) = cache.get(owner, key) {
-                    match client_sign_str.parse::<usize>() {
-                        Ok(client_sign) if client_sign == sign => {
-                            //指定缓存的资源未修改
-                            reply = Ok(Some(true));
-                        },
-                        _ => {
-                            //指定缓存的资源已修改
-                            reply = Ok(Some(false));
-                        },
-                    }
-                }
-            },
-        }
+/*
+* 判断实体标签列表中是否有与指定实体标签匹配的标签，列表为*时匹配任意标签，强比较时弱实体标签不匹配
+*/
+pub fn etag_list_matches(list: &str, etag: &str, is_strong: bool) -> bool {
+    if list.trim() == "*" {
+        return true;
+    }
+
+    let (is_weak, opaque) = split_etag(etag);
+    if is_strong && is_weak {
+        return false;
     }
 
-    reply
+    list.split(',').any(|tag| {
+        let (is_weak, tag_opaque) = split_etag(tag.trim());
+        !(is_strong && is_weak) && tag_opaque == opaque
+    })
+}
+
+//分离实体标签的弱标记
+fn split_etag(etag: &str) -> (bool, &str) {
+    if etag.starts_with("W/") {
+        (true, &etag[2..])
+    } else {
+        (false, etag)
+    }
 }
 
 /*
-* 根据Http请求头的验证缓存是否已修改
+* 根据RFC 7232的优先级验证条件请求，依次为If-Match、If-Unmodified-Since、If-None-Match和If-Modified-Since，时间精确到秒
 */
-pub fn is_modified<S: Socket, W: AsyncIOWait>(cache: &StaticCache,
-                                              req: &HttpRequest<S, W>,
-                                              owner: Option<Atom>,
-                                              key: Atom) -> Result<bool> {
-    let mut reply = Ok(true);
-    if let Some(value) = req.headers().get(IF_MODIFIED_SINCE.as_str()) {
-        match value.to_str() {
-            Err(e) => {
-                return Err(Error::new(ErrorKind::Other, format!("valid http cache modified failed, reason: {:?}", e)));
-            },
-            Ok(date) => {
-                match parse_http_date(date) {
-                    Err(e) => {
-                        return Err(Error::new(ErrorKind::Other, format!("valid http cache modified failed, reason: {:?}", e)));
-                    },
-                    Ok(client_last_modified) => {
-                        reply = Ok(!cache.is_unmodified(owner.clone(), key.clone(), &client_last_modified));
-                    },
-                }
-            },
+pub fn check_preconditions(headers: &HeaderMap,
+                           method: &Method,
+                           etag: Option<&str>,
+                           last_modified: Option<SystemTime>) -> Precondition {
+    let header_str = |key: HeaderName| headers.get(key).and_then(|value| value.to_str().ok());
+    let last_modified = last_modified.and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok()).map(|time| time.as_secs());
+    let to_secs = |date: &str| parse_http_date(date).ok().and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok()).map(|time| time.as_secs());
+    let is_get_or_head = method == &Method::GET || method == &Method::HEAD;
+
+    if let Some(value) = header_str(IF_MATCH) {
+        //有If-Match，则忽略If-Unmodified-Since
+        let is_match = value.trim() == "*" || etag.map(|etag| etag_list_matches(value, etag, true)).unwrap_or(false);
+        if !is_match {
+            return Precondition::Failed;
+        }
+    } else if let Some(date) = header_str(IF_UNMODIFIED_SINCE).and_then(to_secs) {
+        if let Some(last_modified) = last_modified {
+            if last_modified > date {
+                return Precondition::Failed;
+            }
         }
     }
 
-    if let Some(value) = req.headers().get(IF_NONE_MATCH.as_str()) {
-        match value.to_str() {
-            Err(e) => {
-                return Err(Error::new(ErrorKind::Other, format!("valid http cache match failed, reason: {:?}", e)));
-            },
-            Ok(client_sign_str) => {
-                if let CacheRes::Cache((_, _, sign, _)) = cache.get(owner, key) {
-                    match client_sign_str.parse::<usize>() {
-                        Ok(client_sign) if client_sign == sign => {
-                            //指定缓存的资源未修改
-                            reply = Ok(false);
-                        },
-                        _ => {
-                            //指定缓存的资源已修改
-                            reply = Ok(true);
-                        },
-                    }
+    if let Some(value) = header_str(IF_NONE_MATCH) {
+        //有If-None-Match，则忽略If-Modified-Since
+        let is_match = value.trim() == "*" || etag.map(|etag| etag_list_matches(value, etag, false)).unwrap_or(false);
+        if is_match {
+            return if is_get_or_head {
+                Precondition::NotModified
+            } else {
+                Precondition::Failed
+            };
+        }
+    } else if is_get_or_head {
+        if let Some(date) = header_str(IF_MODIFIED_SINCE).and_then(to_secs) {
+            if let Some(last_modified) = last_modified {
+                if last_modified <= date {
+                    return Precondition::NotModified;
                 }
-            },
+            }
         }
     }
 
-    reply
+    Precondition::Pass
+}
+
+/*
+* 根据指定用户和名称的缓存验证条件请求，缓存不存在或已过期则验证通过，同时返回缓存的实体标签
+*/
+pub fn check_cache_preconditions<S: Socket, W: AsyncIOWait>(cache: &StaticCache,
+                                                            req: &HttpRequest<S, W>,
+                                                            owner: Option<Atom>,
+                                                            key: Atom,
+                                                            is_weak: bool) -> (Precondition, Option<String>) {
+    if let CacheRes::Cache((last_modified, _, sign, _)) = cache.peek(owner, key) {
+        let etag = format_etag(sign, is_weak);
+        return (check_preconditions(req.headers(), req.method(), Some(etag.as_str()), Some(last_modified)), Some(etag));
+    }
+
+    (Precondition::Pass, None)
+}
+
+/*
+* 计算资源内容的签名，由内容的Crc32和Adler32组合而成
+*/
+pub fn content_sign(bin: &[u8]) -> usize {
+    let mut crc = Crc::new();
+    crc.update(bin);
+    let adler = encode_adler32(bin).unwrap_or(0);
+    (((crc.sum() as u64) << 32) | adler as u64) as usize
 }
 
 /*
@@ -178,7 +202,7 @@ pub fn set_cache_resp_headers<S: Socket, W: AsyncIOWait>(resp: &mut HttpResponse
                                                          is_only_if_cached: bool,
                                                          max_age: u64,
                                                          last_modified: Option<SystemTime>,
-                                                         etag: usize) {
+                                                         etag: &str) {
     let mut cache_control_value = "".to_string();
 
     //设置是否是私有资源
@@ -220,7 +244,7 @@ pub fn set_cache_resp_headers<S: Socket, W: AsyncIOWait>(resp: &mut HttpResponse
     }
 
     //设置Etag头，用于客户端或代理向服务器进行缓存验证
-    resp.header(ETAG.as_str(), etag.to_string().as_str());
+    resp.header(ETAG.as_str(), etag);
 }
 
 /*
@@ -233,11 +257,11 @@ enum CacheKey {
 }
 
 impl CacheKey {
-    //判断是否是私有缓存主键
-    pub fn is_private(&self) -> bool {
+    //获取缓存名称
+    pub fn name_str(&self) -> &str {
         match self {
-            CacheKey::Private(_) =>  true,
-            _ => false,
+            CacheKey::Private((_, key)) => key.as_ref(),
+            CacheKey::Public(key) => key.as_ref(),
         }
     }
 }
@@ -251,7 +275,6 @@ enum CollectCmd {
     Pause(String),                  //暂停整理
     Continue(String),               //继续整理
     Index((Duration, CacheKey)),    //更新缓存超时索引
-    Clear(usize),                   //清理不小于指定大小的缓存，如果为0表示清理缓存最久的资源
 }
 
 /*
@@ -265,18 +288,62 @@ pub enum CacheRes {
 }
 
 /*
-* Http静态资源缓存
+* Http缓存统计
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits:       usize,  //命中次数
+    pub misses:     usize,  //未命中次数，包括缓存过期
+    pub evictions:  usize,  //因缓存已满或文件改变而淘汰的缓存数量
+    pub size:       usize,  //缓存资源当前大小，单位字节
+    pub len:        usize,  //缓存资源当前数量
+}
+
+/*
+* Http缓存项
+*/
+struct CacheEntry {
+    timeout:        Duration,       //超时时间
+    last_modified:  SystemTime,     //最近修改时间
+    mime:           Mime,           //资源类型
+    sign:           usize,          //签名
+    value:          Arc<Vec<u8>>,   //资源数据
+    hits:           AtomicUsize,    //命中次数
+    tick:           AtomicUsize,    //最近访问序号，用于最近最少使用淘汰
+    is_watched:     bool,           //是否监视缓存对应的文件
+}
+
+impl CacheEntry {
+    //获取缓存资源，过期则返回过期
+    fn to_res(&self, now: &Duration) -> CacheRes {
+        if now >= &self.timeout {
+            //指定的缓存已过期
+            return CacheRes::Expired;
+        }
+
+        CacheRes::Cache((self.last_modified.clone(), self.mime.clone(), self.sign, self.value.clone()))
+    }
+}
+
+/*
+* Http静态资源缓存，缓存已满时按最近最少使用的顺序淘汰缓存
 */
 pub struct StaticCache {
-    max_size:       usize,                                                                          //缓存资源最大大小，单位字节
-    max_len:        usize,                                                                          //缓存资源最大数量
-    cache:          RwLock<XHashMap<CacheKey, (Duration, SystemTime, Mime, usize, Arc<Vec<u8>>)>>,  //公共缓存，值包括超时时间、最近修改时间、资源类型、签名和资源数据
-    timeout_index:  RwLock<BTreeMap<Duration, CacheKey>>,                                           //缓存超时索引
-    size:           AtomicUsize,                                                                    //缓存资源当前大小，单位字节
-    len:            AtomicUsize,                                                                    //缓存资源当前数量
-    is_running:     AtomicBool,                                                                     //缓存整理是否运行中
-    collect_sent:   Sender<CollectCmd>,                                                             //缓存整理控制指令发送者
-    collect_recv:   Receiver<CollectCmd>,                                                           //缓存整理控制指令接收者
+    max_size:       usize,                                  //缓存资源最大大小，单位字节
+    max_len:        usize,                                  //缓存资源最大数量
+    cache:          RwLock<XHashMap<CacheKey, CacheEntry>>, //缓存表
+    timeout_index:  RwLock<BTreeMap<Duration, CacheKey>>,   //缓存超时索引
+    lru_index:      Mutex<BTreeMap<usize, CacheKey>>,       //缓存最近访问索引，可能有已失效的访问序号，淘汰时忽略
+    tick:           AtomicUsize,                            //当前访问序号
+    size:           AtomicUsize,                            //缓存资源当前大小，单位字节
+    len:            AtomicUsize,                            //缓存资源当前数量
+    hits:           AtomicUsize,                            //命中次数
+    misses:         AtomicUsize,                            //未命中次数
+    evictions:      AtomicUsize,                            //淘汰次数
+    is_watch:       AtomicBool,                             //是否在整理时淘汰对应文件已改变的缓存
+    is_running:     AtomicBool,                             //缓存整理是否运行中
+    collect_sent:   Sender<CollectCmd>,                     //缓存整理控制指令发送者
+    collect_recv:   Receiver<CollectCmd>,                   //缓存整理控制指令接收者
 }
 
 unsafe impl Send for StaticCache {}
@@ -292,8 +359,14 @@ impl StaticCache {
             max_len,
             cache: RwLock::new(XHashMap::default()),
             timeout_index: RwLock::new(BTreeMap::new()),
+            lru_index: Mutex::new(BTreeMap::new()),
+            tick: AtomicUsize::new(0),
             size: AtomicUsize::new(0),
             len: AtomicUsize::new(0),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+            evictions: AtomicUsize::new(0),
+            is_watch: AtomicBool::new(false),
             is_running: AtomicBool::new(false),
             collect_sent,
             collect_recv,
@@ -347,10 +420,6 @@ impl StaticCache {
                                 //更新缓存的超时索引
                                 cache.timeout_index.write().insert(timeout, key);
                             },
-                            CollectCmd::Clear(clear_size) => {
-                                //强制清理缓存
-                                collect_clear(cache.as_ref(), clear_size);
-                            },
                         }
                     }
 
@@ -363,6 +432,10 @@ impl StaticCache {
                     if is_collect {
                         sleep(timeout); //休眠指定的毫秒数
                         collect_expired(cache.as_ref());
+                        if cache.is_watch() {
+                            //淘汰对应文件已改变的缓存
+                            cache.collect_changed();
+                        }
                     }
                 }
 
@@ -380,32 +453,44 @@ impl StaticCache {
         self.len.load(Ordering::SeqCst)
     }
 
+    //获取缓存统计
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            size: self.size(),
+            len: self.len(),
+        }
+    }
+
+    //判断是否监视缓存对应的文件
+    pub fn is_watch(&self) -> bool {
+        self.is_watch.load(Ordering::Relaxed)
+    }
+
+    //设置是否监视缓存对应的文件，监视时只监视设置后插入的、名称为文件路径的缓存，并在整理时淘汰对应文件已改变或已移除的缓存
+    pub fn set_watch(&self, is_watch: bool) {
+        self.is_watch.store(is_watch, Ordering::Relaxed);
+    }
+
     //检查指定用户和名称的缓存是否存在
     pub fn contains(&self, owner: Option<Atom>, key: Atom) -> bool {
-        if let Some(owner) = owner {
-            //私有缓存
-            self.cache.read().contains_key(&CacheKey::Private((owner, key)))
-        } else {
-            //公共缓存
-            self.cache.read().contains_key(&CacheKey::Public(key))
-        }
+        self.cache.read().contains_key(&cache_key(owner, key))
+    }
+
+    //获取指定用户和名称的缓存的命中次数，不存在则返回空
+    pub fn hits(&self, owner: Option<Atom>, key: Atom) -> Option<usize> {
+        self.cache.read()
+            .get(&cache_key(owner, key))
+            .map(|entry| entry.hits.load(Ordering::Relaxed))
     }
 
     //检查指定用户和名称的缓存是否过期
     pub fn is_expired(&self, owner: Option<Atom>, key: Atom) -> bool {
-        if let Some(owner) = owner {
-            //私有缓存
-            if let Some((timeout, _, _, _, _)) = self.cache.read().get(&CacheKey::Private((owner, key))) {
-                if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-                    return &now >= timeout;
-                }
-            }
-        } else {
-            //公共缓存
-            if let Some((timeout, _, _, _, _)) = self.cache.read().get(&CacheKey::Public(key)) {
-                if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-                    return &now >= timeout;
-                }
+        if let Some(entry) = self.cache.read().get(&cache_key(owner, key)) {
+            if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+                return now >= entry.timeout;
             }
         }
 
@@ -415,200 +500,175 @@ impl StaticCache {
 
     //检查指定用户、名称和上次修改时间的缓存是否未修改
     pub fn is_unmodified(&self, owner: Option<Atom>, key: Atom, last: &SystemTime) -> bool {
-        if let Some(owner) = owner {
-            //私有缓存
-            if let Some((_, last_modified, _, _, _)) = self.cache.read().get(&CacheKey::Private((owner, key))) {
-                if let Ok(last) = last.duration_since(SystemTime::UNIX_EPOCH) {
-                    if let Ok(last_modified) = last_modified.duration_since(SystemTime::UNIX_EPOCH) {
-                        return last.as_secs() == last_modified.as_secs();
-                    }
-                }
-
-                false
-            } else {
-                //指定的私有缓存不存在，则返回已修改
-                false
-            }
-        } else {
-            //公共缓存
-            if let Some((_, last_modified, _, _, _)) = self.cache.read().get(&CacheKey::Public(key)) {
-                if let Ok(last) = last.duration_since(SystemTime::UNIX_EPOCH) {
-                    if let Ok(last_modified) = last_modified.duration_since(SystemTime::UNIX_EPOCH) {
-                        return last.as_secs() == last_modified.as_secs();
-                    }
+        if let Some(entry) = self.cache.read().get(&cache_key(owner, key)) {
+            if let Ok(last) = last.duration_since(SystemTime::UNIX_EPOCH) {
+                if let Ok(last_modified) = entry.last_modified.duration_since(SystemTime::UNIX_EPOCH) {
+                    return last.as_secs() == last_modified.as_secs();
                 }
-
-                false
-            } else {
-                //指定的公共缓存不存在，则返回已修改
-                false
             }
         }
+
+        //指定的缓存不存在，则返回已修改
+        false
     }
 
-    //获取指定用户和名称的缓存，过期则返回空
+    //获取指定用户和名称的缓存，过期则返回过期，会更新命中统计和最近访问序号
     pub fn get(&self, owner: Option<Atom>, key: Atom) -> CacheRes {
-        if let Some(owner) = owner {
-            //私有缓存
-            if let Some((timeout, last_modified, mime, sign, bin)) = self.cache.read().get(&CacheKey::Private((owner, key))) {
-                if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-                    if &now >= timeout {
-                        //指定的缓存已过期
-                        return CacheRes::Expired;
-                    }
-
-                    return CacheRes::Cache((last_modified.clone(), mime.clone(), sign.clone(), bin.clone()))
+        let cache_key = cache_key(owner, key);
+        if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            if let Some(entry) = self.cache.read().get(&cache_key) {
+                let res = entry.to_res(&now);
+                if let CacheRes::Cache(_) = res {
+                    //命中，则更新命中次数和最近访问序号
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    entry.hits.fetch_add(1, Ordering::Relaxed);
+                    self.touch(&cache_key, entry);
+                } else {
+                    self.misses.fetch_add(1, Ordering::Relaxed);
                 }
+                return res;
             }
-        } else {
-            //公共缓存
-            if let Some((timeout, last_modified, mime, sign, bin)) = self.cache.read().get(&CacheKey::Public(key)) {
-                if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-                    if &now >= timeout {
-                        //指定的缓存已过期
-                        return CacheRes::Expired;
-                    }
+        }
 
-                    return CacheRes::Cache((last_modified.clone(), mime.clone(), sign.clone(), bin.clone()))
-                }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        CacheRes::Empty
+    }
+
+    //获取指定用户和名称的缓存，不会更新命中统计和最近访问序号
+    fn peek(&self, owner: Option<Atom>, key: Atom) -> CacheRes {
+        if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            if let Some(entry) = self.cache.read().get(&cache_key(owner, key)) {
+                return entry.to_res(&now);
             }
         }
 
         CacheRes::Empty
     }
 
-    //插入指定用户、名称、类型和有效时长的缓存，同名缓存则覆盖，并返回指定名称的上个缓存资源，有效时长单位为秒。同时更新缓存的超时索引
+    //插入指定用户、名称、类型和有效时长的缓存，同名缓存则覆盖，并返回指定名称的上个缓存资源，有效时长单位为秒。同时更新缓存的超时索引，缓存已满时会淘汰最近最少使用的缓存
     pub fn insert(&self, owner: Option<Atom>, max_age: u64, last_modified: SystemTime, mime: Mime, full_sign: bool, key: Atom, value: Arc<Vec<u8>>) -> Result<(usize, CacheRes)> {
         let value_size = value.len();
-        if self.size.load(Ordering::SeqCst) + value_size > self.max_size {
-            //已超过指定的缓存大小限制，则强制缓存进行整理，并返回错误
-            self.collect_sent.send(CollectCmd::Clear(value_size));
+        if value_size > self.max_size || self.max_len == 0 {
+            //超过指定的缓存大小限制，则返回错误
             return Err(Error::new(ErrorKind::Other, format!("insert http static cache failed, owner: {:?}, max_age: {:?}, mime: {:?}, key: {:?}, len: {:?}, reason: cache size full", owner, max_age, mime, key, value.len())));
         }
 
-        if self.len.load(Ordering::SeqCst) + 1 > self.max_len {
-            //已超过指定的缓存数量限制，则强制缓存进行整理，返回错误
-            self.collect_sent.send(CollectCmd::Clear(0));
-            return Err(Error::new(ErrorKind::Other, format!("insert http static cache failed, owner: {:?}, max_age: {:?}, mime: {:?}, key: {:?}, len: {:?}, reason: cache length full", owner, max_age, mime, key, value.len())));
-        }
-
-        match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        let now = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
             Err(e) => {
-                Err(Error::new(ErrorKind::Other, format!("insert http static cache failed, owner: {:?}, max_age: {:?}, mime: {:?}, key: {:?}, len: {:?}, reason: {:?}", owner, max_age, mime, key, value.len(), e)))
+                return Err(Error::new(ErrorKind::Other, format!("insert http static cache failed, owner: {:?}, max_age: {:?}, mime: {:?}, key: {:?}, len: {:?}, reason: {:?}", owner, max_age, mime, key, value.len(), e)));
             },
-            Ok(now) => {
-                if let Some(timeout) = now.clone().checked_add(Duration::from_secs(max_age)) {
-                    let cache_key = if let Some(owner) = owner {
-                        CacheKey::Private((owner, key))
-                    } else {
-                        CacheKey::Public(key)
-                    };
-
-                    //计算缓存资源的签名
-                    let sign = if full_sign {
-                        //完整签名
-                        if let Ok(sign) = encode_adler32(value.as_slice()) {
-                            //完整签名成功
-                            sign as usize
-                        } else {
-                            //完整签名失败
-                            0
-                        }
-                    } else {
-                        //简单签名
-                        if let Ok(sign) = encode_adler32(fmt_http_date(last_modified).as_bytes()) {
-                            //简单签名成功
-                            sign as usize
-                        } else {
-                            //简单签名失败
-                            0
-                        }
-                    };
-
-                    self.collect_sent.send(CollectCmd::Index((timeout, cache_key.clone()))); //更新缓存的超时索引
-                    if cache_key.is_private() {
-                        //私有缓存
-                        if let Some((old_timeout, old_last_modifed, old_mime, old_sign, old_value)) = self.cache.write().insert(cache_key, (timeout, last_modified, mime, sign, value)) {
-                            //更新私有缓存成功，则更新缓存大小
-                            self.size.fetch_sub(old_value.len(), Ordering::SeqCst);
-                            self.size.fetch_add(value_size, Ordering::SeqCst);
-
-                            if now >= old_timeout {
-                                //上个缓存已过期
-                                return Ok((sign, CacheRes::Expired));
-                            }
-
-                            return Ok((sign, CacheRes::Cache((old_last_modifed, old_mime, old_sign, old_value))));
-                        }
-                    } else {
-                        //公共缓存
-                        if let Some((old_timeout, old_last_modifed, old_mime, old_sign, old_value)) = self.cache.write().insert(cache_key, (timeout, last_modified, mime, sign, value)) {
-                            //更新公共缓存成功，则更新缓存大小
-                            self.size.fetch_sub(old_value.len(), Ordering::SeqCst);
-                            self.size.fetch_add(value_size, Ordering::SeqCst);
-
-                            if now >= old_timeout {
-                                //上个缓存已过期
-                                return Ok((sign, CacheRes::Expired));
-                            }
-
-                            return Ok((sign, CacheRes::Cache((old_last_modifed, old_mime, old_sign, old_value))));
-                        }
-                    }
+            Ok(now) => now,
+        };
+        let timeout = match now.clone().checked_add(Duration::from_secs(max_age)) {
+            None => {
+                return Err(Error::new(ErrorKind::Other, format!("insert http static cache failed, owner: {:?}, max_age: {:?}, mime: {:?}, key: {:?}, len: {:?}, reason: invalid max age", owner, max_age, mime, key, value.len())));
+            },
+            Some(timeout) => timeout,
+        };
 
-                    //插入缓存成功，则增加缓存大小和数量
-                    self.size.fetch_add(value_size, Ordering::SeqCst);
-                    self.len.fetch_add(1, Ordering::SeqCst);
-                    return Ok((sign, CacheRes::Empty));
-                }
+        let path: &str = key.as_ref();
+        let is_watched = self.is_watch() && metadata(path).map(|meta| meta.is_file()).unwrap_or(false);
+        let cache_key = cache_key(owner, key);
 
-                Err(Error::new(ErrorKind::Other, format!("insert http static cache failed, owner: {:?}, max_age: {:?}, mime: {:?}, key: {:?}, len: {:?}, reason: invalid max age", owner, max_age, mime, value.len(), key)))
-            },
+        //缓存已满，则淘汰最近最少使用的缓存
+        let old_size = self.cache.read().get(&cache_key).map(|entry| entry.value.len());
+        self.evict(value_size.saturating_sub(old_size.unwrap_or(0)), if old_size.is_some() { 0 } else { 1 });
+
+        //计算缓存资源的签名
+        let sign = if full_sign {
+            //完整签名
+            content_sign(value.as_slice())
+        } else {
+            //简单签名
+            if let Ok(sign) = encode_adler32(fmt_http_date(last_modified).as_bytes()) {
+                //简单签名成功
+                sign as usize
+            } else {
+                //简单签名失败
+                0
+            }
+        };
+
+        self.collect_sent.send(CollectCmd::Index((timeout, cache_key.clone()))); //更新缓存的超时索引
+        let tick = self.tick.fetch_add(1, Ordering::SeqCst);
+        let entry = CacheEntry {
+            timeout,
+            last_modified,
+            mime,
+            sign,
+            value,
+            hits: AtomicUsize::new(0),
+            tick: AtomicUsize::new(tick),
+            is_watched,
+        };
+        let old = self.cache.write().insert(cache_key.clone(), entry);
+        {
+            let mut lru_index = self.lru_index.lock();
+            if let Some(old) = &old {
+                //覆盖缓存，则移除上个缓存的访问序号
+                lru_index.remove(&old.tick.load(Ordering::SeqCst));
+            }
+            lru_index.insert(tick, cache_key);
         }
+
+        if let Some(old) = old {
+            //更新缓存成功，则更新缓存大小
+            self.size.fetch_sub(old.value.len(), Ordering::SeqCst);
+            self.size.fetch_add(value_size, Ordering::SeqCst);
+            return Ok((sign, old.to_res(&now)));
+        }
+
+        //插入缓存成功，则增加缓存大小和数量
+        self.size.fetch_add(value_size, Ordering::SeqCst);
+        self.len.fetch_add(1, Ordering::SeqCst);
+        Ok((sign, CacheRes::Empty))
     }
 
     //移除指定用户和名称的缓存，但不移除缓存超时索引
     pub fn remove(&self, owner: Option<Atom>, key: Atom) -> CacheRes {
-        let cache_key = if let Some(owner) = owner {
-            //私有缓存
-            if let Some((timeout, last_modified, mime, sign, bin)) = self.cache.write().remove(&CacheKey::Private((owner, key))) {
-                //移除私有缓存成功
-                self.size.fetch_sub(bin.len(), Ordering::SeqCst);
-                self.len.fetch_sub(1, Ordering::SeqCst);
-
-                if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-                    if now >= timeout {
-                        //指定的缓存已过期
-                        return CacheRes::Expired;
-                    }
-
-                    return CacheRes::Cache((last_modified, mime, sign, bin));
-                }
-            }
-        } else {
-            //公共缓存
-            if let Some((timeout, last_modified, mime, sign, bin)) = self.cache.write().remove(&CacheKey::Public(key)) {
-                //移除公共缓存成功
-                self.size.fetch_sub(bin.len(), Ordering::SeqCst);
-                self.len.fetch_sub(1, Ordering::SeqCst);
-
-                if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-                    if now >= timeout {
-                        //指定的缓存已过期
-                        return CacheRes::Expired;
-                    }
-
-                    return CacheRes::Cache((last_modified, mime, sign, bin));
-                }
+        if let Some(entry) = self.remove_key(&cache_key(owner, key)) {
+            if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+                return entry.to_res(&now);
             }
-        };
+        }
 
         CacheRes::Empty
     }
 
+    //移除名称以指定前缀开始的所有缓存，包括私有缓存，返回移除的缓存数量
+    pub fn invalidate_prefix(&self, prefix: &str) -> usize {
+        let keys = self.cache.read()
+            .keys()
+            .filter(|key| key.name_str().starts_with(prefix))
+            .cloned()
+            .collect::<Vec<CacheKey>>();
+
+        keys.iter()
+            .filter(|key| self.remove_key(key).is_some())
+            .count()
+    }
+
     /// 移除所有缓存
     pub fn remove_all_cache(&self) {
         self.cache.write().clear();
+        self.lru_index.lock().clear();
+        self.size.store(0, Ordering::SeqCst);
+        self.len.store(0, Ordering::SeqCst);
+    }
+
+    //淘汰对应文件已改变或已移除的缓存，返回淘汰的缓存数量
+    pub fn collect_changed(&self) -> usize {
+        let keys = self.cache.read()
+            .iter()
+            .filter(|(key, entry)| entry.is_watched && is_file_changed(key.name_str(), &entry.last_modified))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<CacheKey>>();
+
+        let count = keys.iter()
+            .filter(|key| self.remove_key(key).is_some())
+            .count();
+        self.evictions.fetch_add(count, Ordering::Relaxed);
+        count
     }
 
     //暂停缓存的整理
@@ -638,6 +698,72 @@ impl StaticCache {
 
         Ok(())
     }
+
+    //更新指定缓存的最近访问序号
+    fn touch(&self, key: &CacheKey, entry: &CacheEntry) {
+        let tick = self.tick.fetch_add(1, Ordering::SeqCst);
+        let old_tick = entry.tick.swap(tick, Ordering::SeqCst);
+
+        let mut lru_index = self.lru_index.lock();
+        lru_index.remove(&old_tick);
+        lru_index.insert(tick, key.clone());
+    }
+
+    //移除指定主键的缓存，并更新缓存大小和数量
+    fn remove_key(&self, key: &CacheKey) -> Option<CacheEntry> {
+        let entry = self.cache.write().remove(key)?;
+        self.lru_index.lock().remove(&entry.tick.load(Ordering::SeqCst));
+        self.size.fetch_sub(entry.value.len(), Ordering::SeqCst);
+        self.len.fetch_sub(1, Ordering::SeqCst);
+        Some(entry)
+    }
+
+    //按最近最少使用的顺序淘汰缓存，直到可以再插入指定大小和数量的缓存
+    fn evict(&self, size: usize, len: usize) {
+        while self.size() + size > self.max_size || self.len() + len > self.max_len {
+            let first = {
+                let mut lru_index = self.lru_index.lock();
+                let tick = lru_index.keys().next().cloned();
+                tick.and_then(|tick| lru_index.remove(&tick).map(|key| (tick, key)))
+            };
+            let (tick, key) = match first {
+                None => return, //没有可以淘汰的缓存
+                Some(first) => first,
+            };
+
+            let is_current = self.cache.read()
+                .get(&key)
+                .map(|entry| entry.tick.load(Ordering::SeqCst) == tick)
+                .unwrap_or(false);
+            if is_current && self.remove_key(&key).is_some() {
+                //只淘汰访问序号未失效的缓存
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+//获取指定用户和名称的缓存主键
+fn cache_key(owner: Option<Atom>, key: Atom) -> CacheKey {
+    if let Some(owner) = owner {
+        //私有缓存
+        CacheKey::Private((owner, key))
+    } else {
+        //公共缓存
+        CacheKey::Public(key)
+    }
+}
+
+//判断指定文件是否已改变或已移除
+fn is_file_changed(path: &str, last_modified: &SystemTime) -> bool {
+    match metadata(path).and_then(|meta| meta.modified()) {
+        Err(_) => true,
+        Ok(modified) => {
+            let modified = modified.duration_since(SystemTime::UNIX_EPOCH).map(|time| time.as_secs()).ok();
+            let last_modified = last_modified.duration_since(SystemTime::UNIX_EPOCH).map(|time| time.as_secs()).ok();
+            modified != last_modified
+        },
+    }
 }
 
 //整理过期缓存资源
@@ -656,14 +782,7 @@ fn collect_expired(cache: &StaticCache) {
             if now >= timeout {
                 //当前的缓存资源已过期，则从缓存和缓存超时索引中移除，并继续整理
                 cache.timeout_index.write().remove(&timeout);
-                match key {
-                    CacheKey::Private((owner, key)) => {
-                        cache.remove(Some(owner), key);
-                    },
-                    CacheKey::Public(key) => {
-                        cache.remove(None, key);
-                    },
-                }
+                cache.remove_key(&key);
                 continue;
             }
 
@@ -672,60 +791,3 @@ fn collect_expired(cache: &StaticCache) {
         }
     }
 }
-
-//强制整理缓存
-fn collect_clear(cache: &StaticCache, mut size: usize) {
-    if size == 0 {
-        //移除缓存最久的资源
-        if let Some(e) = cache.timeout_index.write().last_entry() {
-            match e.remove() {
-                CacheKey::Private((owner, key)) => {
-                    cache.remove(Some(owner), key);
-                },
-                CacheKey::Public(key) => {
-                    cache.remove(None, key);
-                },
-            }
-        }
-
-        return;
-    }
-
-    //移除不小于指定大小的缓存，则以缓存时间从久到新的顺序，移除缓存的资源
-    let mut kvs = cache.timeout_index
-        .read()
-        .iter()
-        .map(|(timeout, key)| {
-            (timeout.clone(), key.clone())
-        })
-        .collect::<Vec<(Duration, CacheKey)>>();
-    kvs.reverse(); //反转顺序
-
-    for (timeout, _) in kvs {
-        if size == 0 {
-            //已移除不小于指定大小的缓存，则立即退出强制整理
-            return;
-        }
-
-        let res = if let Some(value) = cache.timeout_index.write().remove(&timeout) {
-            match value {
-                CacheKey::Private((owner, key)) => {
-                    cache.remove(Some(owner), key)
-                },
-                CacheKey::Public(key) => {
-                    cache.remove(None, key)
-                },
-            }
-        } else {
-            CacheRes::Empty
-        };
-
-        if let CacheRes::Cache((_, _, _, bin)) = res {
-            if let Some(n) = size.checked_sub(bin.len()) {
-                size = n;
-            } else {
-                size = 0;
-            }
-        }
-    }
-}
\ No newline at end of file
@@ -1,14 +1,27 @@
-use std::fs::create_dir_all;
+use std::fs::{create_dir_all, remove_file as remove_file_sync, rename, OpenOptions};
 use std::io::{Error, ErrorKind, Result};
-use std::path::PathBuf;
+use std::mem;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
+use adler32::RollingAdler32;
+use flate2::Crc;
+use futures::channel::{mpsc, oneshot};
 use futures::future::{BoxFuture, FutureExt};
-use https::StatusCode;
+use futures::{SinkExt, StreamExt};
+use httpdate::fmt_http_date;
+use https::{
+    header::{ALLOW, CACHE_CONTROL, CONTENT_TYPE, LOCATION},
+    Method, StatusCode,
+};
+use parking_lot::Mutex;
 use path_absolutize::Absolutize;
+use ring::rand::{SecureRandom, SystemRandom};
 
 use async_file::file::{remove_file, AsyncFile, AsyncFileOptions, WriteOptions};
 use handler::SGenType;
+use hash::XHashMap;
 use log::warn;
 use r#async::rt::multi_thread::MultiTaskRuntime;
 use tcp::driver::{AsyncIOWait, Socket};
@@ -31,6 +44,56 @@ const HTTPS_ASYNC_FILE_UPLOAD_PRIORITY: usize = 100;
 */
 const FILE_REMOVE_METHOD: &str = "_$remove";
 
+/*
+* 可恢复上传的协议版本和已支持的扩展
+*/
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,checksum,expiration,termination";
+
+/*
+* 可恢复上传的请求和响应头
+*/
+const TUS_RESUMABLE_HEADER: &str = "tus-resumable";
+const TUS_VERSION_HEADER: &str = "tus-version";
+const TUS_EXTENSION_HEADER: &str = "tus-extension";
+const TUS_MAX_SIZE_HEADER: &str = "tus-max-size";
+const TUS_CHECKSUM_ALGORITHM_HEADER: &str = "tus-checksum-algorithm";
+const UPLOAD_OFFSET_HEADER: &str = "upload-offset";
+const UPLOAD_LENGTH_HEADER: &str = "upload-length";
+const UPLOAD_METADATA_HEADER: &str = "upload-metadata";
+const UPLOAD_CHECKSUM_HEADER: &str = "upload-checksum";
+const UPLOAD_EXPIRES_HEADER: &str = "upload-expires";
+
+/*
+* 可恢复上传的分片请求体类型
+*/
+const OFFSET_OCTET_STREAM: &str = "application/offset+octet-stream";
+
+/*
+* 分片校验和不匹配的响应状态码
+*/
+const CHECKSUM_MISMATCH_STATUS: u16 = 460;
+
+/*
+* 未完成上传的分片文件所在的子目录
+*/
+const UPLOAD_PART_DIR: &str = ".uploads";
+
+/*
+* 默认的未完成上传的过期时长，单位秒
+*/
+const DEFAULT_UPLOAD_EXPIRE: u64 = 86400;
+
+/*
+* 默认的分片写入块大小
+*/
+const DEFAULT_UPLOAD_BLOCK_SIZE: usize = 64 * 1024;
+
+/*
+* 上传唯一id的随机字节数，128位
+*/
+const UPLOAD_ID_BYTES: usize = 16;
+
 /*
 * Http文件上传处理器
 */
@@ -54,6 +117,7 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for UploadFile
             let mut is_remove = false;
             let mut file = String::from("");
             let mut content = vec![];
+            let spooled = context.take_file("content"); //流式分析时，文件内容已写入临时文件
 
             let map = context.as_mut_parts();
             if let Some(SGenType::Str(method)) = map.get("method") {
@@ -75,6 +139,9 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for UploadFile
                 //不是文件移除，则为文件上传
                 if let Some(SGenType::Str(file_name)) = map.get("filename") {
                     file = file_name.to_string();
+                } else if let Some(file_name) = spooled.as_ref().and_then(|spooled| spooled.filename()) {
+                    //流式分析时，使用文件部分的客户端文件名
+                    file = file_name.to_string();
                 } else {
                     return MiddlewareResult::Throw(Error::new(
                         ErrorKind::NotFound,
@@ -84,6 +151,7 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for UploadFile
 
                 //获取文件内容
                 match map.remove("content") {
+                    _ if spooled.is_some() => (), //文件内容在临时文件中
                     Some(SGenType::Str(str)) => {
                         content = str.into_bytes();
                     }
@@ -144,7 +212,20 @@ impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for UploadFile
                             ));
                         }
                     }
-                    if let Err(e) =
+                    if let Some(spooled) = spooled {
+                        //将临时文件移动到上传路径
+                        if let Err(e) = spooled.persist(path.clone()).await {
+                            return MiddlewareResult::Throw(e);
+                        }
+                        if let Some(resp_handler) = resp.get_response_handler() {
+                            if let Err(e) = resp_handler.finish() {
+                                warn!(
+                                    "!!!> Http Body Mut Finish Failed, file: {:?}, reason: {:?}",
+                                    path, e
+                                );
+                            }
+                        }
+                    } else if let Err(e) =
                         async_save_file(self.files_async_runtime.clone(), &resp, path, content)
                     {
                         //存储文件失败
@@ -387,3 +468,873 @@ fn async_save_file<S: Socket, W: AsyncIOWait>(
         "upload file error, reason: invalid response body",
     ))
 }
+
+/*
+* 分片校验算法
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Adler32, //Adler32
+    Crc32,   //Crc32
+}
+
+impl ChecksumAlgorithm {
+    //根据名称获取校验算法，名称忽略大小写
+    pub fn from_name(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case("adler32") {
+            Some(ChecksumAlgorithm::Adler32)
+        } else if name.eq_ignore_ascii_case("crc32") {
+            Some(ChecksumAlgorithm::Crc32)
+        } else {
+            None
+        }
+    }
+
+    //获取校验算法的名称
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Adler32 => "adler32",
+            ChecksumAlgorithm::Crc32 => "crc32",
+        }
+    }
+}
+
+/*
+* 可增量计算的分片校验和
+*/
+pub enum UploadChecksum {
+    Adler32(RollingAdler32), //Adler32
+    Crc32(Crc),              //Crc32
+}
+
+impl UploadChecksum {
+    //构建指定算法的分片校验和
+    pub fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Adler32 => UploadChecksum::Adler32(RollingAdler32::new()),
+            ChecksumAlgorithm::Crc32 => UploadChecksum::Crc32(Crc::new()),
+        }
+    }
+
+    //追加需要校验的数据
+    pub fn update(&mut self, bin: &[u8]) {
+        match self {
+            UploadChecksum::Adler32(adler) => adler.update_buffer(bin),
+            UploadChecksum::Crc32(crc) => crc.update(bin),
+        }
+    }
+
+    //获取当前的校验和
+    pub fn sum(&self) -> u32 {
+        match self {
+            UploadChecksum::Adler32(adler) => adler.hash(),
+            UploadChecksum::Crc32(crc) => crc.sum(),
+        }
+    }
+}
+
+//解析Upload-Checksum头，格式为“算法名 Base64编码的大端校验和”，算法不支持或格式错误返回空
+pub fn parse_upload_checksum(value: &str) -> Option<(ChecksumAlgorithm, u32)> {
+    let mut iter = value.split_whitespace();
+    let algorithm = ChecksumAlgorithm::from_name(iter.next()?)?;
+    let bin = base64::decode(iter.next()?).ok()?;
+    if iter.next().is_some() || bin.len() != 4 {
+        return None;
+    }
+
+    Some((
+        algorithm,
+        u32::from_be_bytes([bin[0], bin[1], bin[2], bin[3]]),
+    ))
+}
+
+//解析Upload-Metadata头，格式为逗号分隔的“键 Base64编码的值”，值可以为空，格式错误返回空
+pub fn parse_upload_metadata(value: &str) -> Option<Vec<(String, String)>> {
+    let mut metadata = Vec::new();
+    for pair in value.split(',') {
+        let mut iter = pair.split_whitespace();
+        let key = match iter.next() {
+            None => continue, //忽略空的键值对
+            Some(key) => key.to_string(),
+        };
+        let value = match iter.next() {
+            None => String::new(),
+            Some(value) => String::from_utf8(base64::decode(value).ok()?).ok()?,
+        };
+        if iter.next().is_some() {
+            return None;
+        }
+
+        metadata.push((key, value));
+    }
+
+    Some(metadata)
+}
+
+/*
+* 已完成的可恢复上传
+*/
+#[derive(Debug, Clone)]
+pub struct UploadInfo {
+    pub id: String,                      //上传唯一id
+    pub path: PathBuf,                   //上传完成后的文件路径
+    pub length: u64,                     //文件长度
+    pub metadata: Vec<(String, String)>, //上传元信息
+}
+
+/*
+* 未完成的可恢复上传
+*/
+struct UploadEntry {
+    length: u64,                         //文件总长度
+    offset: u64,                         //已确认接收的长度
+    metadata: Vec<(String, String)>,     //上传元信息
+    raw_metadata: Option<String>,        //原始的上传元信息头
+    part: PathBuf,                       //分片文件路径
+    target: PathBuf,                     //上传完成后的文件路径
+    expires: SystemTime,                 //过期时间
+    locked: bool,                        //是否正在接收分片
+}
+
+/*
+* 上传的锁定守护者，销毁时解锁上传
+*/
+struct UploadLock<'a> {
+    uploads: &'a Mutex<XHashMap<String, UploadEntry>>, //未完成的上传表
+    id: &'a str,                                       //上传唯一id
+}
+
+impl<'a> Drop for UploadLock<'a> {
+    fn drop(&mut self) {
+        if let Some(entry) = self.uploads.lock().get_mut(self.id) {
+            entry.locked = false;
+        }
+    }
+}
+
+/*
+* 上传完成回调
+*/
+pub type UploadCompleteHook = Arc<dyn Fn(&UploadInfo) + Send + Sync + 'static>;
+
+/*
+* Http可恢复文件上传处理器，兼容tus 1.0.0协议的creation、checksum、expiration和termination扩展
+* POST创建上传，PATCH在指定偏移写入分片，HEAD查询进度，DELETE终止上传
+*/
+pub struct ResumableUpload {
+    files_async_runtime: MultiTaskRuntime<()>,
+    //异步文件运行时
+    root: PathBuf,
+    //文件上传根路径
+    base: String,
+    //上传请求的路径前缀
+    block_size: usize,
+    //分片写入块大小
+    max_size: Option<u64>,
+    //允许上传的最大文件长度
+    expire: Duration,
+    //未完成上传的过期时长
+    uploads: Mutex<XHashMap<String, UploadEntry>>,
+    //未完成的上传表
+    complete_hook: Option<UploadCompleteHook>, //上传完成回调
+}
+
+unsafe impl Send for ResumableUpload {}
+
+unsafe impl Sync for ResumableUpload {}
+
+impl<S: Socket, W: AsyncIOWait> Middleware<S, W, GatewayContext> for ResumableUpload {
+    fn request<'a>(
+        &'a self,
+        _context: &'a mut GatewayContext,
+        req: HttpRequest<S, W>,
+    ) -> BoxFuture<'a, MiddlewareResult<S, W>> {
+        let mut req = req;
+        let future = async move {
+            let path = req.url().path().to_string();
+            let id = if path.starts_with(self.base.as_str()) {
+                path[self.base.len()..].trim_matches('/').to_string()
+            } else if path == self.base.trim_end_matches('/') {
+                String::new()
+            } else {
+                //不是可恢复上传的请求，则继续请求处理
+                return MiddlewareResult::ContinueRequest(req);
+            };
+
+            if req.method() != &Method::OPTIONS {
+                if let Some(version) = req.headers().get(TUS_RESUMABLE_HEADER) {
+                    if version.as_bytes() != TUS_VERSION.as_bytes() {
+                        //不支持的协议版本
+                        let mut resp =
+                            upload_response(&req, StatusCode::PRECONDITION_FAILED.as_u16());
+                        resp.header(TUS_VERSION_HEADER, TUS_VERSION);
+                        return MiddlewareResult::Finish((req, resp));
+                    }
+                }
+            }
+
+            let resp = match (req.method().clone(), id.is_empty()) {
+                (Method::OPTIONS, _) => self.options(&req),
+                (Method::POST, true) => self.create(&req).await,
+                (Method::HEAD, false) => self.head(&req, &id),
+                (Method::PATCH, false) => self.patch(&mut req, &id).await,
+                (Method::DELETE, false) => self.terminate(&req, &id),
+                _ => {
+                    let mut resp = upload_response(&req, StatusCode::METHOD_NOT_ALLOWED.as_u16());
+                    resp.header(ALLOW.as_str(), "OPTIONS, POST, HEAD, PATCH, DELETE");
+                    resp
+                }
+            };
+
+            //完成请求处理
+            MiddlewareResult::Finish((req, resp))
+        };
+        future.boxed()
+    }
+
+    fn response<'a>(
+        &'a self,
+        _context: &'a mut GatewayContext,
+        req: HttpRequest<S, W>,
+        resp: HttpResponse<S, W>,
+    ) -> BoxFuture<'a, MiddlewareResult<S, W>> {
+        let future = async move {
+            //继续响应处理
+            MiddlewareResult::ContinueResponse((req, resp))
+        };
+        future.boxed()
+    }
+}
+
+impl ResumableUpload {
+    //构建指定根目录和请求路径前缀的可恢复文件上传处理器
+    pub fn new<P: Into<PathBuf>>(
+        files_async_runtime: MultiTaskRuntime<()>,
+        dir: P,
+        base: &str,
+    ) -> Self {
+        let root = match trim_path(dir) {
+            Err(e) => {
+                panic!("Create Http Resumable Upload Failed, reason: {:?}", e);
+            }
+            Ok(root) => root,
+        };
+
+        let part_dir = root.join(UPLOAD_PART_DIR);
+        if !part_dir.exists() {
+            //不存在，则创建根目录和分片目录
+            if create_dir_all(&part_dir).is_err() {
+                panic!(
+                    "New ResumableUpload Failed, make part dir failed, dir: {:?}",
+                    part_dir
+                );
+            }
+        }
+
+        let mut base = base.to_string();
+        if !base.starts_with('/') {
+            base.insert(0, '/');
+        }
+        if !base.ends_with('/') {
+            base.push('/');
+        }
+
+        ResumableUpload {
+            files_async_runtime,
+            root,
+            base,
+            block_size: DEFAULT_UPLOAD_BLOCK_SIZE,
+            max_size: None,
+            expire: Duration::from_secs(DEFAULT_UPLOAD_EXPIRE),
+            uploads: Mutex::new(XHashMap::default()),
+            complete_hook: None,
+        }
+    }
+
+    //设置分片写入块大小
+    pub fn set_block_size(&mut self, size: usize) {
+        if size > 0 {
+            self.block_size = size;
+        }
+    }
+
+    //设置允许上传的最大文件长度，为空表示不限制
+    pub fn set_max_size(&mut self, size: Option<u64>) {
+        self.max_size = size;
+    }
+
+    //设置未完成上传的过期时长，每次接收分片后会重新计算过期时间
+    pub fn set_expire(&mut self, expire: Duration) {
+        self.expire = expire;
+    }
+
+    //设置上传完成回调，回调在文件移动到目标路径后调用
+    pub fn set_complete_hook(&mut self, hook: UploadCompleteHook) {
+        self.complete_hook = Some(hook);
+    }
+
+    //获取指定未完成上传的已接收长度和文件总长度
+    pub fn get_offset(&self, id: &str) -> Option<(u64, u64)> {
+        self.uploads
+            .lock()
+            .get(id)
+            .map(|entry| (entry.offset, entry.length))
+    }
+
+    //回收所有已过期且未在接收分片的上传，并移除分片文件，返回回收的数量
+    pub fn collect(&self) -> usize {
+        let now = SystemTime::now();
+        let expired: Vec<UploadEntry> = {
+            let mut uploads = self.uploads.lock();
+            let ids: Vec<String> = uploads
+                .iter()
+                .filter(|(_, entry)| !entry.locked && entry.expires <= now)
+                .map(|(id, _)| id.clone())
+                .collect();
+            ids.iter().filter_map(|id| uploads.remove(id)).collect()
+        };
+
+        for entry in &expired {
+            async_remove_part(self.files_async_runtime.clone(), entry.part.clone());
+        }
+        expired.len()
+    }
+
+    //生成上传唯一id，使用128位的随机数，避免上传id被猜测
+    fn new_id(&self) -> Result<String> {
+        let mut bin = [0u8; UPLOAD_ID_BYTES];
+        if let Err(e) = SystemRandom::new().fill(&mut bin) {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!("generate upload id failed, reason: {:?}", e),
+            ));
+        }
+
+        Ok(bin.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    //获取上传完成后的文件路径，路径必须是根目录下的相对路径
+    fn target_path(&self, file: &str) -> Option<PathBuf> {
+        let file_path = PathBuf::from(file);
+        if !file_path.is_relative() || file_path.file_name().is_none() {
+            return None;
+        }
+
+        let path = self.root.join(file_path);
+        let root = self.root.absolutize().ok()?;
+        let dir = path.parent()?.absolutize().ok()?;
+        if !dir.starts_with(&root) || dir.starts_with(root.join(UPLOAD_PART_DIR)) {
+            //标准化后根路径被改变，或位于分片目录内
+            return None;
+        }
+
+        Some(path)
+    }
+
+    //获取协议支持的特性
+    fn options<S: Socket, W: AsyncIOWait>(&self, req: &HttpRequest<S, W>) -> HttpResponse<S, W> {
+        let mut resp = upload_response(req, StatusCode::NO_CONTENT.as_u16());
+        resp.header(TUS_VERSION_HEADER, TUS_VERSION)
+            .header(TUS_EXTENSION_HEADER, TUS_EXTENSIONS)
+            .header(TUS_CHECKSUM_ALGORITHM_HEADER, "adler32,crc32");
+        if let Some(max_size) = self.max_size {
+            resp.header(TUS_MAX_SIZE_HEADER, &max_size.to_string());
+        }
+        resp
+    }
+
+    //创建上传，并创建空的分片文件
+    async fn create<S: Socket, W: AsyncIOWait>(
+        &self,
+        req: &HttpRequest<S, W>,
+    ) -> HttpResponse<S, W> {
+        self.collect();
+
+        let length = match header_u64(req, UPLOAD_LENGTH_HEADER) {
+            None => return upload_response(req, StatusCode::BAD_REQUEST.as_u16()),
+            Some(length) => length,
+        };
+        if let Some(max_size) = self.max_size {
+            if length > max_size {
+                //超过允许上传的最大文件长度
+                return upload_response(req, StatusCode::PAYLOAD_TOO_LARGE.as_u16());
+            }
+        }
+
+        let raw_metadata = req
+            .headers()
+            .get(UPLOAD_METADATA_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string());
+        let metadata = match &raw_metadata {
+            None => Vec::new(),
+            Some(value) => match parse_upload_metadata(value) {
+                None => return upload_response(req, StatusCode::BAD_REQUEST.as_u16()),
+                Some(metadata) => metadata,
+            },
+        };
+
+        let id = match self.new_id() {
+            Err(e) => {
+                warn!("!!!> Http Create Upload Failed, reason: {:?}", e);
+                return upload_response(req, StatusCode::INTERNAL_SERVER_ERROR.as_u16());
+            }
+            Ok(id) => id,
+        };
+        let target = match metadata.iter().find(|(key, _)| key == "filename") {
+            None => self.root.join(&id),
+            Some((_, file)) => match self.target_path(file) {
+                None => return upload_response(req, StatusCode::BAD_REQUEST.as_u16()),
+                Some(target) => target,
+            },
+        };
+        let part = self.root.join(UPLOAD_PART_DIR).join(&id);
+
+        //只打开而不写入，以创建空的分片文件
+        match spawn_part_writer(self.files_async_runtime.clone(), part.clone(), 0) {
+            Err(e) => {
+                warn!("!!!> Http Create Upload Failed, reason: {:?}", e);
+                return upload_response(req, StatusCode::INTERNAL_SERVER_ERROR.as_u16());
+            }
+            Ok((sender, result)) => {
+                drop(sender);
+                if let Err(e) = wait_part_writer(result).await {
+                    warn!("!!!> Http Create Upload Failed, reason: {:?}", e);
+                    return upload_response(req, StatusCode::INTERNAL_SERVER_ERROR.as_u16());
+                }
+            }
+        }
+
+        let expires = SystemTime::now() + self.expire;
+        let entry = UploadEntry {
+            length,
+            offset: 0,
+            metadata,
+            raw_metadata,
+            part,
+            target,
+            expires,
+            locked: false,
+        };
+        let mut resp = upload_response(req, StatusCode::CREATED.as_u16());
+        resp.header(LOCATION.as_str(), &format!("{}{}", self.base, id))
+            .header(UPLOAD_EXPIRES_HEADER, &fmt_http_date(expires));
+
+        if length == 0 {
+            //空文件，则立即完成上传
+            if let Err(e) = self.complete(&id, entry).await {
+                warn!("!!!> Http Complete Upload Failed, id: {}, reason: {:?}", id, e);
+                return upload_response(req, StatusCode::INTERNAL_SERVER_ERROR.as_u16());
+            }
+        } else {
+            self.uploads.lock().insert(id, entry);
+        }
+
+        resp
+    }
+
+    //查询上传进度
+    fn head<S: Socket, W: AsyncIOWait>(
+        &self,
+        req: &HttpRequest<S, W>,
+        id: &str,
+    ) -> HttpResponse<S, W> {
+        let now = SystemTime::now();
+        let uploads = self.uploads.lock();
+        let mut resp = match uploads.get(id).filter(|entry| entry.expires > now) {
+            Some(entry) => {
+                let mut resp = upload_response(req, StatusCode::OK.as_u16());
+                resp.header(UPLOAD_OFFSET_HEADER, &entry.offset.to_string())
+                    .header(UPLOAD_LENGTH_HEADER, &entry.length.to_string())
+                    .header(UPLOAD_EXPIRES_HEADER, &fmt_http_date(entry.expires));
+                if let Some(metadata) = &entry.raw_metadata {
+                    resp.header(UPLOAD_METADATA_HEADER, metadata);
+                }
+                resp
+            }
+            None => upload_response(req, StatusCode::NOT_FOUND.as_u16()),
+        };
+        resp.header(CACHE_CONTROL.as_str(), "no-store");
+        resp
+    }
+
+    //在指定偏移写入分片，分片校验失败时不确认本次接收的数据
+    async fn patch<S: Socket, W: AsyncIOWait>(
+        &self,
+        req: &mut HttpRequest<S, W>,
+        id: &str,
+    ) -> HttpResponse<S, W> {
+        let is_offset_stream = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().eq_ignore_ascii_case(OFFSET_OCTET_STREAM))
+            .unwrap_or(false);
+        if !is_offset_stream {
+            return upload_response(req, StatusCode::UNSUPPORTED_MEDIA_TYPE.as_u16());
+        }
+
+        let offset = match header_u64(req, UPLOAD_OFFSET_HEADER) {
+            None => return upload_response(req, StatusCode::BAD_REQUEST.as_u16()),
+            Some(offset) => offset,
+        };
+        let checksum = match req.headers().get(UPLOAD_CHECKSUM_HEADER) {
+            None => None,
+            Some(value) => match value.to_str().ok().and_then(parse_upload_checksum) {
+                None => return upload_response(req, StatusCode::BAD_REQUEST.as_u16()),
+                Some(checksum) => Some(checksum),
+            },
+        };
+
+        //锁定上传，同一上传同时只允许接收一个分片
+        let (length, part) = {
+            let now = SystemTime::now();
+            let mut uploads = self.uploads.lock();
+            match uploads.get_mut(id).filter(|entry| entry.expires > now) {
+                Some(entry) => {
+                    if entry.locked {
+                        return upload_response(req, StatusCode::LOCKED.as_u16());
+                    }
+                    if entry.offset != offset {
+                        let mut resp = upload_response(req, StatusCode::CONFLICT.as_u16());
+                        resp.header(UPLOAD_OFFSET_HEADER, &entry.offset.to_string());
+                        return resp;
+                    }
+
+                    entry.locked = true;
+                    (entry.length, entry.part.clone())
+                }
+                None => return upload_response(req, StatusCode::NOT_FOUND.as_u16()),
+            }
+        };
+        //请求处理结束或被取消时，都会解锁上传
+        let _lock = UploadLock {
+            uploads: &self.uploads,
+            id,
+        };
+
+        let result = self
+            .write_part(req, part, offset, length, checksum)
+            .await;
+
+        //确认本次接收的数据
+        let (mut resp, completed) = {
+            let mut uploads = self.uploads.lock();
+            let entry = match uploads.get_mut(id) {
+                None => return upload_response(req, StatusCode::NOT_FOUND.as_u16()),
+                Some(entry) => entry,
+            };
+            let status = match result {
+                Err(status) => status,
+                Ok(len) => {
+                    entry.offset += len;
+                    entry.expires = SystemTime::now() + self.expire;
+                    StatusCode::NO_CONTENT.as_u16()
+                }
+            };
+            let mut resp = upload_response(req, status);
+            resp.header(UPLOAD_OFFSET_HEADER, &entry.offset.to_string())
+                .header(UPLOAD_EXPIRES_HEADER, &fmt_http_date(entry.expires));
+
+            if entry.offset == entry.length {
+                //已接收完成，则移除上传
+                (resp, uploads.remove(id))
+            } else {
+                (resp, None)
+            }
+        };
+
+        if let Some(entry) = completed {
+            //已接收完成，则完成上传
+            if let Err(e) = self.complete(id, entry).await {
+                warn!("!!!> Http Complete Upload Failed, id: {}, reason: {:?}", id, e);
+                resp.status(StatusCode::INTERNAL_SERVER_ERROR.as_u16());
+            }
+        }
+
+        resp
+    }
+
+    //流式接收请求体并写入分片文件，成功返回写入的长度，失败返回响应状态码
+    async fn write_part<S: Socket, W: AsyncIOWait>(
+        &self,
+        req: &mut HttpRequest<S, W>,
+        part: PathBuf,
+        offset: u64,
+        length: u64,
+        checksum: Option<(ChecksumAlgorithm, u32)>,
+    ) -> std::result::Result<u64, u16> {
+        let (mut sender, result) =
+            match spawn_part_writer(self.files_async_runtime.clone(), part, offset) {
+                Err(e) => {
+                    warn!("!!!> Http Write Upload Part Failed, reason: {:?}", e);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.as_u16());
+                }
+                Ok(r) => r,
+            };
+
+        let mut hasher = checksum.map(|(algorithm, _)| UploadChecksum::new(algorithm));
+        let mut status = None;
+        let mut received = 0;
+        let mut buf = Vec::with_capacity(self.block_size);
+        while let Some(bin) = req.next_body(self.block_size).await {
+            if offset + received + bin.len() as u64 > length {
+                //分片超过了文件总长度
+                status = Some(StatusCode::PAYLOAD_TOO_LARGE.as_u16());
+                break;
+            }
+
+            received += bin.len() as u64;
+            if let Some(hasher) = &mut hasher {
+                hasher.update(bin);
+            }
+            buf.extend_from_slice(bin);
+            if buf.len() >= self.block_size {
+                let block = mem::replace(&mut buf, Vec::with_capacity(self.block_size));
+                if sender.send(block).await.is_err() {
+                    //写入已失败，则立即停止接收
+                    break;
+                }
+            }
+        }
+        if status.is_none() && !buf.is_empty() {
+            let _ = sender.send(buf).await;
+        }
+        drop(sender);
+
+        let written = match wait_part_writer(result).await {
+            Err(e) => {
+                warn!("!!!> Http Write Upload Part Failed, reason: {:?}", e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR.as_u16());
+            }
+            Ok(written) => written,
+        };
+        if let Some(status) = status {
+            return Err(status);
+        }
+        if let (Some(hasher), Some((_, sum))) = (hasher, checksum) {
+            if hasher.sum() != sum {
+                //分片校验和不匹配
+                return Err(CHECKSUM_MISMATCH_STATUS);
+            }
+        }
+
+        Ok(written)
+    }
+
+    //终止上传，并移除分片文件
+    fn terminate<S: Socket, W: AsyncIOWait>(
+        &self,
+        req: &HttpRequest<S, W>,
+        id: &str,
+    ) -> HttpResponse<S, W> {
+        let mut uploads = self.uploads.lock();
+        match uploads.get(id).map(|entry| entry.locked) {
+            None => upload_response(req, StatusCode::NOT_FOUND.as_u16()),
+            Some(true) => upload_response(req, StatusCode::LOCKED.as_u16()),
+            Some(false) => {
+                if let Some(entry) = uploads.remove(id) {
+                    async_remove_part(self.files_async_runtime.clone(), entry.part);
+                }
+                upload_response(req, StatusCode::NO_CONTENT.as_u16())
+            }
+        }
+    }
+
+    //完成上传，在异步文件运行时中将分片文件移动到目标路径，并调用上传完成回调
+    //目标路径已存在时，会使用带上传id的文件名，不会覆盖已有文件
+    async fn complete(&self, id: &str, entry: UploadEntry) -> Result<()> {
+        let path = match async_move_part(
+            self.files_async_runtime.clone(),
+            entry.part.clone(),
+            entry.target,
+            id.to_string(),
+        )
+        .await
+        {
+            Err(e) => {
+                async_remove_part(self.files_async_runtime.clone(), entry.part);
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("complete upload failed, reason: {:?}", e),
+                ));
+            }
+            Ok(path) => path,
+        };
+
+        if let Some(hook) = &self.complete_hook {
+            hook(&UploadInfo {
+                id: id.to_string(),
+                path,
+                length: entry.length,
+                metadata: entry.metadata,
+            });
+        }
+        Ok(())
+    }
+}
+
+//构建可恢复上传的空响应
+fn upload_response<S: Socket, W: AsyncIOWait>(
+    req: &HttpRequest<S, W>,
+    status: u16,
+) -> HttpResponse<S, W> {
+    let mut resp = HttpResponse::new(req.get_handle().clone(), req.get_waits().clone(), 1);
+    if let Some(body) = resp.as_mut_body() {
+        body.init();
+    }
+    resp.status(status).header(TUS_RESUMABLE_HEADER, TUS_VERSION);
+    resp
+}
+
+//获取请求头中的无符号整数
+fn header_u64<S: Socket, W: AsyncIOWait>(req: &HttpRequest<S, W>, key: &str) -> Option<u64> {
+    req.headers()
+        .get(key)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+}
+
+//在异步文件运行时中打开分片文件，并从指定偏移开始按序写入接收到的块，发送端关闭后返回写入的总长度
+pub(crate) fn spawn_part_writer(
+    files_async_runtime: MultiTaskRuntime<()>,
+    path: PathBuf,
+    offset: u64,
+) -> Result<(mpsc::Sender<Vec<u8>>, oneshot::Receiver<Result<u64>>)> {
+    let (sender, mut receiver) = mpsc::channel::<Vec<u8>>(2);
+    let (result_sender, result_receiver) = oneshot::channel();
+    let files_async_runtime_copy = files_async_runtime.clone();
+    if let Err(e) = files_async_runtime.spawn(files_async_runtime.alloc(), async move {
+        let file = match AsyncFile::open(
+            files_async_runtime_copy,
+            path.clone(),
+            AsyncFileOptions::ReadWrite,
+        )
+        .await
+        {
+            Err(e) => {
+                //打开文件失败
+                let _ = result_sender.send(Err(Error::new(
+                    ErrorKind::Other,
+                    format!("open upload part failed, file: {:?}, reason: {:?}", path, e),
+                )));
+                return;
+            }
+            Ok(file) => Arc::new(file),
+        };
+
+        let mut pos = offset;
+        while let Some(block) = receiver.next().await {
+            let len = block.len() as u64;
+            if let Err(e) = file.write(pos, Arc::from(block), WriteOptions::Flush).await {
+                //写文件失败
+                let _ = result_sender.send(Err(Error::new(
+                    ErrorKind::Other,
+                    format!("write upload part failed, file: {:?}, reason: {:?}", path, e),
+                )));
+                return;
+            }
+            pos += len;
+        }
+
+        let _ = result_sender.send(Ok(pos - offset));
+    }) {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!("spawn upload part writer failed, reason: {:?}", e),
+        ));
+    }
+
+    Ok((sender, result_receiver))
+}
+
+//等待分片文件写入完成
+pub(crate) async fn wait_part_writer(result: oneshot::Receiver<Result<u64>>) -> Result<u64> {
+    match result.await {
+        Err(e) => Err(Error::new(
+            ErrorKind::Other,
+            format!("wait upload part writer failed, reason: {:?}", e),
+        )),
+        Ok(r) => r,
+    }
+}
+
+//在异步文件运行时中将分片文件移动到目标路径，返回实际的目标路径
+async fn async_move_part(
+    files_async_runtime: MultiTaskRuntime<()>,
+    part: PathBuf,
+    target: PathBuf,
+    unique: String,
+) -> Result<PathBuf> {
+    let (sender, receiver) = oneshot::channel();
+    if let Err(e) = files_async_runtime.spawn(files_async_runtime.alloc(), async move {
+        let _ = sender.send(move_part(&part, &target, &unique));
+    }) {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!("spawn move upload part failed, reason: {:?}", e),
+        ));
+    }
+
+    match receiver.await {
+        Err(e) => Err(Error::new(
+            ErrorKind::Other,
+            format!("wait move upload part failed, reason: {:?}", e),
+        )),
+        Ok(r) => r,
+    }
+}
+
+//将分片文件移动到目标路径，先以独占方式创建目标文件，已存在时改用带唯一标识的文件名，保证不会覆盖已有文件
+fn move_part(part: &Path, target: &Path, unique: &str) -> Result<PathBuf> {
+    if let Some(dir) = target.parent() {
+        create_dir_all(dir)?;
+    }
+
+    for path in vec![target.to_path_buf(), unique_path(target, unique)] {
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Err(ref e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+            Ok(_) => {
+                if let Err(e) = rename(part, &path) {
+                    let _ = remove_file_sync(&path);
+                    return Err(e);
+                }
+                return Ok(path);
+            }
+        }
+    }
+
+    Err(Error::new(
+        ErrorKind::AlreadyExists,
+        format!("move upload part failed, target: {:?}, reason: target already exists", target),
+    ))
+}
+
+//在文件名和扩展名之间插入唯一标识，例如a.txt插入id后为a.id.txt
+fn unique_path(path: &Path, unique: &str) -> PathBuf {
+    let mut name = path.file_stem().map(|stem| stem.to_os_string()).unwrap_or_default();
+    name.push(".");
+    name.push(unique);
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+
+    path.with_file_name(name)
+}
+
+//异步移除分片文件
+pub(crate) fn async_remove_part(files_async_runtime: MultiTaskRuntime<()>, path: PathBuf) {
+    let files_async_runtime_copy = files_async_runtime.clone();
+    if let Err(e) = files_async_runtime.spawn(files_async_runtime.alloc(), async move {
+        if let Err(e) = remove_file(files_async_runtime_copy, path.clone()).await {
+            warn!(
+                "!!!> Http Async Remove Upload Part Failed, file: {:?}, reason: {:?}",
+                path, e
+            );
+        }
+    }) {
+        warn!("!!!> Http Async Remove Upload Part Failed, reason: {:?}", e);
+    }
+}
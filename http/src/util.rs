@@ -1,18 +1,24 @@
 use std::pin::Pin;
 use std::sync::Arc;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::future::Future;
+use std::str::FromStr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::result::Result as GenResult;
 use std::task::{Context, Poll, Waker};
 use std::io::{Error, Result, ErrorKind};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use bytes::Buf;
-use futures::{future::{FutureExt, BoxFuture}};
+use https::HeaderMap;
+use futures::{future::{FutureExt, BoxFuture}, channel::oneshot};
 use crossbeam_channel::{Sender, Receiver, bounded};
 
+use r#async::rt::multi_thread::MultiTaskRuntime;
 use tcp::driver::{Socket, SocketHandle, AsyncIOWait, PendSocket};
 
+use crate::default_parser::{BROTLI_ENCODING, GZIP_ENCODING, negotiate_encoding};
+
 /*
 * 默认支持的Http协议版本号
 */
@@ -35,6 +41,11 @@ pub const DEFAULT_HTTPS_SCHEME: &str = "https";
 pub const DEFAULT_HTTP_PORT: u16 = 80;
 pub const DEFAULT_HTTPS_PORT: u16 = 443;
 
+/*
+* 预压缩文件的编码和扩展名，按服务器的优先顺序排列
+*/
+pub const PRECOMPRESSED_EXTENSIONS: &[(&str, &str)] = &[(BROTLI_ENCODING, "br"), (GZIP_ENCODING, "gz")];
+
 /*
 * Http连接异步发送器
 */
@@ -60,6 +71,11 @@ impl<S: Socket, T: Send + Sync + 'static> Clone for HttpSender<S, T> {
 }
 
 impl<S: Socket, T: Send + Sync + 'static> HttpSender<S, T> {
+    //判断当前Http连接异步通道或对应的Http连接是否已关闭
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed) || self.handle.is_closed()
+    }
+
     //异步发送消息，无论当前Http连接是否挂起，都会发送消息
     //如果对应的Http连接已挂起，则会在发送成功后唤醒对应的Http连接
     //发送空消息，表示消息发送结束
@@ -186,6 +202,164 @@ pub fn channel<S, W, T: Send + Sync + 'static>(handle: SocketHandle<S>, waits: W
     })
 }
 
+/*
+* 转发的客户端地址请求头
+*/
+pub const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/*
+* Ip地址段，例如10.0.0.0/8或::1，Ipv4映射的Ipv6地址会按Ipv4地址匹配
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpRange {
+    addr:   IpAddr, //地址段的网络地址
+    prefix: u8,     //地址段的前缀长度
+}
+
+impl FromStr for IpRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (addr, prefix) = match s.find('/') {
+            None => (&s[..], None),
+            Some(index) => (&s[..index], Some(&s[index + 1..])),
+        };
+
+        let addr = match addr.parse::<IpAddr>() {
+            Err(e) => {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("parse ip range failed, range: {:?}, reason: {:?}", s, e)));
+            },
+            Ok(addr) => normalize_ip(addr),
+        };
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix.map(|prefix| prefix.parse::<u8>()) {
+            None => max_prefix,
+            Some(Ok(prefix)) if prefix <= max_prefix => prefix,
+            _ => {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("parse ip range failed, range: {:?}, reason: invalid prefix", s)));
+            },
+        };
+
+        Ok(IpRange::new(addr, prefix))
+    }
+}
+
+impl IpRange {
+    //构建Ip地址段，前缀长度超过地址长度时，按地址长度处理
+    pub fn new(addr: IpAddr, prefix: u8) -> Self {
+        let addr = normalize_ip(addr);
+        let prefix = if addr.is_ipv4() { prefix.min(32) } else { prefix.min(128) };
+        let addr = match addr {
+            IpAddr::V4(v4) => IpAddr::V4(Ipv4Addr::from(u32::from(v4) & ipv4_mask(prefix))),
+            IpAddr::V6(v6) => IpAddr::V6(Ipv6Addr::from(u128::from(v6) & ipv6_mask(prefix))),
+        };
+
+        IpRange {
+            addr,
+            prefix,
+        }
+    }
+
+    //判断指定地址是否在地址段内
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, normalize_ip(*ip)) {
+            (IpAddr::V4(addr), IpAddr::V4(ip)) => {
+                u32::from(ip) & ipv4_mask(self.prefix) == u32::from(addr)
+            },
+            (IpAddr::V6(addr), IpAddr::V6(ip)) => {
+                u128::from(ip) & ipv6_mask(self.prefix) == u128::from(addr)
+            },
+            _ => false,
+        }
+    }
+}
+
+/*
+* 获取Http请求的客户端地址，只有对端地址是可信代理时，才会从右向左解析X-Forwarded-For请求头，并跳过其中的可信代理地址
+*/
+pub fn client_ip(headers: &HeaderMap, remote: IpAddr, trusted: &[IpRange]) -> IpAddr {
+    let mut client = normalize_ip(remote);
+    if !trusted.iter().any(|range| range.contains(&client)) {
+        //对端地址不是可信代理
+        return client;
+    }
+
+    let mut forwarded: Vec<&str> = Vec::new();
+    for value in headers.get_all(X_FORWARDED_FOR) {
+        if let Ok(value) = value.to_str() {
+            forwarded.extend(value.split(','));
+        }
+    }
+
+    for addr in forwarded.iter().rev() {
+        match addr.trim().parse::<IpAddr>() {
+            Err(_) => break, //无效的地址，则停止解析
+            Ok(addr) => {
+                client = normalize_ip(addr);
+                if !trusted.iter().any(|range| range.contains(&client)) {
+                    //不是可信代理，则为客户端地址
+                    break;
+                }
+            },
+        }
+    }
+
+    client
+}
+
+/*
+* 获取代理转发时的X-Forwarded-For请求头的值，只有对端地址是可信代理时，才会保留请求中的X-Forwarded-For请求头，并追加对端地址
+*/
+pub fn forwarded_for(headers: &HeaderMap, remote: IpAddr, trusted: &[IpRange]) -> String {
+    let remote = normalize_ip(remote);
+    let mut forwarded: Vec<String> = Vec::new();
+    if trusted.iter().any(|range| range.contains(&remote)) {
+        for value in headers.get_all(X_FORWARDED_FOR) {
+            if let Ok(value) = value.to_str() {
+                forwarded.extend(value
+                    .split(',')
+                    .map(|addr| addr.trim())
+                    .filter(|addr| !addr.is_empty())
+                    .map(|addr| addr.to_string()));
+            }
+        }
+    }
+
+    forwarded.push(remote.to_string());
+    forwarded.join(", ")
+}
+
+//将Ipv4映射的Ipv6地址转换为Ipv4地址
+fn normalize_ip(ip: IpAddr) -> IpAddr {
+    if let IpAddr::V6(v6) = ip {
+        let segments = v6.segments();
+        if segments[..5].iter().all(|segment| *segment == 0) && segments[5] == 0xffff {
+            return IpAddr::V4(Ipv4Addr::new((segments[6] >> 8) as u8, segments[6] as u8, (segments[7] >> 8) as u8, segments[7] as u8));
+        }
+    }
+
+    ip
+}
+
+//获取Ipv4地址的前缀掩码
+fn ipv4_mask(prefix: u8) -> u32 {
+    if prefix == 0 {
+        0
+    } else {
+        !0u32 << (32 - prefix as u32)
+    }
+}
+
+//获取Ipv6地址的前缀掩码
+fn ipv6_mask(prefix: u8) -> u128 {
+    if prefix == 0 {
+        0
+    } else {
+        !0u128 << (128 - prefix as u32)
+    }
+}
+
 /*
 * 路径修剪
 */
@@ -197,4 +371,99 @@ pub fn trim_path<P: Into<PathBuf>>(path: P) -> Result<PathBuf> {
     }
 
     Ok(path)
-}
\ No newline at end of file
+}
+
+/*
+* 根据Accept-Encoding的值，选择指定文件存在的预压缩文件，返回编码和预压缩文件路径，例如foo.js.br或foo.js.gz
+*/
+pub fn precompressed_path(path: &Path, accept: Option<&str>) -> Option<(&'static str, PathBuf)> {
+    let accept = accept?;
+    let available: Vec<(&'static str, PathBuf)> = PRECOMPRESSED_EXTENSIONS
+        .iter()
+        .map(|(encoding, ext)| (*encoding, sibling_path(path, ext)))
+        .filter(|(_, sibling)| sibling.is_file())
+        .collect();
+    if available.is_empty() {
+        return None;
+    }
+
+    let encodings: Vec<&'static str> = available.iter().map(|(encoding, _)| *encoding).collect();
+    let encoding = negotiate_encoding(accept, &encodings)?;
+    available.into_iter().find(|(e, _)| *e == encoding)
+}
+
+/*
+* 将批量文件替换为指定编码的预压缩文件，要求全部替换时，只要有非空文件没有预压缩文件就不替换，返回替换后的批量文件和是否有替换
+*/
+pub fn precompressed_files(files: Vec<(u64, PathBuf)>, encoding: &str, is_all: bool) -> (Vec<(u64, PathBuf)>, bool) {
+    let ext = match PRECOMPRESSED_EXTENSIONS.iter().find(|(e, _)| *e == encoding) {
+        None => return (files, false),
+        Some((_, ext)) => *ext,
+    };
+
+    let mut siblings = Vec::with_capacity(files.len());
+    for (size, path) in &files {
+        if *size == 0 {
+            //空文件会在加载时忽略
+            siblings.push(None);
+            continue;
+        }
+
+        let sibling = sibling_path(path, ext);
+        match sibling.metadata() {
+            Ok(meta) if meta.is_file() => siblings.push(Some((meta.len(), sibling))),
+            _ if is_all => return (files, false),
+            _ => siblings.push(None),
+        }
+    }
+
+    if siblings.iter().all(|sibling| sibling.is_none()) {
+        return (files, false);
+    }
+
+    let files = files
+        .into_iter()
+        .zip(siblings.into_iter())
+        .map(|(file, sibling)| sibling.unwrap_or(file))
+        .collect();
+    (files, true)
+}
+
+/*
+* 在文件异步运行时中执行指定的阻塞文件操作，避免阻塞网络线程
+*/
+pub async fn async_files_call<T, F>(files_async_runtime: &MultiTaskRuntime<()>, func: F) -> Result<T>
+    where T: Send + 'static,
+          F: FnOnce() -> T + Send + 'static {
+    let (sender, receiver) = oneshot::channel();
+    if let Err(e) = files_async_runtime.spawn(files_async_runtime.alloc(), async move {
+        let _ = sender.send(func());
+    }) {
+        return Err(Error::new(ErrorKind::Other, format!("async files call failed, reason: {:?}", e)));
+    }
+
+    match receiver.await {
+        Err(_) => Err(Error::new(ErrorKind::Other, "async files call failed, reason: task canceled")),
+        Ok(r) => Ok(r),
+    }
+}
+
+/*
+* 判断指定文件是否是已存在原文件的预压缩文件
+*/
+pub fn is_precompressed_file(path: &Path) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if PRECOMPRESSED_EXTENSIONS.iter().any(|(_, e)| *e == ext) => {
+            path.with_extension("").is_file()
+        },
+        _ => false,
+    }
+}
+
+//获取指定文件增加指定扩展名后的路径
+fn sibling_path(path: &Path, ext: &str) -> PathBuf {
+    let mut sibling = path.as_os_str().to_os_string();
+    sibling.push(".");
+    sibling.push(ext);
+    sibling.into()
+}
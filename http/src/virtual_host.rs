@@ -1,6 +1,8 @@
 use std::sync::Arc;
 use std::io::{Error, Result, ErrorKind};
 
+use https::StatusCode;
+
 use hash::XHashMap;
 use atom::Atom;
 use handler::{Args, Handler, SGenType};
@@ -9,7 +11,8 @@ use tcp::driver::{Socket, AsyncIOWait};
 use crate::{service::{HttpService, ServiceFactory},
             gateway::{GatewayContext, HttpGateway},
             route::{RouterTab, HttpRoute},
-            middleware::Middleware};
+            middleware::Middleware,
+            error_page::{ErrorPage, ErrorPages, default_page}};
 
 /*
 * 虚拟主机池
@@ -25,12 +28,132 @@ pub trait VirtualHostPool<S: Socket, W: AsyncIOWait>: Clone + Send + Sync + 'sta
 
     //增加指定主机名的虚拟主机
     fn add(&mut self, name: &str, host: Self::Host) -> Result<()>;
+
+    //获取请求的主机不存在时回应的状态码
+    fn unknown_host_status(&self) -> StatusCode {
+        StatusCode::MISDIRECTED_REQUEST
+    }
+
+    //获取指定主机名的虚拟主机在请求交给服务前回应的错误页，返回Mime和错误页内容，主机名为空表示主机不存在
+    fn error_page(&self, _name: Option<&str>, status: StatusCode, _path: &str) -> (String, Vec<u8>) {
+        default_page(status)
+    }
+
+    //获取指定主机名、连接是否安全和请求路径的主机级重定向，返回重定向状态码和目标地址，不需要重定向时返回空
+    fn redirect(&self, _name: &str, _is_security: bool, _path: &str) -> Option<(StatusCode, String)> {
+        None
+    }
+}
+
+/*
+* 通配主机名的前缀，例如*.example.com
+*/
+const WILDCARD_HOST_PREFIX: &str = "*.";
+
+/*
+* 匹配任意主机名的重定向主机名
+*/
+const ANY_HOST_NAME: &str = "*";
+
+/*
+* Http和Https的默认端口
+*/
+const DEFAULT_HTTP_PORT: u16 = 80;
+const DEFAULT_HTTPS_PORT: u16 = 443;
+
+/*
+* 主机级重定向
+*/
+#[derive(Debug, Clone)]
+pub struct HostRedirect {
+    status:         StatusCode,     //重定向状态码
+    host:           Option<String>, //重定向的目标主机名，为空表示使用请求的主机名
+    https_port:     Option<u16>,    //重定向到Https的端口，为空表示不重定向到Https
+}
+
+impl HostRedirect {
+    //构建重定向到指定主机的主机级重定向，例如从顶级域名重定向到www
+    pub fn to_host(host: &str) -> Self {
+        HostRedirect {
+            status: StatusCode::MOVED_PERMANENTLY,
+            host: Some(host.to_string()),
+            https_port: None,
+        }
+    }
+
+    //构建将非安全连接的请求重定向到指定端口的Https的主机级重定向，安全连接的请求不会被重定向
+    pub fn to_https(port: u16) -> Self {
+        HostRedirect {
+            status: StatusCode::MOVED_PERMANENTLY,
+            host: None,
+            https_port: Some(port),
+        }
+    }
+
+    //设置重定向状态码，只允许设置重定向状态码
+    pub fn set_status(&mut self, status: StatusCode) -> Result<()> {
+        if !status.is_redirection() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("set host redirect status failed, status: {:?}, reason: invalid redirect status", status)));
+        }
+
+        self.status = status;
+        Ok(())
+    }
+
+    //获取指定主机名、连接是否安全和请求路径的重定向状态码和目标地址，不需要重定向时返回空
+    pub fn location(&self, name: &str, is_security: bool, path: &str) -> Option<(StatusCode, String)> {
+        let (host, port) = split_host_port(name);
+        match (&self.host, self.https_port) {
+            (_, Some(_)) if is_security => {
+                //已是安全连接，则不需要重定向到Https
+                None
+            },
+            (target, Some(https_port)) => {
+                let host = target.as_ref().map(|target| target.as_str()).unwrap_or(host);
+                if https_port == DEFAULT_HTTPS_PORT {
+                    Some((self.status, format!("https://{}{}", host, path)))
+                } else {
+                    Some((self.status, format!("https://{}:{}{}", host, https_port, path)))
+                }
+            },
+            (Some(target), None) => {
+                if target.eq_ignore_ascii_case(host) {
+                    //已是目标主机，则不需要重定向
+                    return None;
+                }
+
+                let scheme = if is_security {
+                    "https"
+                } else {
+                    "http"
+                };
+                match port {
+                    Some(port) if port != DEFAULT_HTTP_PORT && port != DEFAULT_HTTPS_PORT => {
+                        Some((self.status, format!("{}://{}:{}{}", scheme, target, port, path)))
+                    },
+                    _ => Some((self.status, format!("{}://{}{}", scheme, target, path))),
+                }
+            },
+            (None, None) => None,
+        }
+    }
+}
+
+/*
+* 虚拟主机映射
+*/
+struct HostMap<S: Socket, W: AsyncIOWait, H: Middleware<S, W, GatewayContext>> {
+    hosts:      XHashMap<Atom, VirtualHost<S, W, H>>,   //确定主机名的虚拟主机表
+    wildcards:  Vec<(String, VirtualHost<S, W, H>)>,    //通配主机名的虚拟主机表，按通配主机名后缀的长度降序排序
+    aliases:    XHashMap<Atom, Atom>,                   //主机别名表
+    redirects:  XHashMap<Atom, HostRedirect>,           //主机级重定向表
+    default:    Option<VirtualHost<S, W, H>>,           //默认虚拟主机
 }
 
 /*
 * 虚拟主机表
 */
-pub struct VirtualHostTab<S: Socket, W: AsyncIOWait, H: Middleware<S, W, GatewayContext>>(Arc<XHashMap<Atom, VirtualHost<S, W, H>>>);
+pub struct VirtualHostTab<S: Socket, W: AsyncIOWait, H: Middleware<S, W, GatewayContext>>(Arc<HostMap<S, W, H>>);
 
 unsafe impl<S: Socket, W: AsyncIOWait, H: Middleware<S, W, GatewayContext>> Send for VirtualHostTab<S, W, H> {}
 unsafe impl<S: Socket, W: AsyncIOWait, H: Middleware<S, W, GatewayContext>> Sync for VirtualHostTab<S, W, H> {}
@@ -45,28 +168,134 @@ impl<S: Socket, W: AsyncIOWait, H: Middleware<S, W, GatewayContext>> VirtualHost
     type Host = VirtualHost<S, W, H>;
 
     fn size(&self) -> usize {
-        self.0.len()
+        self.0.hosts.len() + self.0.wildcards.len()
     }
 
+    //依次匹配主机别名、确定主机名和通配主机名，都未匹配则返回默认虚拟主机
     fn get(&self, name: &str) -> Option<&Self::Host> {
-        let host: Vec<&str> = name.split(":").collect();
-        self.0.get(&Atom::from(host[0]))
+        let (host_name, _) = split_host_port(name);
+        let mut key = Atom::from(host_name.to_ascii_lowercase());
+        if let Some(name) = self.0.aliases.get(&key) {
+            key = name.clone();
+        }
+
+        if let Some(host) = self.0.hosts.get(&key) {
+            return Some(host);
+        }
+
+        let host_name: &str = key.as_ref();
+        for (suffix, host) in &self.0.wildcards {
+            if host_name.len() > suffix.len() && host_name.ends_with(suffix.as_str()) {
+                //通配主机名匹配任意级的子域名
+                return Some(host);
+            }
+        }
+
+        self.0.default.as_ref()
     }
 
+    //主机名以*.开始，则增加通配主机名的虚拟主机
     fn add(&mut self, name: &str, host: Self::Host) -> Result<()> {
-        if let Some(tab) = Arc::get_mut(&mut self.0) {
-            tab.insert(Atom::from(name), host);
+        if let Some(map) = Arc::get_mut(&mut self.0) {
+            let name = name.to_ascii_lowercase();
+            if name.starts_with(WILDCARD_HOST_PREFIX) {
+                let suffix = name[1..].to_string();
+                map.wildcards.retain(|(key, _)| key != &suffix);
+                map.wildcards.push((suffix, host));
+                map.wildcards.sort_by(|(x, _), (y, _)| y.len().cmp(&x.len()));
+            } else {
+                map.hosts.insert(Atom::from(name), host);
+            }
             return Ok(());
         }
 
         Err(Error::new(ErrorKind::Other, format!("add virtual host error, host: {:?}, reason: not writable", name)))
     }
+
+    fn error_page(&self, name: Option<&str>, status: StatusCode, path: &str) -> (String, Vec<u8>) {
+        let host = match name {
+            Some(name) => self.get(name),
+            None => self.0.default.as_ref(),
+        };
+
+        if let Some(host) = host {
+            return host.error_pages.render(status, path);
+        }
+
+        default_page(status)
+    }
+
+    fn redirect(&self, name: &str, is_security: bool, path: &str) -> Option<(StatusCode, String)> {
+        let (host, _) = split_host_port(name);
+        let host = Atom::from(host.to_ascii_lowercase());
+        self.0.redirects
+            .get(&host)
+            .or_else(|| self.0.redirects.get(&Atom::from(ANY_HOST_NAME)))
+            .and_then(|redirect| redirect.location(name, is_security, path))
+    }
 }
 
 impl<S: Socket, W: AsyncIOWait, H: Middleware<S, W, GatewayContext>> VirtualHostTab<S, W, H> {
     //构建虚拟主机表
     pub fn new() -> Self {
-        VirtualHostTab(Arc::new(XHashMap::default()))
+        VirtualHostTab(Arc::new(HostMap {
+            hosts: XHashMap::default(),
+            wildcards: Vec::new(),
+            aliases: XHashMap::default(),
+            redirects: XHashMap::default(),
+            default: None,
+        }))
+    }
+
+    //设置默认虚拟主机，请求的主机未匹配任何虚拟主机时，由默认虚拟主机处理
+    pub fn set_default(&mut self, host: VirtualHost<S, W, H>) -> Result<()> {
+        if let Some(map) = Arc::get_mut(&mut self.0) {
+            map.default = Some(host);
+            return Ok(());
+        }
+
+        Err(Error::new(ErrorKind::Other, "set default virtual host error, reason: not writable"))
+    }
+
+    //增加指定主机名的别名，别名的请求由指定主机名的虚拟主机处理
+    pub fn add_alias(&mut self, alias: &str, name: &str) -> Result<()> {
+        if let Some(map) = Arc::get_mut(&mut self.0) {
+            map.aliases.insert(Atom::from(alias.to_ascii_lowercase()), Atom::from(name.to_ascii_lowercase()));
+            return Ok(());
+        }
+
+        Err(Error::new(ErrorKind::Other, format!("add virtual host alias error, alias: {:?}, reason: not writable", alias)))
+    }
+
+    //增加指定主机名的主机级重定向，主机名为*表示所有未设置重定向的主机
+    pub fn add_redirect(&mut self, name: &str, redirect: HostRedirect) -> Result<()> {
+        if let Some(map) = Arc::get_mut(&mut self.0) {
+            map.redirects.insert(Atom::from(name.to_ascii_lowercase()), redirect);
+            return Ok(());
+        }
+
+        Err(Error::new(ErrorKind::Other, format!("add virtual host redirect error, host: {:?}, reason: not writable", name)))
+    }
+}
+
+//分离主机名和端口
+fn split_host_port(name: &str) -> (&str, Option<u16>) {
+    let offset = if name.starts_with('[') {
+        //Ipv6地址
+        match name.find(']') {
+            None => return (name, None),
+            Some(index) => index + 1,
+        }
+    } else {
+        0
+    };
+
+    match name[offset..].rfind(':') {
+        None => (name, None),
+        Some(index) => {
+            let index = offset + index;
+            (&name[..index], name[index + 1..].parse().ok())
+        },
     }
 }
 
@@ -74,7 +303,8 @@ impl<S: Socket, W: AsyncIOWait, H: Middleware<S, W, GatewayContext>> VirtualHost
 * 虚拟主机，即Http网关工厂
 */
 pub struct VirtualHost<S: Socket, W: AsyncIOWait, H: Middleware<S, W, GatewayContext>> {
-    router_tab: RouterTab<S, W, GatewayContext, H>, //路由器表
+    router_tab:     RouterTab<S, W, GatewayContext, H>, //路由器表
+    error_pages:    Arc<ErrorPages<S, W>>,              //错误页表
 }
 
 unsafe impl<S: Socket, W: AsyncIOWait, H: Middleware<S, W, GatewayContext>> Send for VirtualHost<S, W, H> {}
@@ -84,6 +314,7 @@ impl<S: Socket, W: AsyncIOWait, H: Middleware<S, W, GatewayContext>> Clone for V
     fn clone(&self) -> Self {
         VirtualHost {
             router_tab: self.router_tab.clone(),
+            error_pages: self.error_pages.clone(),
         }
     }
 }
@@ -92,7 +323,7 @@ impl<S: Socket, W: AsyncIOWait, H: Middleware<S, W, GatewayContext>> ServiceFact
     type Service = HttpGateway<S, W, H>;
 
     fn new_service(&self) -> Self::Service {
-        HttpGateway::with(self.router_tab.clone())
+        HttpGateway::with_error_pages(self.router_tab.clone(), self.error_pages.clone())
     }
 }
 
@@ -101,6 +332,27 @@ impl<S: Socket, W: AsyncIOWait, H: Middleware<S, W, GatewayContext>> VirtualHost
     pub fn with(route: HttpRoute<S, W, GatewayContext, H>) -> Self {
         VirtualHost {
             router_tab: route.into(),
+            error_pages: Arc::new(ErrorPages::new()),
+        }
+    }
+
+    //设置指定状态码的错误页模板，模板中的{status}、{reason}和{path}会被替换，在虚拟主机加入虚拟主机表前设置
+    pub fn set_error_template(&mut self, status: StatusCode, mime: &str, template: &str) -> Result<()> {
+        self.set_error_page(status, ErrorPage::Template(mime.to_string(), template.to_string()))
+    }
+
+    //设置指定状态码的错误页处理器，在虚拟主机加入虚拟主机表前设置
+    pub fn set_error_handler(&mut self, status: StatusCode, handler: Arc<dyn Middleware<S, W, GatewayContext>>) -> Result<()> {
+        self.set_error_page(status, ErrorPage::Handler(handler))
+    }
+
+    //设置指定状态码的错误页
+    fn set_error_page(&mut self, status: StatusCode, page: ErrorPage<S, W>) -> Result<()> {
+        if let Some(pages) = Arc::get_mut(&mut self.error_pages) {
+            pages.set(status, page)?;
+            return Ok(());
         }
+
+        Err(Error::new(ErrorKind::Other, format!("set error page error, status: {:?}, reason: not writable", status)))
     }
 }
\ No newline at end of file
@@ -8,13 +8,14 @@ use std::cell::RefCell;
 use std::time::Instant;
 use std::time::Duration;
 use std::future::Future;
-use std::net::SocketAddr;
+use std::net::{SocketAddr, TcpStream};
 use std::marker::PhantomData;
-use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::io::{Read, Write, Error, ErrorKind};
 use std::error::Error as StdError;
 use std::task::{Context, Poll, Waker};
 
-use https::HeaderMap;
+use https::{HeaderMap, Method, StatusCode};
 use regex::{RegexSetBuilder, RegexSet, RegexBuilder, Regex};
 use route_recognizer::Router;
 use futures::future::{FutureExt, BoxFuture};
@@ -30,6 +31,7 @@ use handler::{Args, Handler, SGenType};
 use worker::{impls::{STORE_TASK_POOL, STORE_WORKER_WALKER},
              worker::WorkerType,
              worker_pool::WorkerPool};
+use r#async::rt::multi_thread::MultiTaskPool;
 use tcp::driver::{Socket, SocketConfig, AsyncIOWait, AsyncServiceFactory};
 use tcp::buffer_pool::WriteBufferPool;
 use tcp::util::{SocketEvent, TlsConfig};
@@ -37,24 +39,36 @@ use tcp::server::{AsyncWaitsHandle, AsyncPortsFactory, SocketListener};
 use tcp::connect::TcpSocket;
 use tcp::tls_connect::TlsSocket;
 
-use http::{server::HttpListenerFactory,
-           virtual_host::{VirtualHostTab, VirtualHost, VirtualHostPool},
+use http::{server::{HttpListenerFactory, HttpLimits},
+           virtual_host::{VirtualHostTab, VirtualHost, VirtualHostPool, HostRedirect},
            gateway::GatewayContext,
-           route::HttpRoute,
+           route::{RouterTab, HttpRoute},
            middleware::{MiddlewareResult, Middleware, MiddlewareChain},
+           error_page::{ErrorPage, ErrorPages, render_template},
            cors_handler::CORSHandler,
-           default_parser::DefaultParser,
-           multi_parts::MutilParts,
-           range_load::RangeLoad,
+           default_parser::{DefaultParser, SUPPORTED_ENCODINGS, parse_accept_encoding, negotiate_encoding},
+           multi_parts::{MutilParts, MultiPartsParser, PartEvent, PartHead, sanitize_filename, part_filename_key},
+           range_load::{RangeLoad, parse_ranges, if_range_matches, multipart_body},
            file_load::FileLoad,
            files_load::FilesLoad,
            batch_load::BatchLoad,
-           upload::UploadFile,
+           upload::{UploadFile, ChecksumAlgorithm, UploadChecksum, parse_upload_checksum, parse_upload_metadata},
            port::HttpPort,
-           static_cache::StaticCache,
+           static_cache::{StaticCache, CacheRes, CacheStats, Precondition, format_etag, etag_list_matches, check_preconditions, content_sign},
            request::HttpRequest,
            response::{ResponseHandler, HttpResponse},
-           util::HttpRecvResult};
+           packet::ChunkedDecoder,
+           sse::SseEvent,
+           proxy::{ReverseProxy, BalanceStrategy, PathRewrite},
+           auth::{CredentialStore, MemoryCredentialStore, JwtAuth, HmacAuth, AuthScheme, parse_basic_credentials, sign_hs256_token, sign_hmac, string_to_sign, body_digest},
+           cookie::{SameSite, SetCookie, CookieParser, parse_cookies},
+           session::{Session, SessionStore, MemorySessionStore, CookieSessionStore, generate_session_id},
+           rate_limit::{RateLimit, RateLimitAlgorithm},
+           gray_route::{GrayRouter, GrayRules, GrayRule, GrayMatcher, GrayUserKey, gray_bucket},
+           access_log::{AccessLogger, AccessLogFormat, AccessLogOutput, AccessRecord, format_clf_time, format_iso_time},
+           config::{ServerConfig, ServerBootstrap, MiddlewareRegistry, HttpMiddleware},
+           h2_frame::{HTTP2_PREFACE, HTTP2_ALPN, FLAG_END_STREAM, FLAG_END_HEADERS, FrameType, FrameHead, Http2Settings, headers_frames, is_http2},
+           util::{HttpRecvResult, IpRange, precompressed_path, precompressed_files, is_precompressed_file, client_ip, forwarded_for}};
 
 #[test]
 fn test_regex() {
@@ -290,6 +304,927 @@ fn test_find_bytes() {
     }
 }
 
+#[test]
+fn test_chunked_decoder() {
+    let data = b"5;ext=1\r\nHello\r\n7\r\n, World\r\n0\r\nX-Checksum: abc\r\n\r\nGET";
+
+    //逐字节到达，且每次最多解码4字节
+    let mut decoder = ChunkedDecoder::new();
+    let mut body = Vec::new();
+    for b in data.iter() {
+        decoder.push(&[*b]);
+        loop {
+            let mut block = Vec::new();
+            decoder.decode(&mut block, 4).unwrap();
+            if block.is_empty() {
+                break;
+            }
+            body.extend_from_slice(&block);
+        }
+    }
+    assert!(decoder.is_done());
+    assert_eq!(body.as_slice(), b"Hello, World");
+    assert_eq!(decoder.trailers().unwrap().get("x-checksum").unwrap(), "abc");
+    assert_eq!(decoder.take_remaining().as_slice(), b"GET");
+
+    //非法的块长度
+    let mut decoder = ChunkedDecoder::new();
+    decoder.push(b"zz\r\nHello\r\n");
+    assert!(decoder.decode(&mut Vec::new(), usize::MAX).is_err());
+
+    //块数据后缺少换行
+    let mut decoder = ChunkedDecoder::new();
+    decoder.push(b"2\r\nHello\r\n");
+    assert!(decoder.decode(&mut Vec::new(), usize::MAX).is_err());
+}
+
+#[test]
+fn test_sse_event() {
+    assert_eq!(SseEvent::new("hello").to_vec().as_slice(), b"data: hello\n\n");
+
+    let mut event = SseEvent::new("line1\r\nline2");
+    event.id("1\n0").event("update").retry(3000);
+    assert_eq!(String::from_utf8(event.to_vec()).unwrap(),
+               "id: 10\nevent: update\nretry: 3000\ndata: line1\ndata: line2\n\n");
+
+    let mut event = SseEvent::new("a\rb\r\n\nc");
+    event.id("1\r2").event("up\r\ndate");
+    assert_eq!(String::from_utf8(event.to_vec()).unwrap(),
+               "id: 12\nevent: update\ndata: a\ndata: b\ndata: \ndata: c\n\n");
+}
+
+#[test]
+fn test_http2_frame() {
+    let mut settings = Http2Settings::default();
+    settings.enable_push = false;
+    settings.max_concurrent_streams = Some(100);
+    settings.initial_window_size = 1024 * 1024;
+    let mut remote = Http2Settings::default();
+    assert!(remote.decode(&settings.encode()).is_ok());
+    assert_eq!(remote.enable_push, false);
+    assert_eq!(remote.max_concurrent_streams, Some(100));
+    assert_eq!(remote.initial_window_size, 1024 * 1024);
+
+    let frames = headers_frames(1, &[0u8; 40], true, 16);
+    assert_eq!(frames.len(), 3);
+    let head = FrameHead::parse(&frames[0]).unwrap();
+    assert_eq!(head.kind, FrameType::Headers);
+    assert_eq!(head.len, 16);
+    assert!(head.has_flag(FLAG_END_STREAM) && !head.has_flag(FLAG_END_HEADERS));
+    let head = FrameHead::parse(&frames[2]).unwrap();
+    assert_eq!(head.kind, FrameType::Continuation);
+    assert_eq!(head.len, 8);
+    assert!(head.has_flag(FLAG_END_HEADERS));
+
+    assert_eq!(is_http2(None, &HTTP2_PREFACE[..10]), None);
+    assert_eq!(is_http2(None, HTTP2_PREFACE), Some(true));
+    assert_eq!(is_http2(None, b"GET / HTTP/1.1\r\n"), Some(false));
+    assert_eq!(is_http2(Some(HTTP2_ALPN), b""), Some(true));
+}
+
+#[test]
+fn test_route_params() {
+    let mut tab: RouterTab<TcpSocket, AsyncWaitsHandle, GatewayContext, TestMultiPartsHandler> = RouterTab::new();
+    tab.add("/user/:name".to_string(), Method::GET, TestMultiPartsHandler);
+    tab.add("/user/:id<int>".to_string(), Method::GET, TestMultiPartsHandler);
+    tab.add("/user/:id<uuid>/profile".to_string(), Method::GET, TestMultiPartsHandler);
+    tab.add("/code/:code<[a-z]{2}>".to_string(), Method::GET, TestMultiPartsHandler);
+    tab.add("/files/*path".to_string(), Method::GET, TestMultiPartsHandler);
+    assert!(tab.finish().is_ok());
+
+    let (_, params) = tab.match_route(&Method::GET, "/user/100").unwrap();
+    assert_eq!(params, vec![("id".to_string(), "100".to_string())]);
+    let (_, params) = tab.match_route(&Method::GET, "/user/Tom%20Li").unwrap();
+    assert_eq!(params, vec![("name".to_string(), "Tom Li".to_string())]);
+    let (_, params) = tab.match_route(&Method::GET, "/user/0e7a5d5a-2c1b-4b8e-9f3a-1d2c3b4a5e6f/profile").unwrap();
+    assert_eq!(params, vec![("id".to_string(), "0e7a5d5a-2c1b-4b8e-9f3a-1d2c3b4a5e6f".to_string())]);
+    assert!(tab.match_route(&Method::GET, "/user/100/profile").is_none());
+    assert!(tab.match_route(&Method::GET, "/code/abc").is_none());
+    let (_, params) = tab.match_route(&Method::GET, "/files/a/b/c.txt").unwrap();
+    assert_eq!(params, vec![("path".to_string(), "a/b/c.txt".to_string())]);
+    let (_, params) = tab.match_route(&Method::GET, "/files/a%2F..%2Fb/c%20d.txt").unwrap();
+    assert_eq!(params, vec![("path".to_string(), "a%2F..%2Fb/c d.txt".to_string())]);
+    let (_, params) = tab.match_route(&Method::GET, "/user/a%2Fb").unwrap();
+    assert_eq!(params, vec![("name".to_string(), "a/b".to_string())]);
+    assert!(tab.match_route(&Method::POST, "/user/100").is_none());
+    let (_, _, route) = tab.match_route_pattern(&Method::GET, "/user/200").unwrap();
+    assert_eq!(route.as_ref() as &str, "/user/:id<int>");
+
+    let mut tab: RouterTab<TcpSocket, AsyncWaitsHandle, GatewayContext, TestMultiPartsHandler> = RouterTab::new();
+    tab.add("/files/*path/x".to_string(), Method::GET, TestMultiPartsHandler);
+    assert!(tab.finish().is_err());
+}
+
+#[test]
+fn test_error_page() {
+    let mut tab: RouterTab<TcpSocket, AsyncWaitsHandle, GatewayContext, TestMultiPartsHandler> = RouterTab::new();
+    tab.add("/user/:id<int>".to_string(), Method::GET, TestMultiPartsHandler);
+    tab.add("/user/:id<int>".to_string(), Method::DELETE, TestMultiPartsHandler);
+    assert!(tab.finish().is_ok());
+    assert_eq!(tab.allow_methods("/user/1"), vec!["DELETE".to_string(), "GET".to_string()]);
+    assert!(tab.allow_methods("/user/x").is_empty());
+
+    assert_eq!(render_template("<h1>{status} {reason}</h1><p>{path}</p>", StatusCode::NOT_FOUND, "/<x>"),
+               "<h1>404 Not Found</h1><p>/&lt;x&gt;</p>");
+
+    let mut pages: ErrorPages<TcpSocket, AsyncWaitsHandle> = ErrorPages::new();
+    assert!(pages.set(StatusCode::OK, ErrorPage::Template("text/html".to_string(), "".to_string())).is_err());
+    assert!(pages.set(StatusCode::NOT_FOUND, ErrorPage::Template("text/html".to_string(), "{status}:{path}".to_string())).is_ok());
+    assert_eq!(pages.render(StatusCode::NOT_FOUND, "/a"), ("text/html".to_string(), b"404:/a".to_vec()));
+    assert_eq!(pages.render(StatusCode::PAYLOAD_TOO_LARGE, "/a").1, b"413 Payload Too Large".to_vec());
+}
+
+#[test]
+fn test_virtual_hosts() {
+    fn new_host(page: &str) -> VirtualHost<TcpSocket, AsyncWaitsHandle, TestMultiPartsHandler> {
+        let mut host = VirtualHost::with(HttpRoute::new());
+        host.set_error_template(StatusCode::NOT_FOUND, "text/plain", page).unwrap();
+        host
+    }
+
+    let mut tab = VirtualHostTab::new();
+    tab.add("www.example.com", new_host("www")).unwrap();
+    tab.add("*.example.com", new_host("sub")).unwrap();
+    tab.add("*.api.example.com", new_host("api")).unwrap();
+    tab.add_alias("example.net", "www.example.com").unwrap();
+    let page = |tab: &VirtualHostTab<TcpSocket, AsyncWaitsHandle, TestMultiPartsHandler>, name: &str| {
+        tab.error_page(Some(name), StatusCode::NOT_FOUND, "/").1
+    };
+    assert_eq!(page(&tab, "WWW.example.com:8080"), b"www".to_vec());
+    assert_eq!(page(&tab, "a.b.example.com"), b"sub".to_vec());
+    assert_eq!(page(&tab, "v1.api.example.com"), b"api".to_vec());
+    assert_eq!(page(&tab, "example.net"), b"www".to_vec());
+    assert!(tab.get("example.com").is_none());
+    tab.set_default(new_host("default")).unwrap();
+    assert_eq!(page(&tab, "example.com"), b"default".to_vec());
+
+    tab.add_redirect("example.com", HostRedirect::to_host("www.example.com")).unwrap();
+    tab.add_redirect("*", HostRedirect::to_https(8443)).unwrap();
+    assert_eq!(tab.redirect("example.com:8080", false, "/a?b=1"),
+               Some((StatusCode::MOVED_PERMANENTLY, "http://www.example.com:8080/a?b=1".to_string())));
+    assert_eq!(tab.redirect("www.example.com", false, "/"),
+               Some((StatusCode::MOVED_PERMANENTLY, "https://www.example.com:8443/".to_string())));
+    assert_eq!(tab.redirect("www.example.com", true, "/"), None);
+}
+
+#[test]
+fn test_proxy() {
+    assert_eq!(PathRewrite::StripPrefix("/api".to_string()).rewrite("/api/users"), "/users");
+    assert_eq!(PathRewrite::StripPrefix("/api".to_string()).rewrite("/api"), "/");
+    assert_eq!(PathRewrite::StripPrefix("/api".to_string()).rewrite("/static/a.js"), "/static/a.js");
+    assert_eq!(PathRewrite::AddPrefix("/v1/".to_string()).rewrite("/users"), "/v1/users");
+    let rewrite = PathRewrite::Replace(Regex::new(r"^/user/(\d+)$").unwrap(), "/users?id=$1".to_string());
+    assert_eq!(rewrite.rewrite("/user/42"), "/users?id=42");
+
+    let rt = MultiTaskPool::new("Test-Http-Proxy".to_string(), 2, 1024 * 1024, 10, None).startup(false);
+    assert!(ReverseProxy::new(rt.clone(), &[], BalanceStrategy::RoundRobin).is_err());
+    let mut proxy = ReverseProxy::new(rt, &[("127.0.0.1:18080", 2), ("127.0.0.1:18081", 1)], BalanceStrategy::LeastConnections).unwrap();
+    assert_eq!(proxy.is_available("127.0.0.1:18080"), Some(true));
+    assert_eq!(proxy.is_available("127.0.0.1:18082"), None);
+    assert!(proxy.set_request_header("x-real-ip", None).is_ok());
+    assert!(proxy.set_response_header("bad header", Some("1")).is_err());
+    assert!(proxy.set_passive_check(2, 1000).is_ok());
+}
+
+#[test]
+fn test_access_log() {
+    let time = std::time::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    assert_eq!(format_clf_time(time), "09/Sep/2001:01:46:40 +0000");
+    assert_eq!(format_iso_time(time + Duration::from_millis(5)), "2001-09-09T01:46:40.005Z");
+
+    let record = AccessRecord {
+        time,
+        remote: "127.0.0.1".to_string(),
+        method: "GET".to_string(),
+        url: "/index.html?a=\"1\"".to_string(),
+        version: "HTTP/1.1".to_string(),
+        host: Some("www.example.com".to_string()),
+        status: 200,
+        size: 0,
+        user_agent: Some("curl/7.64".to_string()),
+        referer: None,
+        request_id: "abc".to_string(),
+        duration: Duration::from_micros(1500),
+    };
+    assert_eq!(AccessLogFormat::Common.format(&record),
+               "127.0.0.1 - - [09/Sep/2001:01:46:40 +0000] \"GET /index.html?a=\\\"1\\\" HTTP/1.1\" 200 -");
+    assert_eq!(AccessLogFormat::Combined.format(&record),
+               "127.0.0.1 - - [09/Sep/2001:01:46:40 +0000] \"GET /index.html?a=\\\"1\\\" HTTP/1.1\" 200 - \"-\" \"curl/7.64\"");
+    let json: serde_json::Value = serde_json::from_str(&AccessLogFormat::Json.format(&record)).unwrap();
+    assert_eq!(json["request_id"], "abc");
+    assert_eq!(json["duration_ms"], 1.5);
+
+    let mut logger = AccessLogger::new(AccessLogFormat::Common, AccessLogOutput::Log(String::new())).unwrap();
+    logger.set_sample(2);
+    logger.add_exclude("^/health$").unwrap();
+    assert!(logger.add_include("(").is_err());
+    assert!(!logger.is_match("/health", 200));
+    assert!(logger.is_match("/a", 200));
+    assert!(!logger.is_match("/a", 200));
+    assert!(logger.is_match("/a", 404));
+}
+
+#[test]
+fn test_accept_encoding() {
+    assert_eq!(parse_accept_encoding("gzip;q=0.5, BR"), vec![("gzip".to_string(), 0.5), ("br".to_string(), 1.0)]);
+    assert_eq!(negotiate_encoding("gzip, deflate, br", SUPPORTED_ENCODINGS), Some("br"));
+    assert_eq!(negotiate_encoding("gzip;q=1.0, br;q=0.8", SUPPORTED_ENCODINGS), Some("gzip"));
+    assert_eq!(negotiate_encoding("br;q=0, *;q=0.5", SUPPORTED_ENCODINGS), Some("gzip"));
+    assert_eq!(negotiate_encoding("gzip;q=0.5, identity", SUPPORTED_ENCODINGS), None);
+    assert_eq!(negotiate_encoding("compress", SUPPORTED_ENCODINGS), None);
+
+    let dir = std::env::temp_dir().join("pi_http_test_precompressed");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("app.js");
+    std::fs::write(&file, b"plain").unwrap();
+    std::fs::write(dir.join("app.js.gz"), b"gzip").unwrap();
+    assert_eq!(precompressed_path(&file, None), None);
+    assert_eq!(precompressed_path(&file, Some("br")), None);
+    assert_eq!(precompressed_path(&file, Some("gzip, br")), Some(("gzip", dir.join("app.js.gz"))));
+    std::fs::write(dir.join("app.js.br"), b"br").unwrap();
+    assert_eq!(precompressed_path(&file, Some("gzip, br")), Some(("br", dir.join("app.js.br"))));
+    assert!(is_precompressed_file(&dir.join("app.js.br")));
+    assert!(!is_precompressed_file(&file));
+
+    //批量文件要求全部替换时，只要有非空文件没有预压缩文件就不替换
+    let other = dir.join("app.css");
+    std::fs::write(&other, b"style").unwrap();
+    let files = vec![(5, file.clone()), (5, other.clone())];
+    assert_eq!(precompressed_files(files.clone(), "gzip", true), (files.clone(), false));
+    assert_eq!(precompressed_files(files.clone(), "gzip", false), (vec![(4, dir.join("app.js.gz")), (5, other.clone())], true));
+    std::fs::write(dir.join("app.css.gz"), b"gz").unwrap();
+    assert_eq!(precompressed_files(files.clone(), "gzip", true), (vec![(4, dir.join("app.js.gz")), (2, dir.join("app.css.gz"))], true));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_range_load() {
+    assert_eq!(parse_ranges("bytes=0-99", 1000), Some(vec![(0, 99)]));
+    assert_eq!(parse_ranges("bytes=500-", 1000), Some(vec![(500, 999)]));
+    assert_eq!(parse_ranges("bytes=-500", 1000), Some(vec![(500, 999)]));
+    assert_eq!(parse_ranges("bytes=-5000", 1000), Some(vec![(0, 999)]));
+    assert_eq!(parse_ranges("bytes=0-0, 900-2000", 1000), Some(vec![(0, 0), (900, 999)]));
+    assert_eq!(parse_ranges("bytes=900-999, 0-9", 1000), Some(vec![(0, 9), (900, 999)]));
+    assert_eq!(parse_ranges("bytes=0-99, 50-149, 150-199, -10", 1000), Some(vec![(0, 199), (990, 999)]));
+    assert_eq!(parse_ranges(&("bytes=".to_string() + &vec!["0-"; 64].join(",")), 1000), Some(vec![(0, 999)]));
+    assert_eq!(parse_ranges("bytes=1000-", 1000), Some(vec![]));
+    assert_eq!(parse_ranges("bytes=5-1", 1000), None);
+    assert_eq!(parse_ranges("items=0-1", 1000), None);
+
+    let time = std::time::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    assert!(if_range_matches("\"abc\"", Some("\"abc\""), None));
+    assert!(!if_range_matches("\"abc\"", Some("W/\"abc\""), None));
+    assert!(!if_range_matches("W/\"abc\"", Some("W/\"abc\""), None));
+    assert!(if_range_matches("Sun, 09 Sep 2001 01:46:40 GMT", None, Some(time)));
+    assert!(!if_range_matches("Sun, 09 Sep 2001 01:46:41 GMT", None, Some(time)));
+
+    let body = multipart_body(&[(0, 1), (4, 4)], &[b"ab", b"e"], 5, Some("text/plain"), "XYZ");
+    assert_eq!(String::from_utf8(body).unwrap(),
+               "--XYZ\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/5\r\n\r\nab\r\n--XYZ\r\nContent-Type: text/plain\r\nContent-Range: bytes 4-4/5\r\n\r\ne\r\n--XYZ--\r\n");
+}
+
+#[test]
+fn test_resumable_upload() {
+    assert_eq!(parse_upload_metadata("filename YS9iLnR4dA==, type dGV4dC9wbGFpbg==,is_public"),
+               Some(vec![("filename".to_string(), "a/b.txt".to_string()),
+                         ("type".to_string(), "text/plain".to_string()),
+                         ("is_public".to_string(), String::new())]));
+    assert_eq!(parse_upload_metadata(""), Some(vec![]));
+    assert_eq!(parse_upload_metadata("filename !!!"), None);
+
+    assert_eq!(parse_upload_checksum("adler32 EeYDmA=="), Some((ChecksumAlgorithm::Adler32, 0x11e60398)));
+    assert_eq!(parse_upload_checksum("CRC32 y/Q5Jg=="), Some((ChecksumAlgorithm::Crc32, 0xcbf43926)));
+    assert_eq!(parse_upload_checksum("md5 y/Q5Jg=="), None);
+    assert_eq!(parse_upload_checksum("crc32 AAAA"), None);
+
+    let mut checksum = UploadChecksum::new(ChecksumAlgorithm::Adler32);
+    checksum.update(b"Wiki");
+    checksum.update(b"pedia");
+    assert_eq!(checksum.sum(), 0x11e60398);
+    let mut checksum = UploadChecksum::new(ChecksumAlgorithm::Crc32);
+    checksum.update(b"12345");
+    checksum.update(b"6789");
+    assert_eq!(checksum.sum(), 0xcbf43926);
+}
+
+#[test]
+fn test_stream_multi_parts() {
+    assert_eq!(sanitize_filename("C:\\Users\\a\\photo.jpg"), Some("photo.jpg".to_string()));
+    assert_eq!(sanitize_filename("../../etc/passwd"), Some("passwd".to_string()));
+    assert_eq!(sanitize_filename("a<b>:c?.txt"), Some("abc.txt".to_string()));
+    assert_eq!(sanitize_filename(".."), None);
+    assert_eq!(sanitize_filename(""), None);
+    assert_eq!(sanitize_filename(&"x".repeat(300)).unwrap().len(), 255);
+    assert_eq!(part_filename_key("content"), "content.filename");
+
+    let body = "preamble\r\n--XYZ\r\n\
+                Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+                hello\r\n--XYZ\r\n\
+                Content-Disposition: form-data; name=\"content\"; filename=\"../a.bin\"\r\n\
+                Content-Type: application/octet-stream\r\n\r\n\
+                0123456789\r\n--XYZ--\r\n";
+    for block_size in &[1, 3, 7, body.len()] {
+        let mut parser = MultiPartsParser::new("XYZ");
+        let mut events = Vec::new();
+        for bin in body.as_bytes().chunks(*block_size) {
+            events.append(&mut parser.feed(bin).unwrap());
+        }
+        assert!(parser.is_finished());
+
+        let mut merged: Vec<PartEvent> = Vec::new();
+        for event in events {
+            if let PartEvent::Data(data) = &event {
+                if let Some(PartEvent::Data(last)) = merged.last_mut() {
+                    last.extend_from_slice(&data[..]);
+                    continue;
+                }
+            }
+            merged.push(event);
+        }
+        assert_eq!(merged, vec![
+            PartEvent::Begin(PartHead { name: "title".to_string(), is_file: false, filename: None, content_type: None }),
+            PartEvent::Data(b"hello".to_vec()),
+            PartEvent::End,
+            PartEvent::Begin(PartHead { name: "content".to_string(), is_file: true, filename: Some("a.bin".to_string()), content_type: Some("application/octet-stream".to_string()) }),
+            PartEvent::Data(b"0123456789".to_vec()),
+            PartEvent::End,
+        ]);
+    }
+
+    let mut parser = MultiPartsParser::new("XYZ");
+    assert!(parser.feed(b"--XYZ\r\nContent-Type: text/plain\r\n\r\n").is_err());
+}
+
+#[test]
+fn test_static_cache() {
+    let now = std::time::SystemTime::now();
+    let cache = StaticCache::new(30, 10);
+    assert!(cache.insert(None, 60, now, mime::TEXT_PLAIN, false, Atom::from("/a/1"), Arc::new(vec![0; 31])).is_err());
+    cache.insert(None, 60, now, mime::TEXT_PLAIN, false, Atom::from("/a/1"), Arc::new(vec![0; 10])).unwrap();
+    cache.insert(None, 60, now, mime::TEXT_PLAIN, false, Atom::from("/a/2"), Arc::new(vec![0; 10])).unwrap();
+    cache.insert(Some(Atom::from("user")), 60, now, mime::TEXT_PLAIN, false, Atom::from("/b/1"), Arc::new(vec![0; 10])).unwrap();
+    if let CacheRes::Cache(_) = cache.get(None, Atom::from("/a/1")) {} else { panic!("cache miss") }
+    if let CacheRes::Empty = cache.get(None, Atom::from("/c")) {} else { panic!("cache hit") }
+
+    //最近最少使用的/a/2被淘汰
+    cache.insert(None, 60, now, mime::TEXT_PLAIN, false, Atom::from("/b/2"), Arc::new(vec![0; 10])).unwrap();
+    assert!(!cache.contains(None, Atom::from("/a/2")));
+    assert!(cache.contains(None, Atom::from("/a/1")));
+    assert_eq!(cache.hits(None, Atom::from("/a/1")), Some(1));
+    assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 1, evictions: 1, size: 30, len: 3 });
+
+    assert_eq!(cache.invalidate_prefix("/b/"), 2);
+    assert_eq!(cache.len(), 1);
+    assert_eq!(cache.size(), 10);
+
+    let dir = std::env::temp_dir().join("pi_http_test_static_cache");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("watch.txt");
+    std::fs::write(&file, b"watch").unwrap();
+    let modified = std::fs::metadata(&file).unwrap().modified().unwrap();
+    let key = Atom::from(file.to_str().unwrap());
+    cache.set_watch(true);
+    cache.insert(None, 60, modified, mime::TEXT_PLAIN, false, key.clone(), Arc::new(b"watch".to_vec())).unwrap();
+    assert_eq!(cache.collect_changed(), 0);
+    std::fs::remove_file(&file).unwrap();
+    assert_eq!(cache.collect_changed(), 1);
+    assert!(!cache.contains(None, key));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_preconditions() {
+    let sign = content_sign(b"hello");
+    assert_eq!(sign, content_sign(b"hello"));
+    assert_ne!(sign, content_sign(b"hellp"));
+    assert_eq!(format_etag(0xabc, false), "\"abc\"");
+    assert_eq!(format_etag(0xabc, true), "W/\"abc\"");
+
+    assert!(etag_list_matches("\"a\", \"abc\"", "\"abc\"", true));
+    assert!(etag_list_matches("W/\"abc\"", "\"abc\"", false));
+    assert!(!etag_list_matches("W/\"abc\"", "\"abc\"", true));
+    assert!(!etag_list_matches("\"abc\"", "W/\"abc\"", true));
+    assert!(etag_list_matches("*", "W/\"abc\"", true));
+
+    let etag = Some("\"abc\"");
+    let time = std::time::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    let before = "Sun, 09 Sep 2001 01:46:39 GMT";
+    let after = "Sun, 09 Sep 2001 01:46:41 GMT";
+    let check = |pairs: &[(&'static str, &str)], method: Method| {
+        let mut headers = HeaderMap::new();
+        for (key, value) in pairs {
+            headers.insert(*key, value.parse().unwrap());
+        }
+        check_preconditions(&headers, &method, etag, Some(time))
+    };
+
+    assert_eq!(check(&[], Method::GET), Precondition::Pass);
+    assert_eq!(check(&[("if-match", "\"xyz\"")], Method::GET), Precondition::Failed);
+    //If-Match通过时忽略If-Unmodified-Since
+    assert_eq!(check(&[("if-match", "\"abc\""), ("if-unmodified-since", before)], Method::PUT), Precondition::Pass);
+    assert_eq!(check(&[("if-unmodified-since", before)], Method::PUT), Precondition::Failed);
+    assert_eq!(check(&[("if-none-match", "W/\"abc\"")], Method::GET), Precondition::NotModified);
+    assert_eq!(check(&[("if-none-match", "\"abc\"")], Method::PUT), Precondition::Failed);
+    //If-None-Match不匹配时忽略If-Modified-Since
+    assert_eq!(check(&[("if-none-match", "\"xyz\""), ("if-modified-since", after)], Method::GET), Precondition::Pass);
+    assert_eq!(check(&[("if-modified-since", after)], Method::GET), Precondition::NotModified);
+    assert_eq!(check(&[("if-modified-since", before)], Method::GET), Precondition::Pass);
+    assert_eq!(check(&[("if-modified-since", after)], Method::POST), Precondition::Pass);
+}
+
+#[test]
+fn test_auth() {
+    //测试Http基础认证
+    assert_eq!(parse_basic_credentials("Basic dXNlcjpwYTpzcw=="), Some(("user".to_string(), "pa:ss".to_string())));
+    assert_eq!(parse_basic_credentials("basic   dXNlcjpwYTpzcw== "), Some(("user".to_string(), "pa:ss".to_string())));
+    assert_eq!(parse_basic_credentials("Bearer dXNlcjpwYTpzcw=="), None);
+    assert_eq!(parse_basic_credentials("Basic !!!"), None);
+    let store = MemoryCredentialStore::new();
+    assert!(store.add_user("user", "pa:ss").is_ok());
+    assert_eq!(store.verify("user", "pa:ss"), Some("user".to_string()));
+    assert_eq!(store.verify("user", "pass"), None);
+    assert_eq!(store.verify("other", "pa:ss"), None);
+    assert!(store.remove_user("user"));
+    assert_eq!(store.verify("user", "pa:ss"), None);
+
+    //测试Jwt令牌认证
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    let mut jwt = JwtAuth::new("test");
+    jwt.set_audience(Some("api".to_string()));
+    jwt.add_hs256_key(Some("k1"), b"secret");
+    let token = sign_hs256_token(Some("k1"), &serde_json::json!({"sub": "alice", "aud": ["web", "api"], "exp": now + 60, "nbf": now}), b"secret");
+    let principal = jwt.verify(&token).unwrap();
+    assert_eq!(principal.name(), "alice");
+    assert_eq!(principal.scheme(), AuthScheme::Bearer);
+    assert!(jwt.verify(&sign_hs256_token(Some("k2"), &serde_json::json!({"sub": "alice", "aud": "api"}), b"secret")).is_err());
+    assert!(jwt.verify(&sign_hs256_token(None, &serde_json::json!({"sub": "alice", "aud": "api"}), b"other")).is_err());
+    assert!(jwt.verify(&sign_hs256_token(None, &serde_json::json!({"sub": "alice", "aud": "web"}), b"secret")).is_err());
+    assert!(jwt.verify(&sign_hs256_token(None, &serde_json::json!({"sub": "alice", "aud": "api", "exp": now - 120}), b"secret")).is_err());
+    assert!(jwt.verify(&sign_hs256_token(None, &serde_json::json!({"sub": "alice", "aud": "api", "nbf": now + 120}), b"secret")).is_err());
+    assert!(jwt.verify(&sign_hs256_token(None, &serde_json::json!({"aud": "api"}), b"secret")).is_err());
+    //不允许未签名的令牌
+    let parts: Vec<&str> = token.split('.').collect();
+    let none_token = format!("{}.{}.", base64::encode_config(b"{\"alg\":\"none\"}", base64::URL_SAFE_NO_PAD), parts[1]);
+    assert!(jwt.verify(&none_token).is_err());
+
+    //测试从JWKS文件加载密钥
+    let path = std::env::temp_dir().join("test_auth_jwks.json");
+    std::fs::write(&path, serde_json::json!({"keys": [
+        {"kty": "oct", "kid": "k2", "alg": "HS256", "k": base64::encode_config(b"secret2", base64::URL_SAFE_NO_PAD)},
+        {"kty": "EC", "kid": "k3", "crv": "P-256", "x": "", "y": ""}
+    ]}).to_string()).unwrap();
+    assert_eq!(jwt.load_jwks(&path).unwrap(), 1);
+    assert_eq!(jwt.keys_len(), 1);
+    assert!(jwt.verify(&token).is_err());
+    assert!(jwt.verify(&sign_hs256_token(Some("k2"), &serde_json::json!({"sub": "bob", "aud": "api"}), b"secret2")).is_ok());
+    std::fs::write(&path, "{\"keys\": []}").unwrap();
+    assert!(jwt.load_jwks(&path).is_err());
+    assert_eq!(jwt.keys_len(), 1);
+    let _ = std::fs::remove_file(&path);
+
+    //测试Hmac签名请求认证
+    let hmac = HmacAuth::new();
+    hmac.add_key("app", b"secret");
+    let timestamp = now.to_string();
+    let message = string_to_sign("post", "/api?x=1", &timestamp, "n1", &body_digest(b"{}"));
+    assert!(message.starts_with("POST\n/api?x=1\n"));
+    let sign = sign_hmac(b"secret", &message);
+    assert!(hmac.verify("app", &timestamp, "n1", &message, &sign).is_ok());
+    //重放的随机数
+    assert!(hmac.verify("app", &timestamp, "n1", &message, &sign).is_err());
+    //错误的签名不会记录随机数
+    assert!(hmac.verify("app", &timestamp, "n2", &message, &sign_hmac(b"other", &message)).is_err());
+    let message = string_to_sign("POST", "/api?x=1", &timestamp, "n2", &body_digest(b"{}"));
+    assert!(hmac.verify("app", &timestamp, "n2", &message, &sign_hmac(b"secret", &message)).is_ok());
+    assert!(hmac.verify("other", &timestamp, "n3", &message, &sign_hmac(b"secret", &message)).is_err());
+    //超出时间偏差的时间戳
+    let timestamp = (now - 600).to_string();
+    let message = string_to_sign("POST", "/api", &timestamp, "n4", &body_digest(b""));
+    assert!(hmac.verify("app", &timestamp, "n4", &message, &sign_hmac(b"secret", &message)).is_err());
+    //未过期的随机数达到最大数量时拒绝新的随机数，已记录的随机数仍不允许重放
+    let mut hmac = HmacAuth::new();
+    hmac.set_max_nonces(1);
+    hmac.add_key("app", b"secret");
+    let timestamp = now.to_string();
+    let message = string_to_sign("GET", "/api", &timestamp, "n5", &body_digest(b""));
+    assert!(hmac.verify("app", &timestamp, "n5", &message, &sign_hmac(b"secret", &message)).is_ok());
+    let other = string_to_sign("GET", "/api", &timestamp, "n6", &body_digest(b""));
+    assert!(hmac.verify("app", &timestamp, "n6", &other, &sign_hmac(b"secret", &other)).is_err());
+    assert!(hmac.verify("app", &timestamp, "n5", &message, &sign_hmac(b"secret", &message)).is_err());
+}
+
+#[test]
+fn test_cookie_session() {
+    //测试Cookie解析
+    let cookies = parse_cookies("a=1; b=\"x y\";c=; =bad; noeq; a=2");
+    assert_eq!(cookies, vec![("a".to_string(), "1".to_string()), ("b".to_string(), "x y".to_string()), ("c".to_string(), "".to_string())]);
+
+    //测试Set-Cookie
+    let mut cookie = SetCookie::new("sid", "abc").unwrap();
+    cookie.path("/").max_age(60).http_only(true).same_site(SameSite::Lax);
+    assert_eq!(cookie.to_string(), "sid=abc; Path=/; Max-Age=60; HttpOnly; SameSite=Lax");
+    let mut cookie = SetCookie::new("sid", "abc").unwrap();
+    cookie.same_site(SameSite::None);
+    assert_eq!(cookie.to_string(), "sid=abc; Secure; SameSite=None");
+    assert_eq!(SetCookie::removal("sid").unwrap().to_string(), "sid=; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT");
+    assert!(SetCookie::new("s id", "abc").is_err());
+    assert!(SetCookie::new("sid", "a;b").is_err());
+
+    //测试会话
+    let id = generate_session_id().unwrap();
+    assert_eq!(id.len(), 32);
+    assert_ne!(id, generate_session_id().unwrap());
+    let mut session = Session::new(id.clone());
+    assert!(session.is_new() && !session.is_changed());
+    session.set("user", serde_json::json!("alice"));
+    assert!(session.is_changed());
+
+    //测试内存会话存储
+    let store = MemorySessionStore::new();
+    let value = store.save(&session, 60).unwrap();
+    assert_eq!(value, id);
+    let mut loaded = store.load(&value).unwrap();
+    assert!(!loaded.is_new() && !loaded.is_changed());
+    assert_eq!(loaded.get("user"), Some(&serde_json::json!("alice")));
+    loaded.regenerate().unwrap();
+    assert_eq!(loaded.previous_id(), Some(id.as_str()));
+    assert_ne!(loaded.id(), id.as_str());
+    store.save(&session, 0).unwrap();
+    assert!(store.load(&value).is_none());
+    assert_eq!(store.collect(), 1);
+    assert_eq!(store.len(), 0);
+
+    //测试内存会话存储的最大会话数量，已满时会先移除已过期的会话
+    let store = MemorySessionStore::with_max_sessions(1);
+    let other = Session::new(generate_session_id().unwrap());
+    store.save(&session, 0).unwrap();
+    store.save(&other, 60).unwrap();
+    assert_eq!(store.len(), 1);
+    assert!(store.save(&session, 60).is_err());
+    assert!(store.save(&other, 120).is_ok());
+
+    //测试签名和加密的Cookie会话存储
+    let stores = vec![CookieSessionStore::signed(b"secret"), CookieSessionStore::encrypted(&[7u8; 32]).unwrap()];
+    for store in stores {
+        let value = store.save(&session, 60).unwrap();
+        assert!(SetCookie::new("sid", &value).is_ok());
+        let loaded = store.load(&value).unwrap();
+        assert_eq!(loaded.id(), id.as_str());
+        assert_eq!(loaded.get("user"), Some(&serde_json::json!("alice")));
+        let mut tampered = value.clone().into_bytes();
+        let index = tampered.len() / 2;
+        tampered[index] = if tampered[index] == b'A' { b'B' } else { b'A' };
+        assert!(store.load(&String::from_utf8(tampered).unwrap()).is_none());
+        assert!(store.load(&store.save(&session, 0).unwrap()).is_none());
+    }
+    assert!(CookieSessionStore::encrypted(b"short").is_err());
+}
+
+#[test]
+fn test_rate_limit() {
+    //测试Ip地址段
+    let range: IpRange = "10.1.2.3/8".parse().unwrap();
+    assert!(range.contains(&"10.255.0.1".parse().unwrap()));
+    assert!(!range.contains(&"11.0.0.1".parse().unwrap()));
+    assert!(range.contains(&"::ffff:10.0.0.1".parse().unwrap()));
+    assert!(!range.contains(&"::1".parse().unwrap()));
+    let range: IpRange = "fd00::/8".parse().unwrap();
+    assert!(range.contains(&"fd12::1".parse().unwrap()));
+    assert!("0.0.0.0/0".parse::<IpRange>().unwrap().contains(&"1.2.3.4".parse().unwrap()));
+    assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+    assert!("10.0.0/8".parse::<IpRange>().is_err());
+
+    //测试客户端地址
+    let trusted = vec!["10.0.0.0/8".parse::<IpRange>().unwrap(), "127.0.0.1".parse::<IpRange>().unwrap()];
+    let mut headers = HeaderMap::new();
+    headers.insert("x-forwarded-for", "1.1.1.1, 2.2.2.2, 10.0.0.2".parse().unwrap());
+    assert_eq!(client_ip(&headers, "127.0.0.1".parse().unwrap(), &trusted).to_string(), "2.2.2.2");
+    assert_eq!(client_ip(&headers, "3.3.3.3".parse().unwrap(), &trusted).to_string(), "3.3.3.3");
+    assert_eq!(client_ip(&headers, "127.0.0.1".parse().unwrap(), &[]).to_string(), "127.0.0.1");
+    headers.insert("x-forwarded-for", "bad, 10.0.0.3".parse().unwrap());
+    assert_eq!(client_ip(&headers, "10.0.0.1".parse().unwrap(), &trusted).to_string(), "10.0.0.3");
+
+    //测试代理转发的客户端地址
+    headers.insert("x-forwarded-for", "1.1.1.1, 2.2.2.2".parse().unwrap());
+    assert_eq!(forwarded_for(&headers, "127.0.0.1".parse().unwrap(), &trusted), "1.1.1.1, 2.2.2.2, 127.0.0.1");
+    assert_eq!(forwarded_for(&headers, "3.3.3.3".parse().unwrap(), &trusted), "3.3.3.3");
+    assert_eq!(forwarded_for(&HeaderMap::new(), "::ffff:10.0.0.1".parse().unwrap(), &trusted), "10.0.0.1");
+
+    //测试令牌桶
+    let now = Instant::now();
+    let limit = RateLimit::new(RateLimitAlgorithm::TokenBucket, 2, Duration::from_secs(10));
+    let decision = limit.check_at("a", now);
+    assert!(decision.is_allowed);
+    assert_eq!((decision.limit, decision.remaining, decision.reset), (2, 1, 5));
+    assert!(limit.check_at("a", now).is_allowed);
+    let decision = limit.check_at("a", now);
+    assert!(!decision.is_allowed);
+    assert_eq!((decision.remaining, decision.retry_after), (0, 5));
+    assert!(limit.check_at("b", now).is_allowed);
+    assert!(!limit.check_at("a", now + Duration::from_secs(4)).is_allowed);
+    assert!(limit.check_at("a", now + Duration::from_secs(6)).is_allowed);
+    assert_eq!(limit.len(), 2);
+    assert_eq!(limit.collect(), 0);
+
+    //测试滑动窗口
+    let limit = RateLimit::new(RateLimitAlgorithm::SlidingWindow, 2, Duration::from_secs(10));
+    assert!(limit.check_at("a", now).is_allowed);
+    assert!(limit.check_at("a", now + Duration::from_secs(1)).is_allowed);
+    let decision = limit.check_at("a", now + Duration::from_secs(2));
+    assert!(!decision.is_allowed);
+    assert_eq!(decision.retry_after, 8);
+    //上一个窗口计数为2，窗口过半后允许一次请求
+    assert!(!limit.check_at("a", now + Duration::from_secs(14)).is_allowed);
+    assert!(limit.check_at("a", now + Duration::from_secs(15)).is_allowed);
+    assert!(!limit.check_at("a", now + Duration::from_secs(16)).is_allowed);
+    assert!(limit.check_at("a", now + Duration::from_secs(40)).is_allowed);
+
+    //测试限流键数量达到限制后淘汰最早的限流键
+    let mut limit = RateLimit::new(RateLimitAlgorithm::TokenBucket, 2, Duration::from_secs(10));
+    limit.set_max_keys(4);
+    for index in 0..4 {
+        assert!(limit.check_at(format!("key{}", index).as_str(), now).is_allowed);
+    }
+    for index in 4..100 {
+        assert!(limit.check_at(format!("key{}", index).as_str(), now + Duration::from_secs(1)).is_allowed);
+    }
+    assert_eq!(limit.len(), 4);
+    //最近的限流键仍然保留
+    assert!(limit.check_at("key99", now + Duration::from_secs(1)).is_allowed);
+    assert!(!limit.check_at("key99", now + Duration::from_secs(1)).is_allowed);
+    //被淘汰的限流键重新计数，并淘汰当前最早的限流键
+    assert!(limit.check_at("key0", now + Duration::from_secs(1)).is_allowed);
+    assert!(limit.check_at("key0", now + Duration::from_secs(1)).is_allowed);
+    assert!(!limit.check_at("key0", now + Duration::from_secs(1)).is_allowed);
+    assert!(!limit.check_at("key99", now + Duration::from_secs(1)).is_allowed);
+    assert_eq!(limit.len(), 4);
+    //下一次回收时，已恢复的限流键被回收
+    assert!(limit.check_at("new", now + Duration::from_secs(21)).is_allowed);
+    assert_eq!(limit.len(), 1);
+}
+
+#[test]
+fn test_gray_route() {
+    assert_eq!(gray_bucket("alice"), gray_bucket("alice"));
+    assert!(gray_bucket("alice") < 100);
+
+    let mut rules = GrayRules::new(None);
+    rules.add_rule(GrayRule::new(GrayMatcher::Header("x-gray".to_string(), "beta".to_string()), 1));
+    rules.add_rule(GrayRule::new(GrayMatcher::Cookie("channel".to_string(), "canary".to_string()), 2));
+    rules.add_rule(GrayRule::new(GrayMatcher::IpRange(vec!["192.168.0.0/16".parse().unwrap()]), 3));
+    rules.add_rule(GrayRule::new(GrayMatcher::Percent(GrayUserKey::Principal, 200), 4));
+    assert_eq!(rules.rules()[3].matcher(), &GrayMatcher::Percent(GrayUserKey::Principal, 100));
+    assert!(rules.is_valid(3) && !rules.is_valid(5));
+
+    let ip = "10.0.0.1".parse().unwrap();
+    let mut headers = HeaderMap::new();
+    let mut cookies = XHashMap::default();
+    assert_eq!(rules.select(&headers, None, None, &ip), None);
+    assert_eq!(rules.select(&headers, None, Some("alice"), &ip), Some(4));
+    assert_eq!(rules.select(&headers, None, None, &"192.168.1.1".parse().unwrap()), Some(3));
+    cookies.insert("channel".to_string(), "canary".to_string());
+    assert_eq!(rules.select(&headers, Some(&cookies), None, &"192.168.1.1".parse().unwrap()), Some(2));
+    headers.insert("x-gray", "beta".parse().unwrap());
+    assert_eq!(rules.select(&headers, Some(&cookies), None, &ip), Some(1));
+
+    //百分比为0时不匹配任何用户
+    let mut rules = GrayRules::new(Some(7));
+    rules.add_rule(GrayRule::new(GrayMatcher::Percent(GrayUserKey::Header("x-user".to_string()), 0), 8));
+    headers.insert("x-user", "bob".parse().unwrap());
+    assert_eq!(rules.select(&headers, None, None, &ip), Some(7));
+
+    //运行时替换规则表
+    let router = GrayRouter::new(GrayRules::new(None));
+    let last = router.set_rules(rules.clone());
+    assert_eq!(last.default_gray(), None);
+    assert_eq!(router.get_rules().default_gray(), Some(7));
+    assert_eq!(*router.get_rules(), rules);
+
+    //粘滞Cookie必须签名
+    let mut router = GrayRouter::new(rules.clone());
+    assert_eq!(router.sign_sticky(7), None);
+    router.set_sticky(Some("gray".to_string()), b"secret", 60);
+    let value = router.sign_sticky(7).unwrap();
+    assert_eq!(router.verify_sticky(value.as_str()), Some(7));
+    assert_eq!(router.verify_sticky("8"), None);
+    assert_eq!(router.verify_sticky(value.replacen("7.", "8.", 1).as_str()), None);
+    let mut other = GrayRouter::new(rules.clone());
+    other.set_sticky(Some("gray".to_string()), b"other", 60);
+    assert_eq!(other.verify_sticky(value.as_str()), None);
+    router.set_sticky(Some("gray".to_string()), b"secret", 0);
+    assert_eq!(router.verify_sticky(router.sign_sticky(7).unwrap().as_str()), None);
+}
+
+#[test]
+fn test_server_config() {
+    let text = r#"
+[server]
+ip = "0.0.0.0"
+timeout = 0
+
+[[listeners]]
+port = 80
+keep_alive = 5000
+
+[listeners.limits]
+max_request_line = 4096
+max_headers = 64
+
+[[listeners]]
+port = 443
+
+[listeners.tls]
+cert = "./server.pem"
+key = "./server.key"
+alpns = "h2,http/1.1"
+
+[caches.static]
+max_size = 1048576
+max_len = 1000
+collect_interval = 0
+
+[middlewares.cors]
+type = "cors"
+methods = ["OPTIONS", "GET", "POST"]
+
+[[middlewares.cors.origins]]
+host = "msg.highapp.com"
+max_age = 10
+
+[middlewares.parser]
+type = "parser"
+min_plain_limit = 128
+
+[middlewares.limit]
+type = "rate_limit"
+algorithm = "sliding_window"
+limit = 100
+window = 1000
+keys = ["remote_addr", "header:x-api-key"]
+
+[middlewares.counter]
+type = "counter"
+name = "api"
+
+[chains]
+cors = ["cors", "counter"]
+api = ["cors", "parser", "limit", "counter"]
+
+[[hosts]]
+names = ["msg.highapp.com", "127.0.0.1"]
+default = true
+
+[[hosts.routes]]
+path = "/**"
+methods = ["OPTIONS"]
+chain = "cors"
+
+[[hosts.routes]]
+path = "/api/**"
+methods = ["get", "POST"]
+chain = "api"
+
+[[hosts.error_pages]]
+status = 404
+template = "<h1>{status} {reason}</h1>"
+"#;
+
+    //分析Toml配置
+    let config = ServerConfig::from_toml_str(text).unwrap();
+    assert_eq!(config.server.ip, "0.0.0.0");
+    assert_eq!(config.server.timeout, None);
+    assert_eq!(config.server.event_size, 1024);
+    assert_eq!(config.listeners.len(), 2);
+    assert_eq!(config.listeners[0].keep_alive, 5000);
+    assert_eq!(config.listeners[0].limits.max_request_line, 4096);
+    assert_eq!(config.listeners[0].limits.max_header_bytes, 64 * 1024);
+    assert!(config.listeners[0].tls.is_none());
+    assert_eq!(config.listeners[1].tls.as_ref().unwrap().alpns, "h2,http/1.1");
+    assert_eq!(config.listeners[1].tls.as_ref().unwrap().session_size, 512);
+    assert_eq!(config.caches[0].name, "static");
+    assert_eq!(config.get_middleware("limit").unwrap().kind, "rate_limit");
+    assert_eq!(config.get_middleware("counter").unwrap().require_str("name").unwrap(), "api");
+    assert_eq!(config.get_chain("api").unwrap().middlewares, vec!["cors", "parser", "limit", "counter"]);
+    assert_eq!(config.hosts[0].routes[1].methods, vec![Method::GET, Method::POST]);
+    assert_eq!(config.hosts[0].error_pages[0].status, StatusCode::NOT_FOUND);
+    assert_eq!(config.hosts[0].error_pages[0].mime, "text/html");
+
+    //Json配置与Toml配置的结构相同
+    let json = ServerConfig::from_json_str(r#"{
+        "listeners": [{"port": 80}],
+        "middlewares": {"parser": {"type": "parser"}},
+        "chains": {"default": ["parser"]},
+        "hosts": [{"names": ["localhost"], "routes": [{"path": "/", "methods": ["GET"], "chain": "default"}]}]
+    }"#).unwrap();
+    let toml = ServerConfig::from_toml_str(r#"
+        [[listeners]]
+        port = 80
+        [middlewares.parser]
+        type = "parser"
+        [chains]
+        default = ["parser"]
+        [[hosts]]
+        names = ["localhost"]
+        routes = [{ path = "/", methods = ["GET"], chain = "default" }]
+    "#).unwrap();
+    assert_eq!(json, toml);
+    assert_eq!(json.listeners[0].keep_alive, 10000);
+    assert_eq!(json.listeners[0].limits, HttpLimits::default());
+
+    //配置错误包括错误字段的路径
+    let error = |text: &str| ServerConfig::from_toml_str(text).err().unwrap().to_string();
+    assert!(error(text.replace(r#"chain = "api""#, r#"chain = "apis""#).as_str()).contains(r#"path: "hosts[0].routes[1].chain""#));
+    assert!(error(text.replace(r#""parser", "limit""#, r#""parser", "limits""#).as_str()).contains(r#"path: "chains.api[2]""#));
+    assert!(error(text.replace("port = 443", "port = 80").as_str()).contains(r#"path: "listeners[1].port""#));
+    assert!(error(text.replace("keep_alive = 5000", "keep_alive = 0").as_str()).contains(r#"path: "listeners[0].keep_alive""#));
+    assert!(error(text.replace("keep_alive = 5000", "keepalive = 5000").as_str()).contains("unknown field"));
+    assert!(error(text.replace(r#"ip = "0.0.0.0""#, r#"ip = "0.0.0""#).as_str()).contains(r#"path: "server.ip""#));
+    assert!(error(text.replace(r#"path = "/api/**""#, r#"path = "/**""#).replace(r#"methods = ["get", "POST"]"#, r#"methods = ["options"]"#).as_str()).contains("duplicate route"));
+    assert!(error(text.replace(r#"methods = ["get", "POST"]"#, r#"methods = ["get", "GET"]"#).as_str()).contains(r#"path: "hosts[0].routes[1].methods[1]""#));
+    assert!(error(text.replace(r#"methods = ["get", "POST"]"#, r#"methods = ["GE T"]"#).as_str()).contains(r#"path: "hosts[0].routes[1].methods[0]""#));
+    assert!(error(text.replace(r#"key = "./server.key""#, "").as_str()).contains(r#"path: "listeners[1].tls.key""#));
+    assert!(error("listeners = []").contains(r#"path: "listeners""#));
+
+    //构建虚拟主机表，同名中间件只构建一次
+    let bootstrap = ServerBootstrap::new(config);
+    assert!(bootstrap.get_cache("static").is_some());
+
+    let mut registry = MiddlewareRegistry::<TcpSocket>::new();
+    assert!(registry.contains("cors"));
+    assert!(!registry.contains("counter"));
+    match bootstrap.build_hosts(&registry).err() {
+        None => panic!("build hosts with unknown middleware type"),
+        Some(e) => assert!(e.to_string().contains(r#"path: "middlewares.counter.type""#)),
+    }
+
+    let created = Arc::new(AtomicUsize::new(0));
+    let counter = created.clone();
+    registry.register("counter", move |config, _context| {
+        config.check_keys(&["name"])?;
+        config.require_str("name")?;
+        counter.fetch_add(1, Ordering::SeqCst);
+        let middleware: HttpMiddleware<TcpSocket> = Arc::new(CookieParser);
+        Ok(middleware)
+    });
+    let hosts = bootstrap.build_hosts(&registry).unwrap();
+    assert_eq!(created.load(Ordering::SeqCst), 1);
+    assert_eq!(hosts.size(), 2);
+    assert!(hosts.get("msg.highapp.com:80").is_some());
+    assert!(hosts.get("www.highapp.com").is_some());
+
+    //中间件选项错误
+    let bootstrap = ServerBootstrap::new(ServerConfig::from_toml_str(text.replace(r#"name = "api""#, "name = 1").as_str()).unwrap());
+    match bootstrap.build_hosts(&registry).err() {
+        None => panic!("build hosts with invalid middleware option"),
+        Some(e) => assert!(e.to_string().contains(r#"path: "middlewares.counter.name""#)),
+    }
+
+    let bootstrap = ServerBootstrap::new(ServerConfig::from_toml_str(text.replace(r#"type = "counter""#, r#"type = "port"
+handler = "gateway""#).replace(r#"name = "api""#, "").as_str()).unwrap());
+    match bootstrap.build_hosts(&registry).err() {
+        None => panic!("build hosts with undefined handler"),
+        Some(e) => assert!(e.to_string().contains(r#"path: "middlewares.counter.handler""#)),
+    }
+
+    //Http和Https监听器共享同名中间件，并可以在运行时替换灰度路由中间件的规则表
+    let gray = r#"
+        [[listeners]]
+        port = 80
+        [middlewares.gray]
+        type = "gray"
+        default = 1
+        sticky_cookie = "gray"
+        sticky_secret = "secret"
+        [[middlewares.gray.rules]]
+        gray = 2
+        percent = 100
+        [chains]
+        default = ["gray"]
+        [[hosts]]
+        names = ["localhost"]
+        routes = [{ path = "/", methods = ["GET"], chain = "default" }]
+    "#;
+    let bootstrap = ServerBootstrap::new(ServerConfig::from_toml_str(gray).unwrap());
+    assert!(bootstrap.get_middleware::<GrayRouter>("gray").is_none());
+    bootstrap.build_hosts(&MiddlewareRegistry::<TcpSocket>::new()).unwrap();
+    let router = bootstrap.get_middleware::<GrayRouter>("gray").unwrap();
+    bootstrap.build_hosts(&MiddlewareRegistry::<TlsSocket>::new()).unwrap();
+    assert!(Arc::ptr_eq(&router, &bootstrap.get_middleware::<GrayRouter>("gray").unwrap()));
+    assert!(bootstrap.get_middleware::<RateLimit>("gray").is_none());
+    assert_eq!(router.get_rules().default_gray(), Some(1));
+    router.set_rules(GrayRules::new(Some(3)));
+    assert_eq!(bootstrap.get_middleware::<GrayRouter>("gray").unwrap().get_rules().default_gray(), Some(3));
+
+    //灰度百分比超过100
+    let bootstrap = ServerBootstrap::new(ServerConfig::from_toml_str(gray.replace("percent = 100", "percent = 101").as_str()).unwrap());
+    match bootstrap.build_hosts(&MiddlewareRegistry::<TcpSocket>::new()).err() {
+        None => panic!("build hosts with invalid gray percent"),
+        Some(e) => assert!(e.to_string().contains(r#"path: "middlewares.gray.rules[0].percent""#)),
+    }
+}
+
 struct TestMultiPartsHandler;
 
 unsafe impl Send for TestMultiPartsHandler {}
@@ -533,6 +1468,59 @@ fn test_http_hosts() {
     thread::sleep(Duration::from_millis(10000000));
 }
 
+#[test]
+fn test_http_limits() {
+    //启动使用较小请求限制的Http监听器，请求超过限制时会在查找虚拟主机前被拒绝
+    let hosts = VirtualHostTab::<TcpSocket, AsyncWaitsHandle, MiddlewareChain<TcpSocket, AsyncWaitsHandle, GatewayContext>>::new();
+    let mut listener = HttpListenerFactory::<TcpSocket, _>::with_hosts(hosts, 10000);
+    listener.set_limits(HttpLimits {
+        max_request_line: 64,
+        max_header_bytes: 256,
+        max_headers: 4,
+        header_timeout: 500,
+        ..HttpLimits::default()
+    });
+    assert_eq!(listener.get_limits().max_body, None);
+
+    let mut factory = AsyncPortsFactory::<TcpSocket>::new();
+    factory.bind(38180, Box::new(listener));
+    let mut config = SocketConfig::new("0.0.0.0", factory.bind_ports().as_slice());
+    config.set_option(16384, 16384, 16384, 16);
+    let buffer = WriteBufferPool::new(10000, 10, 3).ok().unwrap();
+    if let Err(e) = SocketListener::bind(factory, buffer, config, 1024, 1024 * 1024, 1024, Some(10)) {
+        panic!("!!!> Http Listener Bind Error, reason: {:?}", e);
+    }
+    thread::sleep(Duration::from_millis(100));
+
+    //请求行过长
+    let req = format!("GET /{} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n", "a".repeat(128));
+    assert_limit_reply(req.as_bytes(), 414);
+
+    //请求头过大
+    let req = format!("GET / HTTP/1.1\r\nHost: 127.0.0.1\r\nX-Large: {}\r\n\r\n", "a".repeat(512));
+    assert_limit_reply(req.as_bytes(), 431);
+
+    //请求头过多
+    assert_limit_reply(b"GET / HTTP/1.1\r\nHost: 127.0.0.1\r\nA: 1\r\nB: 1\r\nC: 1\r\nD: 1\r\n\r\n", 431);
+
+    //读取请求头超时
+    assert_limit_reply(b"GET / HTTP/1.1\r\nHost: 127.0.0.1\r\n", 408);
+}
+
+//发送请求数据，并断言回应了指定的状态码，且回应后关闭了连接
+fn assert_limit_reply(req: &[u8], status: u16) {
+    let mut stream = TcpStream::connect("127.0.0.1:38180").unwrap();
+    stream.set_read_timeout(Some(Duration::from_millis(5000))).unwrap();
+    stream.write_all(req).unwrap();
+
+    //对端关闭连接后才会读取完成，读取超时则失败
+    let mut buf = Vec::new();
+    stream.read_to_end(&mut buf).unwrap();
+    let resp = String::from_utf8_lossy(&buf);
+    assert!(resp.starts_with(format!("HTTP/1.1 {} ", status).as_str()), "{}", resp);
+    assert!(resp.contains("Connection: close\r\n"), "{}", resp);
+}
+
 #[test]
 fn test_https_hosts() {
     //启动日志系统
@@ -29,6 +29,7 @@ struct ReadBuffer {
     buf_once:       Option<Vec<u8>>,    //临时缓冲区，用于缓存临时的大量数据
     recv_pos_once:  usize,              //临时缓冲区接收位置
     read_pos_once:  usize,              //临时缓冲区已读位置
+    read_once:      bool,               //最近一次读取是否来自临时缓冲区
 }
 
 impl ReadBuffer {
@@ -45,6 +46,7 @@ impl ReadBuffer {
             buf_once: None,
             recv_pos_once: 0,
             read_pos_once: 0,
+            read_once: false,
         }
     }
 
@@ -149,6 +151,19 @@ impl ReadBuffer {
         }
     }
 
+    //回退最近一次读取的指定长度的数据，回退的数据可以被再次读取，返回实际回退的长度
+    pub fn unread(&mut self, len: usize) -> usize {
+        if self.read_once {
+            let len = len.min(self.read_pos_once);
+            self.read_pos_once -= len;
+            len
+        } else {
+            let len = len.min(self.read_pos);
+            self.read_pos -= len;
+            len
+        }
+    }
+
     //从缓冲区的已读位置开始读取指定长度的数据
     pub fn read(&mut self, len: usize) -> Option<&[u8]> {
         //同步返回数据
@@ -160,10 +175,12 @@ impl ReadBuffer {
             if (len == 0) && (recv_pos > read_pos) {
                 //如果需要读取任意有效长度的数据，且当前临时缓冲区内有可读数据
                 self.read_pos_once += recv_pos - read_pos; //更新已读位置
+                self.read_once = true;
                 return self.window_ref_once(read_pos..recv_pos);
             } else if (len > 0) && (len <= (recv_pos - read_pos)) {
                 //如果需要读取指定有效长度的数据，且当前临时缓冲区内至少有指定有效长度的可读数据
                 self.read_pos_once += len; //更新已读位置
+                self.read_once = true;
                 return self.window_ref_once(read_pos..read_pos + len);
             }
         } else {
@@ -174,10 +191,12 @@ impl ReadBuffer {
             if (len == 0) && (recv_pos > read_pos) {
                 //如果需要读取任意有效长度的数据，且当前缓冲区内有可读数据
                 self.read_pos += recv_pos - read_pos; //更新已读位置
+                self.read_once = false;
                 return self.window_ref(read_pos..recv_pos);
             } else if (len > 0) && (len <= (recv_pos - read_pos)) {
                 //如果需要读取指定有效长度的数据，且当前缓冲区内至少有指定有效长度的可读数据
                 self.read_pos += len; //更新已读位置
+                self.read_once = false;
                 return self.window_ref(read_pos..read_pos + len);
             }
         }
@@ -643,6 +662,10 @@ impl Socket for TcpSocket {
         false
     }
 
+    fn get_alpn_protocol(&self) -> Option<&[u8]> {
+        None
+    }
+
     fn read_ready(&mut self, size: usize) -> Result<()> {
         if self.is_closed() {
             //连接已关闭，则返回错误
@@ -717,6 +740,13 @@ impl Socket for TcpSocket {
         Ok(None)
     }
 
+    fn unread(&mut self, size: usize) -> usize {
+        match self.read_buf.as_mut() {
+            None => 0,
+            Some(buf) => buf.unread(size),
+        }
+    }
+
     fn write_ready(&self, handle: WriteBufferHandle) -> Result<()> {
         if self.is_closed() {
             //连接已关闭，则返回错误
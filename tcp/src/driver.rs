@@ -155,6 +155,9 @@ pub trait Socket: Sized + Send + 'static {
     //是否是安全的连接
     fn is_security(&self) -> bool;
 
+    //获取安全连接握手时协商的应用层协议，非安全连接或未协商时返回空
+    fn get_alpn_protocol(&self) -> Option<&[u8]>;
+
     //通知连接读就绪，可以开始接收指定字节数的数据，如果为0则表示读取任意字节数，不会从当前读缓冲区中返回任何数据
     fn read_ready(&mut self, size: usize) -> Result<()>;
 
@@ -163,6 +166,9 @@ pub trait Socket: Sized + Send + 'static {
     //返回None，则表示当前读缓冲里没有指定字节数的数据，等待指定字节数的数据准备好后，异步回调
     fn read(&mut self, size: usize) -> Result<Option<&[u8]>>;
 
+    //回退最近一次读取的指定字节数的数据，回退的数据可以被再次读取，用于将多读的数据交还给连接，返回实际回退的字节数
+    fn unread(&mut self, size: usize) -> usize;
+
     //线程安全的通知连接写就绪，可以开始发送指定的数据
     fn write_ready(&self, handle: WriteBufferHandle) -> Result<()>;
 
@@ -347,6 +353,13 @@ impl<S: Socket> SocketHandle<S> {
         self.0.security
     }
 
+    //非线程安全的获取安全连接握手时协商的应用层协议
+    pub fn get_alpn_protocol(&self) -> Option<&[u8]> {
+        unsafe {
+            (&*self.0.inner).get_alpn_protocol()
+        }
+    }
+
     //非线程安全的获取Tcp连接上下文的只读引用
     pub fn get_context(&self) -> &SocketContext {
         unsafe {
@@ -368,6 +381,13 @@ impl<S: Socket> SocketHandle<S> {
         }
     }
 
+    //非线程安全的回退最近一次读取的指定字节数的数据，返回实际回退的字节数
+    pub fn unread(&self, size: usize) -> usize {
+        unsafe {
+            (&mut *(self.0.inner as *mut S)).unread(size)
+        }
+    }
+
     //线程安全的分配写缓冲
     pub fn alloc(&self) -> Result<Option<WriteBuffer>> {
         self.0.buffer_pool.alloc()
@@ -928,6 +948,45 @@ impl<S: Socket + Stream, A: SocketAdapter<Connect = S>> SocketDriver<S, A> {
         }
     }
 
+    //异步连接指定远端地址，发起连接后立即将连接路由到绑定了指定端口的连接池中，并由绑定在指定端口上的异步服务处理，连接上下文用于向异步服务传递连接参数
+    //连接是否建立成功由连接池在连接可写时确定，连接失败会以读写错误的方式通知异步服务
+    pub fn connect<C: 'static>(&self,
+                               port: u16,
+                               remote: SocketAddr,
+                               tls_cfg: TlsConfig,
+                               context: C) -> Result<()> {
+        if !tls_cfg.is_empty() && !tls_cfg.is_client() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("tcp socket connect failed, remote: {:?}, reason: invalid client tls config", remote)));
+        }
+
+        //与接受的连接一样，使用绑定地址的序号作为路由令牌，保证连接被分派到绑定了指定端口的连接池中
+        let id = match self.addrs.iter().find(|(addr, _)| addr.port() == port) {
+            None => {
+                return Err(Error::new(ErrorKind::AddrNotAvailable, format!("tcp socket connect failed, remote: {:?}, port: {:?}, reason: port not bind", remote, port)));
+            },
+            Some((_, index)) => *index,
+        };
+
+        //非阻塞的发起连接，不会阻塞调用者的线程
+        let stream = match TcpStream::connect(&remote) {
+            Err(e) => {
+                return Err(Error::new(e.kind(), format!("tcp socket connect failed, remote: {:?}, reason: {:?}", remote, e)));
+            },
+            Ok(stream) => stream,
+        };
+
+        //主动连接的本地地址使用未指定ip和指定的服务端口，用于将连接分派给绑定在指定端口上的异步服务
+        let local = if remote.is_ipv6() {
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port)
+        } else {
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port)
+        };
+
+        let mut socket = S::new(&local, &remote, Some(Token(id)), stream, tls_cfg);
+        socket.get_context_mut().set(context);
+        self.route(socket)
+    }
+
     //获取连接适配器
     pub fn get_adapter(&self) -> &A {
         self.adapter.as_ref().unwrap()
@@ -12,7 +12,7 @@ use mio::{
     PollOpt, Token, Ready,
     net::TcpStream
 };
-use rustls::{WriteV, Session, ServerSession};
+use rustls::{WriteV, Session, ClientSession, ServerSession};
 use log::warn;
 
 use crate::{driver::{Socket, Stream, SocketHandle, SocketImage, SocketWakeup},
@@ -30,6 +30,7 @@ struct ReadBuffer {
     buf_once:       Option<Vec<u8>>,    //临时缓冲区，用于缓存临时的大量数据
     recv_pos_once:  usize,              //临时缓冲区接收位置
     read_pos_once:  usize,              //临时缓冲区已读位置
+    read_once:      bool,               //最近一次读取是否来自临时缓冲区
 }
 
 impl ReadBuffer {
@@ -46,6 +47,7 @@ impl ReadBuffer {
             buf_once: None,
             recv_pos_once: 0,
             read_pos_once: 0,
+            read_once: false,
         }
     }
 
@@ -150,6 +152,19 @@ impl ReadBuffer {
         }
     }
 
+    //回退最近一次读取的指定长度的数据，回退的数据可以被再次读取，返回实际回退的长度
+    pub fn unread(&mut self, len: usize) -> usize {
+        if self.read_once {
+            let len = len.min(self.read_pos_once);
+            self.read_pos_once -= len;
+            len
+        } else {
+            let len = len.min(self.read_pos);
+            self.read_pos -= len;
+            len
+        }
+    }
+
     //从缓冲区的已读位置开始读取指定长度的数据
     pub fn read(&mut self, len: usize) -> Option<&[u8]> {
         //同步返回数据
@@ -161,10 +176,12 @@ impl ReadBuffer {
             if (len == 0) && (recv_pos > read_pos) {
                 //如果需要读取任意有效长度的数据，且当前临时缓冲区内有可读数据
                 self.read_pos_once += recv_pos - read_pos; //更新已读位置
+                self.read_once = true;
                 return self.window_ref_once(read_pos..recv_pos);
             } else if (len > 0) && (len <= (recv_pos - read_pos)) {
                 //如果需要读取指定有效长度的数据，且当前临时缓冲区内至少有指定有效长度的可读数据
                 self.read_pos_once += len; //更新已读位置
+                self.read_once = true;
                 return self.window_ref_once(read_pos..read_pos + len);
             }
         } else {
@@ -175,10 +192,12 @@ impl ReadBuffer {
             if (len == 0) && (recv_pos > read_pos) {
                 //如果需要读取任意有效长度的数据，且当前缓冲区内有可读数据
                 self.read_pos += recv_pos - read_pos; //更新已读位置
+                self.read_once = false;
                 return self.window_ref(read_pos..recv_pos);
             } else if (len > 0) && (len <= (recv_pos - read_pos)) {
                 //如果需要读取指定有效长度的数据，且当前缓冲区内至少有指定有效长度的可读数据
                 self.read_pos += len; //更新已读位置
+                self.read_once = false;
                 return self.window_ref(read_pos..read_pos + len);
             }
         }
@@ -337,8 +356,9 @@ impl Stream for TlsSocket {
            stream: TcpStream,
            tls_cfg: TlsConfig) -> Self {
         let tls_session = match tls_cfg {
-            TlsConfig::Client(cfg) => {
-                unimplemented!();
+            TlsConfig::Client(cfg, server_name) => {
+                let name = webpki::DNSNameRef::try_from_ascii_str(&server_name).expect("create tls socket failed, invalid server name");
+                TlsSession::Client(ClientSession::new(&cfg, name))
             },
             TlsConfig::Server(cfg) => {
                 TlsSession::Server(ServerSession::new(&cfg))
@@ -670,6 +690,13 @@ impl Socket for TlsSocket {
         true
     }
 
+    fn get_alpn_protocol(&self) -> Option<&[u8]> {
+        match &self.tls_session {
+            TlsSession::Client(session) => session.get_alpn_protocol(),
+            TlsSession::Server(session) => session.get_alpn_protocol(),
+        }
+    }
+
     fn read_ready(&mut self, size: usize) -> Result<()> {
         if self.is_closed() {
             //连接已关闭，则返回错误
@@ -742,6 +769,13 @@ impl Socket for TlsSocket {
         Ok(None)
     }
 
+    fn unread(&mut self, size: usize) -> usize {
+        match self.read_buf.as_mut() {
+            None => 0,
+            Some(buf) => buf.unread(size),
+        }
+    }
+
     fn write_ready(&self, handle: WriteBufferHandle) -> Result<()> {
         if self.is_closed() {
             //连接已关闭，则返回错误
@@ -792,197 +826,189 @@ impl Socket for TlsSocket {
 impl TlsSocket {
     //根据Tls会话的就绪状态，设置Tcp流的就绪状态
     fn tls_set_ready(&self) {
-        match &self.tls_session {
-            TlsSession::Client(session) => {
-                unimplemented!();
-            },
-            TlsSession::Server(session) => {
-                let r = session.wants_read();
-                let w = session.wants_write();
-                if r && w {
-                    self.ready.insert(Ready::readable() | Ready::writable());
-                } else if r {
-                    self.ready.insert(Ready::readable());
-                } else if w {
-                    self.ready.insert(Ready::writable());
-                }
-            },
+        let session: &dyn Session = match &self.tls_session {
+            TlsSession::Client(session) => session,
+            TlsSession::Server(session) => session,
+        };
+
+        let r = session.wants_read();
+        let w = session.wants_write();
+        if r && w {
+            self.ready.insert(Ready::readable() | Ready::writable());
+        } else if r {
+            self.ready.insert(Ready::readable());
+        } else if w {
+            self.ready.insert(Ready::writable());
         }
     }
 
     //从Tls会话中接收数据
     fn tls_recv(&mut self, used_temp_buf: bool, recv_pos: usize) -> Result<usize> {
-        match &mut self.tls_session {
-            TlsSession::Client(session) => {
-                unimplemented!();
+        let session: &mut dyn Session = match &mut self.tls_session {
+            TlsSession::Client(session) => session,
+            TlsSession::Server(session) => session,
+        };
+
+        match session.read_tls(&mut self.stream) {
+            Ok(0) => {
+                //从Tcp流中无法读取Tls数据，表示连接已关闭或EOF
+                Err(Error::new(ErrorKind::Other, "tls session closed"))
             },
-            TlsSession::Server(session) => {
-                match session.read_tls(&mut self.stream) {
-                    Ok(0) => {
-                        //从Tcp流中无法读取Tls数据，表示连接已关闭或EOF
-                        Err(Error::new(ErrorKind::Other, "tls session closed"))
-                    },
-                    Ok(_) => {
-                        //从Tcp流中读取到Tls数据，则处理Tls数据帧
-                        if let Err(e) = session.process_new_packets() {
-                            //处理Tls数据帧错误，则立即返回错误原因
-                            return Err(Error::new(ErrorKind::InvalidInput, e));
-                        }
+            Ok(_) => {
+                //从Tcp流中读取到Tls数据，则处理Tls数据帧
+                if let Err(e) = session.process_new_packets() {
+                    //处理Tls数据帧错误，则立即返回错误原因
+                    return Err(Error::new(ErrorKind::InvalidInput, e));
+                }
 
-                        //从Tls会话的读缓冲区中读取已处理的数据
-                        if used_temp_buf {
-                            //当前使用临时读缓冲区
-                            if let Some(buf) = &mut self.read_buf.as_mut().unwrap().buf_once {
-                                //临时读缓冲区存在
-                                session.read(&mut buf[recv_pos..])
-                            } else {
-                                Err(Error::new(ErrorKind::Other, "invalid temp buffer"))
-                            }
-                        } else {
-                            //当前使用读缓冲区
-                            session.read(&mut self.read_buf.as_mut().unwrap().buf[recv_pos..])
-                        }
-                    },
-                    Err(e) if (&e).kind() == ErrorKind::WouldBlock => {
-                        //从Tcp流中读取Tls数据阻塞，则尝试从Tls会话的读缓冲区中读取已处理的数据
-                        if used_temp_buf {
-                            //当前使用临时读缓冲区
-                            if let Some(buf) = &mut self.read_buf.as_mut().unwrap().buf_once {
-                                //临时读缓冲区存在
-                                match session.read(&mut buf[recv_pos..]) {
-                                    Err(e_) => {
-                                        //从Tls会话的读缓冲区中读取已处理的数据错误，则立即返回错误原因
-                                        Err(e_)
-                                    },
-                                    Ok(0) => {
-                                        //Tls会话的读缓冲区没有数据，则返回从Tcp流中读取Tls数据阻塞
-                                        Err(e)
-                                    },
-                                    result => result, //返回从Tls会话的读缓冲区中读取已处理数据的长度
-                                }
-                            } else {
-                                Err(Error::new(ErrorKind::Other, "invalid temp buffer"))
-                            }
-                        } else {
-                            //当前使用读缓冲区
-                            match session.read(&mut self.read_buf.as_mut().unwrap().buf[recv_pos..]) {
-                                Err(e_) => {
-                                    //从Tls会话的读缓冲区中读取已处理的数据错误，则立即返回错误原因
-                                    Err(e_)
-                                },
-                                Ok(0) => {
-                                    //Tls会话的读缓冲区没有数据，则返回从Tcp流中读取Tls数据阻塞
-                                    Err(e)
-                                },
-                                result => result, //返回从Tls会话的读缓冲区中读取已处理数据的长度
-                            }
+                //从Tls会话的读缓冲区中读取已处理的数据
+                if used_temp_buf {
+                    //当前使用临时读缓冲区
+                    if let Some(buf) = &mut self.read_buf.as_mut().unwrap().buf_once {
+                        //临时读缓冲区存在
+                        session.read(&mut buf[recv_pos..])
+                    } else {
+                        Err(Error::new(ErrorKind::Other, "invalid temp buffer"))
+                    }
+                } else {
+                    //当前使用读缓冲区
+                    session.read(&mut self.read_buf.as_mut().unwrap().buf[recv_pos..])
+                }
+            },
+            Err(e) if (&e).kind() == ErrorKind::WouldBlock => {
+                //从Tcp流中读取Tls数据阻塞，则尝试从Tls会话的读缓冲区中读取已处理的数据
+                if used_temp_buf {
+                    //当前使用临时读缓冲区
+                    if let Some(buf) = &mut self.read_buf.as_mut().unwrap().buf_once {
+                        //临时读缓冲区存在
+                        match session.read(&mut buf[recv_pos..]) {
+                            Err(e_) => {
+                                //从Tls会话的读缓冲区中读取已处理的数据错误，则立即返回错误原因
+                                Err(e_)
+                            },
+                            Ok(0) => {
+                                //Tls会话的读缓冲区没有数据，则返回从Tcp流中读取Tls数据阻塞
+                                Err(e)
+                            },
+                            result => result, //返回从Tls会话的读缓冲区中读取已处理数据的长度
                         }
-                    },
-                    reason => reason,
+                    } else {
+                        Err(Error::new(ErrorKind::Other, "invalid temp buffer"))
+                    }
+                } else {
+                    //当前使用读缓冲区
+                    match session.read(&mut self.read_buf.as_mut().unwrap().buf[recv_pos..]) {
+                        Err(e_) => {
+                            //从Tls会话的读缓冲区中读取已处理的数据错误，则立即返回错误原因
+                            Err(e_)
+                        },
+                        Ok(0) => {
+                            //Tls会话的读缓冲区没有数据，则返回从Tcp流中读取Tls数据阻塞
+                            Err(e)
+                        },
+                        result => result, //返回从Tls会话的读缓冲区中读取已处理数据的长度
+                    }
                 }
             },
+            reason => reason,
         }
     }
 
     //向Tls会话写入数据
     fn tls_write(&mut self, bufs: &[&IoVec]) -> Result<usize> {
-        match &mut self.tls_session {
-            TlsSession::Client(session) => {
-                unimplemented!();
-            },
-            TlsSession::Server(session) => {
-                let mut total_len = 0; //写入成功的明文数据长度
-
-                #[cfg(any(windows))]
-                {
-                    for buf in bufs {
-                        if let Err(e) = session.write_all(buf) {
-                            //写部分数据失败，则立即返回错误原因
-                            return Err(e);
-                        }
+        let session: &mut dyn Session = match &mut self.tls_session {
+            TlsSession::Client(session) => session,
+            TlsSession::Server(session) => session,
+        };
 
-                        //写部分数据成功，则继续写剩余数据
-                        total_len += buf.len();
-                    }
+        let mut total_len = 0; //写入成功的明文数据长度
+
+        #[cfg(any(windows))]
+        {
+            for buf in bufs {
+                if let Err(e) = session.write_all(buf) {
+                    //写部分数据失败，则立即返回错误原因
+                    return Err(e);
                 }
-                #[cfg(any(unix))]
-                {
-                    for buf in bufs {
-                        if let Err(e) = session.write_all(buf) {
-                            //写部分数据失败，则立即返回错误原因
-                            return Err(e);
-                        }
 
-                        //写部分数据成功，则继续写剩余数据
-                        total_len += buf.len();
-                    }
+                //写部分数据成功，则继续写剩余数据
+                total_len += buf.len();
+            }
+        }
+        #[cfg(any(unix))]
+        {
+            for buf in bufs {
+                if let Err(e) = session.write_all(buf) {
+                    //写部分数据失败，则立即返回错误原因
+                    return Err(e);
                 }
 
-                Ok(total_len)
-            },
+                //写部分数据成功，则继续写剩余数据
+                total_len += buf.len();
+            }
         }
+
+        Ok(total_len)
     }
 
     //从Tls会话中发送数据
     fn tls_send(&mut self) -> Result<usize> {
-        match &mut self.tls_session {
-            TlsSession::Client(session) => {
-                unimplemented!();
-            },
-            TlsSession::Server(session) => {
-                #[cfg(any(windows))]
-                {
-                    //发送Tls数据帧
-                    let mut total_len = 0;
-                    loop {
-                        match session.write_tls(&mut self.stream) {
-                            Ok(0) => {
-                                //发送完成，则返回
-                                return Ok(total_len);
-                            },
-                            Ok(len) => {
-                                if self.flush.load(Ordering::Relaxed) {
-                                    //刷新流缓冲区，保证数据被立即发送
-                                    if let Err(e) = session.flush() {
-                                        warn!("!!!> Tls Stream Flush Failed, reason: {:?}", e);
-                                    }
-                                }
-
-                                total_len += len;
-                            },
-                            reason => {
-                                return reason;
-                            },
+        let session: &mut dyn Session = match &mut self.tls_session {
+            TlsSession::Client(session) => session,
+            TlsSession::Server(session) => session,
+        };
+
+        #[cfg(any(windows))]
+        {
+            //发送Tls数据帧
+            let mut total_len = 0;
+            loop {
+                match session.write_tls(&mut self.stream) {
+                    Ok(0) => {
+                        //发送完成，则返回
+                        return Ok(total_len);
+                    },
+                    Ok(len) => {
+                        if self.flush.load(Ordering::Relaxed) {
+                            //刷新流缓冲区，保证数据被立即发送
+                            if let Err(e) = session.flush() {
+                                warn!("!!!> Tls Stream Flush Failed, reason: {:?}", e);
+                            }
                         }
-                    }
+
+                        total_len += len;
+                    },
+                    reason => {
+                        return reason;
+                    },
                 }
-                #[cfg(any(unix))]
-                {
-                    //发送Tls数据帧
-                    let mut total_len = 0;
-                    loop {
-                        match session.writev_tls(&mut WriteVAdapter::new(&mut self.stream)) {
-                            Ok(0) => {
-                                //发送完成，则返回
-                                return Ok(total_len);
-                            },
-                            Ok(len) => {
-                                if self.flush.load(Ordering::Relaxed) {
-                                    //刷新流缓冲区，保证数据被立即发送
-                                    if let Err(e) = session.flush() {
-                                        warn!("!!!> Tls Stream Flush Failed, reason: {:?}", e);
-                                    }
-                                }
-
-                                total_len += len;
-                            },
-                            reason => {
-                                return reason;
-                            },
+            }
+        }
+        #[cfg(any(unix))]
+        {
+            //发送Tls数据帧
+            let mut total_len = 0;
+            loop {
+                match session.writev_tls(&mut WriteVAdapter::new(&mut self.stream)) {
+                    Ok(0) => {
+                        //发送完成，则返回
+                        return Ok(total_len);
+                    },
+                    Ok(len) => {
+                        if self.flush.load(Ordering::Relaxed) {
+                            //刷新流缓冲区，保证数据被立即发送
+                            if let Err(e) = session.flush() {
+                                warn!("!!!> Tls Stream Flush Failed, reason: {:?}", e);
+                            }
                         }
-                    }
+
+                        total_len += len;
+                    },
+                    reason => {
+                        return reason;
+                    },
                 }
-            },
+            }
         }
     }
 }
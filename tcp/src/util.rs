@@ -5,6 +5,7 @@ use std::fs::File;
 use std::sync::Arc;
 use std::path::Path;
 use std::time::Duration;
+use std::any::TypeId;
 use std::io::{Result as IOResult, Read, BufReader};
 use std::collections::VecDeque;
 use std::sync::atomic::{AtomicU8, Ordering};
@@ -35,11 +36,18 @@ pub fn register_close_sender(uid: u8, sender: Sender<(Token, IOResult<()>)>) {
     TCP_SOCKET_POOL_SENDER_TAB.write().insert(uid, sender);
 }
 
+/*
+* 获取指定唯一id的Tcp连接所属的Tcp连接池的唯一id
+*/
+pub fn get_pool_uid(uid: usize) -> u8 {
+    (uid >> 24 & 0xff) as u8
+}
+
 /*
 * 线程安全的关闭指定唯一id的Tcp连接
 */
 pub fn close_socket(uid: usize, reason: IOResult<()>) -> bool {
-    let pool_uid = (uid >> 24 & 0xff) as u8;
+    let pool_uid = get_pool_uid(uid);
     let token = Token::from(uid & 0xffffff);
     if let Some(sender) = TCP_SOCKET_POOL_SENDER_TAB.read().get(&pool_uid) {
         sender.send((token, reason));
@@ -404,6 +412,16 @@ impl IoList {
         vecs.concat()
     }
 
+    //复制IO列表中的所有IO数据
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut vec = Vec::with_capacity(self.0);
+        for arr in &self.1 {
+            vec.extend_from_slice(arr.as_ref());
+        }
+
+        vec
+    }
+
     //获取当前IO列表字节长度
     pub fn byte_len(&self) -> usize {
         self.0
@@ -414,6 +432,11 @@ impl IoList {
         self.1.len()
     }
 
+    //获取列表前部的IO数据
+    pub fn front(&self) -> Option<&IoBytes> {
+        self.1.front()
+    }
+
     //在列表前部增加IO数据
     pub fn push_front(&mut self, arr: IoBytes) {
         let len = arr.len();
@@ -448,7 +471,8 @@ impl IoList {
 */
 #[derive(Debug)]
 pub struct SocketEvent {
-    inner: *mut (), //内部事件
+    inner:  *mut (),        //内部事件
+    r#type: Option<TypeId>, //内部事件类型
 }
 
 unsafe impl Send for SocketEvent {}
@@ -458,6 +482,7 @@ impl SocketEvent {
     pub fn empty() -> Self {
         SocketEvent {
             inner: ptr::null_mut(),
+            r#type: None,
         }
     }
 
@@ -466,9 +491,18 @@ impl SocketEvent {
         self.inner.is_null()
     }
 
-    //获取事件
+    //判断事件是否是指定类型
+    pub fn is<T: 'static>(&self) -> bool {
+        if let Some(r#type) = &self.r#type {
+            return *r#type == TypeId::of::<T>();
+        }
+
+        false
+    }
+
+    //获取事件，如果事件不是指定类型，则返回空
     pub fn get<T: 'static>(&self) -> Option<T> {
-        if self.is_empty() {
+        if self.is_empty() || !self.is::<T>() {
             return None;
         }
 
@@ -482,6 +516,7 @@ impl SocketEvent {
         }
 
         self.inner = Box::into_raw(Box::new(event)) as *mut T as *mut ();
+        self.r#type = Some(TypeId::of::<T>());
         true
     }
 
@@ -492,7 +527,11 @@ impl SocketEvent {
         }
 
         let result = self.get();
-        self.inner = ptr::null_mut();
+        if result.is_some() {
+            //已移除指定类型的事件，则清空当前事件
+            self.inner = ptr::null_mut();
+            self.r#type = None;
+        }
         result
     }
 }
@@ -504,7 +543,7 @@ impl SocketEvent {
 pub enum TlsConfig {
     Empty,                      //空
     Server(Arc<ServerConfig>),  //服务器配置
-    Client(Arc<ClientConfig>),  //客户端配置
+    Client(Arc<ClientConfig>, String),  //客户端配置和需要验证的服务器名
 }
 
 impl TlsConfig {
@@ -575,8 +614,63 @@ impl TlsConfig {
     }
 
     //构建指定的传输层安全协议的客户端配置
-    pub fn new_client() -> Result<Self, String> {
-        unimplemented!();
+    pub fn new_client(server_name: &str,        //需要验证的服务器名
+                      server_roots_path: &str,  //服务器根证书路径，为空则使用内置的根证书
+                      client_suite: &str,       //客户端使用的密码套件名称列表
+                      client_versions: &str,    //客户端使用的Tls版本名称列表
+                      client_alpns: &str        //客户端支持的ALPN协议名称列表
+    ) -> Result<Self, String> {
+        if let Err(e) = webpki::DNSNameRef::try_from_ascii_str(server_name) {
+            return Err(format!("invalid server name, name: {:?}, reason: {:?}", server_name, e));
+        }
+
+        let server_roots_path = if server_roots_path.is_empty() {
+            None
+        } else {
+            Some(Path::new(server_roots_path))
+        };
+        let client_suite = if client_suite.is_empty() {
+            vec![]
+        } else {
+            client_suite.split(",").into_iter().map(|suite| {
+                suite.to_string()
+            }).collect::<Vec<String>>()
+        };
+        let client_versions = if client_versions.is_empty() {
+            vec![]
+        } else {
+            client_versions.split(",").into_iter().map(|version| {
+                version.to_string()
+            }).collect::<Vec<String>>()
+        };
+        let client_alpns = if client_alpns.is_empty() {
+            vec![]
+        } else {
+            client_alpns.split(",").into_iter().map(|protocol| {
+                protocol.to_string()
+            }).collect::<Vec<String>>()
+        };
+
+        match make_client_config(server_roots_path,
+                                 client_suite,
+                                 client_versions,
+                                 client_alpns) {
+            Err(e) => Err(e),
+            Ok(config) => Ok(TlsConfig::Client(config, server_name.to_string())),
+        }
+    }
+
+    //使用当前客户端配置，构建需要验证指定服务器名的客户端配置
+    pub fn with_server_name(&self, server_name: &str) -> Result<Self, String> {
+        if let TlsConfig::Client(config, _) = self {
+            if let Err(e) = webpki::DNSNameRef::try_from_ascii_str(server_name) {
+                return Err(format!("invalid server name, name: {:?}, reason: {:?}", server_name, e));
+            }
+
+            return Ok(TlsConfig::Client(config.clone(), server_name.to_string()));
+        }
+
+        Err(format!("invalid client config"))
     }
 
     //判断是否是空配置
@@ -599,7 +693,7 @@ impl TlsConfig {
 
     //判断是否是客户端配置
     pub fn is_client(&self) -> bool {
-        if let TlsConfig::Client(_) = self {
+        if let TlsConfig::Client(_, _) = self {
             return true;
         }
 
@@ -757,6 +851,70 @@ fn make_server_config(client_auth_path: Option<&Path>,
     Ok(Arc::new(config))
 }
 
+//生成客户端配置
+fn make_client_config(server_roots_path: Option<&Path>,
+                      client_suite: Vec<String>,
+                      client_versions: Vec<String>,
+                      client_alpns: Vec<String>) -> Result<Arc<rustls::ClientConfig>, String> {
+    let mut config = rustls::ClientConfig::new();
+    config.key_log = Arc::new(rustls::KeyLogFile::new());
+
+    if let Some(path) = server_roots_path {
+        //配置了服务器根证书路径，则只信任指定的根证书
+        match load_certs(path) {
+            Err(e) => {
+                //加载服务器根证书错误，则立即返回错误原因
+                return Err(e);
+            },
+            Ok(roots) => {
+                for root in roots {
+                    if let Err(e) = config.root_store.add(&root) {
+                        //缓存服务器根证书错误，则立即返回错误原因
+                        return Err(format!("save server root failed, reason: {:?}", e));
+                    }
+                }
+            },
+        }
+    } else {
+        //未配置服务器根证书路径，则信任内置的根证书
+        config.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    }
+
+    if !client_suite.is_empty() {
+        match select_suites(&client_suite) {
+            Err(e) => {
+                //选择指定名称的密码套件错误，则立即返回错误原因
+                return Err(e);
+            },
+            Ok(suites) => {
+                //选择指定名称的密码套件成功
+                config.ciphersuites = suites;
+            },
+        }
+    }
+
+    if !client_versions.is_empty() {
+        match select_versions(&client_versions) {
+            Err(e) => {
+                //选择指定名称的Tls版本错误，则立即返回错误原因
+                return Err(e);
+            },
+            Ok(versions) => {
+                //选择指定名称的Tls版本成功
+                config.versions = versions;
+            },
+        }
+    }
+
+    //设置ALPN协议列表，根据列表顺序确定协议优先级
+    config.set_protocols(&client_alpns
+        .iter()
+        .map(|protocol| protocol.as_bytes().to_vec())
+        .collect::<Vec<_>>()[..]);
+
+    Ok(Arc::new(config))
+}
+
 //加载证书
 fn load_certs(path: &Path) -> Result<Vec<rustls::Certificate>, String> {
     match File::open(path) {
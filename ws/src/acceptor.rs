@@ -47,7 +47,7 @@ const WEBSOCKET_EXTENSIONS: &str = "permessage-deflate; client_max_window_bits";
 * 支持的Websocket协议版本号
 */
 const WEBSOCKET_PROTOCOL_VERSION: u8 = 13;
-const WEBSOCKET_PROTOCOL_VERSION_STR: &str = "13";
+pub const WEBSOCKET_PROTOCOL_VERSION_STR: &str = "13";
 
 /*
 * Websocket服务器端Guid
@@ -453,7 +453,7 @@ fn reply_non_standard_handshake(result: GenResult<(String, Vec<u8>), StatusCode>
 }
 
 //计算握手请求相应的服务器端密钥
-fn accept(key: String) -> String {
+pub(crate) fn accept(key: String) -> String {
     let bin = digest(DigestAlgorithm::SHA1, (key + WEBSOCKET_GUID).as_bytes());
     base64::encode(&bin)
 }
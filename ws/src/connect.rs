@@ -1,24 +1,21 @@
 use std::mem;
 use std::sync::Arc;
+use std::str::from_utf8;
 use std::marker::PhantomData;
 use std::net::{SocketAddr, Shutdown};
+use std::result::Result as GenResult;
 use std::io::{ErrorKind, Result, Error};
 
 use mio::Token;
 use log::warn;
 
 use tcp::{driver::{Socket, AsyncIOWait, SocketHandle, AsyncWriteTask},
-          buffer_pool::WriteBuffer,
+          buffer_pool::{WriteBuffer, WriteBufferHandle},
           util::{ContextHandle, SocketContext, SocketEvent}};
 
-use crate::{frame::{WsHead, WsPayload, WsFrame},
-            util::{ChildProtocol, WsFrameType, WsSession, WsStatus}};
-
-/*
-* 服务端状态码
-*/
-const CLOSE_NORMAL_CODE: u16 = 1000;        //正常关闭
-const CLOSE_GOING_AWAY_CODE: u16 = 1001;    //错误关闭
+use crate::{frame::{MAX_CONTROL_PAYLOAD_LEN, MAX_PAYLOAD_LEN, WsHead, WsPayload, WsFrame, random_mask_key},
+            middleware::{WsMiddleware, WsSendResult},
+            util::{DEFAULT_CLOSE_HANDSHAKE_TIMEOUT, ChildProtocol, CloseCode, WsCloseTimeout, WsFrameType, WsSession, WsStatus}};
 
 /*
 * Websocket连接
@@ -26,6 +23,8 @@ const CLOSE_GOING_AWAY_CODE: u16 = 1001;    //错误关闭
 pub struct WsSocket<S: Socket, H: AsyncIOWait> {
     socket:         SocketHandle<S>,                //当前连接的Tcp连接句柄
     window_bits:    u8,                             //当前连接的压缩窗口大小
+    is_client:      bool,                           //是否是客户端连接
+    middleware:     Option<Arc<dyn WsMiddleware<S, H>>>,   //发送消息前执行的中间件
     marker:         PhantomData<H>,
 }
 
@@ -36,6 +35,8 @@ impl<S: Socket, H: AsyncIOWait> Clone for WsSocket<S, H> {
         WsSocket {
             socket: self.socket.clone(),
             window_bits: self.window_bits,
+            is_client: self.is_client,
+            middleware: self.middleware.clone(),
             marker: PhantomData,
         }
     }
@@ -50,20 +51,62 @@ impl<S: Socket, H: AsyncIOWait> WsSocket<S, H> {
         WsSocket {
             socket,
             window_bits,
+            is_client: false,
+            middleware: None,
+            marker: PhantomData,
+        }
+    }
+
+    //构建一个Websocket客户端连接，客户端上行的帧需要掩码
+    pub fn new_client(socket: SocketHandle<S>, window_bits: u8) -> Self {
+        WsSocket {
+            socket,
+            window_bits,
+            is_client: true,
+            middleware: None,
             marker: PhantomData,
         }
     }
 
-    //线程安全的异步广播指定负载
-    pub fn broadcast(connects: &[WsSocket<S, H>], msg_type: WsFrameType, payload: WriteBuffer) -> Result<()> {
-        if connects.len() == 0 {
-            //连接为空，则忽略
+    //根据连接会话构建一个Websocket连接
+    fn with_session(socket: SocketHandle<S>, window_bits: u8, context: &WsSession) -> Self {
+        if context.is_client() {
+            WsSocket::new_client(socket, window_bits)
+        } else {
+            WsSocket::new(socket, window_bits)
+        }
+    }
+
+    //线程安全的异步广播指定负载，绑定了中间件的连接会在发送前执行中间件，未修改消息的连接共享同一个帧缓冲
+    pub fn broadcast(connects: &[WsSocket<S, H>], msg_type: WsFrameType, mut payload: WriteBuffer) -> Result<()> {
+        let mut shared = Vec::with_capacity(connects.len());
+        for connect in connects {
+            match connect.filter_send(&msg_type, &mut payload) {
+                None => {
+                    //未修改消息，则共享帧缓冲
+                    shared.push(connect);
+                },
+                Some(Err(e)) => {
+                    warn!("!!!> Websocket Broadcast Failed, uid: {:?}, reason: {:?}", connect.get_uid(), e);
+                },
+                Some(Ok(_)) => (),
+            }
+        }
+
+        if shared.len() == 0 {
+            //需要共享帧缓冲的连接为空，则忽略
             return Ok(());
         }
 
-        if let Some(mut buf) = WsFrame::<S, H>::single_with_window_bits_and_payload(msg_type, connects[0].window_bits, payload).into_write_buf() {
+        let mut frame = WsFrame::<S, H>::single_with_window_bits_and_payload(msg_type, shared[0].window_bits, payload);
+        if shared[0].is_client {
+            //客户端连接，则需要掩码
+            frame.mask_with_key(random_mask_key());
+        }
+
+        if let Some(mut buf) = frame.into_write_buf() {
             if let Some(handle) = buf.finish() {
-                for connect in connects {
+                for connect in shared {
                     connect.socket.write_ready(handle.clone());
                 }
             }
@@ -74,6 +117,21 @@ impl<S: Socket, H: AsyncIOWait> WsSocket<S, H> {
         Err(Error::new(ErrorKind::InvalidData, "invalid payload"))
     }
 
+    //获取当前连接的压缩窗口大小
+    pub fn window_bits(&self) -> u8 {
+        self.window_bits
+    }
+
+    //判断是否是客户端连接
+    pub fn is_client(&self) -> bool {
+        self.is_client
+    }
+
+    //设置发送消息前执行的中间件
+    pub fn set_middleware(&mut self, middleware: Arc<dyn WsMiddleware<S, H>>) {
+        self.middleware = Some(middleware);
+    }
+
     //线程安全的判断连接是否关闭
     pub fn is_closed(&self) -> bool {
         self.socket.is_closed()
@@ -138,9 +196,77 @@ impl<S: Socket, H: AsyncIOWait> WsSocket<S, H> {
         }
     }
 
-    //线程安全的异步发送指定负载
-    pub fn send(&self, msg_type: WsFrameType, payload: WriteBuffer) -> Result<()> {
-        if let Some(buf) = WsFrame::<S, H>::single_with_window_bits_and_payload(msg_type, self.window_bits, payload).into_write_buf() {
+    //线程安全的异步发送指定负载，绑定了中间件的连接会在发送前执行中间件
+    pub fn send(&self, msg_type: WsFrameType, mut payload: WriteBuffer) -> Result<()> {
+        if let Some(result) = self.filter_send(&msg_type, &mut payload) {
+            //中间件已处理当前消息
+            return result;
+        }
+
+        self.send_frame(msg_type, payload)
+    }
+
+    //在发送前执行当前连接的中间件，中间件只借用负载，返回空表示消息未被修改，需要由调用者继续发送
+    pub(crate) fn filter_send(&self, msg_type: &WsFrameType, payload: &mut WriteBuffer) -> Option<Result<()>> {
+        let middleware = if let Some(middleware) = &self.middleware {
+            middleware
+        } else {
+            //没有中间件，则忽略
+            return None;
+        };
+
+        let iolist = payload.get_iolist_mut();
+        if iolist.len() > 1 {
+            //负载由多个IO数据组成，则合并为一个连续的IO数据，以保证中间件可以借用
+            let bin = iolist.to_vec();
+            iolist.clear();
+            iolist.push_back(bin.into());
+        }
+
+        let result = match iolist.front() {
+            None => middleware.send(self, msg_type, &[]),
+            Some(bytes) => middleware.send(self, msg_type, bytes.as_ref()),
+        };
+
+        match result {
+            WsSendResult::Pass => None,
+            WsSendResult::Replace(msg) => {
+                //使用中间件处理后的消息替换负载，并立即发送
+                let (r#type, bin) = msg.take();
+                match self.alloc() {
+                    None => {
+                        Some(Err(Error::new(ErrorKind::Other, "websocket send failed, reason: alloc write buffer failed")))
+                    },
+                    Some(mut buf) => {
+                        buf.get_iolist_mut().push_back(bin.into());
+                        Some(self.send_frame(r#type, buf))
+                    },
+                }
+            },
+            WsSendResult::Drop => {
+                //丢弃当前消息
+                Some(Ok(()))
+            },
+            WsSendResult::Reject(e) => {
+                //拒绝当前消息
+                Some(Err(e))
+            },
+            WsSendResult::Close(code, reason) => {
+                //关闭当前连接
+                Some(self.close_with(code, &reason))
+            },
+        }
+    }
+
+    //线程安全的异步将指定负载序列化为帧后发送
+    fn send_frame(&self, msg_type: WsFrameType, payload: WriteBuffer) -> Result<()> {
+        let mut frame = WsFrame::<S, H>::single_with_window_bits_and_payload(msg_type, self.window_bits, payload);
+        if self.is_client {
+            //客户端连接，则需要掩码
+            frame.mask_with_key(random_mask_key());
+        }
+
+        if let Some(buf) = frame.into_write_buf() {
             if let Some(handle) = buf.finish() {
                 return self.socket.write_ready(handle);
             }
@@ -151,6 +277,11 @@ impl<S: Socket, H: AsyncIOWait> WsSocket<S, H> {
         Err(Error::new(ErrorKind::InvalidData, "invalid payload"))
     }
 
+    //线程安全的异步发送已完成的帧缓冲，用于多个连接共享同一个帧缓冲，调用者需要先通过filter_send执行中间件
+    pub(crate) fn send_shared(&self, handle: WriteBufferHandle) -> Result<()> {
+        self.socket.write_ready(handle)
+    }
+
     //线程安全的异步唤醒连接
     pub fn wake(&self) -> Result<()> {
         self.socket.wake()
@@ -158,34 +289,140 @@ impl<S: Socket, H: AsyncIOWait> WsSocket<S, H> {
 
     //线程安全的异步关闭当前连接
     pub fn close(&self, reason: Result<()>) -> Result<()> {
-        close::<S, H>(&self.socket, reason)
+        match reason {
+            Ok(_) => {
+                //正常关闭
+                self.close_with(CloseCode::Normal, "")
+            },
+            Err(e) => {
+                //错误关闭
+                self.close_with(CloseCode::Error, &e.to_string())
+            },
+        }
+    }
+
+    //线程安全的异步使用指定状态码和原因关闭当前连接，发送关闭帧后等待对端回应关闭帧，等待超时则强制关闭Tcp连接
+    pub fn close_with(&self, code: CloseCode, reason: &str) -> Result<()> {
+        close::<S, H>(&self.socket, code, reason, DEFAULT_CLOSE_HANDSHAKE_TIMEOUT)
+    }
+
+    //线程安全的异步使用指定状态码、原因和关闭握手超时时长关闭当前连接
+    pub fn close_with_timeout(&self, code: CloseCode, reason: &str, timeout: usize) -> Result<()> {
+        close::<S, H>(&self.socket, code, reason, timeout)
     }
 }
 
-//线程安全的关闭指定Websocket连接，关闭前向对端发送关闭帧
-fn close<S: Socket, H: AsyncIOWait>(handle: &SocketHandle<S>, reason: Result<()>) -> Result<()> {
-    let payload= match &reason {
-        Err(_) => {
-            //错误关闭
-            Some(vec![((CLOSE_GOING_AWAY_CODE >> 8) & 0xff) as u8, (CLOSE_GOING_AWAY_CODE & 0xff) as u8])
-        },
-        Ok(_) => {
-            //正常关闭
-            Some(vec![((CLOSE_NORMAL_CODE >> 8) & 0xff) as u8, (CLOSE_NORMAL_CODE & 0xff) as u8])
-        },
+//线程安全的关闭指定Websocket连接，关闭前向对端发送关闭帧，并等待对端回应关闭帧
+fn close<S: Socket, H: AsyncIOWait>(handle: &SocketHandle<S>,
+                                    code: CloseCode,
+                                    reason: &str,
+                                    timeout: usize) -> Result<()> {
+    if handle.is_closed() {
+        //Tcp连接已关闭，则忽略
+        return Ok(());
+    }
+
+    if !code.is_allowed() {
+        //不允许在关闭帧中发送的状态码
+        return Err(Error::new(ErrorKind::InvalidInput, format!("websocket close failed, code: {}, reason: invalid close code", u16::from(code))));
+    }
+
+    let is_client = if let Some(h) = handle.get_context().get::<WsSession>() {
+        let context = h.as_ref();
+        if !context.is_handshaked() {
+            //当前连接未握手、正在关闭或已关闭，则立即关闭Tcp连接
+            context.set_close(code, reason);
+            return handle.close(close_reason(Some((code, reason.to_string()))));
+        }
+
+        if !context.set_close_initiated() {
+            //已发起关闭握手，则忽略
+            return Ok(());
+        }
+        context.set_close(code, reason);
+        context.is_client()
+    } else {
+        //连接会话为空，则立即关闭Tcp连接
+        return handle.close(close_reason(Some((code, reason.to_string()))));
     };
 
     //创建关闭帧
-    let frame = WsFrame::<S, H>::control_with_payload(WsFrameType::Close, payload);
+    let mut frame = WsFrame::<S, H>::control_with_payload(WsFrameType::Close, Some(code.to_payload(reason)));
+    if is_client {
+        //客户端连接，则需要掩码
+        frame.mask_with_key(random_mask_key());
+    }
     if let Ok(Some(mut buf)) = handle.alloc() {
         buf.get_iolist_mut().push_back(Vec::from(frame).into());
         if let Some(h) = buf.finish() {
-            //向对端发送关闭帧，并关闭当前连接
-            handle.write_ready(h);
-            return handle.close(reason);
+            //向对端发送关闭帧
+            if let Err(e) = handle.write_ready(h) {
+                return handle.close(Err(e));
+            }
+
+            //设置关闭握手超时，等待对端回应关闭帧
+            let mut event = SocketEvent::empty();
+            event.set(WsCloseTimeout);
+            handle.set_timeout(timeout, event);
+            return Ok(());
         }
     }
 
+    //无法发送关闭帧，则立即关闭Tcp连接
+    handle.close(close_reason(Some((code, reason.to_string()))))
+}
+
+//将关闭握手的状态码和原因转换为Tcp连接的关闭原因
+fn close_reason(close: Option<(CloseCode, String)>) -> Result<()> {
+    match close {
+        None => Ok(()),
+        Some((CloseCode::Normal, _)) | Some((CloseCode::Away, _)) | Some((CloseCode::Status, _)) => Ok(()),
+        Some((code, reason)) => {
+            Err(Error::new(ErrorKind::Other, format!("websocket closed, code: {}, reason: {}", u16::from(code), reason)))
+        },
+    }
+}
+
+//获取初始读的头大小，客户端读服务器端下行的帧没有掩码
+fn read_head_len(is_client: bool) -> usize {
+    if is_client {
+        WsHead::CLIENT_READ_HEAD_LEN
+    } else {
+        WsHead::READ_HEAD_LEN
+    }
+}
+
+//检查帧头是否合法，不合法则返回应当用于关闭连接的状态码
+fn check_head(head: &WsHead, window_bits: u8, context: &WsSession) -> GenResult<(), CloseCode> {
+    if !head.is_valid_type() {
+        //无效的操作码
+        return Err(CloseCode::Protocol);
+    }
+
+    if (head.is_rsv1() && window_bits == 0) || head.is_rsv2() || head.is_rsv3() {
+        //未协商的扩展标记
+        return Err(CloseCode::Protocol);
+    }
+
+    if head.is_control() {
+        if !head.is_fin() || head.len() > MAX_CONTROL_PAYLOAD_LEN {
+            //控制帧不允许分帧，且负载长度不允许超过限制
+            return Err(CloseCode::Protocol);
+        }
+
+        return Ok(());
+    }
+
+    let is_undefined = if let WsFrameType::Undefined = context.get_type() {
+        true
+    } else {
+        false
+    };
+    if (head.is_follow() && is_undefined) || (!head.is_follow() && !is_undefined) {
+        //后续帧之前没有首帧，或首帧之前的多帧数据未结束
+        return Err(CloseCode::Protocol);
+    }
+
     Ok(())
 }
 
@@ -204,146 +441,197 @@ impl<S: Socket, H: AsyncIOWait> WsSocket<S, H> {
             return;
         }
 
-        //读数据，并填充帧数据
+        //读数据，并填充帧数据，客户端读服务器端下行的没有掩码的帧
+        let is_client = h.as_ref().is_client();
+        let head_len = read_head_len(is_client);
         let mut frame = WsFrame::<S, H>::default();
-        WsFrame::read_head(handle, waits, window_bits, &mut frame).await;
+        if is_client {
+            WsFrame::read_server_head(handle, waits, &mut frame).await;
+        } else {
+            WsFrame::read_head(handle, waits, window_bits, &mut frame).await;
+        }
+        if handle.is_closed() {
+            //读帧失败，Tcp连接已关闭，则忽略
+            return;
+        }
+
+        if let Err(code) = check_head(frame.get_head(), window_bits, h.as_ref()) {
+            //帧头不合法，则关闭当前Ws连接，并继续读对端回应的关闭帧
+            fail::<S, H>(handle, code, "invalid frame head", head_len);
+            return;
+        }
+
+        let head = frame.get_head().clone();
+        if head.is_control() {
+            //控制帧，则开始控制处理
+            let payload = if let WsPayload::Raw(payload) = frame.payload() {
+                payload
+            } else {
+                Vec::new()
+            };
+
+            WsSocket::handle_control(handle, waits, window_bits, h, head.get_type().into(), payload).await;
+            return;
+        }
 
         if let Some(context) = h.as_mut() {
+            if context.is_close_initiated() {
+                //已发起关闭握手，则丢弃数据帧，并继续读对端回应的关闭帧
+                context.reset();
+                if let Err(e) = handle.read_ready(head_len) {
+                    handle.close(Err(Error::new(ErrorKind::Other, format!("websocket read close frame failed, reason: {:?}", e))));
+                }
+
+                return;
+            }
+
             //根据帧类型，处理帧数据
             if let WsPayload::Raw(payload) = frame.payload() {
                 //当前帧有祼负载，则缓冲负载
                 context.append(payload);
             }
 
-            let head = frame.get_head();
-            if head.is_single() {
-                //数据帧，且只有单帧，则设置帧类型，并开始消息处理
-                context.set_type(head.get_type());
-                if let Err(e) = protocol.decode_protocol(Self::new(handle.clone(), window_bits), waits.clone(), context).await {
-                    //协议处理失败，则立即关闭当前Ws连接
-                    close::<S, H>(handle, Err(e));
-                }
-
-                //重置当前连接的当前帧，并继续读后续帧
+            if context.as_buf().len() as u64 > MAX_PAYLOAD_LEN {
+                //分帧消息的累计负载超过限制，则关闭当前Ws连接，并继续读对端回应的关闭帧
                 context.reset();
-                if let Err(e) = handle.read_ready(WsHead::READ_HEAD_LEN) {
-                    //继续读失败，则立即关闭Ws连接
-                    close::<S, H>(handle, Err(Error::new(ErrorKind::Other, format!("websocket read next message failed, reason: {:?}", e))));
-                }
-
+                fail::<S, H>(handle, CloseCode::Size, "message too big", head_len);
                 return;
-            } else if head.is_first() {
-                //数据帧，当前是首帧，则设置帧类型，并继续读后续帧
-                context.set_type(head.get_type());
+            }
 
-                if let Err(e) = handle.read_ready(WsHead::READ_HEAD_LEN) {
-                    //继续读失败，则立即关闭Ws连接
-                    close::<S, H>(handle, Err(Error::new(ErrorKind::Other, format!("websocket read next frame failed, reason: {:?}", e))));
+            if head.is_single() || head.is_finish() {
+                if head.is_single() {
+                    //数据帧，且只有单帧，则设置帧类型和是否压缩
+                    context.set_type(head.get_type());
+                    context.set_compressed(head.is_rsv1());
                 }
 
-                return;
-            } else if head.is_next() {
-                //数据帧，当前是后续帧，则继续读后续帧
-                if let Err(e) = handle.read_ready(WsHead::READ_HEAD_LEN) {
-                    //继续读失败，则立即关闭Ws连接
-                    close::<S, H>(handle, Err(Error::new(ErrorKind::Other, format!("websocket read next frame failed, reason: {:?}", e))));
+                if let Err(code) = context.inflate(MAX_PAYLOAD_LEN as usize) {
+                    //压缩的消息解压失败或解压后过大，则关闭当前Ws连接，并继续读对端回应的关闭帧
+                    context.reset();
+                    fail::<S, H>(handle, code, "invalid compressed message", head_len);
+                    return;
                 }
 
-                return;
-            } else if head.is_finish() {
-                //数据帧，当前是结束帧，则开始消息处理
-                if let Err(e) = protocol.decode_protocol(Self::new(handle.clone(), window_bits), waits.clone(), context).await {
+                if let WsFrameType::Text = context.get_type() {
+                    //压缩的文本帧已解压，可以统一校验UTF-8编码
+                    if let Err(_) = from_utf8(context.as_buf()) {
+                        //文本帧不是合法的UTF-8编码，则关闭当前Ws连接，并继续读对端回应的关闭帧
+                        context.reset();
+                        fail::<S, H>(handle, CloseCode::Invalid, "invalid utf8 text", head_len);
+                        return;
+                    }
+                }
+
+                //开始消息处理
+                if let Err(e) = protocol.decode_protocol(Self::with_session(handle.clone(), window_bits, context), waits.clone(), context).await {
                     //协议处理失败，则立即关闭当前Ws连接
-                    close::<S, H>(handle, Err(e));
+                    close::<S, H>(handle, CloseCode::Error, &e.to_string(), DEFAULT_CLOSE_HANDSHAKE_TIMEOUT);
                 }
 
                 //重置当前连接的当前帧，并继续读后续帧
                 context.reset();
-                if let Err(e) = handle.read_ready(WsHead::READ_HEAD_LEN) {
-                    //继续读失败，则立即关闭Ws连接
-                    close::<S, H>(handle, Err(Error::new(ErrorKind::Other, format!("websocket read next message failed, reason: {:?}", e))));
+                if let Err(e) = handle.read_ready(head_len) {
+                    //继续读失败，则立即关闭Tcp连接
+                    handle.close(Err(Error::new(ErrorKind::Other, format!("websocket read next message failed, reason: {:?}", e))));
                 }
-
-                return;
             } else {
-                //控制帧，则设置帧类型
-                context.set_type(head.get_type());
+                if head.is_first() {
+                    //数据帧，当前是首帧，则设置帧类型和是否压缩
+                    context.set_type(head.get_type());
+                    context.set_compressed(head.is_rsv1());
+                }
+
+                //继续读后续帧
+                if let Err(e) = handle.read_ready(head_len) {
+                    //继续读失败，则立即关闭Tcp连接
+                    handle.close(Err(Error::new(ErrorKind::Other, format!("websocket read next frame failed, reason: {:?}", e))));
+                }
             }
         } else {
-            //无法获取会话的可写引用，则表示有异常，立即关闭Ws连接
-            close::<S, H>(handle, Err(Error::new(ErrorKind::Other, format!("Websocket Read Failed, reason: invalid writable context"))));
-            return;
+            //无法获取会话的可写引用，则表示有异常，立即关闭Tcp连接
+            handle.close(Err(Error::new(ErrorKind::Other, format!("Websocket Read Failed, reason: invalid writable context"))));
         }
-
-        //控制帧，则开始控制处理
-        WsSocket::handle_control(handle, waits, window_bits, h).await;
     }
 
     //异步处理控制帧
     async fn handle_control(handle: &SocketHandle<S>,
                             waits: &H,
                             window_bits: u8,
-                            mut h: ContextHandle<WsSession>) {
-        let frame_type = h.as_ref().get_type();
+                            mut h: ContextHandle<WsSession>,
+                            frame_type: WsFrameType,
+                            payload: Vec<u8>) {
+        let is_client = h.as_ref().is_client();
+        let head_len = read_head_len(is_client);
         match frame_type {
             wft@WsFrameType::Close => {
                 //处理关闭帧
+                if h.as_ref().is_close_initiated() {
+                    //本端已发起关闭握手，当前关闭帧是对端的回应，则取消关闭握手超时，并立即关闭Tcp连接
+                    handle.unset_timeout();
+                    handle.close(close_reason(h.as_ref().get_close()));
+                    return;
+                }
+
                 if let Some(context) = h.as_mut() {
+                    //对端发起关闭握手，则记录关闭状态码和原因，并生成回应的负载
+                    let reply = match CloseCode::from_payload(&payload) {
+                        Err(code) => {
+                            //关闭帧负载不合法
+                            context.set_close(code, "invalid close frame");
+                            Some(code.to_payload(""))
+                        },
+                        Ok(None) => {
+                            //没有关闭状态码
+                            context.set_close(CloseCode::Status, "");
+                            None
+                        },
+                        Ok(Some((code, reason))) => {
+                            //有关闭状态码，则原样回应状态码和原因
+                            context.set_close(code, &reason);
+                            Some(payload)
+                        },
+                    };
+
                     //修改当前连接为正在关闭中
                     context.set_status(WsStatus::Closing);
 
-                    //填充负载
-                    let payload;
-                    let bin = context.as_buf();
-                    if bin.len() == 0 {
-                        //没有关闭状态码
-                        payload = None;
-                    } else {
-                        //有关闭状态码
-                        payload = Some(vec![bin[0], bin[1]]);
-                    }
-
                     //响应关闭控制帧，并不再继续读连接的数据
-                    WsSocket::resp_control(handle, waits, window_bits, wft, payload).await;
+                    WsSocket::resp_control(handle, waits, window_bits, is_client, wft, reply).await;
 
                     context.reset(); //重置当前连接的当前帧
                 } else {
                     //无法获取会话的可写引用，则表示有异常，立即关闭Tcp连接
                     handle.close(Err(Error::new(ErrorKind::Other, format!("websocket handle close frame failed, reason: invalid writable context"))));
-                    return;
                 }
             },
             WsFrameType::Ping => {
-                if let Some(context) = h.as_mut() {
-                    //处理Ping帧
-                    let bin = context.as_buf();
-                    if bin.len() == 0 {
+                //处理Ping帧
+                if !h.as_ref().is_close_initiated() {
+                    if payload.len() == 0 {
                         //没有Ping负载，写入响应的Pong控制帧
-                        WsSocket::resp_control(handle, waits, window_bits, WsFrameType::Pong, None).await;
+                        WsSocket::resp_control(handle, waits, window_bits, is_client, WsFrameType::Pong, None).await;
                     } else {
                         //有Ping负载，写入响应的Pong控制帧
-                        WsSocket::resp_control(handle, waits, window_bits, WsFrameType::Pong, Some(bin.to_vec())).await;
-                    }
-
-                    //继续读连接的数据
-                    if let Err(e) = handle.read_ready(WsHead::READ_HEAD_LEN) {
-                        //继续读失败，则立即关闭Tcp连接
-                        handle.close(Err(Error::new(ErrorKind::Other, format!("websocket read next frame failed after handle ping, reason: {:?}", e))));
+                        WsSocket::resp_control(handle, waits, window_bits, is_client, WsFrameType::Pong, Some(payload)).await;
                     }
+                }
 
-                    context.reset(); //重置当前连接的当前帧
-                } else {
-                    //无法获取会话的可写引用，则表示有异常，立即关闭Tcp连接
-                    handle.close(Err(Error::new(ErrorKind::Other, format!("websocket handle ping frame failed, reason: invalid writable context"))));
-                    return;
+                //继续读连接的数据
+                if let Err(e) = handle.read_ready(head_len) {
+                    //继续读失败，则立即关闭Tcp连接
+                    handle.close(Err(Error::new(ErrorKind::Other, format!("websocket read next frame failed after handle ping, reason: {:?}", e))));
                 }
             },
             WsFrameType::Pong => {
-                //TODO 处理Pong帧
+                //忽略Pong帧，并继续读连接的数据
+                if let Err(e) = handle.read_ready(head_len) {
+                    //继续读失败，则立即关闭Tcp连接
+                    handle.close(Err(Error::new(ErrorKind::Other, format!("websocket read next frame failed after handle pong, reason: {:?}", e))));
+                }
             },
             wft => {
-                //无效的控制帧，则立即关闭Tcp连接
-                handle.close(Err(Error::new(ErrorKind::Other, format!("websocket handle control frame failed, type: {:?}, reason: invalid control frame", wft))));
+                //无效的控制帧，则关闭当前Ws连接
+                fail::<S, H>(handle, CloseCode::Protocol, &format!("invalid control frame, type: {:?}", wft), head_len);
             }
         }
     }
@@ -352,6 +640,7 @@ impl<S: Socket, H: AsyncIOWait> WsSocket<S, H> {
     async fn resp_control(handle: &SocketHandle<S>,
                           waits: &H,
                           window_bits: u8,
+                          is_client: bool,
                           frame_type: WsFrameType,
                           payload: Option<Vec<u8>>) {
         let mut buf = handle.alloc().ok().unwrap().unwrap();
@@ -359,7 +648,12 @@ impl<S: Socket, H: AsyncIOWait> WsSocket<S, H> {
         match frame_type {
             wft@WsFrameType::Close | wft@WsFrameType::Pong => {
                 //回应关闭帧或回应Ping帧
-                buf.get_iolist_mut().push_back(Vec::from(WsFrame::<S, H>::control_with_payload(wft, payload)).into());
+                let mut frame = WsFrame::<S, H>::control_with_payload(wft, payload);
+                if is_client {
+                    //客户端连接，则需要掩码
+                    frame.mask_with_key(random_mask_key());
+                }
+                buf.get_iolist_mut().push_back(Vec::from(frame).into());
 
                 if let Some(buf_handle) = buf.finish() {
                     if let Err(e) = AsyncWriteTask::async_write(handle.clone(), waits.clone(), buf_handle).await {
@@ -381,7 +675,8 @@ impl<S: Socket, H: AsyncIOWait> WsSocket<S, H> {
                 } else if context.is_closing() {
                     //当前连接正在关闭，且已发送回应的关闭帧，则修改当前连接状态为已关闭，立即释放可写会话，并立即关闭Tcp连接
                     context.set_status(WsStatus::Closed);
-                    handle.close(Ok(()));
+                    handle.close(close_reason(context.get_close()));
+                    return;
                 } else {
                     //当前连接正在握手，则修改当前连接状态为已握手，并立即释放可写会话
                     context.set_status(WsStatus::HandShaked);
@@ -413,24 +708,52 @@ impl<S: Socket, H: AsyncIOWait> WsSocket<S, H> {
             },
             Ok(opt) => {
                 if let Some(context) = opt {
-                    //关闭连接子协议
-                    protocol.close_protocol(Self::new(handle.clone(), window_bits), context, result);
+                    //没有完成关闭握手，则记录为异常关闭
+                    match &result {
+                        Err(e) => context.set_close(CloseCode::Abnormal, &e.to_string()),
+                        Ok(_) => context.set_close(CloseCode::Abnormal, ""),
+                    }
+
+                    //通知连接关闭监听器，并关闭连接子协议
+                    context.notify_closed(handle.get_uid());
+                    protocol.close_protocol(Self::with_session(handle.clone(), window_bits, &context), context, result);
                 }
             },
         }
     }
 
     //异步处理Tcp已超时事件
-    pub async fn handle_timeouted(handle: SocketHandle<S>, waits: H, window_bits: u8, protocol: Arc<dyn ChildProtocol<S, H>>, event: SocketEvent) {
+    pub async fn handle_timeouted(handle: SocketHandle<S>, waits: H, window_bits: u8, protocol: Arc<dyn ChildProtocol<S, H>>, mut event: SocketEvent) {
         let mut h = handle.get_context().get::<WsSession>().unwrap();
+        if let Some(WsCloseTimeout) = event.remove::<WsCloseTimeout>() {
+            //关闭握手超时，则立即关闭Tcp连接
+            let close = h.as_ref().get_close();
+            handle.close(close_reason(close));
+            return;
+        }
+
         if let Some(context) = h.as_mut() {
-            if let Err(e) = protocol.protocol_timeout(Self::new(handle.clone(), window_bits), context, event) {
+            if let Err(e) = protocol.protocol_timeout(Self::with_session(handle.clone(), window_bits, context), context, event) {
                 //协议超时处理失败，则立即关闭当前Ws连接
-                close::<S, H>(&handle, Err(e));
+                close::<S, H>(&handle, CloseCode::Error, &e.to_string(), DEFAULT_CLOSE_HANDSHAKE_TIMEOUT);
             } else {
                 //协议超时处理成功，则立即关闭当前Ws连接
-                close::<S, H>(&handle, Ok(()));
+                close::<S, H>(&handle, CloseCode::Normal, "", DEFAULT_CLOSE_HANDSHAKE_TIMEOUT);
             }
         }
     }
 }
+
+//线程安全的因违反协议而关闭指定Websocket连接，并继续读对端回应的关闭帧
+fn fail<S: Socket, H: AsyncIOWait>(handle: &SocketHandle<S>, code: CloseCode, reason: &str, head_len: usize) {
+    warn!("!!!> Websocket Failed, uid: {:?}, remote: {:?}, code: {:?}, reason: {:?}", handle.get_uid(), handle.get_remote(), code, reason);
+    if let Err(e) = close::<S, H>(handle, code, reason, DEFAULT_CLOSE_HANDSHAKE_TIMEOUT) {
+        handle.close(Err(e));
+        return;
+    }
+
+    if let Err(e) = handle.read_ready(head_len) {
+        //继续读失败，则立即关闭Tcp连接
+        handle.close(Err(Error::new(ErrorKind::Other, format!("websocket read close frame failed, reason: {:?}", e))));
+    }
+}
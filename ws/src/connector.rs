@@ -0,0 +1,505 @@
+use std::sync::Arc;
+use std::str::from_utf8;
+use std::net::SocketAddr;
+use std::io::{Error, Result, ErrorKind};
+
+use url::Url;
+use bytes::BufMut;
+use httparse::{EMPTY_HEADER, Response, Status};
+use http::header::{HOST, CONNECTION, UPGRADE,
+                   SEC_WEBSOCKET_VERSION, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_EXTENSIONS,
+                   SEC_WEBSOCKET_PROTOCOL, SEC_WEBSOCKET_ACCEPT};
+use futures::future::{FutureExt, BoxFuture};
+use log::warn;
+
+use tcp::{server::AsyncWaitsHandle,
+          driver::{Socket, Stream, AsyncIOWait, SocketAdapter, SocketDriver,
+                   AsyncService, AsyncServiceFactory, SocketStatus,
+                   SocketHandle, AsyncReadTask, AsyncWriteTask},
+          util::{SocketEvent, TlsConfig}};
+
+use crate::{acceptor::{MAX_HANDSHAKE_HTTP_HEADER_LIMIT, CONNECT_UPGRADE, WEBSOCKET_PROTOCOL_VERSION_STR, accept},
+            connect::WsSocket,
+            frame::WsHead,
+            util::{ChildProtocol, ChildProtocolFactory, WsStatus, WsSession}};
+
+/*
+* Websocket客户端握手请求的序列化缓冲长度
+*/
+const HANDSHAKE_REQ_BUFFER_SIZE: usize = 256;
+
+/*
+* Websocket客户端默认的连接超时时长，单位毫秒
+*/
+pub const DEFAULT_CONNECT_TIMEOUT: usize = 5000;
+
+/*
+* Websocket客户端连接超时事件，超时时长包括Tcp连接和握手
+*/
+#[derive(Debug, Clone)]
+struct WsConnectTimeout;
+
+/*
+* Websocket客户端握手请求
+*/
+#[derive(Debug, Clone)]
+pub struct WsConnectRequest {
+    url:        Url,                    //连接地址
+    protocols:  Vec<String>,            //客户端需要的子协议列表
+    headers:    Vec<(String, String)>,  //握手请求的自定义Http头
+    key:        String,                 //客户端握手密钥
+    timeout:    usize,                  //连接超时时长，单位毫秒
+}
+
+unsafe impl Send for WsConnectRequest {}
+
+impl WsConnectRequest {
+    //构建指定地址的握手请求，只支持ws和wss
+    pub fn new(url: &str) -> Result<Self> {
+        let url = match Url::parse(url) {
+            Err(e) => {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("websocket connect request failed, url: {:?}, reason: {:?}", url, e)));
+            },
+            Ok(url) => url,
+        };
+
+        match url.scheme() {
+            "ws" | "wss" => (),
+            scheme => {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("websocket connect request failed, scheme: {:?}, reason: invalid scheme", scheme)));
+            },
+        }
+
+        if url.host_str().is_none() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("websocket connect request failed, url: {:?}, reason: invalid host", url.as_str())));
+        }
+
+        Ok(WsConnectRequest {
+            url,
+            protocols: Vec::new(),
+            headers: Vec::new(),
+            key: base64::encode(&rand::random::<[u8; 16]>()),
+            timeout: DEFAULT_CONNECT_TIMEOUT,
+        })
+    }
+
+    //获取连接地址
+    pub fn get_url(&self) -> &Url {
+        &self.url
+    }
+
+    //判断是否是安全的连接地址
+    pub fn is_security(&self) -> bool {
+        self.url.scheme() == "wss"
+    }
+
+    //获取连接地址的主机名
+    pub fn get_host(&self) -> &str {
+        self.url.host_str().unwrap_or("")
+    }
+
+    //获取客户端需要的子协议列表
+    pub fn get_protocols(&self) -> &[String] {
+        &self.protocols[..]
+    }
+
+    //增加客户端需要的子协议，子协议按增加顺序确定优先级
+    pub fn add_protocol(&mut self, protocol: &str) {
+        self.protocols.push(protocol.to_string());
+    }
+
+    //增加握手请求的自定义Http头
+    pub fn add_header(&mut self, key: &str, value: &str) {
+        self.headers.push((key.to_string(), value.to_string()));
+    }
+
+    //解析连接地址对应的所有远端地址
+    pub fn addrs(&self) -> Result<Vec<SocketAddr>> {
+        self.url.socket_addrs(|| None)
+    }
+
+    //将握手请求序列化为Vec<u8>
+    fn to_vec(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(HANDSHAKE_REQ_BUFFER_SIZE);
+
+        buf.put("GET ");
+        buf.put(self.url.path());
+        if let Some(query) = self.url.query() {
+            buf.put("?");
+            buf.put(query);
+        }
+        buf.put(" HTTP/1.1\r\n");
+
+        buf.put(HOST.as_str());
+        buf.put(":");
+        buf.put(self.get_host());
+        if let Some(port) = self.url.port() {
+            buf.put(":");
+            buf.put(port.to_string());
+        }
+        buf.put("\r\n");
+        put_header(&mut buf, CONNECTION.as_str(), UPGRADE.as_str());
+        put_header(&mut buf, UPGRADE.as_str(), CONNECT_UPGRADE);
+        put_header(&mut buf, SEC_WEBSOCKET_VERSION.as_str(), WEBSOCKET_PROTOCOL_VERSION_STR);
+        put_header(&mut buf, SEC_WEBSOCKET_KEY.as_str(), &self.key);
+        if !self.protocols.is_empty() {
+            put_header(&mut buf, SEC_WEBSOCKET_PROTOCOL.as_str(), &self.protocols.join(", "));
+        }
+        for (key, value) in &self.headers {
+            put_header(&mut buf, key, value);
+        }
+        buf.put("\r\n");
+
+        buf
+    }
+}
+
+//序列化指定的Http头
+fn put_header(buf: &mut Vec<u8>, key: &str, value: &str) {
+    buf.put(key);
+    buf.put(":");
+    buf.put(value);
+    buf.put("\r\n");
+}
+
+/*
+* 异步连接指定的Websocket服务器，连接建立后由绑定在指定端口上的Websocket连接器完成握手，wss需要指定客户端的传输层安全协议配置
+* 连接和握手未在超时时长内完成，则关闭连接
+*/
+pub fn connect<S, A>(driver: &SocketDriver<S, A>,
+                     port: u16,
+                     mut request: WsConnectRequest,
+                     timeout: usize,
+                     tls_cfg: TlsConfig) -> Result<()>
+    where S: Socket + Stream,
+          A: SocketAdapter<Connect = S> {
+    let tls_cfg = if request.is_security() {
+        //安全的连接地址，则使用连接地址的主机名验证服务器
+        match tls_cfg.with_server_name(request.get_host()) {
+            Err(e) => {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("websocket connect failed, url: {:?}, reason: {:?}", request.get_url().as_str(), e)));
+            },
+            Ok(cfg) => cfg,
+        }
+    } else {
+        TlsConfig::empty()
+    };
+
+    request.timeout = timeout;
+    let mut last_error = Error::new(ErrorKind::AddrNotAvailable, format!("websocket connect failed, url: {:?}, reason: empty address", request.get_url().as_str()));
+    for addr in request.addrs()? {
+        //依次尝试发起连接所有远端地址，直到发起连接成功
+        match driver.connect(port, addr, tls_cfg.clone(), request.clone()) {
+            Err(e) => {
+                last_error = e;
+                continue;
+            },
+            Ok(_) => {
+                return Ok(());
+            },
+        }
+    }
+
+    Err(last_error)
+}
+
+/*
+* Websocket连接器，用于处理客户端的握手
+*/
+pub struct WebsocketConnector<S: Socket, H: AsyncIOWait> {
+    protocol:   Arc<dyn ChildProtocol<S, H>>,   //连接器支持的子协议
+}
+
+impl<S: Socket, H: AsyncIOWait> AsyncService<S, H> for WebsocketConnector<S, H> {
+    type Out = ();
+    type Future = BoxFuture<'static, Self::Out>;
+
+    fn handle_connected(&self, handle: SocketHandle<S>, waits: H, status: SocketStatus) -> Self::Future {
+        let future = async move {
+            if let SocketStatus::Connected(Err(e)) = status {
+                //Tcp连接失败
+                handle.close(Err(Error::new(ErrorKind::Other, format!("websocket connect failed, reason: {:?}", e))));
+                return;
+            }
+
+            WebsocketConnector::<S, H>::send_handshake(handle, waits).await;
+        };
+        future.boxed()
+    }
+
+    fn handle_readed(&self, handle: SocketHandle<S>, waits: H, status: SocketStatus) -> Self::Future {
+        let protocol = self.protocol.clone();
+
+        let future = async move {
+            if let SocketStatus::Readed(Err(e)) = status {
+                //Tcp读数据失败
+                handle.close(Err(Error::new(ErrorKind::Other, format!("websocket read failed, reason: {:?}", e))));
+                return;
+            }
+
+            WsSocket::<S, H>::handle_readed(&handle, &waits, 0, protocol).await;
+        };
+        future.boxed()
+    }
+
+    fn handle_writed(&self, handle: SocketHandle<S>, waits: H, status: SocketStatus) -> Self::Future {
+        let protocol = self.protocol.clone();
+
+        let future = async move {
+            if let SocketStatus::Writed(Err(e)) = status {
+                //Tcp写数据失败
+                handle.close(Err(Error::new(ErrorKind::Other, format!("websocket write failed, reason: {:?}", e))));
+                return;
+            }
+
+            let is_handshaking = if let Some(h) = handle.get_context().get::<WsSession>() {
+                let context = h.as_ref();
+                !context.is_handshaked() && !context.is_closing() && !context.is_closed()
+            } else {
+                false
+            };
+
+            if is_handshaking {
+                //握手请求已发送，则开始接收握手响应
+                WebsocketConnector::<S, H>::recv_handshake(handle, waits, protocol).await;
+            } else {
+                WsSocket::handle_writed(handle, waits).await;
+            }
+        };
+        future.boxed()
+    }
+
+    fn handle_closed(&self, handle: SocketHandle<S>, waits: H, status: SocketStatus) -> Self::Future {
+        let protocol = self.protocol.clone();
+
+        let future = async move {
+            if let SocketStatus::Closed(result) = status {
+                WsSocket::handle_closed(handle, waits, 0, protocol, result).await;
+            }
+        };
+        future.boxed()
+    }
+
+    fn handle_timeouted(&self, handle: SocketHandle<S>, waits: H, status: SocketStatus) -> Self::Future {
+        let protocol = self.protocol.clone();
+
+        let future = async move {
+            if let SocketStatus::Timeout(mut event) = status {
+                if let Some(WsConnectTimeout) = event.remove::<WsConnectTimeout>() {
+                    //连接或握手超时，则立即关闭Tcp连接
+                    handle.close(Err(Error::new(ErrorKind::TimedOut, format!("websocket connect failed, remote: {:?}, reason: connect timeout", handle.get_remote()))));
+                    return;
+                }
+
+                WsSocket::handle_timeouted(handle, waits, 0, protocol, event).await;
+            }
+        };
+        future.boxed()
+    }
+}
+
+impl<S: Socket, H: AsyncIOWait> WebsocketConnector<S, H> {
+    //构建指定子协议的Websocket连接器
+    pub fn with_protocol(protocol: Arc<dyn ChildProtocol<S, H>>) -> Self {
+        WebsocketConnector {
+            protocol,
+        }
+    }
+
+    //异步发送握手请求
+    async fn send_handshake(handle: SocketHandle<S>, waits: H) {
+        let request = match handle.get_context_mut().remove::<WsConnectRequest>() {
+            Ok(Some(request)) => request,
+            _ => {
+                //没有握手请求，则立即关闭Tcp连接
+                handle.close(Err(Error::new(ErrorKind::Other, format!("websocket handshake failed, reason: invalid connect request"))));
+                return;
+            },
+        };
+
+        //设置连接超时，等待握手完成
+        if request.timeout > 0 {
+            let mut event = SocketEvent::empty();
+            event.set(WsConnectTimeout);
+            handle.set_timeout(request.timeout, event);
+        }
+
+        //握手前绑定Tcp连接上下文，并在握手完成前由会话上下文暂存握手请求
+        let bin = request.to_vec();
+        let mut session = WsSession::new_client();
+        session.get_context_mut().set(request);
+        handle.get_context_mut().set(session);
+
+        let mut buf = handle.alloc().ok().unwrap().unwrap();
+        buf.get_iolist_mut().push_back(bin.into());
+        if let Some(buf_handle) = buf.finish() {
+            if let Err(e) = AsyncWriteTask::async_write(handle.clone(), waits, buf_handle).await {
+                handle.close(Err(Error::new(ErrorKind::Other, format!("websocket handshake write error, reason: {:?}", e))));
+            }
+        }
+    }
+
+    //异步接收握手响应
+    async fn recv_handshake(handle: SocketHandle<S>, waits: H, protocol: Arc<dyn ChildProtocol<S, H>>) {
+        let mut h = handle.get_context().get::<WsSession>().unwrap();
+        let request = if let Some(context) = h.as_mut() {
+            match context.get_context_mut().remove::<WsConnectRequest>() {
+                Ok(Some(request)) => request,
+                _ => {
+                    //没有握手请求，则立即关闭Tcp连接
+                    handle.close(Err(Error::new(ErrorKind::Other, format!("websocket handshake failed, reason: invalid connect request"))));
+                    return;
+                },
+            }
+        } else {
+            //无法获取会话的可写引用，则表示有异常，立即关闭Tcp连接
+            handle.close(Err(Error::new(ErrorKind::Other, format!("websocket handshake failed, reason: invalid writable context"))));
+            return;
+        };
+
+        let mut bin = Vec::with_capacity(HANDSHAKE_REQ_BUFFER_SIZE);
+        let mut remaining = 0; //握手响应后已读取的服务器端数据的长度
+        loop {
+            match AsyncReadTask::async_read(handle.clone(), waits.clone(), 0).await {
+                Err(e) => {
+                    handle.close(Err(Error::new(ErrorKind::Other, format!("websocket handshake by read failed, reason: {:?}", e))));
+                    return;
+                },
+                Ok(part) => {
+                    bin.extend_from_slice(part);
+                },
+            }
+
+            let mut headers = [EMPTY_HEADER; MAX_HANDSHAKE_HTTP_HEADER_LIMIT];
+            let mut resp = Response::new(&mut headers);
+            match resp.parse(&bin[..]) {
+                Err(e) => {
+                    //解析握手响应的Http头错误
+                    handle.close(Err(Error::new(ErrorKind::Other, format!("websocket handshake by http parse failed, reason: {:?}", e))));
+                    return;
+                },
+                Ok(Status::Partial) => {
+                    //握手响应不完整，继续读
+                    continue;
+                },
+                Ok(Status::Complete(len)) => {
+                    //全部握手响应已到达，则检查握手响应
+                    if let Err(e) = check_handshake_response(&resp, &request) {
+                        warn!("!!!> Ws Client Handshake Failed, url: {:?}, reason: {:?}", request.get_url().as_str(), e);
+                        handle.close(Err(e));
+                        return;
+                    }
+
+                    remaining = bin.len() - len;
+                    break;
+                },
+            }
+        }
+
+        //握手成功，则取消连接超时，并将握手响应后的服务器端数据交还给连接，由帧读取继续读取
+        handle.unset_timeout();
+        if remaining > 0 && handle.unread(remaining) < remaining {
+            handle.close(Err(Error::new(ErrorKind::Other, format!("websocket handshake failed, reason: unread frame data failed"))));
+            return;
+        }
+
+        if let Some(context) = h.as_mut() {
+            //握手成功，则修改当前连接状态为已握手，并通知子协议
+            context.set_status(WsStatus::HandShaked);
+            if let Err(e) = protocol.connected_protocol(WsSocket::new_client(handle.clone(), 0), context) {
+                handle.close(Err(Error::new(ErrorKind::Other, format!("websocket connected protocol failed, reason: {:?}", e))));
+                return;
+            }
+        } else {
+            //无法获取会话的可写引用，则表示有异常，立即关闭Tcp连接
+            handle.close(Err(Error::new(ErrorKind::Other, format!("websocket handshake failed, reason: invalid writable context"))));
+            return;
+        }
+
+        //握手完成，准备异步接收服务器端发送的Websocket数据帧
+        if let Err(e) = handle.read_ready(WsHead::CLIENT_READ_HEAD_LEN) {
+            handle.close(Err(Error::new(ErrorKind::Other, format!("websocket handshake Ok, but read ready error, reason: {:?}", e))));
+        }
+    }
+}
+
+//检查握手响应是否合法
+fn check_handshake_response(resp: &Response, request: &WsConnectRequest) -> Result<()> {
+    if resp.code != Some(101) {
+        return Err(Error::new(ErrorKind::Other, format!("invalid handshake status, status: {:?}", resp.code)));
+    }
+
+    let mut is_upgrade = false;
+    let mut is_connection = false;
+    let mut is_accept = false;
+    for header in resp.headers.iter() {
+        let value = match from_utf8(header.value) {
+            Err(e) => {
+                return Err(Error::new(ErrorKind::Other, format!("key: {}, reason: {}", header.name, e)));
+            },
+            Ok(value) => value.trim(),
+        };
+
+        match header.name.to_lowercase().as_str() {
+            key if key == UPGRADE.as_str() => {
+                is_upgrade = value.to_lowercase() == CONNECT_UPGRADE;
+            },
+            key if key == CONNECTION.as_str() => {
+                is_connection = value.to_lowercase().split(",").any(|token| token.trim() == UPGRADE.as_str());
+            },
+            key if key == SEC_WEBSOCKET_ACCEPT.as_str() => {
+                is_accept = value == accept(request.key.clone());
+            },
+            key if key == SEC_WEBSOCKET_PROTOCOL.as_str() => {
+                if !request.protocols.iter().any(|protocol| protocol.as_str() == value) {
+                    //服务器端指定了客户端未请求的子协议
+                    return Err(Error::new(ErrorKind::Other, format!("invalid handshake protocol, protocol: {:?}", value)));
+                }
+            },
+            key if key == SEC_WEBSOCKET_EXTENSIONS.as_str() => {
+                //客户端未请求任何扩展协议
+                return Err(Error::new(ErrorKind::Other, format!("invalid handshake extensions, extensions: {:?}", value)));
+            },
+            _ => (), //忽略其它Http头
+        }
+    }
+
+    if !is_upgrade || !is_connection {
+        return Err(Error::new(ErrorKind::Other, format!("invalid handshake upgrade")));
+    }
+
+    if !is_accept {
+        return Err(Error::new(ErrorKind::Other, format!("invalid handshake accept")));
+    }
+
+    Ok(())
+}
+
+/*
+* Websocket连接器工厂
+*/
+pub struct WebsocketConnectorFactory<S: Socket> {
+    protocol_factory: Arc<dyn ChildProtocolFactory<Connect = S, Waits = AsyncWaitsHandle>>,
+}
+
+impl<S: Socket> AsyncServiceFactory for WebsocketConnectorFactory<S> {
+    type Connect = S;
+    type Waits = AsyncWaitsHandle;
+    type Out = ();
+    type Future = BoxFuture<'static, Self::Out>;
+
+    fn new_service(&self) -> Box<dyn AsyncService<Self::Connect, Self::Waits, Out = Self::Out, Future = Self::Future>> {
+        Box::new(
+            WebsocketConnector::with_protocol(
+                self.protocol_factory.new_protocol()))
+    }
+}
+
+impl<S: Socket> WebsocketConnectorFactory<S> {
+    //构建指定子协议工厂的Websocket连接器工厂
+    pub fn with_protocol_factory(protocol_factory: Arc<dyn ChildProtocolFactory<Connect = S, Waits = AsyncWaitsHandle>>) -> Self {
+        WebsocketConnectorFactory {
+            protocol_factory,
+        }
+    }
+}
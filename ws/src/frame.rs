@@ -30,6 +30,16 @@ pub const CLOSE_OPCODE: u8 = 0x8;       //关闭帧
 pub const PING_OPCODE: u8 = 0x9;        //ping帧
 pub const PONG_OPCODE: u8 = 0xa;        //pong帧
 
+/*
+* 控制帧的最大负载长度
+*/
+pub const MAX_CONTROL_PAYLOAD_LEN: u64 = 125;
+
+/*
+* 数据帧的最大负载长度，16MB，超过则立即断开连接
+*/
+pub const MAX_PAYLOAD_LEN: u64 = 16 * 1024 * 1024;
+
 /*
 * 掩码默认标记
 */
@@ -244,6 +254,9 @@ impl WsHead {
     //初始读的头大小
     pub const READ_HEAD_LEN: usize = 6;
 
+    //客户端初始读的头大小，服务器端下行的帧没有掩码
+    pub const CLIENT_READ_HEAD_LEN: usize = 2;
+
     //判断头是否完整
     pub fn is_complete(&self) -> bool {
         if let WsPayloadLen::Complete(_) = self.len {
@@ -324,6 +337,21 @@ impl WsHead {
         }
     }
 
+    //是否是结束帧
+    #[inline(always)]
+    pub fn is_fin(&self) -> bool {
+        self.fin == FIN_FLAG
+    }
+
+    //是否是有效的操作码
+    #[inline(always)]
+    pub fn is_valid_type(&self) -> bool {
+        match self.r#type {
+            FOLLOW_UP_OPCODE | TEXT_OPCODE | BINARY_OPCODE | CLOSE_OPCODE | PING_OPCODE | PONG_OPCODE => true,
+            _ => false,
+        }
+    }
+
     //是否有rsv1
     #[inline(always)]
     pub fn is_rsv1(&self) -> bool {
@@ -747,6 +775,26 @@ impl<S: Socket, H: AsyncIOWait> WsFrame<S, H> {
         self.payload = WsPayload::Raw(payload);
     }
 
+    //使用指定掩码密钥对当前帧的负载进行编码，并设置帧头的掩码密钥，一般用于客户端上行的帧
+    pub fn mask_with_key(&mut self, key: Vec<u8>) {
+        match &mut self.payload {
+            WsPayload::Raw(payload) => {
+                mask(&mut payload[..], Some(&key));
+            },
+            WsPayload::Buffer(buf) => {
+                //负载缓冲可能由多个IO数据组成，则合并后再编码
+                let iolist = buf.get_iolist_mut();
+                let mut bin = iolist.to_vec();
+                mask(&mut bin[..], Some(&key));
+                iolist.clear();
+                iolist.push_back(bin.into());
+            },
+            WsPayload::Empty => (),
+        }
+
+        self.head.set_key(Some(key));
+    }
+
     //将帧序列化为写缓冲
     pub fn into_write_buf(self) -> Option<WriteBuffer> {
         if let WsPayload::Buffer(mut buf) = self.payload {
@@ -814,9 +862,64 @@ impl<S: Socket, H: AsyncIOWait> WsFrame<S, H> {
         WsFrame::<S, H>::read_payload(handle, waits, frame).await;
     }
 
+    //异步读服务器端下行的Websocket帧头，一般用于客户端
+    pub async fn read_server_head(handle: &SocketHandle<S>,
+                                  waits: &H,
+                                  frame: &mut WsFrame<S, H>) {
+        let (b0, b1) = match AsyncReadTask::async_read(handle.clone(), waits.clone(), WsHead::CLIENT_READ_HEAD_LEN).await {
+            Err(e) => {
+                handle.close(Err(Error::new(ErrorKind::Other, format!("websocket read frame head failed, reason: {:?}", e))));
+                return;
+            },
+            Ok(bin) => (bin[0], bin[1]),
+        };
+
+        if get_bit(b1, 7) != 0 {
+            //服务器端下行数据有掩码，则立即断开连接
+            handle.close(Err(Error::new(ErrorKind::Other, format!("websocket read frame head failed, reason: invalid server mask"))));
+            return;
+        }
+
+        let len = match b1 & 0x7f {
+            n@126 | n@127 => {
+                //还需要接收2个或8个字节的负载长度
+                let size = if n == 126 { 2 } else { 8 };
+                match AsyncReadTask::async_read(handle.clone(), waits.clone(), size).await {
+                    Err(e) => {
+                        handle.close(Err(Error::new(ErrorKind::Other, format!("websocket read frame head failed, reason: {:?}", e))));
+                        return;
+                    },
+                    Ok(bin) => {
+                        bin.iter().fold(0u64, |len, b| (len << 8) | (*b as u64))
+                    },
+                }
+            },
+            n => n as u64,
+        };
+
+        frame.set_head(WsHead {
+            fin: get_bit(b0, 7),
+            rsv1: get_bit(b0, 6),
+            rsv2: get_bit(b0, 5),
+            rsv3: get_bit(b0, 4),
+            r#type: b0 & 0xf,
+            len: WsPayloadLen::Complete(len),
+            key: WsMaskKey::Empty,
+        });
+
+        WsFrame::<S, H>::read_payload(handle, waits, frame).await;
+    }
+
     //异步读Websocket帧负载
     async fn read_payload(handle: &SocketHandle<S>, waits: &H, frame: &mut WsFrame<S, H>) {
-        let len = frame.get_head().len() as usize;
+        let len = frame.get_head().len();
+        if len > MAX_PAYLOAD_LEN {
+            //负载过大，则立即断开连接
+            handle.close(Err(Error::new(ErrorKind::Other, format!("websocket read frame payload failed, len: {}, reason: payload too large", len))));
+            return;
+        }
+
+        let len = len as usize;
         if len > 0 {
             //有负载，则继续异步读负载，并填充Websocket帧
             match AsyncReadTask::async_read(handle.clone(), waits.clone(), len).await {
@@ -853,6 +956,11 @@ fn set_bit(byte: u8, nth: u8, val: u8) -> u8 {
     }
 }
 
+//生成随机的掩码密钥
+pub fn random_mask_key() -> Vec<u8> {
+    rand::random::<[u8; 4]>().to_vec()
+}
+
 //通过掩码密钥进行编解码
 #[inline(always)]
 fn mask(bin: &mut [u8], mask_key: Option<&[u8]>) {
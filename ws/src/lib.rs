@@ -12,8 +12,13 @@ extern crate bytes;
 extern crate fnv;
 extern crate mio;
 extern crate log;
+extern crate parking_lot;
+extern crate rand;
+extern crate url;
+extern crate flate2;
 
 extern crate atom;
+extern crate hash;
 extern crate pi_crypto;
 
 extern crate tcp;
@@ -22,4 +27,7 @@ pub mod server;
 pub mod acceptor;
 pub mod frame;
 pub mod connect;
-pub mod util;
\ No newline at end of file
+pub mod util;
+pub mod room;
+pub mod connector;
+pub mod middleware;
\ No newline at end of file
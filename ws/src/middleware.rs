@@ -0,0 +1,279 @@
+use std::sync::Arc;
+use std::collections::VecDeque;
+use std::io::{Error, Result};
+
+use httparse::Request;
+use futures::future::{FutureExt, BoxFuture};
+
+use tcp::{driver::{Socket, AsyncIOWait, SocketHandle},
+          util::SocketEvent};
+
+use crate::{connect::WsSocket,
+            util::{ChildProtocol, CloseCode, WsFrameType, WsSession}};
+
+/*
+* Websocket消息
+*/
+#[derive(Debug, Clone)]
+pub struct WsMessage {
+    r#type:     WsFrameType,    //消息类型
+    payload:    Vec<u8>,        //消息负载
+}
+
+unsafe impl Send for WsMessage {}
+
+impl WsMessage {
+    //构建指定类型和负载的消息
+    pub fn new(msg_type: WsFrameType, payload: Vec<u8>) -> Self {
+        WsMessage {
+            r#type: msg_type,
+            payload,
+        }
+    }
+
+    //获取消息类型
+    pub fn get_type(&self) -> WsFrameType {
+        self.r#type.clone()
+    }
+
+    //设置消息类型
+    pub fn set_type(&mut self, msg_type: WsFrameType) {
+        self.r#type = msg_type;
+    }
+
+    //获取消息负载的只读引用
+    pub fn as_payload(&self) -> &[u8] {
+        &self.payload[..]
+    }
+
+    //获取消息负载的可写引用
+    pub fn as_payload_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.payload
+    }
+
+    //设置消息负载
+    pub fn set_payload(&mut self, payload: Vec<u8>) {
+        self.payload = payload;
+    }
+
+    //取出消息类型和负载
+    pub fn take(self) -> (WsFrameType, Vec<u8>) {
+        (self.r#type, self.payload)
+    }
+}
+
+/*
+* Websocket中间件处理结果
+*/
+pub enum WsMiddlewareResult {
+    Continue(WsMessage),        //继续后续中间件的处理，可以修改消息
+    Drop,                       //丢弃当前消息，会跳过剩余中间件和子协议的处理，但不会关闭连接
+    Reject(Error),              //拒绝当前消息，接收时会以违反策略关闭当前连接，发送时会向调用者返回错误
+    Close(CloseCode, String),   //使用指定状态码和原因关闭当前连接，会跳过剩余中间件和子协议的处理
+}
+
+/*
+* Websocket中间件发送处理结果
+*/
+pub enum WsSendResult {
+    Pass,                       //不修改当前消息，继续后续中间件的处理
+    Replace(WsMessage),         //使用指定消息替换当前消息，继续后续中间件的处理
+    Drop,                       //丢弃当前消息，会跳过剩余中间件的处理，但不会关闭连接
+    Reject(Error),              //拒绝当前消息，会向调用者返回错误，广播时只会忽略当前连接
+    Close(CloseCode, String),   //使用指定状态码和原因关闭当前连接，会跳过剩余中间件的处理
+}
+
+/*
+* Websocket中间件
+*/
+pub trait WsMiddleware<S: Socket, H: AsyncIOWait>: Send + Sync + 'static {
+    //处理接收的消息，在子协议解码前执行
+    fn recv(&self, connect: &WsSocket<S, H>, context: &mut WsSession, msg: WsMessage) -> WsMiddlewareResult {
+        WsMiddlewareResult::Continue(msg)
+    }
+
+    //处理发送的消息，在序列化为帧前执行，单播、广播和房间广播都会执行，只借用负载，可能在任意线程中执行
+    fn send(&self, connect: &WsSocket<S, H>, msg_type: &WsFrameType, payload: &[u8]) -> WsSendResult {
+        WsSendResult::Pass
+    }
+}
+
+/*
+* Websocket中间件链
+*/
+pub struct WsMiddlewareChain<S: Socket, H: AsyncIOWait> {
+    buf:    Option<VecDeque<Arc<dyn WsMiddleware<S, H>>>>,  //中间件缓冲
+    chain:  Vec<Arc<dyn WsMiddleware<S, H>>>,               //处理链
+}
+
+impl<S: Socket, H: AsyncIOWait> WsMiddleware<S, H> for WsMiddlewareChain<S, H> {
+    fn recv(&self, connect: &WsSocket<S, H>, context: &mut WsSession, msg: WsMessage) -> WsMiddlewareResult {
+        let mut message = msg; //消息缓冲
+        for middleware in &self.chain {
+            match middleware.recv(connect, context, message) {
+                WsMiddlewareResult::Continue(msg) => {
+                    //继续下一个中间件的接收处理
+                    message = msg;
+                },
+                result => {
+                    //丢弃、拒绝或关闭，则立即返回
+                    return result;
+                },
+            }
+        }
+
+        WsMiddlewareResult::Continue(message)
+    }
+
+    fn send(&self, connect: &WsSocket<S, H>, msg_type: &WsFrameType, payload: &[u8]) -> WsSendResult {
+        let mut replaced: Option<WsMessage> = None; //被替换的消息，只有中间件替换消息时才会分配
+        for middleware in self.chain.iter().rev() {
+            //以相反方向执行发送处理
+            let result = if let Some(msg) = &replaced {
+                middleware.send(connect, &msg.r#type, msg.as_payload())
+            } else {
+                middleware.send(connect, msg_type, payload)
+            };
+
+            match result {
+                WsSendResult::Pass => {
+                    //继续下一个中间件的发送处理
+                    continue;
+                },
+                WsSendResult::Replace(msg) => {
+                    //替换消息，并继续下一个中间件的发送处理
+                    replaced = Some(msg);
+                },
+                result => {
+                    //丢弃、拒绝或关闭，则立即返回
+                    return result;
+                },
+            }
+        }
+
+        if let Some(msg) = replaced {
+            WsSendResult::Replace(msg)
+        } else {
+            WsSendResult::Pass
+        }
+    }
+}
+
+impl<S: Socket, H: AsyncIOWait> WsMiddlewareChain<S, H> {
+    //构建Websocket中间件链
+    pub fn new() -> Self {
+        WsMiddlewareChain {
+            buf: Some(VecDeque::new()),
+            chain: Vec::new(),
+        }
+    }
+
+    //在链头增加中间件，靠前的中间件，将在接收消息时先执行，并在发送消息时后执行
+    pub fn push_front(&mut self, ware: Arc<dyn WsMiddleware<S, H>>) {
+        if let Some(buf) = &mut self.buf {
+            buf.push_front(ware);
+        }
+    }
+
+    //在链尾增加中间件，靠后的中间件，将在接收消息时后执行，并在发送消息时先执行
+    pub fn push_back(&mut self, ware: Arc<dyn WsMiddleware<S, H>>) {
+        if let Some(buf) = &mut self.buf {
+            buf.push_back(ware);
+        }
+    }
+
+    //完成中间件链
+    pub fn finish(&mut self) {
+        if let Some(buf) = self.buf.take() {
+            self.chain = buf.into();
+        }
+    }
+}
+
+/*
+* 带中间件链的Websocket子协议，接收的消息在子协议解码前由中间件链处理，通过子协议获得的连接发送的消息也会由中间件链处理
+*/
+pub struct WsMiddlewareProtocol<S: Socket, H: AsyncIOWait> {
+    chain:      Arc<WsMiddlewareChain<S, H>>,   //中间件链
+    protocol:   Arc<dyn ChildProtocol<S, H>>,   //被包装的子协议
+}
+
+impl<S: Socket, H: AsyncIOWait> ChildProtocol<S, H> for WsMiddlewareProtocol<S, H> {
+    fn protocol_name(&self) -> &str {
+        self.protocol.protocol_name()
+    }
+
+    fn non_standard_handshake_protocol(&self, request: &Request) -> Result<(String, Vec<u8>)> {
+        self.protocol.non_standard_handshake_protocol(request)
+    }
+
+    fn handshake_protocol(&self, handle: SocketHandle<S>, request: &Request) -> Result<()> {
+        self.protocol.handshake_protocol(handle, request)
+    }
+
+    fn connected_protocol(&self, connect: WsSocket<S, H>, context: &mut WsSession) -> Result<()> {
+        self.protocol.connected_protocol(self.bind(connect), context)
+    }
+
+    fn decode_protocol(&self, connect: WsSocket<S, H>, waits: H, context: &mut WsSession) -> BoxFuture<'static, Result<()>> {
+        let connect = self.bind(connect);
+        let msg = WsMessage::new(context.get_type(), context.to_vec());
+        match self.chain.recv(&connect, context, msg) {
+            WsMiddlewareResult::Continue(msg) => {
+                //继续解码，则使用中间件处理后的消息替换会话中的消息，并保留消息是否压缩
+                let (msg_type, payload) = msg.take();
+                let is_compressed = context.is_compressed();
+                context.reset();
+                context.set_type(msg_type.into());
+                context.set_compressed(is_compressed);
+                context.append(payload);
+            },
+            WsMiddlewareResult::Drop => {
+                //丢弃当前消息，则忽略解码
+                return async move {
+                    Ok(())
+                }.boxed();
+            },
+            WsMiddlewareResult::Reject(e) => {
+                //拒绝当前消息，则以违反策略关闭当前连接
+                let result = connect.close_with(CloseCode::Policy, &e.to_string());
+                return async move {
+                    result
+                }.boxed();
+            },
+            WsMiddlewareResult::Close(code, reason) => {
+                //关闭当前连接
+                let result = connect.close_with(code, &reason);
+                return async move {
+                    result
+                }.boxed();
+            },
+        }
+
+        self.protocol.decode_protocol(connect, waits, context)
+    }
+
+    fn close_protocol(&self, connect: WsSocket<S, H>, context: WsSession, reason: Result<()>) {
+        self.protocol.close_protocol(self.bind(connect), context, reason)
+    }
+
+    fn protocol_timeout(&self, connect: WsSocket<S, H>, context: &mut WsSession, event: SocketEvent) -> Result<()> {
+        self.protocol.protocol_timeout(self.bind(connect), context, event)
+    }
+}
+
+impl<S: Socket, H: AsyncIOWait> WsMiddlewareProtocol<S, H> {
+    //构建指定中间件链和子协议的带中间件链的子协议
+    pub fn new(chain: Arc<WsMiddlewareChain<S, H>>, protocol: Arc<dyn ChildProtocol<S, H>>) -> Self {
+        WsMiddlewareProtocol {
+            chain,
+            protocol,
+        }
+    }
+
+    //为连接绑定中间件链，所有连接共享同一个中间件链
+    fn bind(&self, mut connect: WsSocket<S, H>) -> WsSocket<S, H> {
+        connect.set_middleware(self.chain.clone());
+        connect
+    }
+}
@@ -0,0 +1,364 @@
+use std::sync::{Arc, Weak};
+use std::io::{Error, Result, ErrorKind};
+
+use parking_lot::RwLock;
+use log::warn;
+
+use atom::Atom;
+use hash::XHashMap;
+
+use tcp::{driver::{Socket, AsyncIOWait},
+          buffer_pool::WriteBuffer,
+          util::get_pool_uid};
+
+use crate::{connect::WsSocket,
+            frame::{WsFrame, random_mask_key},
+            util::{WsCloseListener, WsFrameType}};
+
+/*
+* Websocket房间，房间成员按所属的Tcp连接池分组
+*/
+struct WsRoom<S: Socket, H: AsyncIOWait> {
+    pools:  XHashMap<u8, XHashMap<usize, WsSocket<S, H>>>,  //成员表，按Tcp连接池的唯一id分组
+    size:   usize,                                          //成员数量
+}
+
+impl<S: Socket, H: AsyncIOWait> Default for WsRoom<S, H> {
+    fn default() -> Self {
+        WsRoom {
+            pools: XHashMap::default(),
+            size: 0,
+        }
+    }
+}
+
+impl<S: Socket, H: AsyncIOWait> WsRoom<S, H> {
+    //判断房间是否为空
+    fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    //加入房间，已加入则忽略
+    fn join(&mut self, connect: WsSocket<S, H>) -> bool {
+        let uid = connect.get_uid();
+        let members = self.pools.entry(get_pool_uid(uid)).or_insert_with(XHashMap::default);
+        if members.contains_key(&uid) {
+            return false;
+        }
+
+        members.insert(uid, connect);
+        self.size += 1;
+        true
+    }
+
+    //离开房间，未加入则忽略
+    fn leave(&mut self, uid: usize) -> bool {
+        let pool_uid = get_pool_uid(uid);
+        if let Some(members) = self.pools.get_mut(&pool_uid) {
+            if members.remove(&uid).is_some() {
+                if members.is_empty() {
+                    //Tcp连接池中已没有成员，则移除分组
+                    self.pools.remove(&pool_uid);
+                }
+
+                self.size -= 1;
+                return true;
+            }
+        }
+
+        false
+    }
+
+    //获取所有成员的唯一id
+    fn uids(&self) -> Vec<usize> {
+        let mut uids = Vec::with_capacity(self.size);
+        for members in self.pools.values() {
+            uids.extend(members.keys());
+        }
+
+        uids
+    }
+}
+
+/*
+* Websocket房间表内部表
+*/
+struct InnerRoomTab<S: Socket, H: AsyncIOWait> {
+    rooms:      XHashMap<Atom, WsRoom<S, H>>,                                       //房间表
+    joined:     XHashMap<usize, Vec<Atom>>,                                         //连接已加入的房间表
+    listeners:  XHashMap<usize, (WsSocket<S, H>, Arc<dyn WsCloseListener>)>,        //连接和已注册的连接关闭监听器表
+}
+
+/*
+* Websocket房间表的连接关闭监听器，只弱引用房间表，避免房间表、连接和监听器之间的循环引用
+*/
+struct RoomCloseListener<S: Socket, H: AsyncIOWait>(Weak<RwLock<InnerRoomTab<S, H>>>);
+
+unsafe impl<S: Socket, H: AsyncIOWait> Send for RoomCloseListener<S, H> {}
+unsafe impl<S: Socket, H: AsyncIOWait> Sync for RoomCloseListener<S, H> {}
+
+impl<S: Socket, H: AsyncIOWait> WsCloseListener for RoomCloseListener<S, H> {
+    //连接已关闭，则自动离开所有已加入的房间，房间表已释放则忽略
+    fn closed(&self, uid: usize) {
+        if let Some(tab) = self.0.upgrade() {
+            WsRoomTab(tab).leave_all(uid);
+        }
+    }
+}
+
+/*
+* Websocket房间表，用于按房间管理连接，并向房间广播
+*/
+pub struct WsRoomTab<S: Socket, H: AsyncIOWait>(Arc<RwLock<InnerRoomTab<S, H>>>);
+
+unsafe impl<S: Socket, H: AsyncIOWait> Send for WsRoomTab<S, H> {}
+unsafe impl<S: Socket, H: AsyncIOWait> Sync for WsRoomTab<S, H> {}
+
+impl<S: Socket, H: AsyncIOWait> Clone for WsRoomTab<S, H> {
+    fn clone(&self) -> Self {
+        WsRoomTab(self.0.clone())
+    }
+}
+
+impl<S: Socket, H: AsyncIOWait> WsRoomTab<S, H> {
+    //构建Websocket房间表
+    pub fn new() -> Self {
+        WsRoomTab(Arc::new(RwLock::new(InnerRoomTab {
+            rooms: XHashMap::default(),
+            joined: XHashMap::default(),
+            listeners: XHashMap::default(),
+        })))
+    }
+
+    //获取房间数量
+    pub fn len(&self) -> usize {
+        self.0.read().rooms.len()
+    }
+
+    //获取所有房间名
+    pub fn rooms(&self) -> Vec<Atom> {
+        self.0.read().rooms.keys().cloned().collect()
+    }
+
+    //获取指定房间的成员数量
+    pub fn size(&self, room: &str) -> usize {
+        if let Some(r) = self.0.read().rooms.get(&Atom::from(room)) {
+            return r.size;
+        }
+
+        0
+    }
+
+    //获取指定房间的所有成员的唯一id
+    pub fn members(&self, room: &str) -> Vec<usize> {
+        if let Some(r) = self.0.read().rooms.get(&Atom::from(room)) {
+            return r.uids();
+        }
+
+        Vec::new()
+    }
+
+    //获取指定唯一id的连接已加入的所有房间名
+    pub fn joined(&self, uid: usize) -> Vec<Atom> {
+        if let Some(rooms) = self.0.read().joined.get(&uid) {
+            return rooms.clone();
+        }
+
+        Vec::new()
+    }
+
+    //判断指定唯一id的连接是否已加入指定房间
+    pub fn is_joined(&self, room: &str, uid: usize) -> bool {
+        if let Some(rooms) = self.0.read().joined.get(&uid) {
+            return rooms.contains(&Atom::from(room));
+        }
+
+        false
+    }
+
+    //线程安全的将指定连接加入指定房间，房间不存在则创建，连接关闭时会自动离开所有已加入的房间
+    pub fn join(&self, room: &str, connect: &WsSocket<S, H>) -> bool {
+        if connect.is_closed() {
+            //连接已关闭，则忽略
+            return false;
+        }
+
+        let name = Atom::from(room);
+        let uid = connect.get_uid();
+        let mut tab = self.0.write();
+        let is_first = if let Some(rooms) = tab.joined.get(&uid) {
+            if rooms.contains(&name) {
+                //已加入指定房间，则忽略
+                return false;
+            }
+
+            false
+        } else {
+            true
+        };
+
+        if is_first {
+            //连接首次加入当前房间表的任意房间，则注册连接关闭监听器，每个连接只注册一个
+            let h = if let Some(h) = connect.get_session() {
+                h
+            } else {
+                //连接会话为空，则忽略
+                return false;
+            };
+
+            let listener: Arc<dyn WsCloseListener> = Arc::new(RoomCloseListener(Arc::downgrade(&self.0)));
+            h.as_ref().add_close_listener(listener.clone());
+            if connect.is_closed() {
+                //注册监听器前连接已关闭，监听器可能不会再被通知，则移除监听器并忽略
+                h.as_ref().remove_close_listener(&listener);
+                return false;
+            }
+
+            tab.listeners.insert(uid, (connect.clone(), listener));
+        }
+
+        tab.rooms.entry(name.clone()).or_insert_with(WsRoom::default).join(connect.clone());
+        tab.joined.entry(uid).or_insert_with(Vec::new).push(name);
+        true
+    }
+
+    //线程安全的将指定唯一id的连接离开指定房间，房间为空则移除
+    pub fn leave(&self, room: &str, uid: usize) -> bool {
+        let name = Atom::from(room);
+        let mut tab = self.0.write();
+        let is_empty = if let Some(rooms) = tab.joined.get_mut(&uid) {
+            if let Some(index) = rooms.iter().position(|r| r == &name) {
+                rooms.swap_remove(index);
+            } else {
+                //未加入指定房间，则忽略
+                return false;
+            }
+
+            rooms.is_empty()
+        } else {
+            return false;
+        };
+
+        if is_empty {
+            //已离开所有房间，则移除连接关闭监听器
+            tab.joined.remove(&uid);
+            remove_listener(&mut tab.listeners, uid);
+        }
+
+        leave_room(&mut tab.rooms, &name, uid)
+    }
+
+    //线程安全的将指定唯一id的连接离开所有已加入的房间，返回离开的房间数量
+    pub fn leave_all(&self, uid: usize) -> usize {
+        let mut tab = self.0.write();
+        remove_listener(&mut tab.listeners, uid);
+        if let Some(rooms) = tab.joined.remove(&uid) {
+            let len = rooms.len();
+            for name in rooms {
+                leave_room(&mut tab.rooms, &name, uid);
+            }
+
+            return len;
+        }
+
+        0
+    }
+
+    //线程安全的向指定房间的所有成员广播指定负载，帧只序列化一次，并由所有成员共享，返回成功发送的成员数量
+    pub fn broadcast(&self, room: &str, msg_type: WsFrameType, payload: WriteBuffer) -> Result<usize> {
+        self.broadcast_except(room, None, msg_type, payload)
+    }
+
+    //线程安全的向指定房间除指定唯一id的连接以外的所有成员广播指定负载，返回成功发送的成员数量
+    pub fn broadcast_except(&self,
+                            room: &str,
+                            except: Option<usize>,
+                            msg_type: WsFrameType,
+                            mut payload: WriteBuffer) -> Result<usize> {
+        let tab = self.0.read();
+        let r = if let Some(r) = tab.rooms.get(&Atom::from(room)) {
+            r
+        } else {
+            //房间不存在，则忽略
+            return Ok(0);
+        };
+
+        //按成员所属的Tcp连接池分组，在发送前执行成员绑定的中间件，未修改消息的成员共享同一个帧缓冲
+        let mut count = 0;
+        let mut shared = Vec::with_capacity(r.size);
+        for (pool_uid, members) in r.pools.iter() {
+            for (uid, connect) in members.iter() {
+                if Some(*uid) == except || connect.is_closed() {
+                    //忽略被排除或已关闭的成员
+                    continue;
+                }
+
+                match connect.filter_send(&msg_type, &mut payload) {
+                    None => {
+                        shared.push((pool_uid, uid, connect));
+                    },
+                    Some(Err(e)) => {
+                        warn!("!!!> Websocket Room Broadcast Failed, room: {:?}, pool: {:?}, uid: {:?}, reason: {:?}", room, pool_uid, uid, e);
+                    },
+                    Some(Ok(_)) => {
+                        count += 1;
+                    },
+                }
+            }
+        }
+
+        let (window_bits, is_client) = if let Some((_, _, connect)) = shared.first() {
+            (connect.window_bits(), connect.is_client())
+        } else {
+            //没有需要共享帧缓冲的成员，则忽略
+            return Ok(count);
+        };
+
+        let mut frame = WsFrame::<S, H>::single_with_window_bits_and_payload(msg_type, window_bits, payload);
+        if is_client {
+            //客户端连接，则需要掩码
+            frame.mask_with_key(random_mask_key());
+        }
+
+        if let Some(buf) = frame.into_write_buf() {
+            if let Some(handle) = buf.finish() {
+                for (pool_uid, uid, connect) in shared {
+                    if let Err(e) = connect.send_shared(handle.clone()) {
+                        warn!("!!!> Websocket Room Broadcast Failed, room: {:?}, pool: {:?}, uid: {:?}, reason: {:?}", room, pool_uid, uid, e);
+                        continue;
+                    }
+
+                    count += 1;
+                }
+            }
+
+            return Ok(count);
+        }
+
+        Err(Error::new(ErrorKind::InvalidData, "invalid payload"))
+    }
+}
+
+//移除指定唯一id的连接已注册的连接关闭监听器
+fn remove_listener<S: Socket, H: AsyncIOWait>(listeners: &mut XHashMap<usize, (WsSocket<S, H>, Arc<dyn WsCloseListener>)>, uid: usize) {
+    if let Some((connect, listener)) = listeners.remove(&uid) {
+        if let Some(h) = connect.get_session() {
+            h.as_ref().remove_close_listener(&listener);
+        }
+    }
+}
+
+//将指定唯一id的连接从指定房间中移除，房间为空则移除房间
+fn leave_room<S: Socket, H: AsyncIOWait>(rooms: &mut XHashMap<Atom, WsRoom<S, H>>, name: &Atom, uid: usize) -> bool {
+    let (is_leaved, is_empty) = if let Some(r) = rooms.get_mut(name) {
+        (r.leave(uid), r.is_empty())
+    } else {
+        return false;
+    };
+
+    if is_empty {
+        rooms.remove(name);
+    }
+
+    is_leaved
+}
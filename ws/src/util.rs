@@ -1,9 +1,14 @@
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::collections::HashMap;
+use std::str::from_utf8;
+use std::result::Result as GenResult;
 use std::io::{Error, Result, ErrorKind};
 
 use bytes::BufMut;
+use flate2::{Decompress, FlushDecompress, Status};
 use httparse::Request;
 use fnv::FnvBuildHasher;
 use futures::future::BoxFuture;
@@ -32,10 +37,15 @@ pub trait ChildProtocol<S: Socket, H: AsyncIOWait>: Send + Sync + 'static {
         Ok(())
     }
 
+    //处理客户端握手成功，一般用于客户端在握手成功后立即发送数据，返回错误将立即关闭当前连接
+    fn connected_protocol(&self, connect: WsSocket<S, H>, context: &mut WsSession) -> Result<()> {
+        Ok(())
+    }
+
     //解码子协议，返回错误将立即关闭当前连接
     fn decode_protocol(&self, connect: WsSocket<S, H>, waits: H, context: &mut WsSession) -> BoxFuture<'static, Result<()>>;
 
-    //关闭子协议
+    //关闭子协议，可以通过会话获取关闭握手的状态码和原因
     fn close_protocol(&self, connect: WsSocket<S, H>, context: WsSession, reason: Result<()>);
 
     //子协议超时，返回即关闭当前连接
@@ -125,14 +135,171 @@ impl WsFrameType {
     }
 }
 
+/*
+* Websocket关闭状态码
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseCode {
+    Normal,             //1000，正常关闭
+    Away,               //1001，终端离开
+    Protocol,           //1002，协议错误
+    Unsupported,        //1003，不支持的数据类型
+    Status,             //1005，没有状态码，保留，不允许在关闭帧中发送
+    Abnormal,           //1006，没有关闭帧的异常关闭，保留，不允许在关闭帧中发送
+    Invalid,            //1007，无效的负载数据，例如非UTF-8编码的文本帧
+    Policy,             //1008，违反策略
+    Size,               //1009，消息过大
+    Extension,          //1010，客户端需要的扩展协议未协商
+    Error,              //1011，服务器端内部错误
+    Restart,            //1012，服务重启
+    Again,              //1013，服务过载，稍后再试
+    Gateway,            //1014，网关错误
+    Tls,                //1015，Tls握手失败，保留，不允许在关闭帧中发送
+    Application(u16),   //3000~4999，库、框架和应用使用的状态码
+    Reserved(u16),      //其它保留或无效的状态码
+}
+
+impl From<u16> for CloseCode {
+    fn from(code: u16) -> Self {
+        match code {
+            1000 => CloseCode::Normal,
+            1001 => CloseCode::Away,
+            1002 => CloseCode::Protocol,
+            1003 => CloseCode::Unsupported,
+            1005 => CloseCode::Status,
+            1006 => CloseCode::Abnormal,
+            1007 => CloseCode::Invalid,
+            1008 => CloseCode::Policy,
+            1009 => CloseCode::Size,
+            1010 => CloseCode::Extension,
+            1011 => CloseCode::Error,
+            1012 => CloseCode::Restart,
+            1013 => CloseCode::Again,
+            1014 => CloseCode::Gateway,
+            1015 => CloseCode::Tls,
+            3000..=4999 => CloseCode::Application(code),
+            _ => CloseCode::Reserved(code),
+        }
+    }
+}
+
+impl From<CloseCode> for u16 {
+    fn from(code: CloseCode) -> Self {
+        match code {
+            CloseCode::Normal => 1000,
+            CloseCode::Away => 1001,
+            CloseCode::Protocol => 1002,
+            CloseCode::Unsupported => 1003,
+            CloseCode::Status => 1005,
+            CloseCode::Abnormal => 1006,
+            CloseCode::Invalid => 1007,
+            CloseCode::Policy => 1008,
+            CloseCode::Size => 1009,
+            CloseCode::Extension => 1010,
+            CloseCode::Error => 1011,
+            CloseCode::Restart => 1012,
+            CloseCode::Again => 1013,
+            CloseCode::Gateway => 1014,
+            CloseCode::Tls => 1015,
+            CloseCode::Application(code) => code,
+            CloseCode::Reserved(code) => code,
+        }
+    }
+}
+
+impl CloseCode {
+    //是否是正常关闭
+    pub fn is_normal(&self) -> bool {
+        if let CloseCode::Normal = self {
+            true
+        } else {
+            false
+        }
+    }
+
+    //是否允许在关闭帧中发送或接收
+    pub fn is_allowed(&self) -> bool {
+        match self {
+            CloseCode::Status | CloseCode::Abnormal | CloseCode::Tls | CloseCode::Reserved(_) => false,
+            _ => true,
+        }
+    }
+
+    //将关闭状态码和原因序列化为关闭帧负载，原因会被截断到控制帧允许的最大负载长度
+    pub fn to_payload(&self, reason: &str) -> Vec<u8> {
+        let code: u16 = (*self).into();
+        let mut len = reason.len().min(MAX_CLOSE_REASON_LEN);
+        while !reason.is_char_boundary(len) {
+            //保证截断后的原因仍然是合法的UTF-8编码
+            len -= 1;
+        }
+
+        let mut payload = Vec::with_capacity(2 + len);
+        payload.push(((code >> 8) & 0xff) as u8);
+        payload.push((code & 0xff) as u8);
+        payload.extend_from_slice(reason[0..len].as_bytes());
+        payload
+    }
+
+    //将关闭帧负载反序列化为关闭状态码和原因，负载不合法则返回应当用于关闭连接的状态码
+    pub fn from_payload(payload: &[u8]) -> GenResult<Option<(CloseCode, String)>, CloseCode> {
+        match payload.len() {
+            0 => Ok(None), //没有关闭状态码
+            1 => Err(CloseCode::Protocol), //不完整的关闭状态码
+            _ => {
+                let code = CloseCode::from((((payload[0] as u16) << 8) & 0xff00) | ((payload[1] as u16) & 0xff));
+                if !code.is_allowed() {
+                    //无效的关闭状态码
+                    return Err(CloseCode::Protocol);
+                }
+
+                match from_utf8(&payload[2..]) {
+                    Err(_) => Err(CloseCode::Invalid), //关闭原因不是合法的UTF-8编码
+                    Ok(reason) => Ok(Some((code, reason.to_string()))),
+                }
+            },
+        }
+    }
+}
+
+/*
+* 关闭帧中关闭原因的最大长度
+*/
+pub const MAX_CLOSE_REASON_LEN: usize = 123;
+
+/*
+* 默认的关闭握手超时时长，单位毫秒
+*/
+pub const DEFAULT_CLOSE_HANDSHAKE_TIMEOUT: usize = 5000;
+
+/*
+* Websocket关闭握手超时事件
+*/
+#[derive(Debug, Clone)]
+pub struct WsCloseTimeout;
+
+/*
+* Websocket连接关闭监听器
+*/
+pub trait WsCloseListener: Send + Sync + 'static {
+    //处理指定唯一id的Websocket连接已关闭事件
+    fn closed(&self, uid: usize);
+}
+
 /*
 * Websocket会话
 */
 pub struct WsSession {
     status:     WsStatus,       //当前连接状态
+    client:     bool,           //是否是客户端会话
     r#type:     WsFrameType,    //帧类型
+    compressed: bool,           //当前消息是否被压缩，由首帧的rsv1决定
     frames:     Vec<u8>,        //Websocket帧缓冲
+    inflater:   Option<Decompress>, //每消息压缩的解压器，在连接内保持上下文
     context:    SocketContext,  //会话上下文
+    initiated:  AtomicBool,     //是否由本端发起了关闭握手
+    close:      Mutex<Option<(CloseCode, String)>>, //关闭握手的状态码和原因
+    listeners:  Mutex<Vec<Arc<dyn WsCloseListener>>>,  //连接关闭监听器列表
 }
 
 unsafe impl Send for WsSession {}
@@ -142,14 +309,32 @@ impl Default for WsSession {
     fn default() -> Self {
         WsSession {
             status: WsStatus::HandShaking,
+            client: false,
             r#type: WsFrameType::Undefined,
+            compressed: false,
             frames: Vec::with_capacity(32),
+            inflater: None,
             context: SocketContext::empty(),
+            initiated: AtomicBool::new(false),
+            close: Mutex::new(None),
+            listeners: Mutex::new(Vec::new()),
         }
     }
 }
 
 impl WsSession {
+    //构建客户端会话，客户端上行的帧需要掩码
+    pub fn new_client() -> Self {
+        let mut session = WsSession::default();
+        session.client = true;
+        session
+    }
+
+    //判断是否是客户端会话
+    pub fn is_client(&self) -> bool {
+        self.client
+    }
+
     //判断是否已握手
     pub fn is_handshaked(&self) -> bool {
         match &self.status {
@@ -176,6 +361,66 @@ impl WsSession {
         }
     }
 
+    //判断是否由本端发起了关闭握手
+    pub fn is_close_initiated(&self) -> bool {
+        self.initiated.load(Ordering::SeqCst)
+    }
+
+    //线程安全的设置由本端发起关闭握手，如果已发起则返回false
+    pub fn set_close_initiated(&self) -> bool {
+        !self.initiated.swap(true, Ordering::SeqCst)
+    }
+
+    //获取关闭握手的状态码和原因
+    pub fn get_close(&self) -> Option<(CloseCode, String)> {
+        if let Ok(close) = self.close.lock() {
+            return close.clone();
+        }
+
+        None
+    }
+
+    //线程安全的设置关闭握手的状态码和原因，已设置则忽略
+    pub fn set_close(&self, code: CloseCode, reason: &str) {
+        if let Ok(mut close) = self.close.lock() {
+            if close.is_none() {
+                *close = Some((code, reason.to_string()));
+            }
+        }
+    }
+
+    //线程安全的增加连接关闭监听器
+    pub fn add_close_listener(&self, listener: Arc<dyn WsCloseListener>) {
+        if let Ok(mut listeners) = self.listeners.lock() {
+            listeners.push(listener);
+        }
+    }
+
+    //线程安全的移除指定的连接关闭监听器
+    pub fn remove_close_listener(&self, listener: &Arc<dyn WsCloseListener>) -> bool {
+        if let Ok(mut listeners) = self.listeners.lock() {
+            if let Some(index) = listeners.iter().position(|l| Arc::ptr_eq(l, listener)) {
+                listeners.swap_remove(index);
+                return true;
+            }
+        }
+
+        false
+    }
+
+    //通知并移除所有连接关闭监听器
+    pub fn notify_closed(&self, uid: usize) {
+        let listeners = if let Ok(mut listeners) = self.listeners.lock() {
+            listeners.drain(..).collect::<Vec<Arc<dyn WsCloseListener>>>()
+        } else {
+            return;
+        };
+
+        for listener in listeners {
+            listener.closed(uid);
+        }
+    }
+
     //设置连接状态
     pub fn set_status(&mut self, status: WsStatus) {
         self.status = status;
@@ -202,6 +447,69 @@ impl WsSession {
         self.r#type = frame_type.into();
     }
 
+    //判断当前消息是否被压缩
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    //设置当前消息是否被压缩
+    pub fn set_compressed(&mut self, compressed: bool) {
+        self.compressed = compressed;
+    }
+
+    //解压当前消息的帧缓冲，解压后的长度超过限制或数据无效，则返回应当用于关闭连接的状态码
+    pub fn inflate(&mut self, max_len: usize) -> GenResult<(), CloseCode> {
+        if !self.compressed {
+            return Ok(());
+        }
+
+        //RFC7692要求在解压前补齐被发送端移除的空块尾部
+        self.frames.extend_from_slice(&[0x00, 0x00, 0xff, 0xff]);
+        let inflater = self.inflater.get_or_insert_with(|| Decompress::new(false));
+        let mut output = Vec::with_capacity(self.frames.len() * 2);
+        let mut offset = 0;
+        loop {
+            if output.len() >= max_len {
+                return Err(CloseCode::Size);
+            }
+            if output.len() == output.capacity() {
+                let additional = (max_len - output.len()).min(output.capacity().max(1024));
+                output.reserve(additional);
+            }
+
+            let total_in = inflater.total_in();
+            let total_out = inflater.total_out();
+            let status = match inflater.decompress_vec(&self.frames[offset..], &mut output, FlushDecompress::Sync) {
+                Err(_) => return Err(CloseCode::Protocol),
+                Ok(status) => status,
+            };
+            let consumed = (inflater.total_in() - total_in) as usize;
+            let produced = (inflater.total_out() - total_out) as usize;
+            offset += consumed;
+
+            if let Status::StreamEnd = status {
+                //发送端使用了BFINAL为1的块结束了压缩流，则重置解压器，并忽略补齐的空块尾部
+                inflater.reset(false);
+                break;
+            }
+            if offset >= self.frames.len() && output.len() < output.capacity() {
+                //输入已全部消耗，且输出缓冲未满，则解压完成
+                break;
+            }
+            if consumed == 0 && produced == 0 && output.len() < output.capacity() {
+                //无法继续解压，则数据无效
+                return Err(CloseCode::Protocol);
+            }
+        }
+
+        if output.len() > max_len {
+            return Err(CloseCode::Size);
+        }
+        self.frames = output;
+        self.compressed = false;
+        Ok(())
+    }
+
     //获取帧缓冲的只读引用
     pub fn as_buf(&self) -> &[u8] {
         self.frames.as_slice()
@@ -230,6 +538,7 @@ impl WsSession {
     //重置帧类型和帧缓冲
     pub fn reset(&mut self) {
         self.r#type = WsFrameType::Undefined;
+        self.compressed = false;
         self.frames.clear();
     }
 
@@ -1,9 +1,11 @@
 use std::thread;
-use std::sync::Arc;
+use std::net::TcpStream;
 use std::time::Duration;
-use std::io::{ErrorKind, Result, Error};
+use std::sync::{Arc, Mutex, mpsc::{channel, Sender}};
+use std::io::{ErrorKind, Result, Error, Read, Write};
 
 use futures::future::{FutureExt, BoxFuture};
+use flate2::{Compress, Compression, FlushCompress};
 
 use tcp::connect::TcpSocket;
 use tcp::tls_connect::TlsSocket;
@@ -13,9 +15,12 @@ use tcp::buffer_pool::WriteBufferPool;
 use tcp::util::{SocketEvent, TlsConfig};
 
 use ws::{server::WebsocketListenerFactory,
+         connector::{DEFAULT_CONNECT_TIMEOUT, WsConnectRequest, WebsocketConnectorFactory, connect},
          connect::WsSocket,
+         middleware::{WsMessage, WsMiddleware, WsMiddlewareResult, WsSendResult, WsMiddlewareChain, WsMiddlewareProtocol},
          frame::WsHead,
-         util::{ChildProtocol, ChildProtocolFactory, WsSession}};
+         room::WsRoomTab,
+         util::{ChildProtocol, ChildProtocolFactory, CloseCode, WsFrameType, WsSession}};
 
 struct TestChildProtocol;
 
@@ -43,10 +48,10 @@ impl<S: Socket, H: AsyncIOWait> ChildProtocol<S, H> for TestChildProtocol {
 
     fn close_protocol(&self, connect: WsSocket<S, H>, context: WsSession, reason: Result<()>) {
         if let Err(e) = reason {
-            return println!("websocket closed, reason: {:?}", e);
+            return println!("websocket closed, close: {:?}, reason: {:?}", context.get_close(), e);
         }
 
-        println!("websocket closed");
+        println!("websocket closed, close: {:?}", context.get_close());
     }
 
     fn protocol_timeout(&self, connect: WsSocket<S, H>, context: &mut WsSession, event: SocketEvent) -> Result<()> {
@@ -129,4 +134,336 @@ fn test_tls_websocket_listener() {
     }
 
     thread::sleep(Duration::from_millis(10000000));
-}
\ No newline at end of file
+}
+struct TestRoomChildProtocol(WsRoomTab<TcpSocket, AsyncWaitsHandle>);
+
+impl ChildProtocol<TcpSocket, AsyncWaitsHandle> for TestRoomChildProtocol {
+    fn protocol_name(&self) -> &str {
+        "chat"
+    }
+
+    fn decode_protocol(&self, connect: WsSocket<TcpSocket, AsyncWaitsHandle>, waits: AsyncWaitsHandle, context: &mut WsSession) -> BoxFuture<'static, Result<()>> {
+        let rooms = self.0.clone();
+        let msg = context.to_vec();
+        let msg_type = context.get_type();
+
+        async move {
+            rooms.join("lobby", &connect);
+
+            if let Some(mut buf) = connect.alloc() {
+                buf.get_iolist_mut().push_back(msg.into());
+                match rooms.broadcast_except("lobby", Some(connect.get_uid()), msg_type, buf) {
+                    Err(e) => return Err(e),
+                    Ok(count) => println!("websocket room broadcast, room: lobby, size: {}, count: {}", rooms.size("lobby"), count),
+                }
+            }
+
+            Ok(())
+        }.boxed()
+    }
+
+    fn close_protocol(&self, connect: WsSocket<TcpSocket, AsyncWaitsHandle>, context: WsSession, reason: Result<()>) {
+        println!("websocket closed, uid: {}, joined: {:?}, room size: {}", connect.get_uid(), self.0.joined(connect.get_uid()), self.0.size("lobby"));
+    }
+
+    fn protocol_timeout(&self, connect: WsSocket<TcpSocket, AsyncWaitsHandle>, context: &mut WsSession, event: SocketEvent) -> Result<()> {
+        Ok(())
+    }
+}
+
+struct TestRoomChildProtocolFactory(WsRoomTab<TcpSocket, AsyncWaitsHandle>);
+
+impl ChildProtocolFactory for TestRoomChildProtocolFactory {
+    type Connect = TcpSocket;
+    type Waits = AsyncWaitsHandle;
+
+    fn new_protocol(&self) -> Arc<dyn ChildProtocol<Self::Connect, Self::Waits>> {
+        Arc::new(TestRoomChildProtocol(self.0.clone()))
+    }
+}
+
+#[test]
+fn test_websocket_room() {
+    let mut factory = AsyncPortsFactory::<TcpSocket>::new();
+    factory.bind(38082,
+                 Box::new(WebsocketListenerFactory::<TcpSocket>::with_protocol_factory(
+                     Arc::new(TestRoomChildProtocolFactory(WsRoomTab::new())))));
+    let mut config = SocketConfig::new("0.0.0.0", factory.bind_ports().as_slice());
+    config.set_option(16384, 16384, 16384, 16);
+    let buffer = WriteBufferPool::new(10000, 10, 3).ok().unwrap();
+
+    if let Err(e) = SocketListener::bind(factory, buffer, config, 1024, 1024 * 1024, 1024, Some(10)) {
+        panic!("!!!> Websocket Listener Bind Error, reason: {:?}", e);
+    }
+
+    //首个连接加入房间后，第二个连接的消息会广播给首个连接，但不会回给自己
+    let mut first = ws_handshake(38082, "chat");
+    ws_send_text(&mut first, b"first");
+    thread::sleep(Duration::from_millis(500));
+    let mut second = ws_handshake(38082, "chat");
+    ws_send_text(&mut second, b"second");
+
+    assert_eq!(ws_read_frame(&mut first), (0x01, b"second".to_vec()));
+    second.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+    let mut bin = [0; 1];
+    assert!(second.read(&mut bin).is_err());
+}
+
+struct TestClientChildProtocol(Arc<Mutex<Sender<String>>>);
+
+impl<S: Socket, H: AsyncIOWait> ChildProtocol<S, H> for TestClientChildProtocol {
+    fn protocol_name(&self) -> &str {
+        "echo"
+    }
+
+    fn connected_protocol(&self, connect: WsSocket<S, H>, context: &mut WsSession) -> Result<()> {
+        if let Some(mut buf) = connect.alloc() {
+            buf.get_iolist_mut().push_back(b"Hello Websocket".to_vec().into());
+            return connect.send(WsFrameType::Text, buf);
+        }
+
+        Err(Error::new(ErrorKind::Other, "test websocket client failed, reason: alloc write buffer failed"))
+    }
+
+    fn decode_protocol(&self, connect: WsSocket<S, H>, waits: H, context: &mut WsSession) -> BoxFuture<'static, Result<()>> {
+        println!("websocket client received, type: {:?}, msg: {:?}", context.get_type(), String::from_utf8(context.to_vec()));
+        if let Ok(msg) = String::from_utf8(context.to_vec()) {
+            let _ = self.0.lock().unwrap().send(msg);
+        }
+
+        async move {
+            Ok(())
+        }.boxed()
+    }
+
+    fn close_protocol(&self, connect: WsSocket<S, H>, context: WsSession, reason: Result<()>) {
+        println!("websocket client closed, close: {:?}, reason: {:?}", context.get_close(), reason);
+    }
+
+    fn protocol_timeout(&self, connect: WsSocket<S, H>, context: &mut WsSession, event: SocketEvent) -> Result<()> {
+        println!("websocket client timeout");
+
+        Ok(())
+    }
+}
+
+struct TestClientChildProtocolFactory(Arc<Mutex<Sender<String>>>);
+
+impl ChildProtocolFactory for TestClientChildProtocolFactory {
+    type Connect = TcpSocket;
+    type Waits = AsyncWaitsHandle;
+
+    fn new_protocol(&self) -> Arc<dyn ChildProtocol<Self::Connect, Self::Waits>> {
+        Arc::new(TestClientChildProtocol(self.0.clone()))
+    }
+}
+
+#[test]
+fn test_websocket_connector() {
+    let (sender, receiver) = channel();
+    let mut factory = AsyncPortsFactory::<TcpSocket>::new();
+    factory.bind(38084,
+                 Box::new(WebsocketListenerFactory::<TcpSocket>::with_protocol_factory(
+                     Arc::new(TestChildProtocolFactory))));
+    factory.bind(38085,
+                 Box::new(WebsocketConnectorFactory::<TcpSocket>::with_protocol_factory(
+                     Arc::new(TestClientChildProtocolFactory(Arc::new(Mutex::new(sender)))))));
+    let mut config = SocketConfig::new("0.0.0.0", factory.bind_ports().as_slice());
+    config.set_option(16384, 16384, 16384, 16);
+    let buffer = WriteBufferPool::new(10000, 10, 3).ok().unwrap();
+
+    match SocketListener::bind(factory, buffer, config, 1024, 1024 * 1024, 1024, Some(10)) {
+        Err(e) => {
+            panic!("!!!> Websocket Listener Bind Error, reason: {:?}", e);
+        },
+        Ok(driver) => {
+            let mut request = WsConnectRequest::new("ws://127.0.0.1:38084/").unwrap();
+            request.add_protocol("echo");
+            if let Err(e) = connect(&driver, 38085, request, DEFAULT_CONNECT_TIMEOUT, TlsConfig::empty()) {
+                panic!("!!!> Websocket Connect Error, reason: {:?}", e);
+            }
+        }
+    }
+
+    //服务器端会回应3次客户端连接后发送的消息
+    for _ in 0..3 {
+        assert_eq!(receiver.recv_timeout(Duration::from_millis(10000)).unwrap(), "Hello Websocket");
+    }
+}
+
+struct TestLimitMiddleware(usize);
+
+impl<S: Socket, H: AsyncIOWait> WsMiddleware<S, H> for TestLimitMiddleware {
+    fn recv(&self, connect: &WsSocket<S, H>, context: &mut WsSession, msg: WsMessage) -> WsMiddlewareResult {
+        if msg.as_payload().len() > self.0 {
+            return WsMiddlewareResult::Close(CloseCode::Size, "message too big".to_string());
+        }
+
+        if msg.as_payload().is_empty() {
+            return WsMiddlewareResult::Drop;
+        }
+
+        WsMiddlewareResult::Continue(msg)
+    }
+
+    fn send(&self, connect: &WsSocket<S, H>, msg_type: &WsFrameType, payload: &[u8]) -> WsSendResult {
+        let mut bin = b"echo: ".to_vec();
+        bin.extend_from_slice(payload);
+
+        WsSendResult::Replace(WsMessage::new(msg_type.clone(), bin))
+    }
+}
+
+struct TestMiddlewareChildProtocolFactory;
+
+impl ChildProtocolFactory for TestMiddlewareChildProtocolFactory {
+    type Connect = TcpSocket;
+    type Waits = AsyncWaitsHandle;
+
+    fn new_protocol(&self) -> Arc<dyn ChildProtocol<Self::Connect, Self::Waits>> {
+        let mut chain = WsMiddlewareChain::new();
+        chain.push_back(Arc::new(TestLimitMiddleware(1024)));
+        chain.finish();
+
+        Arc::new(WsMiddlewareProtocol::new(Arc::new(chain), Arc::new(TestChildProtocol)))
+    }
+}
+
+#[test]
+fn test_websocket_middleware() {
+    let mut factory = AsyncPortsFactory::<TcpSocket>::new();
+    factory.bind(38086,
+                 Box::new(WebsocketListenerFactory::<TcpSocket>::with_protocol_factory(
+                     Arc::new(TestMiddlewareChildProtocolFactory))));
+    let mut config = SocketConfig::new("0.0.0.0", factory.bind_ports().as_slice());
+    config.set_option(16384, 16384, 16384, 16);
+    let buffer = WriteBufferPool::new(10000, 10, 3).ok().unwrap();
+
+    if let Err(e) = SocketListener::bind(factory, buffer, config, 1024, 1024 * 1024, 1024, Some(10)) {
+        panic!("!!!> Websocket Listener Bind Error, reason: {:?}", e);
+    }
+
+    //发送的消息会被中间件加上前缀
+    let mut stream = ws_handshake(38086, "echo");
+    ws_send_text(&mut stream, b"Hello Websocket");
+    for _ in 0..3 {
+        assert_eq!(ws_read_frame(&mut stream), (0x01, b"echo: Hello Websocket".to_vec()));
+    }
+
+    //超过限制的消息会被中间件以1009关闭
+    ws_send_text(&mut stream, &vec![b'a'; 2048]);
+    let (opcode, payload) = ws_read_frame(&mut stream);
+    assert_eq!(opcode, 0x08);
+    assert_eq!(CloseCode::from_payload(&payload), Ok(Some((CloseCode::Size, "message too big".to_string()))));
+}
+
+//使用阻塞的Tcp流完成Websocket握手
+fn ws_handshake(port: u16, protocol: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_millis(10000))).unwrap();
+    let req = format!("GET / HTTP/1.1\r\nHost: 127.0.0.1:{}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Protocol: {}\r\n\r\n", port, protocol);
+    stream.write_all(req.as_bytes()).unwrap();
+
+    //逐字节读取握手响应，避免读走后续的帧
+    let mut resp = Vec::new();
+    let mut bin = [0; 1];
+    while !resp.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut bin).unwrap();
+        resp.push(bin[0]);
+    }
+    assert!(resp.starts_with(b"HTTP/1.1 101"));
+
+    stream
+}
+
+//发送带掩码的单帧文本消息
+fn ws_send_text(stream: &mut TcpStream, payload: &[u8]) {
+    let mask = [0x12, 0x34, 0x56, 0x78];
+    let mut bin = vec![0x81];
+    if payload.len() < 126 {
+        bin.push(0x80 | payload.len() as u8);
+    } else {
+        bin.push(0x80 | 126);
+        bin.push((payload.len() >> 8) as u8);
+        bin.push(payload.len() as u8);
+    }
+    bin.extend_from_slice(&mask);
+    for (index, b) in payload.iter().enumerate() {
+        bin.push(b ^ mask[index % 4]);
+    }
+
+    stream.write_all(&bin).unwrap();
+}
+
+//读取服务器端发送的无掩码单帧，返回操作码和负载
+fn ws_read_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut head = [0; 2];
+    stream.read_exact(&mut head).unwrap();
+    let len = match head[1] & 0x7f {
+        126 => {
+            let mut bin = [0; 2];
+            stream.read_exact(&mut bin).unwrap();
+            ((bin[0] as usize) << 8) | bin[1] as usize
+        },
+        127 => {
+            let mut bin = [0; 8];
+            stream.read_exact(&mut bin).unwrap();
+            bin.iter().fold(0, |len, b| (len << 8) | *b as usize)
+        },
+        len => len as usize,
+    };
+
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload).unwrap();
+    (head[0] & 0x0f, payload)
+}
+
+#[test]
+fn test_close_code_payload() {
+    let payload = CloseCode::Normal.to_payload("bye");
+    assert_eq!(payload, vec![0x03, 0xe8, b'b', b'y', b'e']);
+    assert_eq!(CloseCode::from_payload(&payload), Ok(Some((CloseCode::Normal, "bye".to_string()))));
+
+    //过长的原因会在字符边界上截断
+    let reason = format!("a{}", "中".repeat(50));
+    let payload = CloseCode::Application(4000).to_payload(&reason);
+    assert_eq!(payload.len(), 2 + 121);
+    assert_eq!(CloseCode::from_payload(&payload), Ok(Some((CloseCode::Application(4000), reason[0..121].to_string()))));
+
+    assert_eq!(CloseCode::from_payload(&[]), Ok(None));
+    assert_eq!(CloseCode::from_payload(&[0x03]), Err(CloseCode::Protocol));
+    assert_eq!(CloseCode::from_payload(&[0x03, 0xed]), Err(CloseCode::Protocol));
+    assert_eq!(CloseCode::from_payload(&[0x03, 0xe8, 0xff]), Err(CloseCode::Invalid));
+}
+
+fn deflate(compress: &mut Compress, msg: &[u8]) -> Vec<u8> {
+    let mut bin = Vec::with_capacity(msg.len() + 64);
+    compress.compress_vec(msg, &mut bin, FlushCompress::Sync).unwrap();
+    assert!(bin.ends_with(&[0x00, 0x00, 0xff, 0xff]));
+    bin.truncate(bin.len() - 4);
+    bin
+}
+
+#[test]
+fn test_session_inflate() {
+    //同一连接内的消息共享压缩上下文
+    let mut compress = Compress::new(Compression::default(), false);
+    let mut session = WsSession::default();
+    for msg in &[&b"Hello Websocket"[..], &b"Hello Websocket Again"[..]] {
+        session.set_compressed(true);
+        session.append(deflate(&mut compress, msg));
+        assert_eq!(session.inflate(1024), Ok(()));
+        assert!(!session.is_compressed());
+        assert_eq!(session.as_buf(), *msg);
+        session.reset();
+    }
+
+    session.set_compressed(true);
+    session.append(deflate(&mut Compress::new(Compression::default(), false), &vec![b'a'; 4096]));
+    assert_eq!(session.inflate(1024), Err(CloseCode::Size));
+
+    let mut session = WsSession::default();
+    session.set_compressed(true);
+    session.append(vec![0xff, 0xff, 0xff]);
+    assert_eq!(session.inflate(1024), Err(CloseCode::Protocol));
+}